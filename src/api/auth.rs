@@ -0,0 +1,176 @@
+use axum::{
+    extract::{Request, State},
+    http::{Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use jiff::Timestamp;
+
+use crate::{
+    ApiState,
+    tokens::state::{ApiToken, Scope, Session, TokensState},
+    utils::{Verified, cookie, is_htmx_request},
+};
+
+use super::ApiError;
+
+/// The scope needed for a request to the API, routes are grouped by their
+/// first segment, e.g. `/todos/3`.
+pub fn required_scope(method: &Method, path: &str) -> Option<Scope> {
+    let read = matches!(*method, Method::GET | Method::HEAD);
+    let resource = path.trim_start_matches('/').split('/').next()?;
+    match (resource, read) {
+        ("todos" | "lists", true) => Some(Scope::TodosRead),
+        ("todos" | "lists", false) => Some(Scope::TodosWrite),
+        ("rooms", true) => Some(Scope::ChatRead),
+        ("rooms", false) => Some(Scope::ChatPost),
+        _ => None,
+    }
+}
+
+/// The scope needed for a request to the routes of the pages, e.g. `/todo/3`
+/// or `/chat/general/messages`, whether they come from the pages or from
+/// scripts.
+pub fn page_scope(method: &Method, path: &str) -> Option<Scope> {
+    let read = matches!(*method, Method::GET | Method::HEAD);
+    let mut segments = path.trim_start_matches('/').split('/');
+    match (segments.next()?, read) {
+        ("todo" | "todos" | "lists" | "tags" | "activity", true) => Some(Scope::TodosRead),
+        ("todo" | "todos" | "lists" | "tags" | "activity", false) => Some(Scope::TodosWrite),
+        // The chat page itself, moderation and incoming webhooks are
+        // authenticated otherwise
+        ("chat", _) if matches!(segments.next(), None | Some("moderation")) => None,
+        ("chat", true) => Some(Scope::ChatRead),
+        ("chat", false) => Some(Scope::ChatPost),
+        _ => None,
+    }
+}
+
+/// Whether a request is the navigation of a browser to a page, which can be
+/// redirected to the sign in page.
+fn is_page_request(req: &Request) -> bool {
+    let accept = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    req.method() == Method::GET && accept.contains("text/html") && !is_htmx_request(req.headers())
+}
+
+/// The scopes the request was authenticated with, if it was.
+fn granted_scopes(req: &Request) -> Option<&[Scope]> {
+    if let Some(token) = req.extensions().get::<ApiToken>() {
+        return Some(&token.scopes);
+    }
+    let session = req.extensions().get::<Session>()?;
+    Some(&session.scopes)
+}
+
+fn bearer_token(req: &Request) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// Add the `Authorization: Bearer` API token of a request, or the session of
+/// the browser it comes from, to its extensions, see [`Verified`]. Changes
+/// are then made on behalf of their owner.
+///
+/// Requests to the routes of the pages need a token or a session with the
+/// scope of the route, see [`page_scope`], as their names could be made up
+/// otherwise. Browsers are sent to the sign in page.
+pub async fn authenticate(State(state): State<ApiState>, mut req: Request, next: Next) -> Response {
+    let now = Timestamp::now();
+    let secret = bearer_token(&req).map(str::to_owned);
+    let session = cookie(req.headers(), Verified::SESSION_COOKIE);
+    let mut used = None;
+    {
+        let state = state.read().await;
+        if let Some(token) = secret.and_then(|s| state.tokens.authenticate(&s)) {
+            used = TokensState::is_stale(token, now).then_some(token.id);
+            req.extensions_mut().insert(token.clone());
+        } else if let Some(session) = session.and_then(|s| state.tokens.session(&s, now)) {
+            req.extensions_mut().insert(session.clone());
+        }
+    }
+    // Uses are only recorded once in a while, most requests only read
+    if let Some(id) = used {
+        state.write().await.tokens.mark_used(id, now);
+    }
+
+    if let Some(scope) = page_scope(req.method(), req.uri().path()) {
+        match granted_scopes(&req) {
+            Some(scopes) if scopes.contains(&scope) => {}
+            Some(_) => {
+                let message = format!("The API token lacks the {scope} scope");
+                return (StatusCode::FORBIDDEN, message).into_response();
+            }
+            None if is_page_request(&req) => return Redirect::to("/tokens").into_response(),
+            None => {
+                let unauthorized = (StatusCode::UNAUTHORIZED, "Missing or invalid API token");
+                let challenge = [(header::WWW_AUTHENTICATE, "Bearer")];
+                return (challenge, [("HX-Redirect", "/tokens")], unauthorized).into_response();
+            }
+        }
+    }
+    next.run(req).await
+}
+
+/// Requests need an `Authorization: Bearer` API token with the scope of the
+/// route, see [`authenticate`].
+pub async fn require_token(req: Request, next: Next) -> Response {
+    let Some(scope) = required_scope(req.method(), req.uri().path()) else {
+        // Routes outside of the scoped resources are public
+        return next.run(req).await;
+    };
+    let Some(token) = req.extensions().get::<ApiToken>() else {
+        let message = match bearer_token(&req) {
+            Some(_) => "Invalid or revoked API token",
+            None => "Missing API token",
+        };
+        let error = ApiError::new(StatusCode::UNAUTHORIZED, message);
+        return ([(header::WWW_AUTHENTICATE, "Bearer")], error).into_response();
+    };
+    if !token.allows(scope) {
+        let message = format!("The API token lacks the {scope} scope");
+        return ApiError::new(StatusCode::FORBIDDEN, message).into_response();
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::Method;
+
+    use super::{page_scope, required_scope};
+    use crate::tokens::state::Scope;
+
+    #[test]
+    fn test_required_scope() {
+        let scope = |method, path| required_scope(&method, path);
+        assert_eq!(scope(Method::GET, "/todos"), Some(Scope::TodosRead));
+        assert_eq!(scope(Method::PATCH, "/lists/2"), Some(Scope::TodosWrite));
+        assert_eq!(
+            scope(Method::GET, "/rooms/general/messages"),
+            Some(Scope::ChatRead)
+        );
+        assert_eq!(
+            scope(Method::POST, "/rooms/general/messages"),
+            Some(Scope::ChatPost)
+        );
+        assert_eq!(scope(Method::GET, "/nothing"), None);
+
+        let scope = |method, path| page_scope(&method, path);
+        assert_eq!(scope(Method::PUT, "/todo/3/done"), Some(Scope::TodosWrite));
+        assert_eq!(scope(Method::GET, "/activity"), Some(Scope::TodosRead));
+        assert_eq!(
+            scope(Method::POST, "/chat/general/messages"),
+            Some(Scope::ChatPost)
+        );
+        assert_eq!(scope(Method::GET, "/chat"), None);
+        assert_eq!(scope(Method::POST, "/chat/moderation/bans"), None);
+        assert_eq!(scope(Method::POST, "/tokens"), None);
+    }
+}
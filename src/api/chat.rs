@@ -0,0 +1,235 @@
+use axum::{
+    extract::State,
+    http::{StatusCode, header::LOCATION},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::{
+    ApiState,
+    chat::state::{ChatMessage, ChatState, DEFAULT_ROOM, PostError, is_valid_room_name},
+    utils::Verified,
+};
+
+use super::{ApiError, ApiJson, ApiPath, ApiQuery, Envelope, Pagination};
+
+fn room_not_found(room: &str) -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        format!("Room {room:?} does not exist"),
+    )
+}
+
+fn message_not_found(room: &str, id: usize) -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        format!("Message {id} does not exist in {room:?}"),
+    )
+}
+
+/// A message of `room`, messages of other rooms are not found.
+fn room_message<'a>(
+    chat: &'a ChatState,
+    room: &str,
+    id: usize,
+) -> Result<&'a ChatMessage, ApiError> {
+    chat.message(id)
+        .filter(|message| message.room == room)
+        .ok_or_else(|| message_not_found(room, id))
+}
+
+/// Messages can only be changed by their author.
+fn authored_message<'a>(
+    chat: &'a ChatState,
+    room: &str,
+    id: usize,
+    author: &str,
+) -> Result<&'a ChatMessage, ApiError> {
+    let message = room_message(chat, room, id)?;
+    if message.username != author {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!("Message {id} was posted by someone else"),
+        ));
+    }
+    Ok(message)
+}
+
+pub async fn list_rooms(
+    State(state): State<ApiState>,
+    ApiQuery(pagination): ApiQuery<Pagination>,
+) -> Response {
+    let state = state.read().await;
+    pagination.page(state.chat.rooms().to_vec()).into_response()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateRoomRequest {
+    pub name: String,
+    #[serde(default)]
+    pub topic: String,
+}
+
+pub async fn create_room(
+    State(state): State<ApiState>,
+    ApiJson(payload): ApiJson<CreateRoomRequest>,
+) -> Result<Response, ApiError> {
+    let name = payload.name.trim();
+    if !is_valid_room_name(name) {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Room names are 1 to 32 lowercase letters, digits or dashes",
+        ));
+    }
+    let mut state = state.write().await;
+    let room = state.chat.add_room(name, &payload.topic).ok_or_else(|| {
+        ApiError::new(
+            StatusCode::CONFLICT,
+            format!("Room {name:?} already exists"),
+        )
+    })?;
+    let location = [(LOCATION, format!("/api/v1/rooms/{name}"))];
+    Ok((StatusCode::CREATED, location, Envelope::new(room)).into_response())
+}
+
+pub async fn get_room(
+    State(state): State<ApiState>,
+    ApiPath(room): ApiPath<String>,
+) -> Result<Response, ApiError> {
+    let state = state.read().await;
+    let room = state
+        .chat
+        .room(&room)
+        .ok_or_else(|| room_not_found(&room))?;
+    Ok(Envelope::new(room).into_response())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateRoomRequest {
+    pub topic: String,
+}
+
+pub async fn update_room(
+    State(state): State<ApiState>,
+    ApiPath(room): ApiPath<String>,
+    ApiJson(payload): ApiJson<UpdateRoomRequest>,
+) -> Result<Response, ApiError> {
+    let mut state = state.write().await;
+    let updated = state
+        .chat
+        .set_room_topic(&room, &payload.topic)
+        .ok_or_else(|| room_not_found(&room))?;
+    Ok(Envelope::new(updated).into_response())
+}
+
+/// Messages of a deleted room are deleted as well.
+pub async fn delete_room(
+    State(state): State<ApiState>,
+    ApiPath(room): ApiPath<String>,
+) -> Result<StatusCode, ApiError> {
+    if room == DEFAULT_ROOM {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "The default room cannot be deleted",
+        ));
+    }
+    let mut state = state.write().await;
+    state
+        .chat
+        .delete_room(&room)
+        .ok_or_else(|| room_not_found(&room))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Messages of a room, oldest first.
+pub async fn list_messages(
+    State(state): State<ApiState>,
+    ApiPath(room): ApiPath<String>,
+    ApiQuery(pagination): ApiQuery<Pagination>,
+) -> Result<Response, ApiError> {
+    let state = state.read().await;
+    if state.chat.room(&room).is_none() {
+        return Err(room_not_found(&room));
+    }
+    Ok(pagination.page(state.chat.messages(&room)).into_response())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessageRequest {
+    pub content: String,
+}
+
+fn message_content(payload: &MessageRequest) -> Result<&str, ApiError> {
+    let content = payload.content.trim();
+    if content.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Empty message",
+        ));
+    }
+    Ok(content)
+}
+
+/// Post a message as the owner of the token of the request, it is shown right
+/// away on the chat pages of the room.
+pub async fn create_message(
+    State(state): State<ApiState>,
+    Verified { name, .. }: Verified,
+    ApiPath(room): ApiPath<String>,
+    ApiJson(payload): ApiJson<MessageRequest>,
+) -> Result<Response, ApiError> {
+    let content = message_content(&payload)?;
+    let mut state = state.write().await;
+    let author = state
+        .chat
+        .check_author(&name)
+        .map_err(|err| ApiError::new(StatusCode::FORBIDDEN, err.to_string()))?;
+    let message = state
+        .chat
+        .post_message(&room, author, content)
+        .map_err(|err| match err {
+            PostError::RoomNotFound => room_not_found(&room),
+            err => ApiError::new(StatusCode::FORBIDDEN, err.to_string()),
+        })?;
+    let location = [(
+        LOCATION,
+        format!("/api/v1/rooms/{room}/messages/{}", message.id),
+    )];
+    Ok((StatusCode::CREATED, location, Envelope::new(message)).into_response())
+}
+
+pub async fn get_message(
+    State(state): State<ApiState>,
+    ApiPath((room, id)): ApiPath<(String, usize)>,
+) -> Result<Response, ApiError> {
+    let state = state.read().await;
+    let message = room_message(&state.chat, &room, id)?;
+    Ok(Envelope::new(message).into_response())
+}
+
+pub async fn update_message(
+    State(state): State<ApiState>,
+    Verified { name, .. }: Verified,
+    ApiPath((room, id)): ApiPath<(String, usize)>,
+    ApiJson(payload): ApiJson<MessageRequest>,
+) -> Result<Response, ApiError> {
+    let content = message_content(&payload)?;
+    let mut state = state.write().await;
+    authored_message(&state.chat, &room, id, &name)?;
+    let message = state
+        .chat
+        .edit_message(id, content)
+        .ok_or_else(|| message_not_found(&room, id))?;
+    Ok(Envelope::new(message).into_response())
+}
+
+pub async fn delete_message(
+    State(state): State<ApiState>,
+    Verified { name, .. }: Verified,
+    ApiPath((room, id)): ApiPath<(String, usize)>,
+) -> Result<StatusCode, ApiError> {
+    let mut state = state.write().await;
+    authored_message(&state.chat, &room, id, &name)?;
+    state.chat.delete_message(id);
+    Ok(StatusCode::NO_CONTENT)
+}
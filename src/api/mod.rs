@@ -0,0 +1,590 @@
+//! Resource oriented JSON routes, nested under `/api/v1`.
+//!
+//! Single resources are answered as `{"data": ...}`, collections as
+//! `{"data": [...], "meta": {"total", "offset", "limit"}}` and failures as
+//! `{"error": {"code", "message"}}`. Changes go through the same functions as
+//! the htmx routes, so that pages are notified and the history recorded.
+//!
+//! Requests are authenticated with API tokens, see [`auth::authenticate`].
+
+use axum::{
+    Json, Router,
+    extract::{FromRequest, FromRequestParts, Path, Query, Request, rejection::JsonRejection},
+    http::{StatusCode, request::Parts},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::json;
+
+use crate::ApiState;
+
+pub mod auth;
+mod chat;
+mod todos;
+
+pub fn router() -> Router<ApiState> {
+    Router::new()
+        .route("/todos", get(todos::list_todos).post(todos::create_todo))
+        .route(
+            "/todos/{id}",
+            get(todos::get_todo)
+                .patch(todos::update_todo)
+                .delete(todos::delete_todo),
+        )
+        .route("/lists", get(todos::list_lists).post(todos::create_list))
+        .route(
+            "/lists/{id}",
+            get(todos::get_list)
+                .patch(todos::update_list)
+                .delete(todos::delete_list),
+        )
+        .route("/rooms", get(chat::list_rooms).post(chat::create_room))
+        .route(
+            "/rooms/{room}",
+            get(chat::get_room)
+                .patch(chat::update_room)
+                .delete(chat::delete_room),
+        )
+        .route(
+            "/rooms/{room}/messages",
+            get(chat::list_messages).post(chat::create_message),
+        )
+        .route(
+            "/rooms/{room}/messages/{id}",
+            get(chat::get_message)
+                .patch(chat::update_message)
+                .delete(chat::delete_message),
+        )
+        .route_layer(middleware::from_fn(auth::require_token))
+        .fallback(async || ApiError::new(StatusCode::NOT_FOUND, "No such route"))
+}
+
+/// A resource, or a page of a collection along with its [`PageMeta`].
+#[derive(Debug, Serialize)]
+pub struct Envelope<T> {
+    pub data: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<PageMeta>,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(data: T) -> Self {
+        Envelope { data, meta: None }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Envelope<T> {
+    fn into_response(self) -> Response {
+        Json(self).into_response()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PageMeta {
+    /// Number of items of the whole collection.
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+/// `?offset=&limit=` of collection routes.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Pagination {
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Pagination {
+    const MAX_LIMIT: usize = 200;
+
+    /// The requested page of `items`.
+    pub fn page<T>(self, items: Vec<T>) -> Envelope<Vec<T>> {
+        let limit = self.limit.clamp(1, Self::MAX_LIMIT);
+        let total = items.len();
+        let data = items.into_iter().skip(self.offset).take(limit).collect();
+        Envelope {
+            data,
+            meta: Some(PageMeta {
+                total,
+                offset: self.offset,
+                limit,
+            }),
+        }
+    }
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Pagination {
+            offset: 0,
+            limit: 50,
+        }
+    }
+}
+
+/// A failure, answered as `{"error": {"code", "message"}}` where `code` is the
+/// snake cased reason of the status, e.g. `not_found`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn code(&self) -> String {
+        self.status
+            .canonical_reason()
+            .unwrap_or("error")
+            .to_lowercase()
+            .replace([' ', '-'], "_")
+    }
+}
+
+impl From<StatusCode> for ApiError {
+    fn from(status: StatusCode) -> Self {
+        ApiError::new(status, status.canonical_reason().unwrap_or("Error"))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = json!({ "error": { "code": self.code(), "message": self.message } });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+/// Like [`Json`], rejecting invalid bodies with an [`ApiError`].
+pub struct ApiJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for ApiJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(payload) =
+            Json::<T>::from_request(req, state)
+                .await
+                .map_err(|rejection: JsonRejection| {
+                    ApiError::new(rejection.status(), rejection.body_text())
+                })?;
+        Ok(ApiJson(payload))
+    }
+}
+
+/// Like [`Path`], rejecting invalid segments with an [`ApiError`].
+pub struct ApiPath<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for ApiPath<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Send,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(value) = Path::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| ApiError::new(rejection.status(), rejection.body_text()))?;
+        Ok(ApiPath(value))
+    }
+}
+
+/// Like [`Query`], rejecting invalid parameters with an [`ApiError`].
+pub struct ApiQuery<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for ApiQuery<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| ApiError::new(rejection.status(), rejection.body_text()))?;
+        Ok(ApiQuery(value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::Router;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use serde_json::{Value, json};
+    use tower::ServiceExt;
+
+    use super::Pagination;
+    use crate::{build_app, build_test_app};
+
+    #[test]
+    fn test_pagination() {
+        let page = Pagination {
+            offset: 2,
+            limit: 2,
+        }
+        .page(vec![1, 2, 3, 4, 5]);
+        assert_eq!(page.data, vec![3, 4]);
+        assert_eq!(page.meta.unwrap().total, 5);
+
+        let page = Pagination {
+            offset: 0,
+            limit: 1000,
+        }
+        .page(vec![1]);
+        assert_eq!(page.meta.unwrap().limit, Pagination::MAX_LIMIT);
+    }
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        headers: &[(&str, &str)],
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let body = body.map_or_else(Body::empty, |body| Body::from(body.to_string()));
+        let response = app
+            .clone()
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    /// An app along with the secret of an API token of `alice`.
+    struct Client {
+        app: Router,
+        secret: String,
+    }
+
+    impl Client {
+        async fn new(scopes: &[&str]) -> Client {
+            let app = build_app();
+            let token = json!({"name": "tests", "scopes": scopes});
+            let headers = [("X-Username", "alice")];
+            let (status, body) = send(&app, "POST", "/tokens", &headers, Some(token)).await;
+            assert_eq!(status, StatusCode::CREATED);
+            let secret = body["secret"].as_str().unwrap().to_owned();
+            Client { app, secret }
+        }
+
+        async fn send(&self, method: &str, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+            let authorization = format!("Bearer {}", self.secret);
+            let headers = [("Authorization", authorization.as_str())];
+            send(&self.app, method, uri, &headers, body).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_todos_resources() {
+        let client = Client::new(&["todos:read", "todos:write", "chat:read", "chat:post"]).await;
+        let (status, body) = client
+            .send("POST", "/api/v1/lists", Some(json!({"name": "Work"})))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let list_id = body["data"]["id"].as_u64().unwrap();
+
+        for content in ["one #api", "two", "three"] {
+            let todo = json!({"content": content, "list_id": list_id});
+            let (status, _) = client.send("POST", "/api/v1/todos", Some(todo)).await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+        let uri = format!("/api/v1/todos?list_id={list_id}&limit=2&offset=1");
+        let (status, body) = client.send("GET", &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["meta"], json!({"total": 3, "offset": 1, "limit": 2}));
+        assert_eq!(body["data"].as_array().unwrap().len(), 2);
+
+        let uri = format!("/api/v1/todos?list_id={list_id}&tag=api");
+        let (_, body) = client.send("GET", &uri, None).await;
+        assert_eq!(body["meta"]["total"], 1);
+        let id = body["data"][0]["id"].as_u64().unwrap();
+        let patch = json!({"status": "done"});
+        let (status, body) = client
+            .send("PATCH", &format!("/api/v1/todos/{id}"), Some(patch))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["status"], "done");
+
+        let (status, _) = client
+            .send("DELETE", &format!("/api/v1/todos/{id}"), None)
+            .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, body) = client
+            .send("GET", &format!("/api/v1/todos/{id}"), None)
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], "not_found");
+
+        let (status, body) = client
+            .send("POST", "/api/v1/todos", Some(json!({"content": ""})))
+            .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["code"], "unprocessable_entity");
+        let (status, body) = client.send("GET", "/api/v1/todos/abc", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"]["message"].is_string());
+        let (status, _) = client.send("DELETE", "/api/v1/lists/0", None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, body) = client.send("GET", "/api/v1/nothing", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_keys() {
+        let client = Client::new(&["todos:read", "todos:write"]).await;
+        let alice = format!("Bearer {}", client.secret);
+        let create = async |authorization: &str, content: &str| {
+            let headers = [("Authorization", authorization), ("Idempotency-Key", "k1")];
+            let todo = json!({"content": content});
+            send(&client.app, "POST", "/api/v1/todos", &headers, Some(todo)).await
+        };
+        let (status, created) = create(&alice, "milk").await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, replayed) = create(&alice, "milk").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(replayed["data"]["id"], created["data"]["id"]);
+        // The key cannot be reused for another todo
+        let (status, _) = create(&alice, "eggs").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // Keys of other users are their own
+        let token = json!({"name": "tests", "scopes": ["todos:write"]});
+        let headers = [("X-Username", "bob")];
+        let (_, body) = send(&client.app, "POST", "/tokens", &headers, Some(token)).await;
+        let bob = format!("Bearer {}", body["secret"].as_str().unwrap());
+        let (status, body) = create(&bob, "milk").await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_ne!(body["data"]["id"], created["data"]["id"]);
+    }
+
+    #[tokio::test]
+    async fn test_chat_resources() {
+        let client = Client::new(&["todos:read", "todos:write", "chat:read", "chat:post"]).await;
+        let room = json!({"name": "rust", "topic": "Crabs"});
+        let (status, _) = client
+            .send("POST", "/api/v1/rooms", Some(room.clone()))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = client.send("POST", "/api/v1/rooms", Some(room)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let message = json!({"content": "hello"});
+        let (status, body) = client
+            .send("POST", "/api/v1/rooms/rust/messages", Some(message))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["data"]["username"], "alice");
+        let id = body["data"]["id"].as_u64().unwrap();
+
+        let (_, body) = client
+            .send("GET", "/api/v1/rooms/rust/messages", None)
+            .await;
+        assert_eq!(body["meta"]["total"], 1);
+        assert_eq!(body["data"][0]["content"], "hello");
+
+        let uri = format!("/api/v1/rooms/rust/messages/{id}");
+        let (status, body) = client
+            .send("PATCH", &uri, Some(json!({"content": "hi"})))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["content"], "hi");
+        let (status, _) = client
+            .send("GET", "/api/v1/rooms/general/messages/1", None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = client
+            .send("DELETE", "/api/v1/rooms/general/messages/1", None)
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = client.send("DELETE", "/api/v1/rooms/rust", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = client.send("GET", &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = client.send("DELETE", "/api/v1/rooms/general", None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_api_tokens() {
+        let client = Client::new(&["todos:read"]).await;
+        let (status, _) = client.send("GET", "/api/v1/todos", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let todo = json!({"content": "nope"});
+        let (status, body) = client.send("POST", "/api/v1/todos", Some(todo)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(
+            body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("todos:write")
+        );
+        let (status, _) = client.send("GET", "/api/v1/rooms", None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = send(&client.app, "GET", "/api/v1/todos", &[], None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"]["code"], "unauthorized");
+
+        // JSON requests to the pages routes need a token as well
+        let todo = json!({"content": "nope"});
+        let (status, _) = send(&client.app, "POST", "/todo", &[], Some(todo.clone())).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = client.send("POST", "/todo", Some(todo)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = client.send("GET", "/todos", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let alice = [("X-Username", "alice")];
+        let (status, _) = send(&client.app, "GET", "/tokens", &alice, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (_, tokens) = client.send("GET", "/tokens", None).await;
+        assert!(tokens[0]["last_used_at"].is_string());
+        assert!(tokens[0].get("hash").is_none());
+        let id = tokens[0]["id"].as_u64().unwrap();
+        let uri = format!("/tokens/{id}");
+        let (status, _) = send(&client.app, "DELETE", &uri, &alice, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = client.send("DELETE", &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = client.send("GET", "/api/v1/todos", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_claimed_names() {
+        let client = Client::new(&["todos:read"]).await;
+        // Only alice can create tokens for alice, with her scopes at most
+        let token = json!({"name": "mine", "scopes": ["todos:read"]});
+        let alice = [("X-Username", "alice")];
+        let (status, _) = send(&client.app, "POST", "/tokens", &alice, Some(token.clone())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = client.send("POST", "/tokens", Some(token)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["token"]["owner"], "alice");
+        let token = json!({"name": "more", "scopes": ["todos:write"]});
+        let (status, _) = client.send("POST", "/tokens", Some(token)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Claiming a name signs the browser in
+        let request = Request::builder()
+            .method("POST")
+            .uri("/tokens")
+            .header("X-Username", "bob")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from("name=laptop&scopes=chat%3Aread"))
+            .unwrap();
+        let response = client.app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookie = response.headers()["Set-Cookie"].to_str().unwrap();
+        assert!(cookie.starts_with("session=sess_") && cookie.contains("HttpOnly"));
+        let session = cookie.split(';').next().unwrap();
+        let (status, tokens) =
+            send(&client.app, "GET", "/tokens", &[("Cookie", session)], None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(tokens[0]["owner"], "bob");
+    }
+
+    /// A request as sent by a page or a script, without JSON.
+    async fn send_raw(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> axum::response::Response {
+        let mut request = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let request = request.body(Body::from(body.to_owned())).unwrap();
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sessions() {
+        let client = Client::new(&["todos:read"]).await;
+        let form = [("Content-Type", "application/x-www-form-urlencoded")];
+        let body = format!("secret={}", client.secret);
+        let response = send_raw(&client.app, "POST", "/tokens/session", &form, &body).await;
+        let cookie = response.headers()["Set-Cookie"].to_str().unwrap();
+        assert!(cookie.contains("Max-Age="));
+        let session = [("Cookie", cookie.split(';').next().unwrap())];
+
+        // Sessions have the scopes of the token they were opened with
+        let scopes = ["todos:read", "todos:write", "chat:read", "chat:post"];
+        let token = json!({"name": "all", "scopes": scopes});
+        let (status, _) = send(&client.app, "POST", "/tokens", &session, Some(token)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&client.app, "GET", "/todos", &session, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let todo = json!({"content": "nope"});
+        let (status, _) = send(&client.app, "POST", "/todo", &session, Some(todo)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Revoking the token signs the browser out
+        let (_, tokens) = client.send("GET", "/tokens", None).await;
+        let uri = format!("/tokens/{}", tokens[0]["id"]);
+        let (status, _) = client.send("DELETE", &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&client.app, "GET", "/tokens", &session, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_page_routes_need_a_token() {
+        let app = build_app();
+        let response = send_raw(&app, "POST", "/todo", &[], "content=sneaky").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        for uri in ["/todos/events", "/todos/replay"] {
+            let response = send_raw(&app, "GET", uri, &[], "").await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        // Browsers are sent to the sign in page
+        let html = [("Accept", "text/html")];
+        let response = send_raw(&app, "GET", "/todos", &html, "").await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()["Location"], "/tokens");
+
+        // Pages refresh their todos, with their filters, once one is created
+        let (app, authorization) = build_test_app("admin");
+        let headers = [
+            ("Authorization", authorization.as_str()),
+            ("Content-Type", "application/x-www-form-urlencoded"),
+            ("HX-Request", "true"),
+        ];
+        let response = send_raw(&app, "POST", "/todo", &headers, "content=milk").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["HX-Trigger"], "todos-changed");
+    }
+}
@@ -0,0 +1,227 @@
+use axum::{
+    extract::State,
+    http::{
+        HeaderMap, StatusCode,
+        header::{ETAG, LOCATION},
+    },
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::{
+    ApiState, AppState,
+    todos::{
+        handlers::{
+            AddedTodo, CreateTodoRequest, ListRequest, UpdateListRequest, UpdateTodoRequest,
+            add_list, add_todo, edit_list, edit_todo, remove_list, todo_etag, trash_todo,
+        },
+        state::{DEFAULT_LIST_ID, Todo, TodosQuery},
+    },
+    utils::Actor,
+};
+
+use super::{ApiError, ApiJson, ApiPath, ApiQuery, Envelope, Pagination};
+
+fn todo_error(status: StatusCode, id: usize) -> ApiError {
+    let message = match status {
+        StatusCode::NOT_FOUND => format!("Todo {id} does not exist"),
+        StatusCode::PRECONDITION_FAILED => format!("Todo {id} changed since"),
+        StatusCode::UNPROCESSABLE_ENTITY => {
+            "Empty content, invalid due date or recurrence, nothing to update, or an idempotency key used for another request".to_owned()
+        }
+        status => return status.into(),
+    };
+    ApiError::new(status, message)
+}
+
+fn list_error(status: StatusCode, id: usize) -> ApiError {
+    let message = match status {
+        StatusCode::NOT_FOUND => format!("List {id} does not exist"),
+        StatusCode::CONFLICT => "The default list cannot be deleted".to_owned(),
+        StatusCode::UNPROCESSABLE_ENTITY => "Empty name, or nothing to update".to_owned(),
+        status => return status.into(),
+    };
+    ApiError::new(status, message)
+}
+
+/// A todo along with its version as ETag.
+fn todo_response(status: StatusCode, todo: &Todo) -> Response {
+    (status, [(ETAG, todo_etag(todo))], Envelope::new(todo)).into_response()
+}
+
+/// Rejected changes to a todo, outdated requests get its current ETag.
+fn rejected_todo(state: &AppState, id: usize, status: StatusCode) -> Response {
+    let error = todo_error(status, id);
+    match state.todos.todo(id) {
+        Some(todo) if status == StatusCode::PRECONDITION_FAILED => {
+            ([(ETAG, todo_etag(todo))], error).into_response()
+        }
+        _ => error.into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ListFilter {
+    /// The default list when not given.
+    pub list_id: Option<usize>,
+}
+
+pub async fn list_todos(
+    State(state): State<ApiState>,
+    ApiQuery(filter): ApiQuery<ListFilter>,
+    ApiQuery(query): ApiQuery<TodosQuery>,
+    ApiQuery(pagination): ApiQuery<Pagination>,
+) -> Result<Response, ApiError> {
+    let state = state.read().await;
+    let list_id = filter.list_id.unwrap_or(DEFAULT_LIST_ID);
+    if state.todos.list(list_id).is_none() {
+        return Err(list_error(StatusCode::NOT_FOUND, list_id));
+    }
+    let todos = state.todos.query(list_id, &query);
+    Ok(pagination.page(todos).into_response())
+}
+
+/// `201 Created`, or `200 OK` along with `Idempotent-Replayed` when retried
+/// with the same `Idempotency-Key`.
+pub async fn create_todo(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+    ApiJson(payload): ApiJson<CreateTodoRequest>,
+) -> Result<Response, ApiError> {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    let list_id = payload.list_id.unwrap_or(DEFAULT_LIST_ID);
+    let added = add_todo(state, &actor, &headers, payload).map_err(|status| match status {
+        StatusCode::NOT_FOUND => list_error(status, list_id),
+        status => todo_error(status, 0),
+    })?;
+    match added {
+        AddedTodo::Created(id) => {
+            let todo = state
+                .todos
+                .todo(id)
+                .ok_or(todo_error(StatusCode::NOT_FOUND, id))?;
+            let location = [(LOCATION, format!("/api/v1/todos/{id}"))];
+            Ok((location, todo_response(StatusCode::CREATED, todo)).into_response())
+        }
+        AddedTodo::Replayed(Some(id)) => {
+            let todo = state
+                .todos
+                .todo(id)
+                .ok_or(todo_error(StatusCode::NOT_FOUND, id))?;
+            let replayed = [("Idempotent-Replayed", "true")];
+            Ok((replayed, todo_response(StatusCode::OK, todo)).into_response())
+        }
+        AddedTodo::Replayed(None) => Err(ApiError::new(
+            StatusCode::GONE,
+            "The todo created with this idempotency key was deleted since",
+        )),
+    }
+}
+
+pub async fn get_todo(
+    State(state): State<ApiState>,
+    ApiPath(id): ApiPath<usize>,
+) -> Result<Response, ApiError> {
+    let state = state.read().await;
+    let todo = state
+        .todos
+        .todo(id)
+        .ok_or(todo_error(StatusCode::NOT_FOUND, id))?;
+    Ok(todo_response(StatusCode::OK, todo))
+}
+
+pub async fn update_todo(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+    ApiPath(id): ApiPath<usize>,
+    ApiJson(payload): ApiJson<UpdateTodoRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    if let Err(status) = edit_todo(state, &actor, &headers, id, payload) {
+        return rejected_todo(state, id, status);
+    }
+    match state.todos.todo(id) {
+        Some(todo) => todo_response(StatusCode::OK, todo),
+        None => todo_error(StatusCode::NOT_FOUND, id).into_response(),
+    }
+}
+
+/// Todos are moved to the trash, like from the pages.
+pub async fn delete_todo(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+    ApiPath(id): ApiPath<usize>,
+) -> Response {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    match trash_todo(state, &actor, &headers, id) {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(status) => rejected_todo(state, id, status),
+    }
+}
+
+pub async fn list_lists(
+    State(state): State<ApiState>,
+    ApiQuery(pagination): ApiQuery<Pagination>,
+) -> Response {
+    let state = state.read().await;
+    pagination
+        .page(state.todos.list_summaries())
+        .into_response()
+}
+
+pub async fn create_list(
+    State(state): State<ApiState>,
+    ApiJson(payload): ApiJson<ListRequest>,
+) -> Result<Response, ApiError> {
+    let mut state = state.write().await;
+    let id = add_list(&mut state, payload).map_err(|status| list_error(status, 0))?;
+    let list = state
+        .todos
+        .list_summary(id)
+        .ok_or(list_error(StatusCode::NOT_FOUND, id))?;
+    let location = [(LOCATION, format!("/api/v1/lists/{id}"))];
+    Ok((StatusCode::CREATED, location, Envelope::new(list)).into_response())
+}
+
+pub async fn get_list(
+    State(state): State<ApiState>,
+    ApiPath(id): ApiPath<usize>,
+) -> Result<Response, ApiError> {
+    let state = state.read().await;
+    let list = state
+        .todos
+        .list_summary(id)
+        .ok_or(list_error(StatusCode::NOT_FOUND, id))?;
+    Ok(Envelope::new(list).into_response())
+}
+
+pub async fn update_list(
+    State(state): State<ApiState>,
+    ApiPath(id): ApiPath<usize>,
+    ApiJson(payload): ApiJson<UpdateListRequest>,
+) -> Result<Response, ApiError> {
+    let mut state = state.write().await;
+    edit_list(&mut state, id, payload).map_err(|status| list_error(status, id))?;
+    let list = state
+        .todos
+        .list_summary(id)
+        .ok_or(list_error(StatusCode::NOT_FOUND, id))?;
+    Ok(Envelope::new(list).into_response())
+}
+
+/// Todos of a deleted list are deleted as well.
+pub async fn delete_list(
+    State(state): State<ApiState>,
+    ApiPath(id): ApiPath<usize>,
+) -> Result<StatusCode, ApiError> {
+    let mut state = state.write().await;
+    remove_list(&mut state, id).map_err(|status| list_error(status, id))?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,27 @@
+use std::fmt;
+
+use super::state::ChatMessage;
+
+/// A bot reacting to the messages posted in the rooms, registered with
+/// [`ChatState::add_bot`](super::state::ChatState::add_bot). Replies are
+/// posted in the room of the message, under the name of the bot.
+pub trait ChatBot: fmt::Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    /// The reply to a message, if any.
+    fn on_message(&self, message: &ChatMessage) -> Option<String>;
+}
+
+/// Answers `!ping`, to check that the chat is up.
+#[derive(Debug)]
+pub struct PingBot;
+
+impl ChatBot for PingBot {
+    fn name(&self) -> &str {
+        "PingBot"
+    }
+
+    fn on_message(&self, message: &ChatMessage) -> Option<String> {
+        (message.content.trim() == "!ping").then(|| "pong".to_owned())
+    }
+}
@@ -0,0 +1,499 @@
+use axum::http::HeaderMap;
+use jiff::{SignedDuration, Timestamp};
+
+use crate::{
+    AppState,
+    todos::handlers::{AddedTodo, CreateTodoRequest, add_todo},
+    tokens::state::Scope,
+    utils::{Verified, format_timestamp},
+};
+
+use super::{
+    moderation::{BanTarget, ModerationAction},
+    state::ChatState,
+};
+
+/// A message starting with `/`, run instead of being posted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Me(String),
+    Nick(String),
+    Help,
+    Who,
+    TodoAdd(String),
+    Delete(usize),
+    Mute { username: String, minutes: i64 },
+    Unmute(String),
+    Kick(String),
+    Ban(BanTarget),
+    BanIp(String),
+    Unban(BanTarget),
+}
+
+/// Usage of the commands, for `/help`.
+pub const COMMANDS: [(&str, &str); 5] = [
+    ("/me <action>", "Describe what you are doing"),
+    ("/nick <name>", "Change your name"),
+    ("/who", "List the users in the room"),
+    ("/todo add <content>", "Create a todo, #tags included"),
+    ("/help", "Show this help"),
+];
+
+/// Usage of the commands only moderators can run.
+pub const MODERATOR_COMMANDS: [(&str, &str); 7] = [
+    ("/delete <message id>", "Delete a message"),
+    (
+        "/mute <name> [minutes]",
+        "Prevent someone from posting, 10 minutes by default",
+    ),
+    ("/unmute <name>", "Let someone post again"),
+    ("/kick <name>", "Close the pages of someone"),
+    (
+        "/ban <name or IP>",
+        "Ban a name or an address and kick them",
+    ),
+    (
+        "/banip <name>",
+        "Ban the addresses someone is connected from",
+    ),
+    ("/unban <name or IP>", "Lift a ban"),
+];
+
+/// How long `/mute` lasts without a duration.
+const DEFAULT_MUTE_MINUTES: i64 = 10;
+/// Longest `/mute`, a year.
+const MAX_MUTE_MINUTES: i64 = 60 * 24 * 365;
+
+/// What a command sends back to the chat page that issued it, other pages
+/// only see the messages it posts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Private(String),
+    Renamed(String),
+}
+
+fn usage(command: &str) -> String {
+    let usage = COMMANDS
+        .iter()
+        .chain(&MODERATOR_COMMANDS)
+        .find(|(usage, _)| usage.split(' ').next() == Some(command))
+        .map_or(command, |(usage, _)| usage);
+    format!("Usage: {usage}")
+}
+
+/// The command of a message, `None` for regular messages. Unknown commands
+/// and wrong usages are errors explaining what went wrong.
+pub fn parse_command(content: &str) -> Option<Result<Command, String>> {
+    let command = content.trim().strip_prefix('/')?;
+    let (name, args) = command
+        .split_once(char::is_whitespace)
+        .map_or((command, ""), |(name, args)| (name, args.trim()));
+    let command = match (name, args) {
+        ("me", "") => Err(usage("/me")),
+        ("me", action) => Ok(Command::Me(action.to_owned())),
+        ("nick", "") => Err(usage("/nick")),
+        ("nick", name) => Ok(Command::Nick(name.to_owned())),
+        ("who", _) => Ok(Command::Who),
+        ("help", _) => Ok(Command::Help),
+        ("todo", args) => match args.split_once(char::is_whitespace) {
+            Some(("add", content)) if !content.trim().is_empty() => {
+                Ok(Command::TodoAdd(content.trim().to_owned()))
+            }
+            _ => Err(usage("/todo")),
+        },
+        ("delete", id) => id
+            .parse()
+            .map(Command::Delete)
+            .map_err(|_| usage("/delete")),
+        ("mute", "") => Err(usage("/mute")),
+        // Names can contain spaces, the duration is the last word if any
+        ("mute", args) => {
+            let (username, minutes) = match args.rsplit_once(char::is_whitespace) {
+                Some((username, minutes)) if minutes.bytes().all(|b| b.is_ascii_digit()) => {
+                    (username.trim(), minutes.parse().ok())
+                }
+                _ => (args, Some(DEFAULT_MUTE_MINUTES)),
+            };
+            match minutes {
+                Some(minutes) if (1..=MAX_MUTE_MINUTES).contains(&minutes) => Ok(Command::Mute {
+                    username: username.to_owned(),
+                    minutes,
+                }),
+                _ => Err(usage("/mute")),
+            }
+        }
+        ("unmute", "") => Err(usage("/unmute")),
+        ("unmute", username) => Ok(Command::Unmute(username.to_owned())),
+        ("kick", "") => Err(usage("/kick")),
+        ("kick", username) => Ok(Command::Kick(username.to_owned())),
+        ("ban", "") => Err(usage("/ban")),
+        ("ban", target) => Ok(Command::Ban(BanTarget::parse(target))),
+        ("banip", "") => Err(usage("/banip")),
+        ("banip", username) => Ok(Command::BanIp(username.to_owned())),
+        ("unban", "") => Err(usage("/unban")),
+        ("unban", target) => Ok(Command::Unban(BanTarget::parse(target))),
+        _ => Err(format!("Unknown command /{name}, see /help")),
+    };
+    Some(command)
+}
+
+/// Run a command of the chat page `connection`, in `room` under `username`.
+/// Moderation commands are only run for the moderators, as told by the
+/// `verified` user who opened the page, and todos are only created for the
+/// ones allowed to, see [`Verified`].
+pub fn run_command(
+    state: &mut AppState,
+    verified: Option<&Verified>,
+    connection: usize,
+    room: &str,
+    username: &str,
+    command: Command,
+) -> Option<Reply> {
+    let moderator = verified
+        .map(|verified| verified.name.as_str())
+        .filter(|name| state.is_moderator(name));
+    match command {
+        Command::Me(action) => state
+            .chat
+            .post_message(room, username, &format!("* {username} {action}"))
+            .err()
+            .map(|err| Reply::Private(err.to_string())),
+        Command::Nick(name) => {
+            // Muted users would post again under another name otherwise
+            if let Err(err) = state.chat.can_post(username, Timestamp::now()) {
+                return Some(Reply::Private(err.to_string()));
+            }
+            match state.chat.check_username(&name, Some(connection)) {
+                Ok(name) => {
+                    state.chat.rename_connection(connection, &name)?;
+                    Some(Reply::Renamed(name))
+                }
+                Err(err) => Some(Reply::Private(err.to_string())),
+            }
+        }
+        Command::Who => {
+            let users = state.chat.connected_users(room).join(", ");
+            Some(Reply::Private(format!("In #{room}: {users}")))
+        }
+        Command::Help => {
+            let help = COMMANDS
+                .iter()
+                .chain(MODERATOR_COMMANDS.iter().filter(|_| moderator.is_some()))
+                .map(|(usage, description)| format!("{usage}: {description}"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            Some(Reply::Private(help))
+        }
+        Command::TodoAdd(content) => {
+            let Some(verified) = verified.filter(|verified| verified.allows(Scope::TodosWrite))
+            else {
+                return Some(Reply::Private(format!(
+                    "Sign in with the {} scope to create todos",
+                    Scope::TodosWrite
+                )));
+            };
+            let payload = CreateTodoRequest {
+                content,
+                ..CreateTodoRequest::default()
+            };
+            let reply = match add_todo(state, &verified.name, &HeaderMap::new(), payload) {
+                Ok(AddedTodo::Created(id) | AddedTodo::Replayed(Some(id))) => {
+                    match state.todos.todo(id) {
+                        Some(todo) => format!("Created todo #{id}: {}", todo.content),
+                        None => format!("Created todo #{id}"),
+                    }
+                }
+                Ok(AddedTodo::Replayed(None)) | Err(_) => "Could not create the todo".to_owned(),
+            };
+            Some(Reply::Private(reply))
+        }
+        command @ (Command::Delete(_)
+        | Command::Mute { .. }
+        | Command::Unmute(_)
+        | Command::Kick(_)
+        | Command::Ban(_)
+        | Command::BanIp(_)
+        | Command::Unban(_)) => {
+            let Some(moderator) = moderator else {
+                return Some(Reply::Private(
+                    "Only moderators can use this command".to_owned(),
+                ));
+            };
+            let reply = moderate(&mut state.chat, moderator, command, Timestamp::now());
+            Some(Reply::Private(reply))
+        }
+    }
+}
+
+/// Run a moderation command on behalf of `moderator`, returns its outcome.
+fn moderate(chat: &mut ChatState, moderator: &str, command: Command, now: Timestamp) -> String {
+    match command {
+        Command::Delete(id) => match chat.moderate_message(moderator, id, now) {
+            Some(message) => format!("Deleted message #{id} of {}", message.username),
+            None => format!("There is no message #{id}"),
+        },
+        Command::Mute { username, minutes } => {
+            let until = now + SignedDuration::from_mins(minutes);
+            chat.moderation.mute(moderator, &username, until, now);
+            format!("{username} is muted until {}", format_timestamp(until))
+        }
+        Command::Unmute(username) => match chat.moderation.unmute(moderator, &username, now) {
+            true => format!("{username} can post again"),
+            false => format!("{username} is not muted"),
+        },
+        Command::Kick(username) => match chat.kick(&BanTarget::Username(username.clone())) {
+            0 => format!("{username} is not connected"),
+            _ => {
+                let action = ModerationAction::Kicked {
+                    username: username.clone(),
+                };
+                chat.moderation.record(moderator, action, now);
+                format!("Kicked {username}")
+            }
+        },
+        Command::Ban(target) => ban(chat, moderator, target, now),
+        Command::BanIp(username) => {
+            let ips = chat.connection_ips(&username);
+            if ips.is_empty() {
+                return format!("{username} is not connected from a known address");
+            }
+            ips.into_iter()
+                .map(|ip| ban(chat, moderator, BanTarget::Ip(ip), now))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        Command::Unban(target) => {
+            let Some(id) = chat.moderation.ban_of(&target).map(|ban| ban.id) else {
+                return format!("{target} is not banned");
+            };
+            chat.moderation.unban(moderator, id, now);
+            format!("Unbanned {target}")
+        }
+        _ => "Not a moderation command".to_owned(),
+    }
+}
+
+/// Ban `target` and kick the pages it applies to.
+pub fn ban(chat: &mut ChatState, moderator: &str, target: BanTarget, now: Timestamp) -> String {
+    if chat
+        .moderation
+        .ban(moderator, target.clone(), now)
+        .is_none()
+    {
+        return format!("{target} is already banned");
+    }
+    chat.kick(&target);
+    format!("Banned {target}")
+}
+
+#[cfg(test)]
+mod test {
+    use jiff::Timestamp;
+
+    use super::{Command, Reply, parse_command, run_command};
+    use crate::{
+        AppState,
+        chat::{
+            moderation::BanTarget,
+            state::{ChatEvent, DEFAULT_ROOM},
+        },
+        tokens::state::Scope,
+        utils::Verified,
+    };
+
+    fn app_state() -> AppState {
+        AppState::new(vec!["admin".to_owned()])
+    }
+
+    fn verified(name: &str, scopes: &[Scope]) -> Verified {
+        Verified {
+            name: name.to_owned(),
+            scopes: scopes.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("hello / world"), None);
+        assert_eq!(
+            parse_command(" /me  waves "),
+            Some(Ok(Command::Me("waves".to_owned())))
+        );
+        assert_eq!(parse_command("/who"), Some(Ok(Command::Who)));
+        assert_eq!(
+            parse_command("/todo add Buy milk #groceries"),
+            Some(Ok(Command::TodoAdd("Buy milk #groceries".to_owned())))
+        );
+        assert_eq!(
+            parse_command("/todo add "),
+            Some(Err("Usage: /todo add <content>".to_owned()))
+        );
+        assert_eq!(
+            parse_command("/nick"),
+            Some(Err("Usage: /nick <name>".to_owned()))
+        );
+        assert_eq!(
+            parse_command("/mute Jean Paul 5"),
+            Some(Ok(Command::Mute {
+                username: "Jean Paul".to_owned(),
+                minutes: 5
+            }))
+        );
+        assert_eq!(
+            parse_command("/mute bob"),
+            Some(Ok(Command::Mute {
+                username: "bob".to_owned(),
+                minutes: 10
+            }))
+        );
+        assert_eq!(
+            parse_command("/mute bob 0"),
+            Some(Err("Usage: /mute <name> [minutes]".to_owned()))
+        );
+        assert_eq!(
+            parse_command("/ban 2001:db8::1"),
+            Some(Ok(Command::Ban(BanTarget::Ip(
+                "2001:db8::1".parse().unwrap()
+            ))))
+        );
+        assert_eq!(
+            parse_command("/delete first"),
+            Some(Err("Usage: /delete <message id>".to_owned()))
+        );
+        assert_eq!(
+            parse_command("/dance"),
+            Some(Err("Unknown command /dance, see /help".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_run_command() {
+        let mut state = app_state();
+        let alice = state.chat.connect(DEFAULT_ROOM, "alice", None);
+        state.chat.connect(DEFAULT_ROOM, "bob", None);
+        let mut alice_verified = verified("alice", &[Scope::ChatPost]);
+        let mut run = |verified: &Verified, command| {
+            run_command(
+                &mut state,
+                Some(verified),
+                alice,
+                DEFAULT_ROOM,
+                "alice",
+                command,
+            )
+        };
+
+        assert_eq!(run(&alice_verified, Command::Me("waves".to_owned())), None);
+        assert_eq!(
+            run(&alice_verified, Command::Who),
+            Some(Reply::Private("In #general: alice, bob".to_owned()))
+        );
+        assert_eq!(
+            run(&alice_verified, Command::Nick("Bob".to_owned())),
+            Some(Reply::Private(
+                "Someone with this name is already connected".to_owned()
+            ))
+        );
+        assert_eq!(
+            run(&alice_verified, Command::Nick("carol".to_owned())),
+            Some(Reply::Renamed("carol".to_owned()))
+        );
+        // Todos can only be created with the scope to
+        let todo = Command::TodoAdd("Buy milk #groceries".to_owned());
+        assert_eq!(
+            run(&alice_verified, todo.clone()),
+            Some(Reply::Private(
+                "Sign in with the todos:write scope to create todos".to_owned()
+            ))
+        );
+        alice_verified.scopes.push(Scope::TodosWrite);
+        assert_eq!(
+            run(&alice_verified, todo),
+            Some(Reply::Private("Created todo #0: Buy milk".to_owned()))
+        );
+
+        let last = state.chat.recent_messages(DEFAULT_ROOM, 1);
+        assert_eq!(last[0].content, "* alice waves");
+        assert_eq!(state.chat.connected_users(DEFAULT_ROOM), ["bob", "carol"]);
+        assert_eq!(state.todos.todo(0).unwrap().tags, ["groceries"]);
+    }
+
+    #[test]
+    fn test_moderation_commands() {
+        let mut state = app_state();
+        let ip = "192.0.2.7".parse().unwrap();
+        let bob = state.chat.connect(DEFAULT_ROOM, "bob", Some(ip));
+        let mut rx = state.chat.tx_events.subscribe();
+        let id = state
+            .chat
+            .post_message(DEFAULT_ROOM, "bob", "spam")
+            .unwrap()
+            .id;
+        state
+            .chat
+            .moderation
+            .add_moderator("admin", "mod", Timestamp::now());
+        // Commands are run on behalf of the verified name of the page
+        let mut run_as = |verified: Option<&str>, username: &str, command| match run_command(
+            &mut state,
+            verified
+                .map(|name| self::verified(name, &[Scope::ChatPost]))
+                .as_ref(),
+            bob,
+            DEFAULT_ROOM,
+            username,
+            command,
+        ) {
+            Some(Reply::Private(reply)) => reply,
+            reply => panic!("Unexpected reply {reply:?}"),
+        };
+        assert_eq!(
+            run_as(None, "mod", Command::Kick("bob".to_owned())),
+            "Only moderators can use this command"
+        );
+        let mut run = |name: &str, command| run_as(Some(name), name, command);
+
+        assert_eq!(
+            run("bob", Command::Kick("bob".to_owned())),
+            "Only moderators can use this command"
+        );
+        assert_eq!(
+            run("mod", Command::Delete(id)),
+            format!("Deleted message #{id} of bob")
+        );
+        assert!(
+            run(
+                "mod",
+                Command::Mute {
+                    username: "bob".to_owned(),
+                    minutes: 5
+                }
+            )
+            .starts_with("bob is muted until")
+        );
+        assert!(run("bob", Command::Me("waves".to_owned())).starts_with("You are muted until"));
+        // Muted users cannot post under another name
+        assert!(run("bob", Command::Nick("robert".to_owned())).starts_with("You are muted until"));
+        assert_eq!(
+            run("mod", Command::Kick("carol".to_owned())),
+            "carol is not connected"
+        );
+        assert_eq!(
+            run("mod", Command::BanIp("bob".to_owned())),
+            "Banned 192.0.2.7"
+        );
+        assert_eq!(
+            run("mod", Command::Unban(BanTarget::Ip(ip))),
+            "Unbanned 192.0.2.7"
+        );
+
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(ChatEvent::MessageDeleted { .. })
+        ));
+        assert_eq!(rx.try_recv(), Ok(ChatEvent::Kicked { connection: bob }));
+        assert_eq!(state.chat.messages(DEFAULT_ROOM).len(), 1);
+        assert_eq!(state.chat.connected_users(DEFAULT_ROOM), ["bob"]);
+        assert_eq!(state.chat.moderation.log().count(), 5);
+    }
+}
@@ -0,0 +1,215 @@
+use jiff::Timestamp;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct DirectMessage {
+    pub id: usize,
+    pub from: String,
+    pub to: String,
+    pub content: String,
+    pub timestamp: Timestamp,
+    /// When `to` saw the message, unset while it is unread.
+    pub read_at: Option<Timestamp>,
+}
+
+/// A conversation of the DM list of a user.
+#[derive(Debug, Clone, Serialize)]
+pub struct Conversation {
+    /// The other participant.
+    pub with: String,
+    pub last_message: DirectMessage,
+    /// Messages from `with` the user has not seen yet.
+    pub unread: usize,
+}
+
+/// A conversation page of `username` with `peer`, open in a browser.
+#[derive(Debug)]
+struct DirectSocket {
+    id: usize,
+    username: String,
+    peer: String,
+    tx: mpsc::UnboundedSender<DirectMessage>,
+}
+
+/// One-to-one conversations. Unlike room messages, direct messages are not
+/// broadcast: they are only sent to the sockets of their two participants,
+/// which register with [`DirectMessages::connect`].
+#[derive(Debug, Default)]
+pub struct DirectMessages {
+    messages: Vec<DirectMessage>,
+    message_counter: usize,
+    sockets: Vec<DirectSocket>,
+    socket_counter: usize,
+}
+
+impl DirectMessages {
+    pub fn new() -> DirectMessages {
+        DirectMessages::default()
+    }
+
+    /// Send a message to the open pages of the conversation, `None` when it is
+    /// empty or sent to oneself. It is read right away if `to` has the
+    /// conversation open.
+    pub fn send(&mut self, from: &str, to: &str, content: &str) -> Option<&DirectMessage> {
+        let content = content.trim();
+        if content.is_empty() || from == to || to.is_empty() {
+            return None;
+        }
+        self.message_counter += 1;
+        let now = Timestamp::now();
+        let mut message = DirectMessage {
+            id: self.message_counter,
+            from: from.to_owned(),
+            to: to.to_owned(),
+            content: content.to_owned(),
+            timestamp: now,
+            read_at: None,
+        };
+        // Closed pages are forgotten on the way
+        self.sockets.retain(|socket| !socket.tx.is_closed());
+        if self
+            .sockets
+            .iter()
+            .any(|socket| socket.username == to && socket.peer == from)
+        {
+            message.read_at = Some(now);
+        }
+        for socket in &self.sockets {
+            if (socket.username == to && socket.peer == from)
+                || (socket.username == from && socket.peer == to)
+            {
+                let _ = socket.tx.send(message.clone());
+            }
+        }
+        self.messages.push(message);
+        self.messages.last()
+    }
+
+    /// Messages between `user` and `peer`, oldest first.
+    pub fn conversation(&self, user: &str, peer: &str) -> Vec<&DirectMessage> {
+        self.messages
+            .iter()
+            .filter(|m| (m.from == user && m.to == peer) || (m.from == peer && m.to == user))
+            .collect()
+    }
+
+    /// Mark the messages `peer` sent to `user` as read, returns how many were
+    /// unread.
+    pub fn mark_read(&mut self, user: &str, peer: &str, now: Timestamp) -> usize {
+        let mut count = 0;
+        for message in &mut self.messages {
+            if message.to == user && message.from == peer && message.read_at.is_none() {
+                message.read_at = Some(now);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Conversations of `user`, the most recently active first.
+    pub fn conversations(&self, user: &str) -> Vec<Conversation> {
+        let mut conversations: Vec<Conversation> = vec![];
+        for message in self.messages.iter().rev() {
+            let with = match (message.from == user, message.to == user) {
+                (true, _) => &message.to,
+                (_, true) => &message.from,
+                _ => continue,
+            };
+            let unread = usize::from(message.to == user && message.read_at.is_none());
+            match conversations.iter_mut().find(|c| &c.with == with) {
+                Some(conversation) => conversation.unread += unread,
+                None => conversations.push(Conversation {
+                    with: with.clone(),
+                    last_message: message.clone(),
+                    unread,
+                }),
+            }
+        }
+        conversations
+    }
+
+    /// Messages sent to `user` that were not read yet.
+    pub fn unread_count(&self, user: &str) -> usize {
+        self.messages
+            .iter()
+            .filter(|m| m.to == user && m.read_at.is_none())
+            .count()
+    }
+
+    /// Register a page of `username` showing the conversation with `peer`, it
+    /// receives the new messages of the conversation until
+    /// [`DirectMessages::disconnect`].
+    pub fn connect(
+        &mut self,
+        username: &str,
+        peer: &str,
+    ) -> (usize, mpsc::UnboundedReceiver<DirectMessage>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let id = self.socket_counter;
+        self.socket_counter += 1;
+        self.sockets.push(DirectSocket {
+            id,
+            username: username.to_owned(),
+            peer: peer.to_owned(),
+            tx,
+        });
+        (id, rx)
+    }
+
+    pub fn disconnect(&mut self, id: usize) {
+        self.sockets.retain(|socket| socket.id != id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use jiff::Timestamp;
+
+    use super::DirectMessages;
+
+    #[test]
+    fn test_direct_messages() {
+        let mut dms = DirectMessages::new();
+        assert!(dms.send("alice", "alice", "hi me").is_none());
+        assert!(dms.send("alice", "bob", " ").is_none());
+
+        let (_, mut alice_rx) = dms.connect("alice", "bob");
+        let (_, mut carol_rx) = dms.connect("carol", "alice");
+        let (bob, mut bob_rx) = dms.connect("bob", "dave");
+        let message = dms.send("alice", "bob", "hi bob").unwrap().clone();
+        assert_eq!(alice_rx.try_recv().unwrap(), message);
+        // Bob is not looking at the conversation with alice
+        assert!(bob_rx.try_recv().is_err());
+        assert!(carol_rx.try_recv().is_err());
+        dms.send("carol", "bob", "hi bob, carol here");
+        dms.send("alice", "bob", "are you there?");
+
+        assert_eq!(dms.unread_count("bob"), 3);
+        let conversations = dms.conversations("bob");
+        let summary: Vec<_> = conversations
+            .iter()
+            .map(|c| (c.with.as_str(), c.unread, c.last_message.content.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("alice", 2, "are you there?"),
+                ("carol", 1, "hi bob, carol here")
+            ]
+        );
+        assert_eq!(dms.conversation("alice", "bob").len(), 2);
+
+        assert_eq!(dms.mark_read("bob", "alice", Timestamp::now()), 2);
+        assert_eq!(dms.unread_count("bob"), 1);
+        assert_eq!(dms.conversations("alice")[0].unread, 0);
+
+        // Messages sent while the conversation is open are read right away
+        dms.disconnect(bob);
+        let (_, mut bob_rx) = dms.connect("bob", "alice");
+        dms.send("alice", "bob", "there you are");
+        assert!(bob_rx.try_recv().unwrap().read_at.is_some());
+        assert_eq!(dms.unread_count("bob"), 1);
+    }
+}
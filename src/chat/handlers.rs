@@ -1,44 +1,85 @@
-use std::time::Instant;
+use std::net::IpAddr;
 
 use axum::{
+    Json,
     extract::{
-        FromRequest, Request, State, WebSocketUpgrade,
+        FromRequest, Path, Query, Request, State, WebSocketUpgrade,
         ws::{Message, WebSocket},
     },
-    http::{StatusCode, header},
-    response::IntoResponse,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Redirect, Response},
 };
 use futures_util::{
     SinkExt, StreamExt,
     stream::{SplitSink, SplitStream},
 };
+use jiff::Timestamp;
 use maud::{DOCTYPE, html};
-use serde::Deserialize;
-use tokio::sync::{broadcast, mpsc, oneshot};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
 
 use crate::{
     ApiState,
-    chat::{state::ChatMessage, templates::username_form},
+    chat::{
+        commands::{Reply, ban, parse_command, run_command},
+        direct::DirectMessage,
+        moderation::{Ban, BanTarget, LogEntry, Moderation, Mute},
+        state::{ChatEvent, ChatMessage, DEFAULT_ROOM, IncomingWebhook, PostError},
+        templates::{
+            chat_notice, conversations_view, direct_message, dm_url, moderation_view,
+            new_direct_message, removed_chat_message, room_url, rooms_switcher, username_form,
+            webhooks_view,
+        },
+    },
+    todos::templates::username_input,
+    tokens::state::Scope,
+    utils::{Actor, ClientIp, ContentNegotiator, Verified, is_htmx_request},
 };
 
-use super::{
-    state::ChatHistoryRequest,
-    templates::{chat, new_chat_message},
-};
+use super::templates::{chat, new_chat_message, new_message_form};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatQuery {
+    pub room: Option<String>,
+}
 
+/// The chat page, and its socket. Banned users are turned away before the
+/// socket is upgraded, by their name or their address. Moderation commands
+/// are only available to verified moderators, see [`Verified`].
 pub async fn handle_chat_ws(
     State(state): State<ApiState>,
+    Query(query): Query<ChatQuery>,
+    actor: Actor,
+    verified: Option<Verified>,
+    ClientIp(ip): ClientIp,
     WebsocketContentNegotiator(ws): WebsocketContentNegotiator,
 ) -> impl IntoResponse {
-    if let Some(ws) = ws {
+    let room = query.room.unwrap_or_else(|| DEFAULT_ROOM.to_owned());
+    {
         let state = state.read().await;
-        let tx_broadcast = state.chat.tx_broadcast.clone();
-        let tx_history = state.chat.tx_history.clone();
+        if state.chat.room(&room).is_none() {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        let username = (!actor.is_anonymous()).then_some(actor.0.as_str());
+        if state.chat.moderation.is_banned(username, ip) {
+            tracing::info!("Rejected banned user {username:?} from {ip:?}");
+            return (StatusCode::FORBIDDEN, "You are banned from the chat").into_response();
+        }
+    }
+
+    if let Some(ws) = ws {
         return ws
-            .on_upgrade(move |socket| handle_socket(socket, tx_broadcast, tx_history))
+            .on_upgrade(move |socket| handle_socket(socket, state, room, verified, ip))
             .into_response();
     }
 
+    let (rooms, unread, moderator) = {
+        let state = state.read().await;
+        let unread = state.chat.direct.unread_count(&actor.0);
+        let moderator = verified.is_some_and(|verified| state.is_moderator(&verified.name));
+        (state.chat.rooms().to_vec(), unread, moderator)
+    };
+
     html!(
         (DOCTYPE)
         html {
@@ -49,8 +90,23 @@ pub async fn handle_chat_ws(
             }
             body {
                 h1.text-2xl.font-bold.text-center.mt-2 { "Welcome to the chat" }
-                div hx-ext="ws" ws-connect="/chat" {
-                    (username_form())
+                (rooms_switcher(&rooms, &room))
+                div.text-center.text-sm {
+                    a.link.link-hover href={ "/chat/" (room) "/webhooks" } { "Webhooks" }
+                    " · "
+                    a.link.link-hover href="/chat/dm" {
+                        "Direct messages"
+                        @if unread > 0 {
+                            span.badge.badge-sm.badge-primary.ml-1 { (unread) }
+                        }
+                    }
+                    @if moderator {
+                        " · "
+                        a.link.link-hover href="/chat/moderation" { "Moderation" }
+                    }
+                }
+                div hx-ext="ws" ws-connect={ "/chat?room=" (room) } {
+                    (username_form(None))
                 }
             }
         }
@@ -58,16 +114,601 @@ pub async fn handle_chat_ws(
     .into_response()
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct PostMessageRequest {
+    pub content: String,
+}
+
+/// Post a message to a room as the [`Verified`] user of the request, it shows
+/// up live on the chat pages like messages sent from them.
+pub async fn post_chat_message(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Verified { name, .. }: Verified,
+    Path((room,)): Path<(String,)>,
+    ContentNegotiator(payload): ContentNegotiator<PostMessageRequest>,
+) -> Response {
+    let content = payload.content.trim();
+    if content.is_empty() {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    }
+
+    let mut state = state.write().await;
+    let author = match state.chat.check_author(&name) {
+        Ok(author) => author,
+        Err(err) => return (StatusCode::FORBIDDEN, err.to_string()).into_response(),
+    };
+    let message = match state.chat.post_message(&room, author, content) {
+        Ok(message) => message,
+        Err(PostError::RoomNotFound) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => return (StatusCode::FORBIDDEN, err.to_string()).into_response(),
+    };
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => (StatusCode::CREATED, Json(message)).into_response(),
+        _ => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+pub async fn get_webhooks(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((room,)): Path<(String,)>,
+) -> Response {
+    let state = state.read().await;
+    if state.chat.room(&room).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let webhooks = state.chat.webhooks(&room);
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(webhooks).into_response(),
+        _ => html! {
+            (DOCTYPE)
+            html {
+                head {
+                    script src="/assets/htmx.min.js" {}
+                    link href="/assets/style/output.css" rel="stylesheet";
+                }
+                body.flex.flex-col.items-center {
+                    h1.text-2xl.text-center.mt-2 { "Webhooks of #" (room) }
+                    a.link.link-hover.my-4 href=(room_url(&room)) { "Back to the chat" }
+                    (webhooks_view(&room, &webhooks, None))
+                }
+            }
+        }
+        .into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookRequest {
+    pub name: String,
+}
+
+/// A created webhook, `url` cannot be retrieved afterwards.
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedWebhook<'a> {
+    pub webhook: &'a IncomingWebhook,
+    pub url: String,
+}
+
+pub fn webhook_url(secret: &str) -> String {
+    format!("/hooks/chat/{secret}")
+}
+
+pub async fn create_webhook(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+    Path((room,)): Path<(String,)>,
+    ContentNegotiator(payload): ContentNegotiator<WebhookRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    if state.chat.room(&room).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let Some((webhook, secret)) =
+        state
+            .chat
+            .add_webhook(&room, &payload.name, &actor, Timestamp::now())
+    else {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    };
+    let url = webhook_url(&secret);
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => {
+            (StatusCode::CREATED, Json(CreatedWebhook { webhook, url })).into_response()
+        }
+        _ => webhooks_view(&room, &state.chat.webhooks(&room), Some(&url)).into_response(),
+    }
+}
+
+pub async fn delete_webhook(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((room, id)): Path<(String, usize)>,
+) -> Response {
+    let mut state = state.write().await;
+    let Some(webhook) = state.chat.delete_webhook(&room, id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(webhook).into_response(),
+        _ => webhooks_view(&room, &state.chat.webhooks(&room), None).into_response(),
+    }
+}
+
+/// What scripts post to a webhook. `text` is accepted as well as `content`,
+/// like the payloads of other chat services.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookPayload {
+    #[serde(alias = "text")]
+    pub content: String,
+    pub username: Option<String>,
+}
+
+/// Messages posted to a webhook are shown in its room, under the name of the
+/// webhook, or the one of the payload followed by "via" and the name of the
+/// webhook. The secret of the URL authenticates the request.
+pub async fn receive_webhook(
+    State(state): State<ApiState>,
+    Path((secret,)): Path<(String,)>,
+    ContentNegotiator(payload): ContentNegotiator<WebhookPayload>,
+) -> Response {
+    let content = payload.content.trim();
+    if content.is_empty() {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    }
+
+    let mut state = state.write().await;
+    let Some(webhook) = state.chat.use_webhook(&secret, Timestamp::now()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let (room, name) = (webhook.room.clone(), webhook.name.clone());
+    // The name of the payload is checked like the ones of the chat and
+    // marked, so that scripts cannot pass for connected users
+    let username = match payload.username.filter(|u| !u.trim().is_empty()) {
+        Some(username) => match state.chat.check_username(&username, None) {
+            Ok(username) => {
+                if let Err(err) = state.chat.can_post(&username, Timestamp::now()) {
+                    return (StatusCode::FORBIDDEN, err.to_string()).into_response();
+                }
+                format!("{username} via {name}")
+            }
+            Err(err) => return (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response(),
+        },
+        None => name,
+    };
+    match state.chat.post_message(&room, &username, content) {
+        Ok(message) => (StatusCode::CREATED, Json(message)).into_response(),
+        Err(PostError::RoomNotFound) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => (StatusCode::FORBIDDEN, err.to_string()).into_response(),
+    }
+}
+
+/// Delete a message of a room. Verified moderators can delete any message,
+/// which is logged, others only their own.
+pub async fn delete_chat_message(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Verified { name, .. }: Verified,
+    Path((room, id)): Path<(String, usize)>,
+) -> Response {
+    let mut state = state.write().await;
+    let Some(message) = state.chat.message(id).filter(|m| m.room == room) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let message = if state.is_moderator(&name) {
+        state.chat.moderate_message(&name, id, Timestamp::now())
+    } else if message.username == name {
+        state.chat.delete_message(id)
+    } else {
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    match (headers.get("Accept").and_then(|h| h.to_str().ok()), message) {
+        (Some("application/json"), Some(message)) => Json(message).into_response(),
+        _ => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModerationSummary<'a> {
+    pub moderators: &'a [String],
+    pub bans: &'a [Ban],
+    pub mutes: Vec<&'a Mute>,
+    pub log: Vec<&'a LogEntry>,
+}
+
+/// The moderation once changed, `status` is used for JSON clients. Admins
+/// can manage the moderators from the page.
+fn moderation_response(
+    moderation: &Moderation,
+    headers: &HeaderMap,
+    admin: bool,
+    status: StatusCode,
+) -> Response {
+    let now = Timestamp::now();
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => {
+            let summary = ModerationSummary {
+                moderators: moderation.moderators(),
+                bans: moderation.bans(),
+                mutes: moderation.mutes(now),
+                log: moderation.log().collect(),
+            };
+            (status, Json(summary)).into_response()
+        }
+        _ => moderation_view(moderation, admin, now).into_response(),
+    }
+}
+
+/// The bans, mutes and moderators of the chat, along with the log of the
+/// moderation actions, for verified moderators only.
+pub async fn get_moderation(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    actor: Actor,
+    verified: Option<Verified>,
+) -> Response {
+    let state = state.read().await;
+    let moderation = &state.chat.moderation;
+    let name = verified.map(|verified| verified.name);
+    let moderator = name.as_ref().is_some_and(|name| state.is_moderator(name));
+    let admin = name.as_ref().is_some_and(|name| state.is_admin(name));
+    if is_htmx_request(&headers)
+        || headers
+            .get("Accept")
+            .is_some_and(|h| h == "application/json")
+    {
+        if !moderator {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        return moderation_response(moderation, &headers, admin, StatusCode::OK);
+    }
+
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                script src="/assets/htmx.min.js" {}
+                link href="/assets/style/output.css" rel="stylesheet";
+            }
+            body.flex.flex-col.items-center.gap-4 {
+                h1.text-2xl.text-center.mt-2 { "Chat moderation" }
+                a.link.link-hover href=(room_url(DEFAULT_ROOM)) { "Back to the chat" }
+                (username_input(&actor.0))
+                @if !moderator {
+                    p.text-gray-500 {
+                        "Only moderators can moderate the chat, once "
+                        a.link href="/tokens" { "signed in" } "."
+                    }
+                } @else {
+                    p.text-gray-500.text-sm {
+                        "In the chat, " code { "/help" } " lists the moderation commands."
+                    }
+                    (moderation_view(moderation, admin, Timestamp::now()))
+                }
+            }
+        }
+    }
+    .into_response()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModeratorRequest {
+    pub username: String,
+}
+
+/// Grant the role to a user, for verified admins only. Their name must be
+/// claimed, anyone could take it otherwise.
+pub async fn add_moderator(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Verified { name, .. }: Verified,
+    ContentNegotiator(payload): ContentNegotiator<ModeratorRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    if !state.is_admin(&name) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if !state.tokens.is_claimed(payload.username.trim()) {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    }
+    let moderation = &mut state.chat.moderation;
+    if !moderation.add_moderator(&name, &payload.username, Timestamp::now()) {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    }
+    moderation_response(moderation, &headers, true, StatusCode::CREATED)
+}
+
+pub async fn remove_moderator(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Verified { name, .. }: Verified,
+    Path((username,)): Path<(String,)>,
+) -> Response {
+    let mut state = state.write().await;
+    if !state.is_admin(&name) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let moderation = &mut state.chat.moderation;
+    if !moderation.remove_moderator(&name, &username, Timestamp::now()) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    moderation_response(moderation, &headers, true, StatusCode::OK)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BanRequest {
+    /// A username or an IP address.
+    pub target: String,
+}
+
+/// Ban a username or an address, their pages are closed right away.
+pub async fn create_ban(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Verified { name, .. }: Verified,
+    ContentNegotiator(payload): ContentNegotiator<BanRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    if !state.is_moderator(&name) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let admin = state.is_admin(&name);
+    let chat = &mut state.chat;
+    let target = BanTarget::parse(&payload.target);
+    if target == BanTarget::Username(String::new()) {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    }
+    if chat.moderation.ban_of(&target).is_some() {
+        return StatusCode::CONFLICT.into_response();
+    }
+    ban(chat, &name, target, Timestamp::now());
+    moderation_response(&chat.moderation, &headers, admin, StatusCode::CREATED)
+}
+
+pub async fn delete_ban(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Verified { name, .. }: Verified,
+    Path((id,)): Path<(usize,)>,
+) -> Response {
+    let mut state = state.write().await;
+    if !state.is_moderator(&name) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let admin = state.is_admin(&name);
+    let moderation = &mut state.chat.moderation;
+    if moderation.unban(&name, id, Timestamp::now()).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    moderation_response(moderation, &headers, admin, StatusCode::OK)
+}
+
+pub async fn delete_mute(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Verified { name, .. }: Verified,
+    Path((username,)): Path<(String,)>,
+) -> Response {
+    let mut state = state.write().await;
+    if !state.is_moderator(&name) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let admin = state.is_admin(&name);
+    let moderation = &mut state.chat.moderation;
+    if !moderation.unmute(&name, &username, Timestamp::now()) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    moderation_response(moderation, &headers, admin, StatusCode::OK)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DirectMessagesQuery {
+    /// Open the conversation with this user.
+    pub with: Option<String>,
+}
+
+/// The conversations of the signed in user, along with their unread counts.
+/// Direct messages are private, they are only shown to [`Verified`] users.
+pub async fn get_direct_messages(
+    State(state): State<ApiState>,
+    Query(query): Query<DirectMessagesQuery>,
+    headers: HeaderMap,
+    Verified { name, .. }: Verified,
+) -> Response {
+    if let Some(with) = query.with.as_deref().map(str::trim)
+        && !with.is_empty()
+    {
+        return Redirect::to(&dm_url(with)).into_response();
+    }
+    let conversations = state.read().await.chat.direct.conversations(&name);
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(conversations).into_response(),
+        _ if is_htmx_request(&headers) => conversations_view(&conversations).into_response(),
+        _ => html! {
+            (DOCTYPE)
+            html {
+                head {
+                    script src="/assets/htmx.min.js" {}
+                    link href="/assets/style/output.css" rel="stylesheet";
+                }
+                body.flex.flex-col.items-center.gap-4 {
+                    h1.text-2xl.text-center.mt-2 { "Direct messages" }
+                    a.link.link-hover href=(room_url(DEFAULT_ROOM)) { "Back to the chat" }
+                    p.text-gray-500 { "Signed in as " strong { (name) } }
+                    form.join action="/chat/dm" method="get" {
+                        input.input.input-sm.join-item type="text" name="with" placeholder="Username" required;
+                        button.btn.btn-sm.btn-primary.join-item { "New message" }
+                    }
+                    (conversations_view(&conversations))
+                }
+            }
+        }
+        .into_response(),
+    }
+}
+
+/// The conversation of the signed in user with `username`, which is marked
+/// as read. Chat pages connect to it to receive its new messages live.
+pub async fn get_conversation(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    verified: Verified,
+    Path((username,)): Path<(String,)>,
+    WebsocketContentNegotiator(ws): WebsocketContentNegotiator,
+) -> Response {
+    if let Some(ws) = ws {
+        // Reading the conversation only needs chat:read, sending chat:post
+        let can_send = verified.allows(Scope::ChatPost);
+        let name = verified.name;
+        return ws
+            .on_upgrade(move |socket| handle_direct_socket(socket, state, name, can_send, username))
+            .into_response();
+    }
+    let name = verified.name;
+
+    let messages: Vec<DirectMessage> = {
+        let mut state = state.write().await;
+        state
+            .chat
+            .direct
+            .mark_read(&name, &username, Timestamp::now());
+        let messages = state.chat.direct.conversation(&name, &username);
+        messages.into_iter().cloned().collect()
+    };
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(messages).into_response(),
+        _ => html! {
+            (DOCTYPE)
+            html {
+                head {
+                    script src="/assets/htmx.min.js" {}
+                    script src="/assets/ws.min.js" {}
+                    link href="/assets/style/output.css" rel="stylesheet";
+                }
+                body.flex.flex-col.items-center {
+                    h1.text-2xl.font-bold.text-center.mt-2 { "Conversation with " (username) }
+                    a.link.link-hover.my-2 href="/chat/dm" { "All conversations" }
+                    div.chat-container hx-ext="ws" ws-connect=(dm_url(&username)) {
+                        div #messages {
+                            @for message in &messages {
+                                (direct_message(&name, message))
+                            }
+                        }
+                        (new_message_form())
+                    }
+                }
+            }
+        }
+        .into_response(),
+    }
+}
+
+/// Send a direct message as the signed in user, unless they are muted or
+/// banned from the chat.
+pub async fn send_direct_message(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Verified { name, .. }: Verified,
+    Path((username,)): Path<(String,)>,
+    ContentNegotiator(payload): ContentNegotiator<PostMessageRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    if let Err(err) = state.chat.can_post(&name, Timestamp::now()) {
+        return (StatusCode::FORBIDDEN, err.to_string()).into_response();
+    }
+    let Some(message) = state.chat.direct.send(&name, &username, &payload.content) else {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    };
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => (StatusCode::CREATED, Json(message)).into_response(),
+        _ => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+/// Messages of the conversation page of `username` with `peer` are sent
+/// through [`DirectMessages`](super::direct::DirectMessages), which forwards
+/// them to the pages of both participants. Messages are only sent when
+/// `can_send`, and the user is neither muted nor banned.
+async fn handle_direct_socket(
+    socket: WebSocket,
+    state: ApiState,
+    username: String,
+    can_send: bool,
+    peer: String,
+) {
+    let (id, mut rx_messages) = state.write().await.chat.direct.connect(&username, &peer);
+    let (mut sink, mut stream) = socket.split();
+
+    let cloned_username = username.clone();
+    let sink_handle = tokio::spawn(async move {
+        while let Some(message) = rx_messages.recv().await {
+            let html = new_direct_message(&cloned_username, &message);
+            if sink.send(Message::text(html.into_string())).await.is_err() {
+                break;
+            }
+        }
+    });
+    let cloned_state = state.clone();
+    let cloned_username = username.clone();
+    let stream_handle = tokio::spawn(async move {
+        while let Some(Ok(Message::Text(msg))) = stream.next().await {
+            if let Ok(WSIncomingMessage::NewMessage { content }) = serde_json::from_str(&msg) {
+                if !can_send {
+                    continue;
+                }
+                let mut state = cloned_state.write().await;
+                if let Err(err) = state.chat.can_post(&cloned_username, Timestamp::now()) {
+                    tracing::info!("Dropped direct message of {cloned_username:?}: {err}");
+                    continue;
+                }
+                state.chat.direct.send(&cloned_username, &peer, &content);
+            }
+        }
+    });
+
+    tokio::select! {
+        _ = sink_handle => {}
+        _ = stream_handle => {}
+    }
+    state.write().await.chat.direct.disconnect(id);
+    tracing::info!("Direct messages connection for {username:?} finished");
+}
+
 async fn handle_socket(
     socket: WebSocket,
-    tx_broadcast: broadcast::Sender<ChatMessage>,
-    tx_history: mpsc::Sender<ChatHistoryRequest>,
+    state: ApiState,
+    room: String,
+    verified: Option<Verified>,
+    ip: Option<IpAddr>,
 ) {
     tokio::spawn(async move {
-        handle_chat_connection(socket, tx_broadcast, tx_history).await;
+        handle_chat_connection(socket, state, room, verified, ip).await;
     });
 }
 
+/// A chat page connected to a room, once a name was picked on it.
+#[derive(Debug, Clone)]
+struct ChatPage {
+    connection: usize,
+    room: String,
+    /// The name chosen on the page, which can differ from the verified one.
+    username: String,
+    /// Who opened the page, if signed in, moderation commands are run on
+    /// their behalf.
+    verified: Option<Verified>,
+    ip: Option<IpAddr>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(untagged)]
 enum WSIncomingMessage {
@@ -77,43 +718,91 @@ enum WSIncomingMessage {
 
 async fn handle_chat_connection(
     mut socket: WebSocket,
-    tx_broadcast: broadcast::Sender<ChatMessage>,
-    tx_history: mpsc::Sender<ChatHistoryRequest>,
+    state: ApiState,
+    room: String,
+    verified: Option<Verified>,
+    ip: Option<IpAddr>,
 ) {
-    // First, wait for user to supply a username
-    let username = loop {
-        if let Some(Ok(Message::Text(msg))) = socket.next().await {
-            if let Ok(WSIncomingMessage::Username { username }) = serde_json::from_str(&msg) {
-                break username;
-            }
+    // First, wait for user to supply a valid username. Rejected ones are
+    // explained in place of the form, so that another one can be picked
+    let (page, moderator, subscriptions, messages) = loop {
+        let msg = match socket.next().await {
+            Some(Ok(Message::Text(msg))) => msg,
+            Some(Ok(_)) => continue,
+            Some(Err(_)) | None => return,
+        };
+        let Ok(WSIncomingMessage::Username { username }) = serde_json::from_str(&msg) else {
+            continue;
         };
+        let mut state = state.write().await;
+        match state.chat.check_username(&username, None) {
+            Ok(username) => {
+                // Subscribing before taking the snapshot, so that no message is
+                // missed
+                let rx_broadcast = state.chat.tx_broadcast.subscribe();
+                let rx_events = state.chat.tx_events.subscribe();
+                let connection = state.chat.connect(&room, &username, ip);
+                let messages = state.chat.recent_messages(&room, 10);
+                let moderator = verified
+                    .as_ref()
+                    .is_some_and(|verified| state.is_moderator(&verified.name));
+                let page = ChatPage {
+                    connection,
+                    room: room.clone(),
+                    username,
+                    verified: verified.clone(),
+                    ip,
+                };
+                break (page, moderator, (rx_broadcast, rx_events), messages);
+            }
+            Err(err) => {
+                drop(state);
+                tracing::info!("Rejected username {username:?}: {err}");
+                let form = username_form(Some(&err)).into_string();
+                if socket.send(Message::text(form)).await.is_err() {
+                    return;
+                }
+            }
+        }
     };
-
-    if username.is_empty() {
-        tracing::warn!("Username is empty, terminating processing this socket");
-        return;
-    }
-    tracing::info!("Starting chat connection for {username:?}");
+    let ChatPage {
+        connection,
+        username,
+        ..
+    } = page.clone();
+    tracing::info!("Starting chat connection for {username:?} in {room:?}");
 
     let (sink, stream) = socket.split();
-    let cloned_username = username.clone();
-    let rx_broadcast = tx_broadcast.subscribe();
-    let sink_handle =
-        tokio::spawn(
-            async move { process_sink(sink, cloned_username, rx_broadcast, tx_history).await },
-        );
-    let cloned_username = username.clone();
-    let stream_handle =
-        tokio::spawn(async move { process_stream(stream, cloned_username, tx_broadcast).await });
+    // Command replies only meant for this page
+    let (tx_replies, rx_replies) = mpsc::unbounded_channel();
+    let cloned_page = page.clone();
+    let mut sink_handle = tokio::spawn(async move {
+        process_sink(
+            sink,
+            cloned_page,
+            moderator,
+            messages,
+            subscriptions,
+            rx_replies,
+        )
+        .await
+    });
+    let cloned_state = state.clone();
+    let mut stream_handle =
+        tokio::spawn(async move { process_stream(stream, page, cloned_state, tx_replies).await });
 
     tokio::select! {
-        _ = sink_handle => {
+        _ = &mut sink_handle => {
             tracing::info!("Sink handle finished for {username:?}");
         }
-        _ = stream_handle => {
+        _ = &mut stream_handle => {
             tracing::info!("Stream handle finished for {username:?}");
         }
     }
+    // Dropping both halves of the socket closes it, e.g. when kicked
+    sink_handle.abort();
+    stream_handle.abort();
+    state.write().await.chat.disconnect(connection);
     tracing::info!("Chat connection for {username:?} finished");
 }
 
@@ -145,57 +834,384 @@ where
 
 async fn process_sink(
     mut sink: SplitSink<WebSocket, Message>,
-    username: String,
-    mut rx_broadcast: broadcast::Receiver<ChatMessage>,
-    tx_history: mpsc::Sender<ChatHistoryRequest>,
+    page: ChatPage,
+    moderator: bool,
+    messages: Vec<ChatMessage>,
+    (mut rx_broadcast, mut rx_events): (
+        broadcast::Receiver<ChatMessage>,
+        broadcast::Receiver<ChatEvent>,
+    ),
+    mut rx_replies: mpsc::UnboundedReceiver<Reply>,
 ) {
+    let ChatPage {
+        connection,
+        room,
+        mut username,
+        ..
+    } = page;
     // Send a snapshot of the existing messages
-    let (tx_back, rx) = oneshot::channel();
-    let _ = tx_history.send(ChatHistoryRequest { tx_back }).await;
-
-    match rx.await {
-        Ok(messages) => {
-            let _ = sink
-                .send(Message::text(chat(&username, messages).into_string()))
-                .await;
-        }
-        Err(err) => {
-            tracing::error!(
-                "Unable to send intitial messages, stoping processing websocket. {err:?}"
-            );
-            return;
-        }
-    };
+    let _ = sink
+        .send(Message::text(
+            chat(&username, messages, moderator).into_string(),
+        ))
+        .await;
 
-    // Listen for new messages
-    while let Ok(msg) = rx_broadcast.recv().await {
-        let _ = sink
-            .send(Message::text(
-                new_chat_message(&username, msg).into_string(),
-            ))
-            .await;
+    // Listen for new messages of the room, moderation of the page, and
+    // replies to the commands
+    loop {
+        let html = tokio::select! {
+            msg = rx_broadcast.recv() => match msg {
+                Ok(msg) if msg.room == room => new_chat_message(&username, msg, moderator),
+                Ok(_) => continue,
+                Err(_) => break,
+            },
+            event = rx_events.recv() => match event {
+                Ok(ChatEvent::MessageDeleted { room: deleted_from, id }) if deleted_from == room => {
+                    removed_chat_message(id)
+                }
+                Ok(ChatEvent::Kicked { connection: kicked }) if kicked == connection => {
+                    let notice = chat_notice("A moderator removed you from the chat");
+                    let _ = sink.send(Message::text(notice.into_string())).await;
+                    let _ = sink.send(Message::Close(None)).await;
+                    break;
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            reply = rx_replies.recv() => match reply {
+                Some(Reply::Private(text)) => chat_notice(&text),
+                Some(Reply::Renamed(name)) => {
+                    let notice = chat_notice(&format!("You are now known as {name}"));
+                    username = name;
+                    notice
+                }
+                None => break,
+            },
+        };
+        let _ = sink.send(Message::text(html.into_string())).await;
     }
 }
 
 async fn process_stream(
     mut stream: SplitStream<WebSocket>,
-    username: String,
-    tx: broadcast::Sender<ChatMessage>,
+    page: ChatPage,
+    state: ApiState,
+    tx_replies: mpsc::UnboundedSender<Reply>,
 ) {
+    let ChatPage {
+        connection,
+        room,
+        mut username,
+        verified,
+        ip,
+    } = page;
     while let Some(Ok(Message::Text(msg))) = stream.next().await {
         match serde_json::from_str::<WSIncomingMessage>(&msg) {
             Ok(WSIncomingMessage::Username { username }) => {
                 tracing::info!("Username: {username:?}");
             }
             Ok(WSIncomingMessage::NewMessage { content }) => {
-                let chat_message = ChatMessage {
-                    content,
-                    username: username.clone(),
-                    timestamp: Instant::now(),
+                let mut state = state.write().await;
+                // Bans are enforced right away, even if the kick is not
+                // received yet
+                if state.chat.moderation.is_banned(Some(&username), ip) {
+                    break;
+                }
+                let reply = match parse_command(&content) {
+                    Some(Ok(command)) => {
+                        let verified = verified.as_ref();
+                        run_command(&mut state, verified, connection, &room, &username, command)
+                    }
+                    Some(Err(error)) => Some(Reply::Private(error)),
+                    None => match state.chat.post_message(&room, &username, &content) {
+                        Ok(_) => None,
+                        // The room was deleted
+                        Err(PostError::RoomNotFound) => break,
+                        Err(err) => Some(Reply::Private(err.to_string())),
+                    },
                 };
-                let _ = tx.send(chat_message);
+                if let Some(Reply::Renamed(name)) = &reply {
+                    username.clone_from(name);
+                }
+                if let Some(reply) = reply {
+                    let _ = tx_replies.send(reply);
+                }
             }
             Err(err) => tracing::error!("{err:?}"),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use serde_json::{Value, json};
+    use tower::ServiceExt;
+
+    use crate::{build_app, build_test_app};
+
+    /// The `Authorization` header of a new token of `username`, with all the
+    /// scopes.
+    async fn authorization(app: &Router, username: &str) -> String {
+        let scopes = ["todos:read", "todos:write", "chat:read", "chat:post"];
+        let request = Request::builder()
+            .method("POST")
+            .uri("/tokens")
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .header("X-Username", username)
+            .body(Body::from(
+                json!({"name": "tests", "scopes": scopes}).to_string(),
+            ))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        format!("Bearer {}", body["secret"].as_str().unwrap())
+    }
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        authorization: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json");
+        if let Some(authorization) = authorization {
+            request = request.header("Authorization", authorization);
+        }
+        let body = body.map_or_else(Body::empty, |body| Body::from(body.to_string()));
+        let response = app
+            .clone()
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn test_post_messages_and_webhooks() {
+        let app = build_app();
+        let alice = authorization(&app, "alice").await;
+        let post =
+            async |uri: &str, body: Value| send(&app, "POST", uri, Some(&alice), Some(body)).await;
+        let (status, message) = post("/chat/general/messages", json!({"content": "hi"})).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(
+            (&message["username"], &message["room"]),
+            (&json!("alice"), &json!("general"))
+        );
+        let (status, _) = post("/chat/missing/messages", json!({"content": "hi"})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        // JSON requests need a token, names could be made up otherwise
+        let message = json!({"content": "hi"});
+        let (status, _) = send(&app, "POST", "/chat/general/messages", None, Some(message)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let request = Request::builder()
+            .method("POST")
+            .uri("/chat/general/messages")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Username", "system")
+            .body(Body::from("content=hi"))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        // Nor can reserved names or the names of bots post, signed in or not
+        for username in ["system", "PingBot"] {
+            let authorization = authorization(&app, username).await;
+            for uri in ["/chat/general/messages", "/api/v1/rooms/general/messages"] {
+                let message = json!({"content": "hi"});
+                let (status, _) =
+                    send(&app, "POST", uri, Some(&authorization), Some(message)).await;
+                assert_eq!(status, StatusCode::FORBIDDEN);
+            }
+        }
+
+        let (status, created) = post("/chat/general/webhooks", json!({"name": "CI"})).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let url = created["url"].as_str().unwrap();
+        assert!(created["webhook"].get("hash").is_none());
+
+        let (status, message) = send(
+            &app,
+            "POST",
+            url,
+            None,
+            Some(json!({"text": "Build passed"})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(message["username"], "CI");
+        assert_eq!(message["content"], "Build passed");
+        let (status, message) = send(
+            &app,
+            "POST",
+            url,
+            None,
+            Some(json!({"text": "Deployed", "username": "deploy"})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(message["username"], "deploy via CI");
+        // Payload names follow the rules of the chat
+        for username in ["system", "<b>alice</b>"] {
+            let payload = json!({"text": "x", "username": username});
+            let (status, _) = send(&app, "POST", url, None, Some(payload)).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        let (status, _) = post("/hooks/chat/whk_wrong", json!({"text": "x"})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_direct_messages() {
+        let app = build_app();
+        let alice = authorization(&app, "alice").await;
+        let bob = authorization(&app, "bob").await;
+        let carol = authorization(&app, "carol").await;
+        let post =
+            async |uri: &str, body: Value| send(&app, "POST", uri, Some(&alice), Some(body)).await;
+        let get = async |uri: &str, authorization: &str| {
+            let (status, body) = send(&app, "GET", uri, Some(authorization), None).await;
+            assert_eq!(status, StatusCode::OK);
+            body
+        };
+        let (status, message) = post("/chat/dm/bob", json!({"content": "hi bob"})).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(
+            (&message["from"], &message["to"]),
+            (&json!("alice"), &json!("bob"))
+        );
+        let (status, _) = post("/chat/dm/alice", json!({"content": "hi me"})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let conversations = get("/chat/dm", &bob).await;
+        assert_eq!(conversations[0]["with"], "alice");
+        assert_eq!(conversations[0]["unread"], 1);
+        assert_eq!(get("/chat/dm", &carol).await, json!([]));
+
+        let messages = get("/chat/dm/alice", &bob).await;
+        assert_eq!(messages[0]["content"], "hi bob");
+        // Names cannot be made up to read the conversations of others
+        let request = Request::builder()
+            .uri("/chat/dm/alice")
+            .header("X-Username", "bob")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(get("/chat/dm", &bob).await[0]["unread"], 0);
+        // Room messages are still routed to their room
+        let (status, _) = post("/chat/general/messages", json!({"content": "hi"})).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_moderation() {
+        let (app, alice) = build_test_app("alice");
+        let carol = authorization(&app, "carol").await;
+        let mallory = authorization(&app, "mallory").await;
+        let bob = authorization(&app, "bob").await;
+        let post =
+            async |uri: &str, body: Value| send(&app, "POST", uri, Some(&alice), Some(body)).await;
+        // Moderators are told by their token, names could be made up otherwise
+        let ban = json!({"target": "Mallory"});
+        let (status, _) = send(&app, "POST", "/chat/moderation/bans", None, Some(ban)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let ban = json!({"target": "Mallory"});
+        let (status, _) = send(&app, "POST", "/chat/moderation/bans", Some(&bob), Some(ban)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        // alice is an admin and thus a moderator
+        let (status, _) = post("/chat/moderation/bans", json!({"target": "Mallory"})).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = post("/chat/moderation/bans", json!({"target": "mallory"})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            send(&app, "GET", "/chat", Some(&mallory), None).await.0,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send(&app, "GET", "/chat", Some(&bob), None).await.0,
+            StatusCode::OK
+        );
+        // Banned users cannot post by any other way either
+        let message = json!({"content": "still here"});
+        let uris = [
+            "/chat/general/messages",
+            "/api/v1/rooms/general/messages",
+            "/chat/dm/bob",
+        ];
+        for uri in uris {
+            let (status, _) = send(&app, "POST", uri, Some(&mallory), Some(message.clone())).await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+        }
+
+        let (status, _) = send(&app, "GET", "/chat/moderation", None, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, moderation) = send(&app, "GET", "/chat/moderation", Some(&alice), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            moderation["bans"][0]["target"],
+            json!({"username": "Mallory"})
+        );
+        assert_eq!(
+            (
+                &moderation["log"][0]["action"],
+                &moderation["log"][0]["moderator"]
+            ),
+            (&json!("banned"), &json!("alice"))
+        );
+
+        // Only claimed names can be granted the role
+        let (status, _) = post("/chat/moderation/moderators", json!({"username": "dave"})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = post("/chat/moderation/moderators", json!({"username": "carol"})).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = send(&app, "GET", "/chat/moderation", Some(&carol), None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, message) = post("/chat/general/messages", json!({"content": "spam"})).await;
+        let uri = format!("/chat/general/messages/{}", message["id"]);
+        assert_eq!(
+            send(&app, "DELETE", &uri, None, None).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(&app, "DELETE", &uri, Some(&bob), None).await.0,
+            StatusCode::FORBIDDEN
+        );
+        let (status, deleted) = send(&app, "DELETE", &uri, Some(&carol), None).await;
+        assert_eq!(
+            (status, &deleted["content"]),
+            (StatusCode::OK, &json!("spam"))
+        );
+        assert_eq!(
+            send(&app, "DELETE", &uri, Some(&carol), None).await.0,
+            StatusCode::NOT_FOUND
+        );
+        let (_, moderation) = send(&app, "GET", "/chat/moderation", Some(&alice), None).await;
+        assert_eq!(
+            (
+                &moderation["log"][0]["author"],
+                &moderation["log"][0]["moderator"]
+            ),
+            (&json!("alice"), &json!("carol"))
+        );
+    }
+}
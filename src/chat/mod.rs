@@ -1,3 +1,7 @@
+pub mod bots;
+pub mod commands;
+pub mod direct;
 pub mod handlers;
+pub mod moderation;
 pub mod state;
 pub mod templates;
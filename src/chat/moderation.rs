@@ -0,0 +1,321 @@
+use std::{fmt, net::IpAddr};
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+/// Who a ban applies to: a username, regardless of case, or an IP address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BanTarget {
+    Username(String),
+    Ip(IpAddr),
+}
+
+impl BanTarget {
+    /// An IP address when `target` is one, a username otherwise.
+    pub fn parse(target: &str) -> BanTarget {
+        let target = target.trim();
+        match target.parse() {
+            Ok(ip) => BanTarget::Ip(ip),
+            Err(_) => BanTarget::Username(target.to_owned()),
+        }
+    }
+
+    pub fn matches(&self, username: Option<&str>, ip: Option<IpAddr>) -> bool {
+        match self {
+            BanTarget::Username(banned) => username.is_some_and(|u| u.eq_ignore_ascii_case(banned)),
+            BanTarget::Ip(banned) => ip == Some(*banned),
+        }
+    }
+}
+
+impl fmt::Display for BanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BanTarget::Username(username) => f.write_str(username),
+            BanTarget::Ip(ip) => write!(f, "{ip}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Ban {
+    pub id: usize,
+    pub target: BanTarget,
+    pub by: String,
+    pub at: Timestamp,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Mute {
+    pub username: String,
+    pub until: Timestamp,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ModerationAction {
+    MessageDeleted {
+        message_id: usize,
+        room: String,
+        author: String,
+        content: String,
+    },
+    Muted {
+        username: String,
+        until: Timestamp,
+    },
+    Unmuted {
+        username: String,
+    },
+    Kicked {
+        username: String,
+    },
+    Banned {
+        target: BanTarget,
+    },
+    Unbanned {
+        target: BanTarget,
+    },
+    ModeratorAdded {
+        username: String,
+    },
+    ModeratorRemoved {
+        username: String,
+    },
+}
+
+impl fmt::Display for ModerationAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModerationAction::MessageDeleted {
+                message_id,
+                room,
+                author,
+                content,
+            } => write!(
+                f,
+                "deleted message #{message_id} of {author} in #{room}: {content:?}"
+            ),
+            ModerationAction::Muted { username, until } => {
+                write!(f, "muted {username} until {until}")
+            }
+            ModerationAction::Unmuted { username } => write!(f, "unmuted {username}"),
+            ModerationAction::Kicked { username } => write!(f, "kicked {username}"),
+            ModerationAction::Banned { target } => write!(f, "banned {target}"),
+            ModerationAction::Unbanned { target } => write!(f, "unbanned {target}"),
+            ModerationAction::ModeratorAdded { username } => {
+                write!(f, "made {username} a moderator")
+            }
+            ModerationAction::ModeratorRemoved { username } => {
+                write!(f, "removed {username} from the moderators")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogEntry {
+    pub id: usize,
+    pub moderator: String,
+    pub at: Timestamp,
+    #[serde(flatten)]
+    pub action: ModerationAction,
+}
+
+/// Moderators of the chat, the users they muted or banned, and the log of
+/// their actions.
+///
+/// Admins, listed in [`ADMIN_USERS_VAR`](crate::utils::ADMIN_USERS_VAR), are always moderators and are the
+/// only ones who can grant the role.
+#[derive(Debug, Default)]
+pub struct Moderation {
+    moderators: Vec<String>,
+    mutes: Vec<Mute>,
+    bans: Vec<Ban>,
+    ban_counter: usize,
+    log: Vec<LogEntry>,
+    log_counter: usize,
+}
+
+impl Moderation {
+    pub fn new() -> Moderation {
+        Moderation::default()
+    }
+
+    /// Whether `username` was granted the role, admins aside.
+    pub fn is_moderator(&self, username: &str) -> bool {
+        self.moderators.iter().any(|m| m == username)
+    }
+
+    /// Moderators granted the role, admins excluded.
+    pub fn moderators(&self) -> &[String] {
+        &self.moderators
+    }
+
+    pub fn add_moderator(&mut self, by: &str, username: &str, now: Timestamp) -> bool {
+        let username = username.trim();
+        if username.is_empty() || self.moderators.iter().any(|m| m == username) {
+            return false;
+        }
+        self.moderators.push(username.to_owned());
+        let username = username.to_owned();
+        self.record(by, ModerationAction::ModeratorAdded { username }, now);
+        true
+    }
+
+    pub fn remove_moderator(&mut self, by: &str, username: &str, now: Timestamp) -> bool {
+        let Some(idx) = self.moderators.iter().position(|m| m == username) else {
+            return false;
+        };
+        let username = self.moderators.remove(idx);
+        self.record(by, ModerationAction::ModeratorRemoved { username }, now);
+        true
+    }
+
+    /// Prevent `username` from posting until `until`.
+    pub fn mute(&mut self, by: &str, username: &str, until: Timestamp, now: Timestamp) {
+        self.mutes
+            .retain(|m| !m.username.eq_ignore_ascii_case(username) && m.until > now);
+        self.mutes.push(Mute {
+            username: username.to_owned(),
+            until,
+        });
+        let username = username.to_owned();
+        self.record(by, ModerationAction::Muted { username, until }, now);
+    }
+
+    pub fn unmute(&mut self, by: &str, username: &str, now: Timestamp) -> bool {
+        if self.muted_until(username, now).is_none() {
+            return false;
+        }
+        self.mutes
+            .retain(|m| !m.username.eq_ignore_ascii_case(username));
+        let username = username.to_owned();
+        self.record(by, ModerationAction::Unmuted { username }, now);
+        true
+    }
+
+    /// Until when `username` is muted, if they are.
+    pub fn muted_until(&self, username: &str, now: Timestamp) -> Option<Timestamp> {
+        self.mutes
+            .iter()
+            .find(|m| m.username.eq_ignore_ascii_case(username) && m.until > now)
+            .map(|m| m.until)
+    }
+
+    /// Mutes still in effect at `now`.
+    pub fn mutes(&self, now: Timestamp) -> Vec<&Mute> {
+        self.mutes.iter().filter(|m| m.until > now).collect()
+    }
+
+    /// Ban a username or an IP address from the chat, `None` if it already is.
+    pub fn ban(&mut self, by: &str, target: BanTarget, now: Timestamp) -> Option<&Ban> {
+        if self.ban_of(&target).is_some() {
+            return None;
+        }
+        self.bans.push(Ban {
+            id: self.ban_counter,
+            target: target.clone(),
+            by: by.to_owned(),
+            at: now,
+        });
+        self.ban_counter += 1;
+        self.record(by, ModerationAction::Banned { target }, now);
+        self.bans.last()
+    }
+
+    pub fn unban(&mut self, by: &str, id: usize, now: Timestamp) -> Option<Ban> {
+        let idx = self.bans.iter().position(|b| b.id == id)?;
+        let ban = self.bans.remove(idx);
+        let target = ban.target.clone();
+        self.record(by, ModerationAction::Unbanned { target }, now);
+        Some(ban)
+    }
+
+    pub fn bans(&self) -> &[Ban] {
+        &self.bans
+    }
+
+    /// The ban of `target`, usernames being compared regardless of case.
+    pub fn ban_of(&self, target: &BanTarget) -> Option<&Ban> {
+        let (username, ip) = match target {
+            BanTarget::Username(username) => (Some(username.as_str()), None),
+            BanTarget::Ip(ip) => (None, Some(*ip)),
+        };
+        self.bans.iter().find(|b| b.target.matches(username, ip))
+    }
+
+    pub fn is_banned(&self, username: Option<&str>, ip: Option<IpAddr>) -> bool {
+        self.bans.iter().any(|b| b.target.matches(username, ip))
+    }
+
+    /// Add an action to the log, for the ones that are not recorded here.
+    pub fn record(&mut self, moderator: &str, action: ModerationAction, now: Timestamp) {
+        tracing::info!("Moderation: {moderator} {action}");
+        self.log.push(LogEntry {
+            id: self.log_counter,
+            moderator: moderator.to_owned(),
+            at: now,
+            action,
+        });
+        self.log_counter += 1;
+    }
+
+    /// The actions of the moderators, most recent first.
+    pub fn log(&self) -> impl Iterator<Item = &LogEntry> {
+        self.log.iter().rev()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use jiff::{SignedDuration, Timestamp};
+
+    use super::{BanTarget, Moderation, ModerationAction};
+
+    #[test]
+    fn test_moderation() {
+        let mut moderation = Moderation::new();
+        let now = Timestamp::now();
+        assert!(moderation.add_moderator("root", "mod", now));
+        assert!(!moderation.add_moderator("root", "mod", now));
+
+        let until = now + SignedDuration::from_mins(10);
+        moderation.mute("mod", "Bob", until, now);
+        assert_eq!(moderation.muted_until("bob", now), Some(until));
+        assert_eq!(moderation.muted_until("bob", until), None);
+        assert!(moderation.unmute("mod", "bob", now));
+        assert!(!moderation.unmute("mod", "bob", now));
+
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7));
+        assert_eq!(BanTarget::parse(" 10.0.0.7 "), BanTarget::Ip(ip));
+        let id = moderation
+            .ban("mod", BanTarget::parse("Troll"), now)
+            .unwrap()
+            .id;
+        assert!(
+            moderation
+                .ban("mod", BanTarget::parse("troll"), now)
+                .is_none()
+        );
+        moderation.ban("mod", BanTarget::Ip(ip), now);
+        assert!(moderation.is_banned(Some("troll"), None));
+        assert!(moderation.is_banned(Some("alice"), Some(ip)));
+        assert!(!moderation.is_banned(None, None));
+        moderation.unban("mod", id, now);
+        assert!(!moderation.is_banned(Some("troll"), None));
+
+        let log: Vec<String> = moderation.log().map(|e| e.action.to_string()).collect();
+        assert_eq!(log[0], "unbanned Troll");
+        assert_eq!(log.len(), 6);
+        assert_eq!(
+            moderation.log().last().unwrap().action,
+            ModerationAction::ModeratorAdded {
+                username: "mod".to_owned()
+            }
+        );
+    }
+}
@@ -1,28 +1,510 @@
-use std::time::Instant;
+use std::{fmt, net::IpAddr};
 
-use tokio::sync::{broadcast, mpsc, oneshot};
+use jiff::Timestamp;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone)]
+use crate::utils::{format_timestamp, generate_secret, hash_secret};
+
+use super::{
+    bots::ChatBot,
+    direct::DirectMessages,
+    moderation::{BanTarget, Moderation, ModerationAction},
+};
+
+/// The room chat pages join when none is given, it cannot be deleted.
+pub const DEFAULT_ROOM: &str = "general";
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ChatMessage {
+    pub id: usize,
+    pub room: String,
     pub username: String,
     pub content: String,
-    pub timestamp: Instant,
+    pub timestamp: Timestamp,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ChatRoom {
+    /// Lowercase letters, digits and dashes, used in URLs.
+    pub name: String,
+    pub topic: String,
+    pub created_at: Timestamp,
+}
+
+/// A URL scripts and CI systems post messages of a room to, without a chat
+/// connection. Only the hash of its secret is kept.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IncomingWebhook {
+    pub id: usize,
+    pub room: String,
+    /// Messages are posted under this name, or the one they give followed by
+    /// "via" and this name.
+    pub name: String,
+    pub created_by: String,
+    /// The first characters of the secret, to tell webhooks apart.
+    pub prefix: String,
+    #[serde(skip)]
+    hash: String,
+    pub created_at: Timestamp,
+    pub last_used_at: Option<Timestamp>,
+}
+
+/// A chat page connected to a room.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Connection {
+    pub id: usize,
+    pub room: String,
+    pub username: String,
+    /// Where the page connected from, when known. It is not shown, only
+    /// matched against the banned addresses.
+    #[serde(skip)]
+    pub ip: Option<IpAddr>,
+    pub connected_at: Timestamp,
+}
+
+/// What happens in rooms besides messages, sent to `tx_events`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatEvent {
+    Joined {
+        room: String,
+        username: String,
+    },
+    MessageDeleted {
+        room: String,
+        id: usize,
+    },
+    /// A moderator removed the page `connection` from the chat.
+    Kicked {
+        connection: usize,
+    },
+}
+
+const WEBHOOK_SECRET_PREFIX: &str = "whk_";
+
+/// Whether `name` can be used for a room: 1 to 32 lowercase letters, digits
+/// or dashes. `dm` and `moderation` are taken by the pages of the same name,
+/// e.g. `/chat/dm`.
+pub fn is_valid_room_name(name: &str) -> bool {
+    !matches!(name, "dm" | "moderation")
+        && (1..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Why a message could not be posted, see [`ChatState::post_message`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PostError {
+    RoomNotFound,
+    Muted { until: Timestamp },
+    Banned,
+}
+
+impl fmt::Display for PostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PostError::RoomNotFound => write!(f, "This room does not exist"),
+            PostError::Muted { until } => {
+                write!(f, "You are muted until {}", format_timestamp(*until))
+            }
+            PostError::Banned => write!(f, "You are banned from the chat"),
+        }
+    }
+}
+
+/// Longest username accepted in the chat.
+pub const MAX_USERNAME_LEN: usize = 24;
+
+/// Names nobody can take, compared regardless of case. Bots names are
+/// reserved as well.
+const RESERVED_USERNAMES: [&str; 5] = ["admin", "anonymous", "moderator", "system", "you"];
+
+/// Why a chat username was rejected, see [`ChatState::check_username`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UsernameError {
+    Empty,
+    TooLong,
+    InvalidCharacters,
+    Reserved,
+    Taken,
+    Banned,
+}
+
+impl fmt::Display for UsernameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsernameError::Empty => write!(f, "Choose a name to join the chat"),
+            UsernameError::TooLong => {
+                write!(f, "Names are at most {MAX_USERNAME_LEN} characters long")
+            }
+            UsernameError::InvalidCharacters => write!(
+                f,
+                "Names can only contain letters, digits, spaces, dashes, underscores and dots"
+            ),
+            UsernameError::Reserved => write!(f, "This name is reserved"),
+            UsernameError::Taken => write!(f, "Someone with this name is already connected"),
+            UsernameError::Banned => write!(f, "This name is banned from the chat"),
+        }
+    }
 }
 
+/// Rooms and the messages posted in them. New messages are also sent to
+/// `tx_broadcast`, for the connected chat pages.
 #[derive(Debug)]
 pub struct ChatState {
     pub tx_broadcast: broadcast::Sender<ChatMessage>,
-    pub tx_history: mpsc::Sender<ChatHistoryRequest>,
+    pub tx_events: broadcast::Sender<ChatEvent>,
+    rooms: Vec<ChatRoom>,
+    messages: Vec<ChatMessage>,
+    message_counter: usize,
+    webhooks: Vec<IncomingWebhook>,
+    webhook_counter: usize,
+    connections: Vec<Connection>,
+    connection_counter: usize,
+    bots: Vec<Box<dyn ChatBot>>,
+    pub direct: DirectMessages,
+    pub moderation: Moderation,
 }
 
 impl ChatState {
     pub fn new() -> ChatState {
-        let (tx_broadcast, rx_broadcast) = broadcast::channel(128);
-        let tx_history = ChatHistory::start(rx_broadcast);
-        ChatState {
+        let (tx_broadcast, _) = broadcast::channel(128);
+        let (tx_events, _) = broadcast::channel(64);
+        let mut state = ChatState {
             tx_broadcast,
-            tx_history,
+            tx_events,
+            rooms: vec![],
+            messages: vec![],
+            message_counter: 0,
+            webhooks: vec![],
+            webhook_counter: 0,
+            connections: vec![],
+            connection_counter: 0,
+            bots: vec![],
+            direct: DirectMessages::new(),
+            moderation: Moderation::new(),
+        };
+        state.add_room(DEFAULT_ROOM, "");
+        let _ = state.post_message(DEFAULT_ROOM, "Test user", "test message");
+        state
+    }
+
+    pub fn rooms(&self) -> &[ChatRoom] {
+        &self.rooms
+    }
+
+    pub fn room(&self, name: &str) -> Option<&ChatRoom> {
+        self.rooms.iter().find(|r| r.name == name)
+    }
+
+    /// `None` when the name is invalid or already taken.
+    pub fn add_room(&mut self, name: &str, topic: &str) -> Option<&ChatRoom> {
+        if !is_valid_room_name(name) || self.room(name).is_some() {
+            return None;
+        }
+        self.rooms.push(ChatRoom {
+            name: name.to_owned(),
+            topic: topic.trim().to_owned(),
+            created_at: Timestamp::now(),
+        });
+        self.rooms.last()
+    }
+
+    pub fn set_room_topic(&mut self, name: &str, topic: &str) -> Option<&ChatRoom> {
+        let room = self.rooms.iter_mut().find(|r| r.name == name)?;
+        room.topic = topic.trim().to_owned();
+        Some(room)
+    }
+
+    /// Delete a room along with its messages and webhooks. The default room is
+    /// kept.
+    pub fn delete_room(&mut self, name: &str) -> Option<ChatRoom> {
+        if name == DEFAULT_ROOM {
+            return None;
+        }
+        let idx = self.rooms.iter().position(|r| r.name == name)?;
+        self.messages.retain(|m| m.room != name);
+        self.webhooks.retain(|w| w.room != name);
+        Some(self.rooms.remove(idx))
+    }
+
+    /// Messages of a room, oldest first.
+    pub fn messages(&self, room: &str) -> Vec<&ChatMessage> {
+        self.messages.iter().filter(|m| m.room == room).collect()
+    }
+
+    /// The last `count` messages of a room, oldest first.
+    pub fn recent_messages(&self, room: &str, count: usize) -> Vec<ChatMessage> {
+        let messages = self.messages(room);
+        let idx = messages.len().saturating_sub(count);
+        messages[idx..].iter().map(|&m| m.clone()).collect()
+    }
+
+    pub fn message(&self, id: usize) -> Option<&ChatMessage> {
+        self.messages.iter().find(|m| m.id == id)
+    }
+
+    /// Record a message and send it to the connected pages, unless the room
+    /// does not exist or `username` cannot post, see [`ChatState::can_post`].
+    /// The bots replies, if any, are posted right after it.
+    pub fn post_message(
+        &mut self,
+        room: &str,
+        username: &str,
+        content: &str,
+    ) -> Result<&ChatMessage, PostError> {
+        self.room(room).ok_or(PostError::RoomNotFound)?;
+        self.can_post(username, Timestamp::now())?;
+        let message = self.push_message(room, username, content);
+        let id = message.id;
+
+        // Bots do not answer each other, so that they cannot loop
+        if !self.bots.iter().any(|bot| bot.name() == username) {
+            let replies: Vec<(String, String)> = self
+                .bots
+                .iter()
+                .filter_map(|bot| Some((bot.name().to_owned(), bot.on_message(&message)?)))
+                .collect();
+            for (bot, reply) in replies {
+                self.push_message(room, &bot, &reply);
+            }
         }
+        Ok(self.message(id).expect("the message was just posted"))
+    }
+
+    /// Whether `username` can post at `now`, i.e. is neither banned nor
+    /// muted. Every way of posting to the rooms goes through this check.
+    pub fn can_post(&self, username: &str, now: Timestamp) -> Result<(), PostError> {
+        if self.moderation.is_banned(Some(username), None) {
+            return Err(PostError::Banned);
+        }
+        match self.moderation.muted_until(username, now) {
+            Some(until) => Err(PostError::Muted { until }),
+            None => Ok(()),
+        }
+    }
+
+    fn push_message(&mut self, room: &str, username: &str, content: &str) -> ChatMessage {
+        self.message_counter += 1;
+        let message = ChatMessage {
+            id: self.message_counter,
+            room: room.to_owned(),
+            username: username.to_owned(),
+            content: content.to_owned(),
+            timestamp: Timestamp::now(),
+        };
+        let _ = self.tx_broadcast.send(message.clone());
+        self.messages.push(message.clone());
+        message
+    }
+
+    /// Register a bot, it sees the messages of all rooms.
+    pub fn add_bot(&mut self, bot: Box<dyn ChatBot>) {
+        self.bots.push(bot);
+    }
+
+    pub fn edit_message(&mut self, id: usize, content: &str) -> Option<&ChatMessage> {
+        let message = self.messages.iter_mut().find(|m| m.id == id)?;
+        message.content = content.to_owned();
+        Some(message)
+    }
+
+    /// Delete a message, it is removed from the connected pages as well.
+    pub fn delete_message(&mut self, id: usize) -> Option<ChatMessage> {
+        let idx = self.messages.iter().position(|m| m.id == id)?;
+        let message = self.messages.remove(idx);
+        let _ = self.tx_events.send(ChatEvent::MessageDeleted {
+            room: message.room.clone(),
+            id,
+        });
+        Some(message)
+    }
+
+    /// Delete a message on behalf of `moderator`, the deletion is logged.
+    pub fn moderate_message(
+        &mut self,
+        moderator: &str,
+        id: usize,
+        now: Timestamp,
+    ) -> Option<ChatMessage> {
+        let message = self.delete_message(id)?;
+        let action = ModerationAction::MessageDeleted {
+            message_id: id,
+            room: message.room.clone(),
+            author: message.username.clone(),
+            content: message.content.clone(),
+        };
+        self.moderation.record(moderator, action, now);
+        Some(message)
+    }
+
+    pub fn webhooks(&self, room: &str) -> Vec<&IncomingWebhook> {
+        self.webhooks.iter().filter(|w| w.room == room).collect()
+    }
+
+    /// Create a webhook along with its secret, `None` when the room does not
+    /// exist or without a name.
+    pub fn add_webhook(
+        &mut self,
+        room: &str,
+        name: &str,
+        created_by: &str,
+        now: Timestamp,
+    ) -> Option<(&IncomingWebhook, String)> {
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        self.room(room)?;
+        let secret = generate_secret(WEBHOOK_SECRET_PREFIX);
+        self.webhooks.push(IncomingWebhook {
+            id: self.webhook_counter,
+            room: room.to_owned(),
+            name: name.to_owned(),
+            created_by: created_by.to_owned(),
+            prefix: secret[..WEBHOOK_SECRET_PREFIX.len() + 6].to_owned(),
+            hash: hash_secret(&secret),
+            created_at: now,
+            last_used_at: None,
+        });
+        self.webhook_counter += 1;
+        let webhook = self.webhooks.last()?;
+        Some((webhook, secret))
+    }
+
+    /// The webhook of a secret, marked as used at `now`.
+    pub fn use_webhook(&mut self, secret: &str, now: Timestamp) -> Option<&IncomingWebhook> {
+        let hash = hash_secret(secret);
+        let webhook = self.webhooks.iter_mut().find(|w| w.hash == hash)?;
+        webhook.last_used_at = Some(now);
+        Some(webhook)
+    }
+
+    pub fn delete_webhook(&mut self, room: &str, id: usize) -> Option<IncomingWebhook> {
+        let idx = self
+            .webhooks
+            .iter()
+            .position(|w| w.id == id && w.room == room)?;
+        Some(self.webhooks.remove(idx))
+    }
+
+    /// Record a chat page joining a room, until [`ChatState::disconnect`].
+    pub fn connect(&mut self, room: &str, username: &str, ip: Option<IpAddr>) -> usize {
+        let id = self.connection_counter;
+        self.connection_counter += 1;
+        self.connections.push(Connection {
+            id,
+            room: room.to_owned(),
+            username: username.to_owned(),
+            ip,
+            connected_at: Timestamp::now(),
+        });
+        let _ = self.tx_events.send(ChatEvent::Joined {
+            room: room.to_owned(),
+            username: username.to_owned(),
+        });
+        id
+    }
+
+    pub fn disconnect(&mut self, id: usize) -> Option<Connection> {
+        let idx = self.connections.iter().position(|c| c.id == id)?;
+        Some(self.connections.remove(idx))
+    }
+
+    /// Close the pages of the users `target` applies to, returns how many
+    /// were. They leave the chat once their socket is closed.
+    pub fn kick(&self, target: &BanTarget) -> usize {
+        let kicked: Vec<usize> = self
+            .connections
+            .iter()
+            .filter(|c| target.matches(Some(&c.username), c.ip))
+            .map(|c| c.id)
+            .collect();
+        for &connection in &kicked {
+            let _ = self.tx_events.send(ChatEvent::Kicked { connection });
+        }
+        kicked.len()
+    }
+
+    /// The addresses `username` is connected from.
+    pub fn connection_ips(&self, username: &str) -> Vec<IpAddr> {
+        let mut ips: Vec<IpAddr> = self
+            .connections
+            .iter()
+            .filter(|c| c.username.eq_ignore_ascii_case(username))
+            .filter_map(|c| c.ip)
+            .collect();
+        ips.sort_unstable();
+        ips.dedup();
+        ips
+    }
+
+    pub fn rename_connection(&mut self, id: usize, username: &str) -> Option<&Connection> {
+        let connection = self.connections.iter_mut().find(|c| c.id == id)?;
+        connection.username = username.to_owned();
+        Some(connection)
+    }
+
+    /// The trimmed `name`, if messages can be posted under it: valid and
+    /// neither reserved nor the name of a bot.
+    pub fn check_author<'a>(&self, name: &'a str) -> Result<&'a str, UsernameError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(UsernameError::Empty);
+        }
+        if name.chars().count() > MAX_USERNAME_LEN {
+            return Err(UsernameError::TooLong);
+        }
+        let valid_char = |c: char| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.');
+        if !name.chars().all(valid_char) {
+            return Err(UsernameError::InvalidCharacters);
+        }
+        let reserved = RESERVED_USERNAMES
+            .iter()
+            .copied()
+            .chain(self.bots.iter().map(|bot| bot.name()))
+            .any(|reserved| reserved.eq_ignore_ascii_case(name));
+        if reserved {
+            return Err(UsernameError::Reserved);
+        }
+        Ok(name)
+    }
+
+    /// The trimmed `name`, if it can be used to join the chat: valid, not
+    /// reserved nor banned, and not used by another connected user, other
+    /// than `connection` when renaming it.
+    pub fn check_username(
+        &self,
+        name: &str,
+        connection: Option<usize>,
+    ) -> Result<String, UsernameError> {
+        let name = self.check_author(name)?;
+        if self.moderation.is_banned(Some(name), None) {
+            return Err(UsernameError::Banned);
+        }
+        let taken = self
+            .connections
+            .iter()
+            .any(|c| Some(c.id) != connection && c.username.to_lowercase() == name.to_lowercase());
+        if taken {
+            return Err(UsernameError::Taken);
+        }
+        Ok(name.to_owned())
+    }
+
+    /// Names of the users connected to a room, sorted.
+    pub fn connected_users(&self, room: &str) -> Vec<&str> {
+        let mut users: Vec<&str> = self
+            .connections
+            .iter()
+            .filter(|c| c.room == room)
+            .map(|c| c.username.as_str())
+            .collect();
+        users.sort_unstable();
+        users.dedup();
+        users
     }
 }
 
@@ -32,52 +514,179 @@ impl Default for ChatState {
     }
 }
 
-pub struct ChatHistoryRequest {
-    pub tx_back: oneshot::Sender<Vec<ChatMessage>>,
-}
+#[cfg(test)]
+mod test {
+    use jiff::{SignedDuration, Timestamp};
 
-pub struct ChatHistory {
-    messages: Vec<ChatMessage>,
-    rx_broadcast: broadcast::Receiver<ChatMessage>,
-    rx_client: mpsc::Receiver<ChatHistoryRequest>,
-}
+    use super::{ChatEvent, ChatState, DEFAULT_ROOM, PostError, UsernameError};
+    use crate::chat::{bots::PingBot, moderation::BanTarget};
+
+    #[test]
+    fn test_rooms_messages() {
+        let mut state = ChatState::new();
+        let mut rx = state.tx_broadcast.subscribe();
+        assert!(state.add_room("rust", "All things Rust").is_some());
+        assert!(state.add_room("rust", "").is_none());
+        assert!(state.add_room("Not valid", "").is_none());
+        assert!(state.add_room("dm", "").is_none());
+        assert!(state.add_room("moderation", "").is_none());
 
-impl ChatHistory {
-    pub fn start(
-        rx_broadcast: broadcast::Receiver<ChatMessage>,
-    ) -> mpsc::Sender<ChatHistoryRequest> {
-        let (tx, rx_client) = mpsc::channel(32);
-        let mut chat_history = ChatHistory {
-            messages: vec![ChatMessage {
-                username: "Test user".to_owned(),
-                content: "test message".to_owned(),
-                timestamp: Instant::now(),
-            }],
-            rx_broadcast,
-            rx_client,
+        let id = state.post_message("rust", "alice", "hello").unwrap().id;
+        assert_eq!(rx.try_recv().unwrap().id, id);
+        assert_eq!(
+            state.post_message("missing", "alice", "hello").err(),
+            Some(PostError::RoomNotFound)
+        );
+        assert_eq!(state.messages("rust").len(), 1);
+        assert_eq!(state.recent_messages(DEFAULT_ROOM, 10).len(), 1);
+
+        assert!(state.delete_room(DEFAULT_ROOM).is_none());
+        assert!(state.delete_room("rust").is_some());
+        assert!(state.message(id).is_none());
+    }
+
+    #[test]
+    fn test_bots_and_connections() {
+        let mut state = ChatState::new();
+        state.add_bot(Box::new(PingBot));
+        let id = state
+            .post_message(DEFAULT_ROOM, "alice", "!ping")
+            .unwrap()
+            .id;
+        let messages = state.messages(DEFAULT_ROOM);
+        let [.., message, reply] = &messages[..] else {
+            panic!("The bot replied");
         };
+        assert_eq!(message.id, id);
+        assert_eq!(
+            (reply.username.as_str(), reply.content.as_str()),
+            ("PingBot", "pong")
+        );
+        state.post_message(DEFAULT_ROOM, "alice", "ping").unwrap();
+        assert_eq!(state.messages(DEFAULT_ROOM).len(), 4);
 
-        tokio::spawn(async move {
-            chat_history.run().await;
-        });
+        let mut rx = state.tx_events.subscribe();
+        let alice = state.connect(DEFAULT_ROOM, "alice", None);
+        assert!(rx.try_recv().is_ok());
+        state.connect(DEFAULT_ROOM, "bob", None);
+        state.connect(DEFAULT_ROOM, "alice", None);
+        assert_eq!(state.connected_users(DEFAULT_ROOM), ["alice", "bob"]);
+        state.rename_connection(alice, "carol");
+        assert_eq!(
+            state.connected_users(DEFAULT_ROOM),
+            ["alice", "bob", "carol"]
+        );
+        state.disconnect(alice);
+        assert_eq!(state.connected_users(DEFAULT_ROOM), ["alice", "bob"]);
+        assert!(state.connected_users("missing").is_empty());
+    }
+
+    #[test]
+    fn test_moderate_connections() {
+        let mut state = ChatState::new();
+        let ip = "192.0.2.1".parse().unwrap();
+        let mut rx = state.tx_events.subscribe();
+        state.connect(DEFAULT_ROOM, "alice", None);
+        let bob = state.connect(DEFAULT_ROOM, "bob", Some(ip));
+        while rx.try_recv().is_ok() {}
 
-        tx
+        assert_eq!(state.connection_ips("Bob"), [ip]);
+        assert_eq!(state.kick(&BanTarget::Ip(ip)), 1);
+        assert_eq!(rx.try_recv(), Ok(ChatEvent::Kicked { connection: bob }));
+        assert_eq!(state.kick(&BanTarget::parse("carol")), 0);
+
+        let id = state.post_message(DEFAULT_ROOM, "bob", "spam").unwrap().id;
+        assert!(
+            state
+                .moderate_message("alice", id, Timestamp::now())
+                .is_some()
+        );
+        assert!(
+            state
+                .moderate_message("alice", id, Timestamp::now())
+                .is_none()
+        );
+        assert_eq!(
+            rx.try_recv(),
+            Ok(ChatEvent::MessageDeleted {
+                room: DEFAULT_ROOM.to_owned(),
+                id
+            })
+        );
+        let entry = state.moderation.log().next().unwrap();
+        assert_eq!(entry.moderator, "alice");
+        assert_eq!(
+            entry.action.to_string(),
+            format!("deleted message #{id} of bob in #general: \"spam\"")
+        );
     }
 
-    pub async fn run(&mut self) {
-        tracing::info!("Starting ChatHistory actor");
+    #[test]
+    fn test_can_post() {
+        let mut state = ChatState::new();
+        let now = Timestamp::now();
+        let until = now + SignedDuration::from_mins(5);
+        state.moderation.mute("mod", "Bob", until, now);
+        let post = |state: &mut ChatState, username| {
+            state.post_message(DEFAULT_ROOM, username, "hi").err()
+        };
+        assert_eq!(post(&mut state, "bob"), Some(PostError::Muted { until }));
+        assert_eq!(post(&mut state, "alice"), None);
+        state.moderation.ban("mod", BanTarget::parse("alice"), now);
+        assert_eq!(post(&mut state, "alice"), Some(PostError::Banned));
+        assert_eq!(state.messages(DEFAULT_ROOM).len(), 2);
+    }
 
-        loop {
-            tokio::select! {
-                Ok(msg) = self.rx_broadcast.recv() => {
-                    self.messages.push(msg);
-                }
-                Some(ChatHistoryRequest { tx_back }) = self.rx_client.recv() => {
-                    let idx = self.messages.len().saturating_sub(10);
-                    let _ = tx_back.send(
-                        self.messages[idx..].to_vec());
-                }
-            }
-        }
+    #[test]
+    fn test_check_username() {
+        let mut state = ChatState::new();
+        state.add_bot(Box::new(PingBot));
+        let alice = state.connect(DEFAULT_ROOM, "Alice", None);
+        let check = |name: &str| state.check_username(name, None);
+        assert_eq!(check("  Bob  "), Ok("Bob".to_owned()));
+        assert_eq!(check("Zoé_d.Arc"), Ok("Zoé_d.Arc".to_owned()));
+        assert_eq!(check("   "), Err(UsernameError::Empty));
+        assert_eq!(check(&"a".repeat(25)), Err(UsernameError::TooLong));
+        assert_eq!(check("<b>hi</b>"), Err(UsernameError::InvalidCharacters));
+        assert_eq!(check("System"), Err(UsernameError::Reserved));
+        assert_eq!(check("pingbot"), Err(UsernameError::Reserved));
+        assert_eq!(check("alice"), Err(UsernameError::Taken));
+        // Renaming to the same name in another case
+        assert_eq!(
+            state.check_username("ALICE", Some(alice)),
+            Ok("ALICE".to_owned())
+        );
+        state.disconnect(alice);
+        assert_eq!(state.check_username("alice", None), Ok("alice".to_owned()));
+        state
+            .moderation
+            .ban("mod", BanTarget::parse("Alice"), Timestamp::now());
+        assert_eq!(
+            state.check_username("alice", None),
+            Err(UsernameError::Banned)
+        );
+    }
+
+    #[test]
+    fn test_webhooks() {
+        let mut state = ChatState::new();
+        let now = Timestamp::now();
+        state.add_room("ci", "");
+        assert!(state.add_webhook("missing", "CI", "alice", now).is_none());
+        assert!(state.add_webhook("ci", " ", "alice", now).is_none());
+        let (webhook, secret) = state.add_webhook("ci", "CI", "alice", now).unwrap();
+        let id = webhook.id;
+        assert!(secret.starts_with(&webhook.prefix));
+
+        let webhook = state.use_webhook(&secret, now).unwrap();
+        assert_eq!(
+            (webhook.room.as_str(), webhook.last_used_at),
+            ("ci", Some(now))
+        );
+        assert!(state.use_webhook("whk_wrong", now).is_none());
+
+        assert!(state.delete_webhook(DEFAULT_ROOM, id).is_none());
+        state.delete_room("ci");
+        assert!(state.use_webhook(&secret, now).is_none());
     }
 }
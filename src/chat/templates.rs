@@ -1,28 +1,79 @@
 use maud::{Markup, html};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
 
-use super::state::ChatMessage;
+use jiff::Timestamp;
 
-pub fn username_form() -> Markup {
+use crate::utils::format_timestamp;
+
+use super::{
+    direct::{Conversation, DirectMessage},
+    moderation::Moderation,
+    state::{ChatMessage, ChatRoom, IncomingWebhook, MAX_USERNAME_LEN, UsernameError},
+};
+
+pub fn room_url(room: &str) -> String {
+    format!("/chat?room={room}")
+}
+
+/// The page of the conversation with `username`.
+pub fn dm_url(username: &str) -> String {
+    format!(
+        "/chat/dm/{}",
+        utf8_percent_encode(username, NON_ALPHANUMERIC)
+    )
+}
+
+pub fn rooms_switcher(rooms: &[ChatRoom], current: &str) -> Markup {
+    let topic = rooms
+        .iter()
+        .find(|r| r.name == current)
+        .map(|r| r.topic.as_str())
+        .filter(|topic| !topic.is_empty());
+    html! {
+        div.flex.flex-col.items-center.gap-2.mt-4 #rooms-switcher {
+            div.tabs.tabs-box role="tablist" {
+                @for room in rooms {
+                    a.tab.tab-active[room.name == current] role="tab" href=(room_url(&room.name)) {
+                        "#" (room.name)
+                    }
+                }
+            }
+            @if let Some(topic) = topic {
+                p.text-sm.text-gray-500 { (topic) }
+            }
+        }
+    }
+}
+
+/// The form to join the chat, along with why the previous name was rejected,
+/// if any. Rejections are swapped in place of the form.
+pub fn username_form(error: Option<&UsernameError>) -> Markup {
     html! {
     form.mx-auto #username-form
         ws-send
+        hx-swap-oob=[error.map(|_| "true")]
         fiedlset.fieldset.w-xs.bg-base-200.border.border-base-300.p-4.mt-8.rounded-box {
             legend.fieldset-legend { "Choose a username" }
             div.join {
-                input.input.join-item #todo type="text" name="username" {}
+                input.input.join-item.input-error[error.is_some()] #todo type="text" name="username"
+                    maxlength=(MAX_USERNAME_LEN) required {}
                 button.btn.btn-primary.join-item {"Rejoindre"}
             }
+            @if let Some(error) = error {
+                p.text-error.text-sm #username-error { (error) }
+            }
         }
     }
 }
 
-pub fn chat(user: &str, messages: Vec<ChatMessage>) -> Markup {
+/// The chat once joined. Moderators get a button to delete each message.
+pub fn chat(user: &str, messages: Vec<ChatMessage>, moderator: bool) -> Markup {
     html! {
         div #username-form hx-swap-oob="true" {
             div.chat-container {
                 div #messages {
                     @for message in messages {
-                        (chat_message(user, message))
+                        (chat_message(user, message, moderator))
                     }
                 }
                 (new_message_form())
@@ -31,14 +82,26 @@ pub fn chat(user: &str, messages: Vec<ChatMessage>) -> Markup {
     }
 }
 
-pub fn chat_message(user: &str, message: ChatMessage) -> Markup {
+pub fn chat_message(user: &str, message: ChatMessage, moderator: bool) -> Markup {
     let class = if message.username == user {
         "chat chat-end"
     } else {
         "chat chat-start"
     };
     html! {
-        div class=(class) {
+        div class=(class) id=(format!("message-{}", message.id)) {
+            div.chat-header {
+                (message.username)
+                @if moderator {
+                    button.btn.btn-xs.btn-ghost.text-error.ml-1
+                        hx-delete={ "/chat/" (message.room) "/messages/" (message.id) }
+                        hx-swap="none"
+                        hx-confirm="Delete this message?"
+                        title={ "Delete message #" (message.id) } {
+                        "✕"
+                    }
+                }
+            }
             div.chat-bubble {
                 (message.content)
             }
@@ -46,10 +109,78 @@ pub fn chat_message(user: &str, message: ChatMessage) -> Markup {
     }
 }
 
-pub fn new_chat_message(user: &str, message: ChatMessage) -> Markup {
+pub fn new_chat_message(user: &str, message: ChatMessage, moderator: bool) -> Markup {
+    html! {
+        div hx-swap-oob="beforeend:#messages" {
+            (chat_message(user, message, moderator))
+        }
+    }
+}
+
+/// Removes a deleted message from the page.
+pub fn removed_chat_message(id: usize) -> Markup {
+    html! {
+        div id=(format!("message-{id}")) hx-swap-oob="delete" {}
+    }
+}
+
+pub fn direct_message(user: &str, message: &DirectMessage) -> Markup {
+    let class = if message.from == user {
+        "chat chat-end"
+    } else {
+        "chat chat-start"
+    };
+    html! {
+        div class=(class) {
+            div.chat-header {
+                (message.from)
+                time.text-xs.opacity-50.ml-1 { (format_timestamp(message.timestamp)) }
+            }
+            div.chat-bubble { (message.content) }
+        }
+    }
+}
+
+pub fn new_direct_message(user: &str, message: &DirectMessage) -> Markup {
+    html! {
+        div hx-swap-oob="beforeend:#messages" {
+            (direct_message(user, message))
+        }
+    }
+}
+
+/// The DM list of a user, refreshed to keep the unread counts up to date.
+pub fn conversations_view(conversations: &[Conversation]) -> Markup {
+    html! {
+        ul.list.bg-base-100.rounded-box.shadow-md.w-md #conversations
+            hx-get="/chat/dm" hx-trigger="every 10s" hx-swap="outerHTML" {
+            @if conversations.is_empty() {
+                li.list-row.text-gray-500 { "No conversations yet." }
+            }
+            @for conversation in conversations {
+                li.list-row {
+                    a.link.link-hover.font-bold href=(dm_url(&conversation.with)) {
+                        (conversation.with)
+                    }
+                    span.truncate.text-gray-500 {
+                        (conversation.last_message.content)
+                    }
+                    @if conversation.unread > 0 {
+                        span.badge.badge-primary.unread { (conversation.unread) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A line only shown to one chat page, e.g. the reply to a command.
+pub fn chat_notice(text: &str) -> Markup {
     html! {
         div hx-swap-oob="beforeend:#messages" {
-            (chat_message(user, message))
+            div.chat-notice.text-center.text-sm.text-gray-500.italic.whitespace-pre-line.my-1 {
+                (text)
+            }
         }
     }
 }
@@ -68,3 +199,181 @@ pub fn new_message_form() -> Markup {
         }
     }
 }
+
+/// The webhooks of a room, along with the URL of the one just created, which
+/// is only shown once.
+pub fn webhooks_view(
+    room: &str,
+    webhooks: &[&IncomingWebhook],
+    created_url: Option<&str>,
+) -> Markup {
+    let webhooks_url = format!("/chat/{room}/webhooks");
+    html! {
+        div.flex.flex-col.items-center.gap-4 #webhooks {
+            @if let Some(url) = created_url {
+                div.alert.alert-success.flex.flex-col role="alert" {
+                    span { "Copy the URL now, it will not be shown again:" }
+                    code.select-all #webhook-url { (url) }
+                }
+            }
+            p.text-gray-500.text-sm {
+                "POST " code { "{\"content\": \"...\"}" } " to a webhook URL to post a message in #" (room) "."
+            }
+            form.join #new-webhook hx-post=(webhooks_url) hx-target="#webhooks" hx-swap="outerHTML" {
+                input.input.input-sm.join-item type="text" name="name" placeholder="Webhook name" required;
+                button.btn.btn-sm.btn-primary.join-item { "Create webhook" }
+            }
+            @if !webhooks.is_empty() {
+                table.table.bg-base-100.rounded-box.shadow-md {
+                    thead {
+                        tr {
+                            th { "Name" }
+                            th { "Secret" }
+                            th { "Created by" }
+                            th { "Last used" }
+                            th {}
+                        }
+                    }
+                    tbody {
+                        @for webhook in webhooks {
+                            tr data-webhook-id=(webhook.id) {
+                                td { (webhook.name) }
+                                td.font-mono { (webhook.prefix) "…" }
+                                td { (webhook.created_by) }
+                                td {
+                                    @match webhook.last_used_at {
+                                        Some(used) => (format_timestamp(used)),
+                                        None => span.text-gray-500 { "Never" },
+                                    }
+                                }
+                                td {
+                                    button.btn.btn-sm.btn-error
+                                        hx-delete={ (webhooks_url) "/" (webhook.id) }
+                                        hx-target="#webhooks"
+                                        hx-swap="outerHTML"
+                                        hx-confirm={ "Delete " (webhook.name) "?" } {
+                                        "Delete"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// What moderators see of the chat moderation: the bans and mutes in effect,
+/// along with the log of their actions. Only admins manage the moderators.
+pub fn moderation_view(moderation: &Moderation, admin: bool, now: Timestamp) -> Markup {
+    let mutes = moderation.mutes(now);
+    html! {
+        div.flex.flex-col.items-center.gap-6.w-full #moderation {
+            section.flex.flex-col.items-center.gap-2 {
+                h2.text-xl { "Moderators" }
+                p.text-gray-500.text-sm { "Admins are moderators as well." }
+                ul.list.bg-base-100.rounded-box.shadow-md.w-md {
+                    @if moderation.moderators().is_empty() {
+                        li.list-row.text-gray-500 { "No moderators yet." }
+                    }
+                    @for moderator in moderation.moderators() {
+                        li.list-row {
+                            span.grow { (moderator) }
+                            @if admin {
+                                button.btn.btn-xs.btn-error
+                                    hx-delete={ "/chat/moderation/moderators/" (utf8_percent_encode(moderator, NON_ALPHANUMERIC)) }
+                                    hx-target="#moderation"
+                                    hx-swap="outerHTML" {
+                                    "Remove"
+                                }
+                            }
+                        }
+                    }
+                }
+                @if admin {
+                    form.join hx-post="/chat/moderation/moderators" hx-target="#moderation" hx-swap="outerHTML" {
+                        input.input.input-sm.join-item type="text" name="username" placeholder="Username" required;
+                        button.btn.btn-sm.btn-primary.join-item { "Add moderator" }
+                    }
+                }
+            }
+            section.flex.flex-col.items-center.gap-2 {
+                h2.text-xl { "Bans" }
+                form.join hx-post="/chat/moderation/bans" hx-target="#moderation" hx-swap="outerHTML" {
+                    input.input.input-sm.join-item type="text" name="target" placeholder="Username or IP address" required;
+                    button.btn.btn-sm.btn-error.join-item { "Ban" }
+                }
+                @if !moderation.bans().is_empty() {
+                    table.table.bg-base-100.rounded-box.shadow-md {
+                        thead {
+                            tr {
+                                th { "Banned" }
+                                th { "By" }
+                                th { "At" }
+                                th {}
+                            }
+                        }
+                        tbody {
+                            @for ban in moderation.bans() {
+                                tr data-ban-id=(ban.id) {
+                                    td { (ban.target) }
+                                    td { (ban.by) }
+                                    td { (format_timestamp(ban.at)) }
+                                    td {
+                                        button.btn.btn-sm
+                                            hx-delete={ "/chat/moderation/bans/" (ban.id) }
+                                            hx-target="#moderation"
+                                            hx-swap="outerHTML" {
+                                            "Unban"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            @if !mutes.is_empty() {
+                section.flex.flex-col.items-center.gap-2 {
+                    h2.text-xl { "Muted" }
+                    ul.list.bg-base-100.rounded-box.shadow-md.w-md {
+                        @for mute in &mutes {
+                            li.list-row {
+                                span.grow { (mute.username) }
+                                span.text-gray-500 { "until " (format_timestamp(mute.until)) }
+                                button.btn.btn-xs
+                                    hx-delete={ "/chat/moderation/mutes/" (utf8_percent_encode(&mute.username, NON_ALPHANUMERIC)) }
+                                    hx-target="#moderation"
+                                    hx-swap="outerHTML" {
+                                    "Unmute"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            section.flex.flex-col.items-center.gap-2 {
+                h2.text-xl { "Log" }
+                table.table.bg-base-100.rounded-box.shadow-md #moderation-log {
+                    thead {
+                        tr {
+                            th { "At" }
+                            th { "Moderator" }
+                            th { "Action" }
+                        }
+                    }
+                    tbody {
+                        @for entry in moderation.log() {
+                            tr {
+                                td { (format_timestamp(entry.at)) }
+                                td { (entry.moderator) }
+                                td { (entry.action) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
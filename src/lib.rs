@@ -1,43 +1,239 @@
 use std::sync::Arc;
 
 use axum::{
-    Router,
-    routing::{delete, get, post},
+    Router, middleware,
+    routing::{delete, get, post, put},
 };
-use chat::{handlers::handle_chat_ws, state::ChatState};
+use chat::{
+    bots::PingBot,
+    handlers::{
+        add_moderator, create_ban, create_webhook, delete_ban, delete_chat_message, delete_mute,
+        delete_webhook, get_conversation, get_direct_messages, get_moderation, get_webhooks,
+        handle_chat_ws, post_chat_message, receive_webhook, remove_moderator, send_direct_message,
+    },
+    state::ChatState,
+};
+use jiff::Timestamp;
 use maud::{DOCTYPE, Markup, html};
+use openapi::{get_api_docs, get_openapi};
 
 use todos::{
-    handlers::{create_todo, delete_todo, get_todos, toggle_todo},
-    state::TodosState,
+    events::{DATA_DIR_VAR, EventLog, EventStore, run_event_persistence},
+    handlers::{
+        bulk_todos, create_list, create_subtask, create_todo, delete_list, delete_subtask,
+        delete_tag, delete_todo, empty_trash, export_todos, get_activity, get_board, get_calendar,
+        get_events, get_list, get_list_board, get_list_todos, get_lists, get_tag, get_tags,
+        get_todo, get_todo_history, get_todos, get_trash, import_calendar, import_todos, merge_tag,
+        move_todo_to_list, rename_tag, reorder_todos, replay_todos, restore_todos, set_todo_done,
+        todos_ws, toggle_subtask, toggle_todo, update_list, update_todo,
+    },
+    reminders::run_reminders,
+    state::{TodosEvent, TodosState},
+    trash::run_trash_purge,
+};
+use tokens::{
+    handlers::{create_token, get_tokens, revoke_token, sign_in},
+    state::{Scope, TokensState},
 };
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, broadcast};
 use tower_http::services::ServeDir;
 use tower_http::trace::TraceLayer;
+use utils::admin_users;
+use webhooks::{
+    delivery::{run_webhook_deliveries, run_webhook_events, run_webhooks_persistence},
+    handlers::{create_subscription, delete_subscription, get_deliveries, get_subscriptions},
+    state::WebhooksState,
+};
 
+pub mod api;
 pub mod chat;
+pub mod openapi;
 pub mod todos;
+pub mod tokens;
 pub mod utils;
+pub mod webhooks;
 
 pub struct AppState {
     todos: TodosState,
+    todos_events: broadcast::Sender<TodosEvent>,
+    /// The persisted todos events, if any.
+    todos_log: Option<EventLog>,
     chat: ChatState,
+    tokens: TokensState,
+    webhooks: WebhooksState,
+    /// The names listed in [`utils::ADMIN_USERS_VAR`].
+    admins: Vec<String>,
 }
 pub type ApiState = Arc<RwLock<AppState>>;
 
+impl AppState {
+    /// A state kept in memory only.
+    fn new(admins: Vec<String>) -> AppState {
+        let (todos_events, _) = broadcast::channel(64);
+        let mut chat = ChatState::new();
+        chat.add_bot(Box::new(PingBot));
+        AppState {
+            todos: TodosState::new(),
+            todos_events,
+            todos_log: None,
+            chat,
+            tokens: TokensState::new(),
+            webhooks: WebhooksState::new(),
+            admins,
+        }
+    }
+
+    /// Whether `name` administrates the app. Names are only trusted once
+    /// verified, see [`utils::Verified`].
+    fn is_admin(&self, name: &str) -> bool {
+        self.admins.iter().any(|admin| admin == name)
+    }
+
+    /// Admins are always moderators of the chat, besides the users they
+    /// granted the role.
+    fn is_moderator(&self, name: &str) -> bool {
+        self.is_admin(name) || self.chat.moderation.is_moderator(name)
+    }
+}
+
 pub fn build_app() -> Router {
-    let state = Arc::new(RwLock::new(AppState {
-        todos: TodosState::new(),
-        chat: ChatState::new(),
-    }));
+    let mut state = AppState::new(admin_users());
+    let mut store = EventStore::from_env();
+    if let Some(store) = &mut store {
+        state.todos = store.load().expect("Could not load the todos");
+        state.todos_log = Some(store.log());
+    }
+    if let Some(dir) = std::env::var_os(DATA_DIR_VAR) {
+        let dir = dir.as_ref();
+        state.webhooks = WebhooksState::load(dir).expect("Could not load the webhooks");
+        state.tokens = TokensState::load(dir).expect("Could not load the API tokens");
+    }
+    // Admin names cannot be claimed from the pages, they sign in with this
+    // token instead. It is only created once when the tokens are persisted,
+    // and printed rather than logged so that it stays out of the logs
+    for admin in &state.admins {
+        if state.tokens.is_claimed(admin) {
+            continue;
+        }
+        if let Some((_, secret)) =
+            state
+                .tokens
+                .create(admin, "admin", &Scope::VARIANTS, Timestamp::now())
+        {
+            println!("API token of the admin {admin}, to sign in at /tokens: {secret}");
+        }
+    }
+    router(state, store)
+}
+
+/// An app kept in memory and administrated by `admin`, along with the
+/// `Authorization` header of their token, which has all the scopes.
+#[cfg(test)]
+fn build_test_app(admin: &str) -> (Router, String) {
+    let mut state = AppState::new(vec![admin.to_owned()]);
+    let (_, secret) = state
+        .tokens
+        .create(admin, "tests", &Scope::VARIANTS, Timestamp::now())
+        .expect("a token of the admin");
+    (router(state, None), format!("Bearer {secret}"))
+}
+
+fn router(state: AppState, store: Option<EventStore>) -> Router {
+    let state = Arc::new(RwLock::new(state));
+    tokio::spawn(run_reminders(state.clone()));
+    tokio::spawn(run_trash_purge(state.clone()));
+    tokio::spawn(run_webhook_events(state.clone()));
+    tokio::spawn(run_webhook_deliveries(state.clone()));
+    tokio::spawn(run_webhooks_persistence(state.clone()));
+    if let Some(store) = store {
+        tokio::spawn(run_event_persistence(state.clone(), store));
+    }
 
     Router::new()
         .route("/", get(root))
         .route("/todos", get(get_todos))
+        .route("/todos/board", get(get_board))
+        .route(
+            "/todos/calendar.ics",
+            get(get_calendar).post(import_calendar),
+        )
+        .route("/todos/bulk", post(bulk_todos))
+        .route("/todos/events", get(get_events))
+        .route("/todos/export", get(export_todos))
+        .route("/todos/import", post(import_todos))
+        .route("/todos/reorder", post(reorder_todos))
+        .route("/todos/replay", get(replay_todos))
+        .route("/todos/trash", get(get_trash).delete(empty_trash))
+        .route("/todos/trash/restore", post(restore_todos))
+        .route("/todos/ws", get(todos_ws))
         .route("/todo", post(create_todo))
         .route("/todo/{id}/toggle", post(toggle_todo))
-        .route("/todo/{id}", delete(delete_todo))
+        .route(
+            "/todo/{id}",
+            get(get_todo).patch(update_todo).delete(delete_todo),
+        )
+        .route("/todo/{id}/done", put(set_todo_done))
+        .route("/todo/{id}/move", post(move_todo_to_list))
+        .route("/todo/{id}/history", get(get_todo_history))
+        .route("/todo/{id}/subtasks", post(create_subtask))
+        .route("/todo/{id}/subtasks/{subtask_id}", delete(delete_subtask))
+        .route(
+            "/todo/{id}/subtasks/{subtask_id}/toggle",
+            post(toggle_subtask),
+        )
+        .route("/lists", get(get_lists).post(create_list))
+        .route(
+            "/lists/{list_id}",
+            get(get_list).patch(update_list).delete(delete_list),
+        )
+        .route("/lists/{list_id}/todos", get(get_list_todos))
+        .route("/lists/{list_id}/board", get(get_list_board))
+        .route("/tags", get(get_tags))
+        .route(
+            "/tags/{tag_id}",
+            get(get_tag).patch(rename_tag).delete(delete_tag),
+        )
+        .route("/tags/{tag_id}/merge", post(merge_tag))
+        .route("/activity", get(get_activity))
+        .route("/openapi.json", get(get_openapi))
+        .route("/docs", get(get_api_docs))
+        .route("/tokens", get(get_tokens).post(create_token))
+        .route("/tokens/session", post(sign_in))
+        .route("/tokens/{id}", delete(revoke_token))
+        .nest("/api/v1", api::router())
+        .route(
+            "/webhooks",
+            get(get_subscriptions).post(create_subscription),
+        )
+        .route("/webhooks/deliveries", get(get_deliveries))
+        .route("/webhooks/{id}", delete(delete_subscription))
         .route("/chat", get(handle_chat_ws))
+        .route("/chat/dm", get(get_direct_messages))
+        .route(
+            "/chat/dm/{username}",
+            get(get_conversation).post(send_direct_message),
+        )
+        .route("/chat/moderation", get(get_moderation))
+        .route("/chat/moderation/moderators", post(add_moderator))
+        .route(
+            "/chat/moderation/moderators/{username}",
+            delete(remove_moderator),
+        )
+        .route("/chat/moderation/bans", post(create_ban))
+        .route("/chat/moderation/bans/{id}", delete(delete_ban))
+        .route("/chat/moderation/mutes/{username}", delete(delete_mute))
+        .route("/chat/{room}/messages", post(post_chat_message))
+        .route("/chat/{room}/messages/{id}", delete(delete_chat_message))
+        .route(
+            "/chat/{room}/webhooks",
+            get(get_webhooks).post(create_webhook),
+        )
+        .route("/chat/{room}/webhooks/{id}", delete(delete_webhook))
+        .route("/hooks/chat/{secret}", post(receive_webhook))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            api::auth::authenticate,
+        ))
         .layer(TraceLayer::new_for_http())
         .nest_service("/assets", ServeDir::new("assets"))
         .with_state(state)
@@ -57,6 +253,9 @@ async fn root() -> Markup {
                 div.join.join-horizontal.mt-4.w-full.justify-center {
                     a.btn.btn-primary.join-item href="/todos" { "Todos" }
                     a.btn.btn-primary.join-item href="/chat" { "Chat" }
+                    a.btn.btn-primary.join-item href="/docs" { "API" }
+                    a.btn.btn-primary.join-item href="/tokens" { "Tokens" }
+                    a.btn.btn-primary.join-item href="/webhooks" { "Webhooks" }
                 }
             }
         }
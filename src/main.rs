@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 use poc_rust_htmx::build_app;
 
 #[tokio::main]
@@ -21,7 +23,11 @@ async fn main() {
         .await
         .expect("Could not bind to 0.0.0.0:3001");
 
-    axum::serve(listener, app)
-        .await
-        .expect("Could not start application");
+    // The address of the clients is used to ban them from the chat
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .expect("Could not start application");
 }
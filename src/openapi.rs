@@ -0,0 +1,347 @@
+use axum::Json;
+use maud::{DOCTYPE, Markup, html};
+use utoipa::{
+    OpenApi,
+    openapi::{
+        self, PathItem, RefOr,
+        path::{Operation, Parameter},
+        schema::Schema,
+    },
+};
+
+use crate::todos::handlers;
+
+/// The JSON side of the todos routes. The spec is generated from the
+/// handlers `#[utoipa::path]` attributes and the types they use, routes have
+/// to be listed here as well as in [`crate::build_app`].
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Todos API",
+        description = "Routes answer with JSON when requests have an \
+            `Accept: application/json` header, and HTML fragments otherwise. \
+            Request bodies are either JSON, sent with \
+            `Content-Type: application/json`, or forms."
+    ),
+    paths(
+        handlers::get_todos,
+        handlers::create_todo,
+        handlers::get_todo,
+        handlers::update_todo,
+        handlers::delete_todo,
+        handlers::toggle_todo,
+        handlers::set_todo_done,
+        handlers::move_todo_to_list,
+        handlers::get_todo_history,
+        handlers::create_subtask,
+        handlers::toggle_subtask,
+        handlers::delete_subtask,
+    ),
+    tags((name = "todos", description = "Todos of the default list and of other lists")),
+)]
+pub struct ApiDoc;
+
+pub async fn get_openapi() -> Json<openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// The operations of a path, in the order they are usually listed.
+fn operations(item: &PathItem) -> Vec<(&'static str, &Operation)> {
+    [
+        ("GET", &item.get),
+        ("POST", &item.post),
+        ("PUT", &item.put),
+        ("PATCH", &item.patch),
+        ("DELETE", &item.delete),
+    ]
+    .into_iter()
+    .filter_map(|(method, operation)| operation.as_ref().map(|o| (method, o)))
+    .collect()
+}
+
+/// The name of a referenced schema, e.g. `Todo` or `Todo[]` for arrays,
+/// inline schemas are shown as is.
+fn schema_label(schema: &RefOr<Schema>) -> String {
+    let value = serde_json::to_value(schema).unwrap_or_default();
+    let name = |value: &serde_json::Value| {
+        value["$ref"]
+            .as_str()
+            .and_then(|r| r.rsplit('/').next())
+            .map(str::to_owned)
+    };
+    name(&value)
+        .or_else(|| name(&value["items"]).map(|name| format!("{name}[]")))
+        .unwrap_or_else(|| value.to_string())
+}
+
+/// `path`, `query` or `header`.
+fn parameter_location(parameter: &Parameter) -> String {
+    serde_json::to_value(&parameter.parameter_in)
+        .ok()
+        .and_then(|location| location.as_str().map(str::to_owned))
+        .unwrap_or_default()
+}
+
+fn operation_view(path: &str, method: &str, operation: &Operation) -> Markup {
+    let parameters = operation.parameters.as_deref().unwrap_or_default();
+    let bodies = operation
+        .request_body
+        .iter()
+        .flat_map(|body| &body.content)
+        .filter_map(|(content_type, content)| Some((content_type, content.schema.as_ref()?)));
+    html! {
+        div.card.bg-base-100.shadow-md.operation data-method=(method) data-path=(path) {
+            div.card-body {
+                h2.card-title.font-mono {
+                    span.badge.badge-primary { (method) }
+                    (path)
+                }
+                @if let Some(summary) = &operation.summary {
+                    p { (summary) }
+                }
+                @if let Some(description) = &operation.description {
+                    p.text-gray-500 { (description) }
+                }
+                @if !parameters.is_empty() {
+                    h3.font-bold { "Parameters" }
+                    ul {
+                        @for parameter in parameters {
+                            li {
+                                code { (parameter.name) }
+                                " (" (parameter_location(parameter)) ")"
+                                @if let Some(description) = &parameter.description {
+                                    " " (description)
+                                }
+                            }
+                        }
+                    }
+                }
+                @for (content_type, schema) in bodies {
+                    h3.font-bold { "Request body" }
+                    p { code { (content_type) } " " (schema_link(schema)) }
+                }
+                h3.font-bold { "Responses" }
+                ul {
+                    @for (status, response) in &operation.responses.responses {
+                        li {
+                            code { (status) }
+                            @if let RefOr::T(response) = response {
+                                " " (response.description)
+                                @for content in response.content.values() {
+                                    @if let Some(schema) = &content.schema {
+                                        " " (schema_link(schema))
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn schema_link(schema: &RefOr<Schema>) -> Markup {
+    let label = schema_label(schema);
+    html! {
+        a.link.font-mono href={ "#schema-" (label.trim_end_matches("[]")) } { (label) }
+    }
+}
+
+/// A browsable version of the spec.
+pub async fn get_api_docs() -> Markup {
+    let spec = ApiDoc::openapi();
+    let schemas = spec
+        .components
+        .as_ref()
+        .map(|components| &components.schemas);
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                link href="/assets/style/output.css" rel="stylesheet";
+            }
+            body.flex.flex-col.gap-4.p-6 {
+                h1.text-2xl.font-bold.text-center { (spec.info.title) }
+                @if let Some(description) = &spec.info.description {
+                    p.text-center { (description) }
+                }
+                a.link.link-hover.self-center href="/openapi.json" { "OpenAPI document" }
+                @for (path, item) in &spec.paths.paths {
+                    @for (method, operation) in operations(item) {
+                        (operation_view(path, method, operation))
+                    }
+                }
+                h2.text-xl.font-bold { "Schemas" }
+                @for (name, schema) in schemas.into_iter().flatten() {
+                    div.card.bg-base-100.shadow-md id={ "schema-" (name) } {
+                        div.card-body {
+                            h3.card-title.font-mono { (name) }
+                            pre.text-sm {
+                                (serde_json::to_string_pretty(schema).unwrap_or_default())
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::Reverse;
+
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+    use utoipa::OpenApi;
+
+    use super::{ApiDoc, operations};
+    use crate::build_app;
+
+    /// The methods and paths of the routes of single todos, e.g.
+    /// `("PUT", "/todo/{id}/done")`, as declared in [`build_app`]. The router
+    /// cannot list its routes, they are read from its source instead.
+    fn todo_routes() -> Vec<(String, String)> {
+        let source = include_str!("lib.rs");
+        let mut routes = Vec::new();
+        for call in source.split(".route(").skip(1) {
+            // The arguments of the call, up to its closing parenthesis
+            let mut depth = 1;
+            let end = call
+                .find(|c| {
+                    depth += match c {
+                        '(' => 1,
+                        ')' => -1,
+                        _ => 0,
+                    };
+                    depth == 0
+                })
+                .unwrap();
+            let Some((path, methods)) = call[..end]
+                .trim_start()
+                .strip_prefix('"')
+                .and_then(|call| call.split_once('"'))
+            else {
+                continue;
+            };
+            if path != "/todo" && !path.starts_with("/todo/") {
+                continue;
+            }
+            for method in ["get", "post", "put", "patch", "delete"] {
+                let called = methods.match_indices(&format!("{method}(")).any(|(i, _)| {
+                    !methods[..i].ends_with(|c: char| c.is_alphanumeric() || c == '_')
+                });
+                if called {
+                    routes.push((method.to_uppercase(), path.to_owned()));
+                }
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn test_todo_routes_documented() {
+        let spec = ApiDoc::openapi();
+        let routes = todo_routes();
+        assert!(routes.contains(&("PUT".to_owned(), "/todo/{id}/done".to_owned())));
+        for (method, path) in routes {
+            let documented = spec
+                .paths
+                .paths
+                .get(&path)
+                .is_some_and(|item| operations(item).iter().any(|(m, _)| *m == method));
+            assert!(documented, "{method} {path} is routed but not documented");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spec_routes_exist() {
+        let app = build_app();
+        let token = r#"{"name": "tests", "scopes": ["todos:read", "todos:write"]}"#;
+        let request = Request::builder()
+            .method("POST")
+            .uri("/tokens")
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .header("X-Username", "alice")
+            .body(Body::from(token))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let token: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let authorization = format!("Bearer {}", token["secret"].as_str().unwrap());
+        let request = |method: &str, uri: &str, body: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Accept", "application/json")
+                .header("Content-Type", "application/json")
+                .header("Authorization", &authorization)
+                .body(Body::from(body.to_owned()))
+                .unwrap()
+        };
+        let response = app
+            .clone()
+            .oneshot(request("POST", "/todo", r#"{"content": "drift"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/todo/0/subtasks",
+                r#"{"content": "step"}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let spec = ApiDoc::openapi();
+        let mut routes: Vec<(&str, String)> = spec
+            .paths
+            .paths
+            .iter()
+            .flat_map(|(path, item)| {
+                operations(item).into_iter().map(move |(method, _)| {
+                    let uri = path.replace("{id}", "0").replace("{subtask_id}", "0");
+                    (method, uri)
+                })
+            })
+            .collect();
+        assert!(!routes.is_empty());
+        // Deleting the todo last, so that the other routes find it, after
+        // its subtask
+        routes.sort_by_key(|(method, uri)| (*method == "DELETE", Reverse(uri.len())));
+
+        for (method, uri) in routes {
+            let response = app
+                .clone()
+                .oneshot(request(method, &uri, "{}"))
+                .await
+                .unwrap();
+            // Unknown paths and methods are rejected by the router
+            assert!(
+                ![StatusCode::NOT_FOUND, StatusCode::METHOD_NOT_ALLOWED]
+                    .contains(&response.status()),
+                "{method} {uri} is documented but not routed"
+            );
+        }
+
+        let response = app
+            .oneshot(request("GET", "/openapi.json", ""))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let served: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(served, serde_json::to_value(spec).unwrap());
+    }
+}
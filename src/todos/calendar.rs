@@ -0,0 +1,489 @@
+use jiff::{
+    Timestamp, Zoned,
+    civil::{Date, DateTime},
+    tz::TimeZone,
+};
+use serde::Deserialize;
+
+use super::{
+    state::{Priority, Todo, TodoStatus, TodosState},
+    tags::normalize_tag,
+    transfer::ImportError,
+};
+
+const PRODID: &str = "-//poc-rust-htmx//Todos//EN";
+
+/// Todos exported from this app have UIDs such as `todo-3@poc-rust-htmx`.
+const UID_DOMAIN: &str = "poc-rust-htmx";
+
+/// Non standard property keeping the exact status of todos, iCalendar
+/// statuses have no equivalent for blocked todos.
+const STATUS_PROPERTY: &str = "X-TODO-STATUS";
+
+const UTC_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const LOCAL_FORMAT: &str = "%Y%m%dT%H%M%S";
+const DATE_FORMAT: &str = "%Y%m%d";
+
+/// Lines longer than this many bytes are folded, see RFC 5545 section 3.1.
+const MAX_LINE_LENGTH: usize = 75;
+
+/// How todos appear in calendars. Tasks are not shown by every client, which
+/// can subscribe to events at the due dates instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Component {
+    #[default]
+    Vtodo,
+    Vevent,
+}
+
+impl Component {
+    fn name(&self) -> &'static str {
+        match self {
+            Component::Vtodo => "VTODO",
+            Component::Vevent => "VEVENT",
+        }
+    }
+}
+
+pub fn todo_uid(todo_id: usize) -> String {
+    format!("todo-{todo_id}@{UID_DOMAIN}")
+}
+
+/// The todo an UID from [`todo_uid`] refers to.
+pub fn todo_id_from_uid(uid: &str) -> Option<usize> {
+    uid.strip_suffix(UID_DOMAIN)?
+        .strip_suffix('@')?
+        .strip_prefix("todo-")?
+        .parse()
+        .ok()
+}
+
+/// A task or event of an uploaded calendar. Missing properties leave the
+/// todo unchanged, except for the due date which is cleared.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalendarItem {
+    pub uid: String,
+    pub summary: String,
+    pub status: Option<TodoStatus>,
+    pub priority: Option<Priority>,
+    pub due: Option<Zoned>,
+    pub completed: Option<Timestamp>,
+    pub tags: Vec<String>,
+}
+
+/// iCalendar priorities go from 1 (highest) to 9 (lowest), 0 meaning
+/// undefined.
+fn ical_priority(priority: Priority) -> u8 {
+    match priority {
+        Priority::Urgent => 1,
+        Priority::High => 3,
+        Priority::Normal => 5,
+        Priority::Low => 9,
+    }
+}
+
+fn parse_ical_priority(value: &str) -> Result<Option<Priority>, String> {
+    match value.trim().parse::<u8>() {
+        Ok(0) => Ok(None),
+        Ok(1..=2) => Ok(Some(Priority::Urgent)),
+        Ok(3..=4) => Ok(Some(Priority::High)),
+        Ok(5) => Ok(Some(Priority::Normal)),
+        Ok(6..=9) => Ok(Some(Priority::Low)),
+        _ => Err(format!("invalid priority {value:?}")),
+    }
+}
+
+fn ical_status(status: TodoStatus) -> &'static str {
+    match status {
+        TodoStatus::Backlog | TodoStatus::Blocked => "NEEDS-ACTION",
+        TodoStatus::InProgress => "IN-PROCESS",
+        TodoStatus::Done => "COMPLETED",
+    }
+}
+
+/// Cancelled tasks and event statuses have no equivalent, and are ignored.
+fn parse_ical_status(value: &str) -> Option<TodoStatus> {
+    match value.trim() {
+        "NEEDS-ACTION" => Some(TodoStatus::Backlog),
+        "IN-PROCESS" => Some(TodoStatus::InProgress),
+        "COMPLETED" => Some(TodoStatus::Done),
+        _ => None,
+    }
+}
+
+fn utc(timestamp: Timestamp) -> String {
+    timestamp.strftime(UTC_FORMAT).to_string()
+}
+
+/// The calendar of the todos with a due date.
+pub fn export(state: &TodosState, todos: &[&Todo], component: Component, now: Timestamp) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_owned(),
+        "VERSION:2.0".to_owned(),
+        format!("PRODID:{PRODID}"),
+        "X-WR-CALNAME:Todos".to_owned(),
+    ];
+    for todo in todos {
+        let Some(due) = &todo.due else {
+            continue;
+        };
+        lines.push(format!("BEGIN:{}", component.name()));
+        lines.push(format!("UID:{}", escape(&state.calendar_uid(todo.id))));
+        lines.push(format!("DTSTAMP:{}", utc(now)));
+        lines.push(format!("SEQUENCE:{}", todo.version));
+        lines.push(format!("SUMMARY:{}", escape(&todo.content)));
+        match component {
+            Component::Vtodo => {
+                lines.push(format!("DUE:{}", utc(due.timestamp())));
+                lines.push(format!("STATUS:{}", ical_status(todo.status)));
+                if let Some(completed_at) = todo.completed_at {
+                    lines.push(format!("COMPLETED:{}", utc(completed_at)));
+                }
+            }
+            Component::Vevent => lines.push(format!("DTSTART:{}", utc(due.timestamp()))),
+        }
+        lines.push(format!("{STATUS_PROPERTY}:{}", todo.status.as_str()));
+        lines.push(format!("PRIORITY:{}", ical_priority(todo.priority)));
+        if !todo.tags.is_empty() {
+            let tags: Vec<String> = todo.tags.iter().map(|tag| escape(tag)).collect();
+            lines.push(format!("CATEGORIES:{}", tags.join(",")));
+        }
+        lines.push(format!("END:{}", component.name()));
+    }
+    lines.push("END:VCALENDAR".to_owned());
+
+    lines.iter().map(|line| fold(line) + "\r\n").collect()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n' | 'N') => unescaped.push('\n'),
+                Some(c) => unescaped.push(c),
+                None => unescaped.push('\\'),
+            },
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Split a list value on the separators that are not escaped.
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                item.push(c);
+                item.extend(chars.next());
+            }
+            ',' => items.push(unescape(&std::mem::take(&mut item))),
+            c => item.push(c),
+        }
+    }
+    items.push(unescape(&item));
+    items
+}
+
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len());
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > MAX_LINE_LENGTH {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded
+}
+
+/// Join folded lines back, along with the line each one starts on.
+fn unfold(input: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some((_, previous))) => previous.push_str(continuation),
+            _ => lines.push((index + 1, line.to_owned())),
+        }
+    }
+    lines
+}
+
+/// A content line: `NAME;PARAM=value:VALUE`.
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Property> {
+        // Parameter values may be quoted, and contain colons
+        let mut in_quotes = false;
+        let colon = line.char_indices().find_map(|(i, c)| match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                None
+            }
+            ':' if !in_quotes => Some(i),
+            _ => None,
+        })?;
+        let (head, value) = (&line[..colon], &line[colon + 1..]);
+        let mut parts = head.split(';');
+        let name = parts.next()?.trim().to_uppercase();
+        let params = parts
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| {
+                (
+                    key.trim().to_uppercase(),
+                    value.trim_matches('"').to_owned(),
+                )
+            })
+            .collect();
+        Some(Property {
+            name,
+            params,
+            value: value.to_owned(),
+        })
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Date-times are either in UTC, in the time zone of their `TZID`, or
+/// floating, in which case they are in the server time zone like dates.
+fn parse_date_time(property: &Property) -> Result<Zoned, String> {
+    let value = property.value.trim();
+    let invalid = || format!("invalid {} {value:?}", property.name);
+    if property.param("VALUE") == Some("DATE") || !value.contains('T') {
+        return Date::strptime(DATE_FORMAT, value)
+            .and_then(|date| date.to_zoned(TimeZone::system()))
+            .map_err(|_| invalid());
+    }
+    let (value, time_zone) = match (value.strip_suffix('Z'), property.param("TZID")) {
+        (Some(value), _) => (value, TimeZone::UTC),
+        (None, Some(tzid)) => (
+            value,
+            TimeZone::get(tzid).map_err(|_| format!("unknown time zone {tzid:?}"))?,
+        ),
+        (None, None) => (value, TimeZone::system()),
+    };
+    DateTime::strptime(LOCAL_FORMAT, value)
+        .and_then(|datetime| datetime.to_zoned(time_zone))
+        // UTC is only used for transport, shown in the server time zone instead
+        .map(|due| match due.time_zone() == &TimeZone::UTC {
+            true => due.with_time_zone(TimeZone::system()),
+            false => due,
+        })
+        .map_err(|_| invalid())
+}
+
+fn parse_item(properties: &[Property]) -> Result<CalendarItem, String> {
+    let property = |name: &str| properties.iter().find(|p| p.name == name);
+
+    let uid = property("UID")
+        .map(|p| unescape(p.value.trim()))
+        .filter(|uid| !uid.is_empty())
+        .ok_or("missing UID")?;
+    let summary = property("SUMMARY")
+        .map(|p| unescape(&p.value).trim().to_owned())
+        .filter(|summary| !summary.is_empty())
+        .ok_or("missing SUMMARY")?;
+    let completed = property("COMPLETED")
+        .map(|p| parse_date_time(p).map(|completed| completed.timestamp()))
+        .transpose()?;
+    let status = match (property(STATUS_PROPERTY), property("STATUS")) {
+        (Some(p), _) => Some(
+            TodoStatus::VARIANTS
+                .into_iter()
+                .find(|s| s.as_str() == p.value.trim())
+                .ok_or_else(|| format!("invalid status {:?}", p.value))?,
+        ),
+        (None, Some(p)) => parse_ical_status(&p.value),
+        (None, None) => completed.map(|_| TodoStatus::Done),
+    };
+    let priority = property("PRIORITY")
+        .map(|p| parse_ical_priority(&p.value))
+        .transpose()?
+        .flatten();
+    let due = property("DUE")
+        .or_else(|| property("DTSTART"))
+        .map(parse_date_time)
+        .transpose()?;
+
+    let mut tags: Vec<String> = Vec::new();
+    for p in properties.iter().filter(|p| p.name == "CATEGORIES") {
+        for tag in split_list(&p.value) {
+            let tag = normalize_tag(&tag).ok_or_else(|| format!("invalid tag {tag:?}"))?;
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+    }
+
+    Ok(CalendarItem {
+        uid,
+        summary,
+        status,
+        priority,
+        due,
+        completed,
+        tags,
+    })
+}
+
+/// Parse the tasks and events of a calendar. Invalid ones are reported with
+/// the line they start on.
+pub fn parse(input: &str) -> (Vec<CalendarItem>, Vec<ImportError>) {
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+    // The start line and properties of the current task or event
+    let mut current: Option<(usize, Vec<Property>)> = None;
+    // Depth of the components nested in it, such as alarms
+    let mut nested = 0;
+
+    for (line, content) in unfold(input) {
+        if content.trim().is_empty() {
+            continue;
+        }
+        let Some(property) = Property::parse(&content) else {
+            errors.push(ImportError::new(line, "invalid content line"));
+            continue;
+        };
+        let component = property.value.trim().to_uppercase();
+        let is_item = component == "VTODO" || component == "VEVENT";
+        match (property.name.as_str(), &mut current) {
+            ("BEGIN", None) if is_item => current = Some((line, Vec::new())),
+            ("BEGIN", Some(_)) => nested += 1,
+            ("END", Some(_)) if nested > 0 => nested -= 1,
+            ("END", Some(_)) if is_item => {
+                if let Some((start, properties)) = current.take() {
+                    match parse_item(&properties) {
+                        Ok(item) => items.push(item),
+                        Err(message) => errors.push(ImportError::new(start, message)),
+                    }
+                }
+            }
+            (_, Some((_, properties))) if nested == 0 => properties.push(property),
+            _ => {}
+        }
+    }
+    if let Some((start, _)) = current {
+        errors.push(ImportError::new(start, "unterminated component"));
+    }
+    (items, errors)
+}
+
+#[cfg(test)]
+mod test {
+    use jiff::{Timestamp, Zoned};
+
+    use super::{Component, export, fold, parse, todo_id_from_uid, todo_uid, unfold};
+    use crate::todos::state::{DEFAULT_LIST_ID, Priority, TodoStatus, TodosState};
+
+    #[test]
+    fn test_export_parse_round_trip() {
+        let mut state = TodosState::new();
+        let due = "2025-01-31T18:00[Europe/Paris]".parse::<Zoned>().unwrap();
+        let todo_id = state
+            .add_todo(DEFAULT_LIST_ID, "call mom; then dad, maybe")
+            .unwrap()
+            .id;
+        state.set_due(todo_id, Some(due.clone()), None);
+        state.set_priority(todo_id, Priority::High);
+        state.add_tags(todo_id, &["family".to_owned(), "phone".to_owned()]);
+        let completed = "2025-01-30T10:00[Europe/Paris]".parse::<Zoned>().unwrap();
+        state.set_status_at(todo_id, TodoStatus::Done, &completed);
+        // Todos without due date are not exported
+        state.add_todo(DEFAULT_LIST_ID, "someday");
+
+        let todos: Vec<_> = state.todos().iter().collect();
+        for component in [Component::Vtodo, Component::Vevent] {
+            let calendar = export(&state, &todos, component, Timestamp::now());
+            let (items, errors) = parse(&calendar);
+            assert_eq!(errors, vec![]);
+            assert_eq!(items.len(), 1);
+            let item = &items[0];
+            assert_eq!(item.uid, todo_uid(todo_id));
+            assert_eq!(item.summary, "call mom; then dad, maybe");
+            assert_eq!(item.status, Some(TodoStatus::Done));
+            assert_eq!(item.priority, Some(Priority::High));
+            assert_eq!(
+                item.due.as_ref().map(Zoned::timestamp),
+                Some(due.timestamp())
+            );
+            assert_eq!(item.tags, vec!["family", "phone"]);
+            let completed_at = (component == Component::Vtodo).then(|| completed.timestamp());
+            assert_eq!(item.completed, completed_at);
+        }
+    }
+
+    #[test]
+    fn test_parse_calendar_clients_items() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VTODO\r\n\
+            UID:abc-123\r\n\
+            SUMMARY:Renew pass\r\n \
+            port\r\n\
+            DUE;TZID=Europe/Paris:20250301T090000\r\n\
+            STATUS:IN-PROCESS\r\n\
+            PRIORITY:7\r\n\
+            BEGIN:VALARM\r\n\
+            SUMMARY:Alarm\r\n\
+            END:VALARM\r\n\
+            END:VTODO\r\n\
+            BEGIN:VTODO\r\n\
+            SUMMARY:No UID\r\n\
+            END:VTODO\r\n\
+            BEGIN:VEVENT\r\n\
+            UID:def-456\r\n\
+            SUMMARY:Dentist\r\n\
+            DTSTART;VALUE=DATE:20250302\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+        let (items, errors) = parse(calendar);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 13);
+        assert_eq!(errors[0].message, "missing UID");
+
+        assert_eq!(items[0].summary, "Renew passport");
+        assert_eq!(items[0].status, Some(TodoStatus::InProgress));
+        assert_eq!(items[0].priority, Some(Priority::Low));
+        let due = "2025-03-01T09:00[Europe/Paris]".parse::<Zoned>().unwrap();
+        assert_eq!(items[0].due, Some(due));
+        assert_eq!(items[1].uid, "def-456");
+        assert_eq!(items[1].status, None);
+        assert!(items[1].due.is_some());
+    }
+
+    #[test]
+    fn test_fold_long_lines() {
+        let line = format!("SUMMARY:{}", "é".repeat(60));
+        let folded = fold(&line);
+        assert!(folded.split("\r\n").all(|l| l.len() <= 75));
+        assert_eq!(unfold(&folded), vec![(1, line)]);
+
+        assert_eq!(todo_id_from_uid(&todo_uid(42)), Some(42));
+        assert_eq!(todo_id_from_uid("todo-42@example.com"), None);
+    }
+}
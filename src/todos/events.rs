@@ -0,0 +1,498 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use jiff::{Timestamp, Zoned};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use super::{
+    history::Change,
+    recurrence::Recurrence,
+    state::{BulkOperation, Placement, Priority, TodoStatus, TodosState},
+};
+use crate::ApiState;
+
+/// A change to the todos. [`TodosState`] is the result of applying all its
+/// events in order, see [`TodosState::replay`].
+///
+/// Events only hold the inputs of a change: anything derived from the state,
+/// such as the IDs of new todos, is computed again when the event is applied.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum DomainEvent {
+    ListAdded {
+        name: String,
+    },
+    ListRenamed {
+        list_id: usize,
+        name: String,
+    },
+    ListAutoCompleteSet {
+        list_id: usize,
+        auto_complete: bool,
+    },
+    ListDeleted {
+        list_id: usize,
+    },
+    /// A todo created along with its details, so that replays never see it
+    /// half created.
+    TodoAdded {
+        list_id: usize,
+        content: String,
+        #[serde(default)]
+        due: Option<Zoned>,
+        #[serde(default)]
+        reminder_minutes: Option<u32>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        priority: Priority,
+        #[serde(default)]
+        recurrence: Option<Recurrence>,
+    },
+    /// `at` is when the todo was completed, next occurrences of recurring
+    /// todos may depend on it.
+    TodoToggled {
+        todo_id: usize,
+        at: Zoned,
+    },
+    TodoStatusSet {
+        todo_id: usize,
+        status: TodoStatus,
+        at: Zoned,
+    },
+    TodoContentSet {
+        todo_id: usize,
+        content: String,
+    },
+    TodoPrioritySet {
+        todo_id: usize,
+        priority: Priority,
+    },
+    TodoDueSet {
+        todo_id: usize,
+        due: Option<Zoned>,
+        reminder_minutes: Option<u32>,
+    },
+    TodoRecurrenceSet {
+        todo_id: usize,
+        recurrence: Option<Recurrence>,
+    },
+    RemindersSent {
+        todo_ids: Vec<usize>,
+    },
+    SubtaskAdded {
+        todo_id: usize,
+        content: String,
+    },
+    SubtaskToggled {
+        todo_id: usize,
+        subtask_id: usize,
+        at: Zoned,
+    },
+    SubtaskDeleted {
+        todo_id: usize,
+        subtask_id: usize,
+    },
+    TagsAdded {
+        todo_id: usize,
+        names: Vec<String>,
+    },
+    TagRenamed {
+        tag_id: usize,
+        name: String,
+    },
+    TagMerged {
+        tag_id: usize,
+        into_id: usize,
+    },
+    TagDeleted {
+        tag_id: usize,
+    },
+    BulkApplied {
+        ids: Vec<usize>,
+        operation: BulkOperation,
+        at: Zoned,
+    },
+    TodoDeleted {
+        todo_id: usize,
+        at: Timestamp,
+    },
+    TodosRestored {
+        ids: Vec<usize>,
+    },
+    TrashPurged {
+        before: Timestamp,
+    },
+    TodoMovedToList {
+        todo_id: usize,
+        list_id: usize,
+    },
+    TodoMoved {
+        todo_id: usize,
+        placement: Placement,
+        target_id: usize,
+    },
+    TodosReordered {
+        ids: Vec<usize>,
+    },
+    IdempotencyKeyUsed {
+        #[serde(default)]
+        actor: String,
+        key: String,
+        #[serde(default)]
+        request_hash: String,
+        todo_id: usize,
+        at: Timestamp,
+    },
+    CalendarUidLinked {
+        uid: String,
+        todo_id: usize,
+    },
+    /// An entry of the todos history, `content` being the one of the todo
+    /// when it was changed.
+    ChangeRecorded {
+        todo_id: usize,
+        content: String,
+        actor: String,
+        change: Change,
+        at: Timestamp,
+    },
+}
+
+/// An event of the log along with its sequence number.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// Events are numbered from 1, in the order they were applied. Kept
+    /// first, so that lines of the events file can be skipped by sequence
+    /// number without being parsed.
+    pub seq: u64,
+    pub recorded_at: Timestamp,
+    #[serde(flatten)]
+    pub event: DomainEvent,
+}
+
+/// Directory the todos are persisted to. Todos only live in memory when it
+/// is not set.
+pub const DATA_DIR_VAR: &str = "TODOS_DATA_DIR";
+
+const EVENTS_FILE: &str = "events.jsonl";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+/// How many events are recorded between two snapshots.
+const SNAPSHOT_INTERVAL: u64 = 100;
+
+/// Upper bound on how long recorded events wait before being persisted, for
+/// changes that are not notified to clients.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Persists the todos to a directory.
+///
+/// `events.jsonl` holds every event, one per line, and is only ever appended
+/// to. `snapshot.json` holds the state at some version, so that only the
+/// events recorded after it are applied on startup. Past states are replayed
+/// from the events file, see [`EventLog`].
+#[derive(Debug)]
+pub struct EventStore {
+    dir: PathBuf,
+    /// Version of the last persisted event.
+    persisted: u64,
+    /// Version of the last snapshot.
+    snapshot: u64,
+}
+
+impl EventStore {
+    pub fn new(dir: impl Into<PathBuf>) -> EventStore {
+        EventStore {
+            dir: dir.into(),
+            persisted: 0,
+            snapshot: 0,
+        }
+    }
+
+    /// The store of the directory set in [`DATA_DIR_VAR`], if any.
+    pub fn from_env() -> Option<EventStore> {
+        std::env::var_os(DATA_DIR_VAR).map(EventStore::new)
+    }
+
+    /// The events file, to replay past states from.
+    pub fn log(&self) -> EventLog {
+        EventLog {
+            dir: self.dir.clone(),
+        }
+    }
+
+    /// Load the latest snapshot, and apply the events recorded after it.
+    pub fn load(&mut self) -> io::Result<TodosState> {
+        fs::create_dir_all(&self.dir)?;
+        let mut state = match fs::read(self.dir.join(SNAPSHOT_FILE)) {
+            Ok(snapshot) => serde_json::from_slice(&snapshot)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => TodosState::new(),
+            Err(err) => return Err(err),
+        };
+        self.snapshot = state.version();
+        state.set_persisted(self.snapshot);
+
+        // The events already in the snapshot are skipped without being parsed
+        let mut events = Vec::new();
+        self.log().read_events(|line| match line_seq(line) {
+            Some(seq) if seq <= self.snapshot => Ok(true),
+            _ => {
+                events.push(serde_json::from_str::<RecordedEvent>(line)?);
+                Ok(true)
+            }
+        })?;
+        state.apply_recorded(events);
+        self.persisted = state.version();
+        state.set_persisted(self.persisted);
+        tracing::info!(
+            "Loaded todos from {} at version {}, snapshot at version {}",
+            self.dir.display(),
+            self.persisted,
+            self.snapshot
+        );
+        Ok(state)
+    }
+
+    /// The events recorded since the last write, along with a snapshot once
+    /// enough events have been recorded since the previous one. Only
+    /// serializes them, so that the state is not locked during the writes.
+    pub fn pending(&self, state: &TodosState) -> io::Result<Option<PendingEvents>> {
+        let events = state.events();
+        let new_events = &events[events.partition_point(|e| e.seq <= self.persisted)..];
+        if new_events.is_empty() {
+            return Ok(None);
+        }
+
+        let mut lines = String::new();
+        for event in new_events {
+            lines.push_str(&serde_json::to_string(event)?);
+            lines.push('\n');
+        }
+        let version = state.version();
+        let snapshot = (version - self.snapshot >= SNAPSHOT_INTERVAL)
+            .then(|| serde_json::to_vec(state))
+            .transpose()?;
+        Ok(Some(PendingEvents {
+            lines,
+            snapshot,
+            version,
+        }))
+    }
+
+    /// Append the pending events, and write their snapshot if any.
+    pub fn write(&mut self, pending: &PendingEvents) -> io::Result<()> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(EVENTS_FILE))?
+            .write_all(pending.lines.as_bytes())?;
+        self.persisted = pending.version;
+
+        if let Some(snapshot) = &pending.snapshot {
+            // Written aside then renamed, so that a crash never leaves a
+            // partial snapshot behind
+            let tmp = self.dir.join(format!("{SNAPSHOT_FILE}.tmp"));
+            fs::write(&tmp, snapshot)?;
+            fs::rename(tmp, self.dir.join(SNAPSHOT_FILE))?;
+            self.snapshot = pending.version;
+        }
+        Ok(())
+    }
+
+    /// Append the events recorded since the last call, and write a new
+    /// snapshot once enough events have been recorded since the previous one.
+    /// Persisted events are then dropped from memory, see
+    /// [`TodosState::events`].
+    pub fn persist(&mut self, state: &mut TodosState) -> io::Result<()> {
+        if let Some(pending) = self.pending(state)? {
+            self.write(&pending)?;
+            state.set_persisted(self.persisted);
+        }
+        Ok(())
+    }
+}
+
+/// Events serialized by [`EventStore::pending`], yet to be written.
+#[derive(Debug)]
+pub struct PendingEvents {
+    /// The events, one per line.
+    lines: String,
+    /// The state at `version`, when a snapshot is due.
+    snapshot: Option<Vec<u8>>,
+    version: u64,
+}
+
+/// The persisted todos events, read from disk when the ones in memory do not
+/// go back far enough.
+#[derive(Debug, Clone)]
+pub struct EventLog {
+    dir: PathBuf,
+}
+
+impl EventLog {
+    /// Call `f` with each line of the events file, until it returns `false`.
+    fn read_events(&self, mut f: impl FnMut(&str) -> io::Result<bool>) -> io::Result<()> {
+        let file = match File::open(self.dir.join(EVENTS_FILE)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if !line.trim().is_empty() && !f(&line)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// The events recorded after `version`, the ones of `todos` not persisted
+    /// yet included.
+    pub fn events_after(&self, todos: &TodosState, version: u64) -> io::Result<Vec<RecordedEvent>> {
+        let mut events = Vec::new();
+        self.read_events(|line| {
+            if line_seq(line).is_none_or(|seq| seq > version) {
+                let event: RecordedEvent = serde_json::from_str(line)?;
+                if event.seq > version {
+                    events.push(event);
+                }
+            }
+            Ok(true)
+        })?;
+        let last = events.last().map_or(version, |e| e.seq);
+        let unpersisted = todos.events().iter().filter(|e| e.seq > last);
+        events.extend(unpersisted.cloned());
+        Ok(events)
+    }
+
+    /// Replay the events, the ones of `todos` not persisted yet included,
+    /// while `until` holds.
+    pub fn replay(
+        &self,
+        todos: &TodosState,
+        until: impl Fn(&RecordedEvent) -> bool,
+    ) -> io::Result<TodosState> {
+        let mut state = TodosState::new();
+        let mut done = false;
+        self.read_events(|line| {
+            let event: RecordedEvent = serde_json::from_str(line)?;
+            done = !until(&event);
+            if !done {
+                state.apply_recorded([event]);
+            }
+            Ok(!done)
+        })?;
+        if !done {
+            let version = state.version();
+            let unpersisted = todos.events().iter().filter(|e| e.seq > version);
+            state.apply_recorded(unpersisted.take_while(|e| until(e)).cloned());
+        }
+        Ok(state)
+    }
+}
+
+/// The sequence number of an event line, without parsing the whole event.
+/// Relies on `seq` being serialized first, see [`RecordedEvent`].
+fn line_seq(line: &str) -> Option<u64> {
+    let rest = line.strip_prefix(r#"{"seq":"#)?;
+    let end = rest.find(|c: char| !c.is_ascii_digit())?;
+    rest[..end].parse().ok()
+}
+
+/// Persist the todos events as they are recorded. Files are written on the
+/// blocking threads, without holding the lock of the state.
+pub async fn run_event_persistence(state: ApiState, mut store: EventStore) {
+    tracing::info!("Starting todos persistence");
+    let mut rx_events = state.read().await.todos_events.subscribe();
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            event = rx_events.recv() => {
+                if let Err(RecvError::Closed) = event {
+                    break;
+                }
+            }
+        }
+
+        let pending = match store.pending(&state.read().await.todos) {
+            Ok(Some(pending)) => pending,
+            Ok(None) => continue,
+            Err(err) => {
+                tracing::error!("Could not persist todos: {err}");
+                continue;
+            }
+        };
+        let written = tokio::task::spawn_blocking(move || {
+            let result = store.write(&pending);
+            (store, pending.version, result)
+        })
+        .await;
+        let (returned, version, result) = match written {
+            Ok(written) => written,
+            Err(err) => {
+                tracing::error!("Todos persistence stopped: {err}");
+                break;
+            }
+        };
+        store = returned;
+        match result {
+            Ok(()) => state.write().await.todos.set_persisted(version),
+            Err(err) => tracing::error!("Could not persist todos: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EventStore;
+    use crate::todos::{history::Change, state::DEFAULT_LIST_ID};
+
+    #[test]
+    fn test_persist_and_load() {
+        let dir = std::env::temp_dir().join(format!("todos-events-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut store = EventStore::new(&dir);
+        let mut state = store.load().unwrap();
+        for i in 0..150 {
+            state.add_todo(DEFAULT_LIST_ID, &format!("todo {i}"));
+        }
+        state.toggle_todo(3);
+        state.record_change("alice", 3, Change::Created);
+        store.persist(&mut state).unwrap();
+        let version = state.version();
+        state.delete_todo(4);
+        state.record_change("bob", 4, Change::Deleted);
+        store.persist(&mut state).unwrap();
+        // Persisting twice does not duplicate events
+        store.persist(&mut state).unwrap();
+
+        let loaded = EventStore::new(&dir).load().unwrap();
+        assert_eq!(
+            serde_json::to_value(&loaded).unwrap(),
+            serde_json::to_value(&state).unwrap()
+        );
+        assert_eq!(loaded.history(), state.history());
+        assert_eq!(loaded.history().latest().count(), 2);
+        assert!(dir.join("snapshot.json").exists());
+        // Only the events recorded after the snapshot are loaded
+        let seqs: Vec<_> = loaded.events().iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, vec![version + 1, version + 2]);
+
+        // Past states are replayed from disk
+        let log = store.log();
+        let past = log.replay(&loaded, |e| e.seq <= 3).unwrap();
+        assert_eq!(past.todos().len(), 3);
+        let events = log.events_after(&loaded, version).unwrap();
+        assert_eq!(events, loaded.events());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,38 +1,134 @@
 use axum::{
     Json,
-    extract::{Path, State},
-    http::{HeaderMap, StatusCode},
-    response::IntoResponse,
+    extract::{
+        FromRequest, Multipart, Path, Query, Request, State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    http::{
+        HeaderMap, StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE, ETAG},
+    },
+    response::{IntoResponse, Redirect, Response},
 };
-use maud::{DOCTYPE, html};
-use serde::Deserialize;
+use futures_util::{SinkExt, StreamExt};
+use jiff::{Timestamp, Zoned};
+use maud::{DOCTYPE, Markup, html};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use utoipa::ToSchema;
 
 use crate::{
-    ApiState,
-    todos::templates::{todo_form, todos_view},
-    utils::ContentNegotiator,
+    ApiState, AppState,
+    todos::{
+        calendar::{self, Component},
+        history::{Change, HistoryEntry},
+        idempotency::{UsedKey, request_hash},
+        recurrence::{Recurrence, RepeatKind},
+        state::{
+            BulkOperation, BulkSummary, DEFAULT_LIST_ID, ListSummary, NewTodo, Placement, Priority,
+            Todo, TodoStatus, TodosEvent, TodosQuery, TodosState, TrashedTodo, parse_due,
+        },
+        tags::{TagError, TagSummary, normalize_tag, parse_tags},
+        templates::{
+            activity_view, board_sync, board_url, calendar_import_result, history_drawer,
+            history_view, idempotency_key_input, import_done, import_preview, list_url,
+            lists_switcher, reminder_toast, tags_cloud, tags_table, toasts, todo_form, todos_board,
+            todos_bulk_form, todos_filter, todos_refresh, todos_reorder_form, todos_sync,
+            todos_view, transfer_form, trash_view, undo_toast, username_input,
+        },
+        transfer::{Format, ImportError, TodoRecord, export, parse},
+    },
+    utils::{Actor, ContentNegotiator, empty_string_as_none, if_match, is_htmx_request},
 };
 
-use super::templates::todo_view;
+use super::templates::{todo_view, todo_view_expanded};
 
-pub async fn get_todos(State(state): State<ApiState>, headers: HeaderMap) -> impl IntoResponse {
+/// Todos of the default list, filtered and sorted.
+#[utoipa::path(
+    get,
+    path = "/todos",
+    tag = "todos",
+    params(TodosQuery),
+    responses((status = OK, description = "Todos of the default list", body = [Todo])),
+)]
+pub async fn get_todos(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    actor: Actor,
+    Query(query): Query<TodosQuery>,
+) -> Response {
+    let state = state.read().await;
+    render_list_todos(&state, DEFAULT_LIST_ID, &query, &headers, &actor)
+}
+
+pub async fn get_list_todos(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    actor: Actor,
+    Path((list_id,)): Path<(usize,)>,
+    Query(query): Query<TodosQuery>,
+) -> Response {
     let state = state.read().await;
+    if state.todos.list(list_id).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    render_list_todos(&state, list_id, &query, &headers, &actor)
+}
+
+/// The todos of a list, along with the refreshed lists and tags counters.
+fn list_fragment(state: &AppState, list_id: usize, query: &TodosQuery) -> Markup {
+    let lists = state.todos.list_summaries();
+    let tags = state.todos.tag_summaries(Some(list_id));
+    html! {
+        (todos_view(&state.todos.query(list_id, query)))
+        (lists_switcher(&lists, list_id, true))
+        (tags_cloud(&tags, &query.tag, true))
+    }
+}
+
+fn render_list_todos(
+    state: &AppState,
+    list_id: usize,
+    query: &TodosQuery,
+    headers: &HeaderMap,
+    Actor(actor): &Actor,
+) -> Response {
+    let todos = state.todos.query(list_id, query);
+    let lists = state.todos.list_summaries();
+    let tags = state.todos.tag_summaries(Some(list_id));
 
     match headers.get("Accept").and_then(|h| h.to_str().ok()) {
-        Some("application/json") => Json(state.todos.todos()).into_response(),
+        Some("application/json") => Json(todos).into_response(),
+        _ if is_htmx_request(headers) => list_fragment(state, list_id, query).into_response(),
         _ => html! {
             (DOCTYPE)
             html {
                 head {
                     script src="/assets/htmx.min.js" {}
+                    script src="/assets/ws.min.js" {}
                     link href="/assets/style/output.css" rel="stylesheet";
                 }
-                body.flex.flex-col {
-                    h1.text-2xl.text-center.mt-2 { "A basic todos app" }
-                    div.self-center.pt-8 {
-                        (todo_form())
-                    }
-                    (todos_view(state.todos.todos()))
+                body hx-ext="ws" ws-connect="/todos/ws" {
+                    (history_drawer(html! {
+                        h1.text-2xl.text-center.mt-2 { "A basic todos app" }
+                        (username_input(actor))
+                        (lists_switcher(&lists, list_id, false))
+                        div.self-center.pt-8 {
+                            (todo_form(list_id))
+                        }
+                        (todos_filter(list_id, query, &tags))
+                        div.flex.gap-4.self-center.mt-2 {
+                            a.link.link-hover href=(board_url(list_id)) { "Board view" }
+                            a.link.link-hover href="/todos/trash" { "Trash" }
+                            a.link.link-hover href="/activity" { "Activity" }
+                        }
+                        (transfer_form(list_id))
+                        (todos_bulk_form(list_id))
+                        (todos_view(&todos))
+                        (todos_reorder_form())
+                        (todos_sync(list_id))
+                        (toasts())
+                    }))
                 }
             }
         }
@@ -40,50 +136,1728 @@ pub async fn get_todos(State(state): State<ApiState>, headers: HeaderMap) -> imp
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+pub async fn get_board(State(state): State<ApiState>, headers: HeaderMap) -> Response {
+    let state = state.read().await;
+    render_board(&state, DEFAULT_LIST_ID, &headers)
+}
+
+pub async fn get_list_board(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((list_id,)): Path<(usize,)>,
+) -> Response {
+    let state = state.read().await;
+    if state.todos.list(list_id).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    render_board(&state, list_id, &headers)
+}
+
+/// A board column, with its todos in manual order.
+#[derive(Debug, Clone, Serialize)]
+pub struct BoardColumn<'a> {
+    pub status: TodoStatus,
+    pub todos: Vec<&'a Todo>,
+}
+
+fn render_board(state: &AppState, list_id: usize, headers: &HeaderMap) -> Response {
+    let todos = state.todos.query(list_id, &TodosQuery::default());
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => {
+            let columns: Vec<BoardColumn> = TodoStatus::VARIANTS
+                .into_iter()
+                .map(|status| BoardColumn {
+                    status,
+                    todos: todos
+                        .iter()
+                        .copied()
+                        .filter(|t| t.status == status)
+                        .collect(),
+                })
+                .collect();
+            Json(columns).into_response()
+        }
+        _ if is_htmx_request(headers) => todos_board(&todos).into_response(),
+        _ => {
+            let name = state.todos.list(list_id).map(|l| l.name.as_str());
+            html! {
+                (DOCTYPE)
+                html {
+                    head {
+                        script src="/assets/htmx.min.js" {}
+                        script src="/assets/ws.min.js" {}
+                        link href="/assets/style/output.css" rel="stylesheet";
+                    }
+                    body.flex.flex-col hx-ext="ws" ws-connect="/todos/ws" {
+                        h1.text-2xl.text-center.mt-2 { (name.unwrap_or_default()) " board" }
+                        a.link.link-hover.self-center.mt-2 href=(list_url(list_id)) { "List view" }
+                        (todos_board(&todos))
+                        (board_sync(list_id))
+                        (toasts())
+                    }
+                }
+            }
+            .into_response()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct CreateTodoRequest {
     pub content: String,
+    pub list_id: Option<usize>,
+    pub priority: Option<Priority>,
+    /// A zoned datetime, an RFC 3339 timestamp, or a local datetime in the
+    /// server time zone.
+    #[schema(example = "2025-03-30T09:00[Europe/Paris]")]
+    pub due: Option<String>,
+    /// How long before `due` to be reminded of the todo.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub reminder_minutes: Option<u32>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub repeat: Option<RepeatKind>,
+    /// Day of the month for monthly todos, number of days for todos
+    /// repeating after completion.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub repeat_every: Option<u32>,
+    /// Comma separated weekdays for weekly todos, e.g. `mon,thu`.
+    pub repeat_weekdays: Option<String>,
+    /// Same as the `Idempotency-Key` header, for forms.
+    #[serde(skip_serializing)]
+    pub idempotency_key: Option<String>,
 }
 
+/// Create a todo, inline `#tags` in its content are added to it.
+#[utoipa::path(
+    post,
+    path = "/todo",
+    tag = "todos",
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Retrying with the same key returns the todo created the first time"),
+    ),
+    request_body = CreateTodoRequest,
+    responses(
+        (status = OK, description = "The created todo", body = Todo, headers(("ETag" = String))),
+        (status = GONE, description = "The todo created with this idempotency key was deleted since"),
+        (status = NOT_FOUND, description = "The list does not exist"),
+        (status = UNPROCESSABLE_ENTITY, description = "Empty content, invalid due date or recurrence, or an idempotency key used for another request"),
+    ),
+)]
 pub async fn create_todo(
     State(state): State<ApiState>,
     headers: HeaderMap,
+    Actor(actor): Actor,
     ContentNegotiator(payload): ContentNegotiator<CreateTodoRequest>,
 ) -> impl IntoResponse {
-    let content = payload.content;
+    let mut state = state.write().await;
+    let state = &mut *state;
+    let todo_id = match add_todo(state, &actor, &headers, payload) {
+        Ok(AddedTodo::Created(todo_id)) => todo_id,
+        Ok(AddedTodo::Replayed(todo_id)) => {
+            return replayed_todo(&headers, todo_id.and_then(|id| state.todos.todo(id)));
+        }
+        Err(status) => return status.into_response(),
+    };
+    let Some(todo) = state.todos.todo(todo_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let etag = [(ETAG, todo_etag(todo))];
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => (etag, Json(todo)).into_response(),
+        // Pages refresh their todos rather than appending the new one, which
+        // may not match their filters nor go last in their order
+        _ => (
+            etag,
+            [("HX-Trigger", "todos-changed")],
+            idempotency_key_input(true),
+        )
+            .into_response(),
+    }
+}
+
+/// Outcome of [`add_todo`].
+pub(crate) enum AddedTodo {
+    Created(usize),
+    /// The todo created the first time with the same idempotency key, `None`
+    /// when it was deleted since.
+    Replayed(Option<usize>),
+}
+
+/// Create a todo from a request, recording it in the history and notifying
+/// the connected pages. Shared by the htmx and the `/api/v1` routes.
+pub(crate) fn add_todo(
+    state: &mut AppState,
+    actor: &str,
+    headers: &HeaderMap,
+    payload: CreateTodoRequest,
+) -> Result<AddedTodo, StatusCode> {
+    let request_hash = request_hash(&payload);
+    let (content, tags) = parse_tags(&payload.content);
     if content.is_empty() {
-        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
     }
+    let due = match payload.due.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(due) => Some(parse_due(due).map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?),
+    };
+    let reminder_minutes = payload.reminder_minutes.filter(|_| due.is_some());
+    let recurrence = match payload.repeat {
+        None => None,
+        Some(kind) => Some(
+            Recurrence::from_form(
+                kind,
+                payload.repeat_every,
+                payload.repeat_weekdays.as_deref(),
+                due.as_ref(),
+                &Zoned::now(),
+            )
+            .map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?,
+        ),
+    };
 
-    let mut state = state.write().await;
-    let todo = state.todos.add_todo(&content);
+    let idempotency_key = headers
+        .get("Idempotency-Key")
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_owned)
+        .or(payload.idempotency_key)
+        .filter(|key| !key.trim().is_empty());
+    let now = Timestamp::now();
+    let used = idempotency_key
+        .as_deref()
+        .and_then(|key| state.todos.idempotent_todo(actor, key, &request_hash, now));
+    match used {
+        Some(UsedKey::Replay(todo_id)) => {
+            let todo_id = state.todos.todo(todo_id).map(|t| t.id);
+            return Ok(AddedTodo::Replayed(todo_id));
+        }
+        Some(UsedKey::Mismatch) => return Err(StatusCode::UNPROCESSABLE_ENTITY),
+        None => {}
+    }
+
+    let list_id = payload.list_id.unwrap_or(DEFAULT_LIST_ID);
+    let todo = NewTodo {
+        content,
+        due,
+        reminder_minutes,
+        tags,
+        priority: payload.priority.unwrap_or_default(),
+        recurrence,
+    };
+    let todo_id = state
+        .todos
+        .create_todo(list_id, todo)
+        .ok_or(StatusCode::NOT_FOUND)?
+        .id;
+    if let Some(key) = &idempotency_key {
+        state
+            .todos
+            .remember_idempotency_key(actor, key, &request_hash, todo_id, now);
+    }
+    state.todos.record_change(actor, todo_id, Change::Created);
+    let _ = state.todos_events.send(TodosEvent::Created(todo_id));
+    Ok(AddedTodo::Created(todo_id))
+}
+
+/// Response to a creation retried with the same idempotency key: the todo
+/// created the first time, as it is now. Pages refresh their todos, as for
+/// new ones.
+fn replayed_todo(headers: &HeaderMap, todo: Option<&Todo>) -> Response {
+    let Some(todo) = todo else {
+        // Deleted since it was created
+        return StatusCode::GONE.into_response();
+    };
+    let etag = [(ETAG, todo_etag(todo))];
+    let replayed = [("Idempotent-Replayed", "true")];
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => (etag, replayed, Json(todo)).into_response(),
+        _ => (
+            etag,
+            replayed,
+            [("HX-Trigger", "todos-changed")],
+            idempotency_key_input(true),
+        )
+            .into_response(),
+    }
+}
+
+pub(crate) fn todo_etag(todo: &Todo) -> String {
+    format!("\"{}\"", todo.version)
+}
+
+/// `412 Precondition Failed` when the request was made against an outdated
+/// version of the todo. htmx clients refresh their todos to catch up.
+fn outdated_todo(headers: &HeaderMap, todo: &Todo) -> Option<Response> {
+    if if_match(headers, &todo_etag(todo)) {
+        return None;
+    }
+    let response = (
+        StatusCode::PRECONDITION_FAILED,
+        [(ETAG, todo_etag(todo))],
+        [("HX-Trigger", "todos-changed")],
+    );
+    Some(response.into_response())
+}
 
+/// A todo, along with its version as ETag.
+#[utoipa::path(
+    get,
+    path = "/todo/{id}",
+    tag = "todos",
+    params(("id" = usize, Path)),
+    responses(
+        (status = OK, body = Todo, headers(("ETag" = String))),
+        (status = NOT_FOUND, description = "The todo does not exist"),
+    ),
+)]
+pub async fn get_todo(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((id,)): Path<(usize,)>,
+) -> Response {
+    let state = state.read().await;
+    let Some(todo) = state.todos.todo(id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let etag = [(ETAG, todo_etag(todo))];
     match headers.get("Accept").and_then(|h| h.to_str().ok()) {
-        Some("application/json") => Json(todo).into_response(),
-        _ => todo_view(todo).into_response(),
+        Some("application/json") => (etag, Json(todo)).into_response(),
+        _ => (etag, todo_view(todo)).into_response(),
     }
 }
 
+/// Toggle a todo between done and open.
+#[utoipa::path(
+    post,
+    path = "/todo/{id}/toggle",
+    tag = "todos",
+    params(("id" = usize, Path), ("If-Match" = Option<String>, Header, description = "Only apply the change if the todo is still at this ETag"),),
+    responses(
+        (status = OK, description = "The toggled todo", body = Todo, headers(("ETag" = String))),
+        (status = NOT_FOUND, description = "The todo does not exist"),
+        (status = PRECONDITION_FAILED, description = "The todo changed since", headers(("ETag" = String))),
+    ),
+)]
 pub async fn toggle_todo(
     State(state): State<ApiState>,
     headers: HeaderMap,
+    Actor(actor): Actor,
     Path((id,)): Path<(usize,)>,
 ) -> impl IntoResponse {
     let mut state = state.write().await;
-    let Some(todo) = state.todos.toggle_todo(id) else {
+    let state = &mut *state;
+    let Some(before) = state.todos.todo(id).cloned() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if let Some(response) = outdated_todo(&headers, &before) {
+        return response;
+    }
+    let Some((_, next)) = state.todos.toggle_todo(id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let next = next.map(|next| next.id);
+    state.todos.record_diff(&actor, &before);
+    let _ = state.todos_events.send(TodosEvent::Updated(id));
+    record_next(state, &actor, next);
+
+    status_changed_response(&headers, &state.todos, id, next)
+}
+
+/// Record the next occurrence of a recurring todo, if one was created.
+fn record_next(state: &mut AppState, actor: &str, next: Option<usize>) {
+    if let Some(next) = next {
+        state.todos.record_change(actor, next, Change::Created);
+        let _ = state.todos_events.send(TodosEvent::Created(next));
+    }
+}
+
+fn status_changed_response(
+    headers: &HeaderMap,
+    todos: &TodosState,
+    id: usize,
+    next: Option<usize>,
+) -> Response {
+    let Some(todo) = todos.todo(id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let next = next.and_then(|next| todos.todo(next));
+    let etag = [(ETAG, todo_etag(todo))];
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => (etag, Json(todo)).into_response(),
+        // The next occurrence of a recurring todo is shown right after it
+        _ => (
+            etag,
+            html! {
+                (todo_view(todo))
+                @if let Some(next) = next {
+                    (todo_view(next))
+                }
+            },
+        )
+            .into_response(),
+    }
+}
+
+/// Mark a todo as done or not. Unlike toggling it, sending the same request
+/// several times has the same effect as sending it once, so that it is safe
+/// to retry.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct SetDoneRequest {
+    pub done: bool,
+}
+
+#[utoipa::path(
+    put,
+    path = "/todo/{id}/done",
+    tag = "todos",
+    params(("id" = usize, Path), ("If-Match" = Option<String>, Header, description = "Only apply the change if the todo is still at this ETag"),),
+    request_body = SetDoneRequest,
+    responses(
+        (status = OK, description = "The todo, unchanged if it already had the requested state", body = Todo, headers(("ETag" = String))),
+        (status = NOT_FOUND, description = "The todo does not exist"),
+        (status = PRECONDITION_FAILED, description = "The todo changed since", headers(("ETag" = String))),
+    ),
+)]
+pub async fn set_todo_done(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+    Path((id,)): Path<(usize,)>,
+    ContentNegotiator(payload): ContentNegotiator<SetDoneRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    let Some(before) = state.todos.todo(id).cloned() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if let Some(response) = outdated_todo(&headers, &before) {
+        return response;
+    }
+    let status = match (payload.done, before.is_done()) {
+        (true, _) => TodoStatus::Done,
+        (false, true) => TodoStatus::Backlog,
+        // Open todos keep their status
+        (false, false) => before.status,
+    };
+    let Some((todo, next)) = state.todos.set_status(id, status) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let changed = todo.version != before.version;
+    let next = next.map(|next| next.id);
+    if changed {
+        state.todos.record_diff(&actor, &before);
+        let _ = state.todos_events.send(TodosEvent::Updated(id));
+    }
+    record_next(state, &actor, next);
+
+    status_changed_response(&headers, &state.todos, id, next)
+}
+
+/// Edit the content of a todo, and/or change its status and priority.
+/// Inline `#tags` in the new content are added to the todo.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct UpdateTodoRequest {
+    pub content: Option<String>,
+    pub status: Option<TodoStatus>,
+    pub priority: Option<Priority>,
+}
+
+/// Edit the content, status or priority of a todo.
+#[utoipa::path(
+    patch,
+    path = "/todo/{id}",
+    tag = "todos",
+    params(("id" = usize, Path), ("If-Match" = Option<String>, Header, description = "Only apply the change if the todo is still at this ETag"),),
+    request_body = UpdateTodoRequest,
+    responses(
+        (status = OK, description = "The updated todo", body = Todo, headers(("ETag" = String))),
+        (status = NOT_FOUND, description = "The todo does not exist"),
+        (status = PRECONDITION_FAILED, description = "The todo changed since", headers(("ETag" = String))),
+        (status = UNPROCESSABLE_ENTITY, description = "Empty content, or nothing to update"),
+    ),
+)]
+pub async fn update_todo(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+    Path((id,)): Path<(usize,)>,
+    ContentNegotiator(payload): ContentNegotiator<UpdateTodoRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    if let Err(status) = edit_todo(state, &actor, &headers, id, payload) {
+        return rejected_todo(state, &headers, id, status);
+    }
+    let Some(todo) = state.todos.todo(id) else {
         return StatusCode::NOT_FOUND.into_response();
     };
 
+    let etag = [(ETAG, todo_etag(todo))];
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => (etag, Json(todo)).into_response(),
+        _ => (
+            StatusCode::NO_CONTENT,
+            etag,
+            [("HX-Trigger", "todos-changed")],
+        )
+            .into_response(),
+    }
+}
+
+/// Apply an [`UpdateTodoRequest`] to a todo, recording the change in the
+/// history and notifying the connected pages.
+pub(crate) fn edit_todo(
+    state: &mut AppState,
+    actor: &str,
+    headers: &HeaderMap,
+    id: usize,
+    payload: UpdateTodoRequest,
+) -> Result<(), StatusCode> {
+    let content = payload.content.as_deref().map(parse_tags);
+    if content
+        .as_ref()
+        .is_some_and(|(content, _)| content.is_empty())
+        || (content.is_none() && payload.status.is_none() && payload.priority.is_none())
+    {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let before = state.todos.todo(id).cloned().ok_or(StatusCode::NOT_FOUND)?;
+    if !if_match(headers, &todo_etag(&before)) {
+        return Err(StatusCode::PRECONDITION_FAILED);
+    }
+    if let Some((content, tags)) = content {
+        state.todos.set_content(id, &content);
+        state.todos.add_tags(id, &tags);
+    }
+    if let Some(priority) = payload.priority {
+        state.todos.set_priority(id, priority);
+    }
+    if let Some(status) = payload.status
+        && let Some((_, Some(next))) = state.todos.set_status(id, status)
+    {
+        let next = next.id;
+        record_next(state, actor, Some(next));
+    }
+    state.todos.todo(id).ok_or(StatusCode::NOT_FOUND)?;
+    state.todos.record_diff(actor, &before);
+    let _ = state.todos_events.send(TodosEvent::Updated(id));
+    Ok(())
+}
+
+/// Move a todo to the trash, recording it in the history and notifying the
+/// connected pages.
+pub(crate) fn trash_todo(
+    state: &mut AppState,
+    actor: &str,
+    headers: &HeaderMap,
+    id: usize,
+) -> Result<TrashedTodo, StatusCode> {
+    let todo = state.todos.todo(id).ok_or(StatusCode::NOT_FOUND)?;
+    if !if_match(headers, &todo_etag(todo)) {
+        return Err(StatusCode::PRECONDITION_FAILED);
+    }
+    let trashed = state
+        .todos
+        .delete_todo(id)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    state.todos.record_change(actor, id, Change::Deleted);
+    let _ = state
+        .todos_events
+        .send(TodosEvent::Deleted(trashed.todo.id));
+    Ok(trashed)
+}
+
+/// Response to a change of a todo that could not be applied, outdated
+/// requests get the current ETag of the todo.
+fn rejected_todo(state: &AppState, headers: &HeaderMap, id: usize, status: StatusCode) -> Response {
+    match state.todos.todo(id) {
+        Some(todo) if status == StatusCode::PRECONDITION_FAILED => {
+            outdated_todo(headers, todo).unwrap_or_else(|| status.into_response())
+        }
+        _ => status.into_response(),
+    }
+}
+
+fn deleted_message(count: usize) -> String {
+    match count {
+        1 => "Todo deleted".to_owned(),
+        count => format!("{count} todos deleted"),
+    }
+}
+
+/// Todos are moved to the trash, and can be restored from there.
+#[utoipa::path(
+    delete,
+    path = "/todo/{id}",
+    tag = "todos",
+    params(("id" = usize, Path), ("If-Match" = Option<String>, Header, description = "Only apply the change if the todo is still at this ETag"),),
+    responses(
+        (status = OK, description = "The trashed todo", body = TrashedTodo),
+        (status = NOT_FOUND, description = "The todo does not exist"),
+        (status = PRECONDITION_FAILED, description = "The todo changed since", headers(("ETag" = String))),
+    ),
+)]
+pub async fn delete_todo(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+    Path((id,)): Path<(usize,)>,
+) -> Response {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    let trashed = match trash_todo(state, &actor, &headers, id) {
+        Ok(trashed) => trashed,
+        Err(status) => return rejected_todo(state, &headers, id, status),
+    };
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(trashed).into_response(),
+        // The deleted row is replaced by nothing
+        _ => undo_toast(&deleted_message(1), &[trashed.todo.id]).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/todo/{id}/history",
+    tag = "todos",
+    params(("id" = usize, Path)),
+    responses(
+        (status = OK, description = "Changes made to the todo, oldest first", body = [HistoryEntry]),
+        (status = NOT_FOUND, description = "The todo does not exist, and never had changes recorded"),
+    ),
+)]
+pub async fn get_todo_history(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((id,)): Path<(usize,)>,
+) -> Response {
+    let state = state.read().await;
+    let entries = state.todos.history().for_todo(id);
+    let todo = state.todos.todo(id);
+    // Deleted todos keep their history
+    if todo.is_none() && entries.is_empty() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(entries).into_response(),
+        _ => history_view(id, todo, &entries).into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ActivityQuery {
+    /// Only keep the changes made by this actor.
+    pub actor: Option<String>,
+    #[serde(default = "ActivityQuery::default_limit")]
+    pub limit: usize,
+}
+
+impl ActivityQuery {
+    fn default_limit() -> usize {
+        100
+    }
+}
+
+pub async fn get_activity(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Query(query): Query<ActivityQuery>,
+) -> Response {
+    let state = state.read().await;
+    let actor = query.actor.as_deref().filter(|actor| !actor.is_empty());
+    let entries: Vec<_> = state
+        .todos
+        .history()
+        .latest()
+        .filter(|e| actor.is_none_or(|actor| e.actor == actor))
+        .take(query.limit)
+        .collect();
+
     match headers.get("Accept").and_then(|h| h.to_str().ok()) {
-        Some("application/json") => Json(todo).into_response(),
-        _ => todo_view(todo).into_response(),
+        Some("application/json") => Json(entries).into_response(),
+        _ => html! {
+            (DOCTYPE)
+            html {
+                head {
+                    script src="/assets/htmx.min.js" {}
+                    link href="/assets/style/output.css" rel="stylesheet";
+                }
+                body.flex.flex-col.items-center {
+                    h1.text-2xl.text-center.mt-2 { "Activity" }
+                    a.link.link-hover.my-4 href="/todos" { "Back to todos" }
+                    (activity_view(&entries))
+                }
+            }
+        }
+        .into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventsQuery {
+    /// Only return the events recorded after this version.
+    #[serde(default)]
+    pub after: u64,
+}
+
+/// The todos events log, so that clients can catch up from the last version
+/// they have seen. Older events are read from disk when the todos are
+/// persisted, and are gone otherwise.
+pub async fn get_events(
+    State(state): State<ApiState>,
+    Query(query): Query<EventsQuery>,
+) -> Response {
+    let state = state.read().await;
+    if let Some(events) = state.todos.events_after(query.after) {
+        return Json(events).into_response();
     }
+    let Some(log) = &state.todos_log else {
+        return StatusCode::GONE.into_response();
+    };
+    match log.events_after(&state.todos, query.after) {
+        Ok(events) => Json(events).into_response(),
+        Err(err) => {
+            tracing::error!("Could not read the todos events: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplayQuery {
+    pub at: Option<Timestamp>,
+    pub version: Option<u64>,
+}
+
+/// The todos state as it was at a given time or version, the latest one if
+/// none is given. Past states are replayed from disk when the todos are
+/// persisted, and are gone otherwise once their events left memory.
+pub async fn replay_todos(
+    State(state): State<ApiState>,
+    Query(query): Query<ReplayQuery>,
+) -> Response {
+    let state = state.read().await;
+    let todos = match (query.at, query.version) {
+        (Some(at), _) => state.todos.at(at),
+        (None, Some(version)) => state.todos.at_version(version),
+        (None, None) => Some(state.todos.clone()),
+    };
+    if let Some(todos) = todos {
+        return Json(todos).into_response();
+    }
+    let Some(log) = &state.todos_log else {
+        return StatusCode::GONE.into_response();
+    };
+    let replayed = match (query.at, query.version) {
+        (Some(at), _) => log.replay(&state.todos, |e| e.recorded_at <= at),
+        (None, version) => log.replay(&state.todos, |e| version.is_none_or(|v| e.seq <= v)),
+    };
+    match replayed {
+        Ok(todos) => Json(todos).into_response(),
+        Err(err) => {
+            tracing::error!("Could not replay the todos: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportQuery {
+    pub format: Format,
+    pub list_id: Option<usize>,
+}
+
+pub async fn export_todos(
+    State(state): State<ApiState>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let state = state.read().await;
+    let list_id = query.list_id.unwrap_or(DEFAULT_LIST_ID);
+    if state.todos.list(list_id).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let todos = state.todos.query(list_id, &TodosQuery::default());
+    let format = query.format;
+    (
+        [
+            (CONTENT_TYPE, format.content_type().to_owned()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", format.file_name()),
+            ),
+        ],
+        export(format, &todos),
+    )
+        .into_response()
+}
+
+/// Fields of the import form, either an uploaded `file` or its `data`
+/// posted back from the preview.
+#[derive(Debug, Default)]
+struct ImportForm {
+    data: Option<String>,
+    file_name: Option<String>,
+    format: Option<Format>,
+    list_id: Option<usize>,
+    dry_run: bool,
+}
+
+async fn read_import_form(mut multipart: Multipart) -> Result<ImportForm, StatusCode> {
+    let mut form = ImportForm::default();
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST)?
+    {
+        let name = field.name().unwrap_or_default().to_owned();
+        let file_name = field.file_name().map(str::to_owned);
+        let value = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+        match name.as_str() {
+            "file" | "data" => {
+                form.data = Some(value);
+                form.file_name = file_name;
+            }
+            "format" if !value.trim().is_empty() => {
+                form.format = Some(Format::parse(&value).ok_or(StatusCode::UNPROCESSABLE_ENTITY)?);
+            }
+            "list_id" => {
+                form.list_id = Some(
+                    value
+                        .trim()
+                        .parse()
+                        .map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?,
+                );
+            }
+            "dry_run" => form.dry_run = value.trim() == "true",
+            _ => {}
+        }
+    }
+    Ok(form)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub todos: Vec<TodoRecord>,
+    pub errors: Vec<ImportError>,
+    /// IDs of the created todos, empty unless the import was committed.
+    pub imported: Vec<usize>,
 }
 
-pub async fn delete_todo(State(state): State<ApiState>, Path((id,)): Path<(usize,)>) -> StatusCode {
+/// Import an uploaded file. Nothing is imported when `dry_run` is set or when
+/// some lines are invalid, the parsed todos and errors are returned instead
+/// so that they can be reviewed.
+pub async fn import_todos(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+    multipart: Multipart,
+) -> Response {
+    let form = match read_import_form(multipart).await {
+        Ok(form) => form,
+        Err(status) => return status.into_response(),
+    };
+    let Some(data) = form.data else {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    };
+    let Some(format) = form
+        .format
+        .or_else(|| form.file_name.as_deref().and_then(Format::from_file_name))
+    else {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    };
+    let (records, errors) = parse(format, &data);
+
     let mut state = state.write().await;
-    let Some(_todo) = state.todos.delete_todo(id) else {
-        return StatusCode::NOT_FOUND;
+    let state = &mut *state;
+    let list_id = form.list_id.unwrap_or(DEFAULT_LIST_ID);
+    if state.todos.list(list_id).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let commit = !form.dry_run && errors.is_empty();
+    let imported = match commit {
+        true => state
+            .todos
+            .import_todos(list_id, &records)
+            .unwrap_or_default(),
+        false => Vec::new(),
+    };
+    if commit {
+        for &id in &imported {
+            state.todos.record_change(&actor, id, Change::Created);
+        }
+        let _ = state.todos_events.send(TodosEvent::Bulk(BulkSummary {
+            created: imported.clone(),
+            ..BulkSummary::default()
+        }));
+    }
+
+    let status = match form.dry_run || errors.is_empty() {
+        true => StatusCode::OK,
+        false => StatusCode::UNPROCESSABLE_ENTITY,
+    };
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => (
+            status,
+            Json(ImportReport {
+                dry_run: form.dry_run,
+                todos: records,
+                errors,
+                imported,
+            }),
+        )
+            .into_response(),
+        // htmx does not swap error responses by default
+        _ if !commit => import_preview(list_id, format, &data, &records, &errors).into_response(),
+        _ => (
+            [("HX-Trigger", "todos-changed")],
+            import_done(imported.len()),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CalendarQuery {
+    /// All lists when not set.
+    pub list_id: Option<usize>,
+    #[serde(default)]
+    pub component: Component,
+}
+
+/// The todos with a due date, as a feed calendar clients can subscribe to.
+pub async fn get_calendar(
+    State(state): State<ApiState>,
+    Query(query): Query<CalendarQuery>,
+) -> Response {
+    let state = state.read().await;
+    let todos = match query.list_id {
+        Some(list_id) if state.todos.list(list_id).is_none() => {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        Some(list_id) => state.todos.query(list_id, &TodosQuery::default()),
+        None => state.todos.todos().iter().collect(),
     };
-    StatusCode::OK
+    (
+        [(CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        calendar::export(&state.todos, &todos, query.component, Timestamp::now()),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CalendarImportReport {
+    pub created: Vec<usize>,
+    pub updated: Vec<usize>,
+    pub errors: Vec<ImportError>,
+}
+
+/// Create or update todos from a calendar, sent either as the request body
+/// or as the `file` of a form. Nothing is changed when some items are
+/// invalid.
+pub async fn import_calendar(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+    Query(query): Query<CalendarQuery>,
+    request: Request,
+) -> Response {
+    let is_form = headers
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| h.starts_with("multipart/form-data"));
+    let (data, list_id) = match is_form {
+        true => {
+            let form = match Multipart::from_request(request, &()).await {
+                Ok(multipart) => read_import_form(multipart).await,
+                Err(_) => Err(StatusCode::BAD_REQUEST),
+            };
+            match form {
+                Ok(form) => (form.data, form.list_id.or(query.list_id)),
+                Err(status) => return status.into_response(),
+            }
+        }
+        false => (String::from_request(request, &()).await.ok(), query.list_id),
+    };
+    let Some(data) = data else {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    };
+    let (items, errors) = calendar::parse(&data);
+
+    let mut state = state.write().await;
+    let state = &mut *state;
+    let list_id = list_id.unwrap_or(DEFAULT_LIST_ID);
+    if state.todos.list(list_id).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let summary = match errors.is_empty() {
+        true => {
+            let before: Vec<Todo> = items
+                .iter()
+                .filter_map(|item| state.todos.calendar_todo(&item.uid))
+                .filter_map(|todo_id| state.todos.todo(todo_id).cloned())
+                .collect();
+            let summary = state
+                .todos
+                .import_calendar(list_id, &items)
+                .unwrap_or_default();
+            record_bulk(state, &actor, &before, &summary);
+            let _ = state.todos_events.send(TodosEvent::Bulk(summary.clone()));
+            summary
+        }
+        false => BulkSummary::default(),
+    };
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => {
+            let status = match errors.is_empty() {
+                true => StatusCode::OK,
+                false => StatusCode::UNPROCESSABLE_ENTITY,
+            };
+            let report = CalendarImportReport {
+                created: summary.created,
+                updated: summary.updated,
+                errors,
+            };
+            (status, Json(report)).into_response()
+        }
+        _ => (
+            [("HX-Trigger", "todos-changed")],
+            calendar_import_result(&summary, &errors),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn get_trash(State(state): State<ApiState>, headers: HeaderMap) -> Response {
+    let state = state.read().await;
+    let trash = state.todos.trash();
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(trash).into_response(),
+        _ => html! {
+            (DOCTYPE)
+            html {
+                head {
+                    script src="/assets/htmx.min.js" {}
+                    link href="/assets/style/output.css" rel="stylesheet";
+                }
+                body.flex.flex-col.items-center {
+                    h1.text-2xl.text-center.mt-2 { "Trash" }
+                    a.link.link-hover.my-4 href="/todos" { "Back to todos" }
+                    (trash_view(trash))
+                }
+            }
+        }
+        .into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RestoreRequest {
+    pub ids: Vec<usize>,
+}
+
+pub async fn restore_todos(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+    ContentNegotiator(payload): ContentNegotiator<RestoreRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    let Some(ids) = state.todos.restore(&payload.ids) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    for &id in &ids {
+        state.todos.record_change(&actor, id, Change::Restored);
+    }
+    let _ = state.todos_events.send(TodosEvent::Restored(ids.clone()));
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => {
+            let todos: Vec<&Todo> = state
+                .todos
+                .todos()
+                .iter()
+                .filter(|t| ids.contains(&t.id))
+                .collect();
+            Json(todos).into_response()
+        }
+        // Refresh the trash when restoring from there, and the todos list
+        // when undoing from a toast
+        _ => (
+            [("HX-Trigger", "todos-changed")],
+            trash_view(state.todos.trash()),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn empty_trash(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+) -> Response {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    let purged = state.todos.purge_trash(Timestamp::MAX);
+    for trashed in &purged {
+        state
+            .todos
+            .record_todo_change(&actor, &trashed.todo, Change::Purged);
+    }
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(purged).into_response(),
+        _ => trash_view(state.todos.trash()).into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CreateSubtaskRequest {
+    pub content: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/todo/{id}/subtasks",
+    tag = "todos",
+    params(("id" = usize, Path), ("If-Match" = Option<String>, Header, description = "Only apply the change if the todo is still at this ETag"),),
+    request_body = CreateSubtaskRequest,
+    responses(
+        (status = OK, description = "The todo with its new subtask", body = Todo, headers(("ETag" = String))),
+        (status = NOT_FOUND, description = "The todo does not exist"),
+        (status = PRECONDITION_FAILED, description = "The todo changed since", headers(("ETag" = String))),
+        (status = UNPROCESSABLE_ENTITY, description = "Empty content"),
+    ),
+)]
+pub async fn create_subtask(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((id,)): Path<(usize,)>,
+    ContentNegotiator(payload): ContentNegotiator<CreateSubtaskRequest>,
+) -> Response {
+    let content = payload.content.trim();
+    if content.is_empty() {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    }
+
+    let mut state = state.write().await;
+    let state = &mut *state;
+    let Some(todo) = state.todos.todo(id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if let Some(response) = outdated_todo(&headers, todo) {
+        return response;
+    }
+    let Some(todo) = state.todos.add_subtask(id, content) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let _ = state.todos_events.send(TodosEvent::Updated(todo.id));
+
+    subtasks_changed_response(&headers, todo)
+}
+
+fn subtasks_changed_response(headers: &HeaderMap, todo: &Todo) -> Response {
+    let etag = [(ETAG, todo_etag(todo))];
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => (etag, Json(todo)).into_response(),
+        _ => (etag, todo_view_expanded(todo)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/todo/{id}/subtasks/{subtask_id}/toggle",
+    tag = "todos",
+    params(("id" = usize, Path), ("subtask_id" = usize, Path), ("If-Match" = Option<String>, Header, description = "Only apply the change if the todo is still at this ETag"),),
+    responses(
+        (status = OK, description = "The todo, completed along with its last subtask when its list auto-completes todos", body = Todo, headers(("ETag" = String))),
+        (status = NOT_FOUND, description = "The todo or the subtask does not exist"),
+        (status = PRECONDITION_FAILED, description = "The todo changed since", headers(("ETag" = String))),
+    ),
+)]
+pub async fn toggle_subtask(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+    Path((id, subtask_id)): Path<(usize, usize)>,
+) -> Response {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    let Some(before) = state.todos.todo(id).cloned() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if let Some(response) = outdated_todo(&headers, &before) {
+        return response;
+    }
+    let Some((_, next)) = state.todos.toggle_subtask(id, subtask_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let next = next.map(|next| next.id);
+    // Records the todo completion when the list auto-completes todos
+    state.todos.record_diff(&actor, &before);
+    let _ = state.todos_events.send(TodosEvent::Updated(id));
+    record_next(state, &actor, next);
+    let (Some(todo), next) = (
+        state.todos.todo(id),
+        next.and_then(|next| state.todos.todo(next)),
+    ) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let etag = [(ETAG, todo_etag(todo))];
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => (etag, Json(todo)).into_response(),
+        _ => (
+            etag,
+            html! {
+                (todo_view_expanded(todo))
+                @if let Some(next) = next {
+                    (todo_view(next))
+                }
+            },
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/todo/{id}/subtasks/{subtask_id}",
+    tag = "todos",
+    params(("id" = usize, Path), ("subtask_id" = usize, Path), ("If-Match" = Option<String>, Header, description = "Only apply the change if the todo is still at this ETag"),),
+    responses(
+        (status = OK, description = "The todo without the subtask", body = Todo, headers(("ETag" = String))),
+        (status = NOT_FOUND, description = "The todo or the subtask does not exist"),
+        (status = PRECONDITION_FAILED, description = "The todo changed since", headers(("ETag" = String))),
+    ),
+)]
+pub async fn delete_subtask(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((id, subtask_id)): Path<(usize, usize)>,
+) -> Response {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    let Some(todo) = state.todos.todo(id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if let Some(response) = outdated_todo(&headers, todo) {
+        return response;
+    }
+    let Some(todo) = state.todos.delete_subtask(id, subtask_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let _ = state.todos_events.send(TodosEvent::Updated(todo.id));
+
+    subtasks_changed_response(&headers, todo)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BulkAction {
+    Complete,
+    Reopen,
+    Delete,
+    Tag,
+    /// Delete the done todos of the list, `ids` are ignored.
+    ClearCompleted,
+}
+
+/// An operation on the selected todos. The page also sends its current
+/// filters, so that the returned list matches them.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BulkRequest {
+    pub action: BulkAction,
+    #[serde(default)]
+    pub ids: Vec<usize>,
+    /// Tag added by the `tag` action.
+    pub add_tag: Option<String>,
+    pub list_id: Option<usize>,
+    #[serde(flatten)]
+    pub query: TodosQuery,
+}
+
+pub async fn bulk_todos(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+    ContentNegotiator(payload): ContentNegotiator<BulkRequest>,
+) -> Response {
+    let operation = match payload.action {
+        BulkAction::Complete => Some(BulkOperation::Complete),
+        BulkAction::Reopen => Some(BulkOperation::Reopen),
+        BulkAction::Delete => Some(BulkOperation::Delete),
+        BulkAction::Tag => match payload.add_tag.as_deref().and_then(normalize_tag) {
+            Some(name) => Some(BulkOperation::Tag(name)),
+            None => return StatusCode::UNPROCESSABLE_ENTITY.into_response(),
+        },
+        BulkAction::ClearCompleted => None,
+    };
+    if operation.is_some() && payload.ids.is_empty() {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    }
+
+    let mut state = state.write().await;
+    let state = &mut *state;
+    let list_id = payload.list_id.unwrap_or(DEFAULT_LIST_ID);
+    let before: Vec<Todo> = payload
+        .ids
+        .iter()
+        .filter_map(|id| state.todos.todo(*id).cloned())
+        .collect();
+    let summary = match operation {
+        Some(operation) => state.todos.bulk(&payload.ids, &operation),
+        None => state.todos.clear_completed(list_id),
+    };
+    let Some(summary) = summary else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    record_bulk(state, &actor, &before, &summary);
+    let _ = state.todos_events.send(TodosEvent::Bulk(summary.clone()));
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(summary).into_response(),
+        _ => html! {
+            (list_fragment(state, list_id, &payload.query))
+            @if !summary.deleted.is_empty() {
+                (undo_toast(&deleted_message(summary.deleted.len()), &summary.deleted))
+            }
+        }
+        .into_response(),
+    }
+}
+
+/// Record the changes made by a bulk operation, `before` holding the updated
+/// todos as they were.
+fn record_bulk(state: &mut AppState, actor: &str, before: &[Todo], summary: &BulkSummary) {
+    for todo in before.iter().filter(|t| summary.updated.contains(&t.id)) {
+        state.todos.record_diff(actor, todo);
+    }
+    for &id in &summary.created {
+        state.todos.record_change(actor, id, Change::Created);
+    }
+    for &id in &summary.deleted {
+        state.todos.record_change(actor, id, Change::Deleted);
+    }
+}
+
+/// Either a full ordering of todo IDs, or a single todo to move before/after
+/// another one.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReorderRequest {
+    pub ids: Option<Vec<usize>>,
+    pub id: Option<usize>,
+    pub placement: Option<Placement>,
+    pub target: Option<usize>,
+}
+
+pub async fn reorder_todos(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    ContentNegotiator(payload): ContentNegotiator<ReorderRequest>,
+) -> impl IntoResponse {
+    let mut state = state.write().await;
+    let state = &mut *state;
+
+    match payload {
+        ReorderRequest {
+            ids: Some(ids),
+            id: None,
+            placement: None,
+            target: None,
+        } => state.todos.reorder(&ids),
+        ReorderRequest {
+            ids: None,
+            id: Some(id),
+            placement: Some(placement),
+            target: Some(target),
+        } => {
+            if state.todos.move_todo(id, placement, target).is_none() {
+                return StatusCode::NOT_FOUND.into_response();
+            }
+        }
+        _ => return StatusCode::UNPROCESSABLE_ENTITY.into_response(),
+    }
+    let _ = state.todos_events.send(TodosEvent::Reordered);
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(state.todos.todos()).into_response(),
+        // The list is refreshed with the client's current filters
+        _ => (StatusCode::NO_CONTENT, [("HX-Trigger", "todos-changed")]).into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct MoveTodoRequest {
+    pub list_id: usize,
+}
+
+#[utoipa::path(
+    post,
+    path = "/todo/{id}/move",
+    tag = "todos",
+    params(("id" = usize, Path), ("If-Match" = Option<String>, Header, description = "Only apply the change if the todo is still at this ETag"),),
+    request_body = MoveTodoRequest,
+    responses(
+        (status = OK, description = "The moved todo, at the end of the list", body = Todo, headers(("ETag" = String))),
+        (status = NOT_FOUND, description = "The todo or the list does not exist"),
+        (status = PRECONDITION_FAILED, description = "The todo changed since", headers(("ETag" = String))),
+    ),
+)]
+pub async fn move_todo_to_list(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Actor(actor): Actor,
+    Path((id,)): Path<(usize,)>,
+    ContentNegotiator(payload): ContentNegotiator<MoveTodoRequest>,
+) -> impl IntoResponse {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    let Some(before) = state.todos.todo(id).cloned() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if let Some(response) = outdated_todo(&headers, &before) {
+        return response;
+    }
+    if state.todos.move_todo_to_list(id, payload.list_id).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    state.todos.record_diff(&actor, &before);
+    let _ = state.todos_events.send(TodosEvent::Updated(id));
+    let Some(todo) = state.todos.todo(id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let etag = [(ETAG, todo_etag(todo))];
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => (etag, Json(todo)).into_response(),
+        _ => (
+            StatusCode::NO_CONTENT,
+            etag,
+            [("HX-Trigger", "todos-changed")],
+        )
+            .into_response(),
+    }
+}
+
+/// A todo list with its counters and nested todos.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListWithTodos<'a> {
+    #[serde(flatten)]
+    pub list: ListSummary,
+    pub todos: Vec<&'a Todo>,
+}
+
+pub async fn get_lists(State(state): State<ApiState>, headers: HeaderMap) -> Response {
+    let state = state.read().await;
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(state.todos.list_summaries()).into_response(),
+        _ => Redirect::to("/todos").into_response(),
+    }
+}
+
+pub async fn get_list(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((list_id,)): Path<(usize,)>,
+) -> Response {
+    let state = state.read().await;
+    let Some(list) = state.todos.list_summary(list_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(ListWithTodos {
+            list,
+            todos: state.todos.query(list_id, &TodosQuery::default()),
+        })
+        .into_response(),
+        _ => Redirect::to(&list_url(list_id)).into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ListRequest {
+    pub name: String,
+}
+
+/// Create a list and notify the connected pages. Shared by the htmx and the
+/// `/api/v1` routes, like the other list changes.
+pub(crate) fn add_list(state: &mut AppState, payload: ListRequest) -> Result<usize, StatusCode> {
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let list_id = state.todos.add_list(name).id;
+    let _ = state.todos_events.send(TodosEvent::ListsChanged);
+    Ok(list_id)
+}
+
+pub async fn create_list(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    ContentNegotiator(payload): ContentNegotiator<ListRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    let list_id = match add_list(&mut state, payload) {
+        Ok(list_id) => list_id,
+        Err(status) => return status.into_response(),
+    };
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(state.todos.list_summary(list_id)).into_response(),
+        _ => [("HX-Redirect", list_url(list_id))].into_response(),
+    }
+}
+
+/// Rename a list and/or change its settings.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateListRequest {
+    pub name: Option<String>,
+    pub auto_complete: Option<bool>,
+}
+
+pub(crate) fn edit_list(
+    state: &mut AppState,
+    list_id: usize,
+    payload: UpdateListRequest,
+) -> Result<(), StatusCode> {
+    let name = payload.name.as_deref().map(str::trim);
+    if name.is_some_and(str::is_empty) || (name.is_none() && payload.auto_complete.is_none()) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    if state.todos.list(list_id).is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if let Some(name) = name {
+        state.todos.rename_list(list_id, name);
+    }
+    if let Some(auto_complete) = payload.auto_complete {
+        state.todos.set_list_auto_complete(list_id, auto_complete);
+    }
+    let _ = state.todos_events.send(TodosEvent::ListsChanged);
+    Ok(())
+}
+
+pub async fn update_list(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((list_id,)): Path<(usize,)>,
+    ContentNegotiator(payload): ContentNegotiator<UpdateListRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    if let Err(status) = edit_list(&mut state, list_id, payload) {
+        return status.into_response();
+    }
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(state.todos.list_summary(list_id)).into_response(),
+        _ => (StatusCode::NO_CONTENT, [("HX-Trigger", "todos-changed")]).into_response(),
+    }
+}
+
+/// The default list cannot be deleted.
+pub(crate) fn remove_list(state: &mut AppState, list_id: usize) -> Result<(), StatusCode> {
+    if list_id == DEFAULT_LIST_ID {
+        return Err(StatusCode::CONFLICT);
+    }
+    state
+        .todos
+        .delete_list(list_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let _ = state.todos_events.send(TodosEvent::ListsChanged);
+    Ok(())
+}
+
+pub async fn delete_list(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((list_id,)): Path<(usize,)>,
+) -> Response {
+    let mut state = state.write().await;
+    if let Err(status) = remove_list(&mut state, list_id) {
+        return status.into_response();
+    }
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => StatusCode::OK.into_response(),
+        _ => [("HX-Redirect", "/todos")].into_response(),
+    }
+}
+
+pub async fn get_tags(State(state): State<ApiState>, headers: HeaderMap) -> Response {
+    let state = state.read().await;
+    let tags = state.todos.tag_summaries(None);
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(tags).into_response(),
+        _ => html! {
+            (DOCTYPE)
+            html {
+                head {
+                    script src="/assets/htmx.min.js" {}
+                    link href="/assets/style/output.css" rel="stylesheet";
+                }
+                body.flex.flex-col.items-center {
+                    h1.text-2xl.text-center.mt-2 { "Tags" }
+                    a.link.link-hover.my-4 href="/todos" { "Back to todos" }
+                    (tags_table(&tags))
+                }
+            }
+        }
+        .into_response(),
+    }
+}
+
+/// A tag with its counter and the todos using it, across all lists.
+#[derive(Debug, Clone, Serialize)]
+pub struct TagWithTodos<'a> {
+    #[serde(flatten)]
+    pub tag: TagSummary,
+    pub todos: Vec<&'a Todo>,
+}
+
+pub async fn get_tag(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((tag_id,)): Path<(usize,)>,
+) -> Response {
+    let state = state.read().await;
+    let Some(tag) = state
+        .todos
+        .tag_summaries(None)
+        .into_iter()
+        .find(|t| t.id == tag_id)
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => {
+            let todos = state
+                .todos
+                .todos()
+                .iter()
+                .filter(|t| t.tags.contains(&tag.name))
+                .collect();
+            Json(TagWithTodos { tag, todos }).into_response()
+        }
+        _ => Redirect::to(&format!("/todos?tag={}", tag.name)).into_response(),
+    }
+}
+
+fn tag_error_status(error: TagError) -> StatusCode {
+    match error {
+        TagError::NotFound => StatusCode::NOT_FOUND,
+        TagError::InvalidName => StatusCode::UNPROCESSABLE_ENTITY,
+        TagError::AlreadyExists => StatusCode::CONFLICT,
+    }
+}
+
+/// Respond to a tag change with the updated tag, or the updated tags table.
+fn tag_changed_response(state: &AppState, headers: &HeaderMap, tag_id: usize) -> Response {
+    let tags = state.todos.tag_summaries(None);
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(tags.iter().find(|t| t.id == tag_id)).into_response(),
+        _ => tags_table(&tags).into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RenameTagRequest {
+    pub name: String,
+}
+
+pub async fn rename_tag(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((tag_id,)): Path<(usize,)>,
+    ContentNegotiator(payload): ContentNegotiator<RenameTagRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    if let Err(error) = state.todos.rename_tag(tag_id, &payload.name) {
+        return tag_error_status(error).into_response();
+    }
+    let _ = state.todos_events.send(TodosEvent::TagsChanged);
+    tag_changed_response(state, &headers, tag_id)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MergeTagRequest {
+    pub into: usize,
+}
+
+pub async fn merge_tag(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((tag_id,)): Path<(usize,)>,
+    ContentNegotiator(payload): ContentNegotiator<MergeTagRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    if let Err(error) = state.todos.merge_tag(tag_id, payload.into) {
+        return tag_error_status(error).into_response();
+    }
+    let _ = state.todos_events.send(TodosEvent::TagsChanged);
+    tag_changed_response(state, &headers, payload.into)
+}
+
+pub async fn delete_tag(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path((tag_id,)): Path<(usize,)>,
+) -> Response {
+    let mut state = state.write().await;
+    let state = &mut *state;
+    if state.todos.delete_tag(tag_id).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let _ = state.todos_events.send(TodosEvent::TagsChanged);
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => StatusCode::OK.into_response(),
+        _ => tags_table(&state.todos.tag_summaries(None)).into_response(),
+    }
+}
+
+pub async fn todos_ws(State(state): State<ApiState>, ws: WebSocketUpgrade) -> impl IntoResponse {
+    let rx_events = state.read().await.todos_events.subscribe();
+    ws.on_upgrade(move |socket| handle_todos_socket(socket, rx_events))
+}
+
+/// Ask the connected client to refresh its list whenever the todos change,
+/// so that every client converges to the same order.
+async fn handle_todos_socket(socket: WebSocket, mut rx_events: broadcast::Receiver<TodosEvent>) {
+    let (mut sink, mut stream) = socket.split();
+
+    loop {
+        tokio::select! {
+            event = rx_events.recv() => {
+                let message = match event {
+                    Ok(TodosEvent::Reminder { content, due, .. }) => reminder_toast(&content, &due),
+                    Ok(_) | Err(RecvError::Lagged(_)) => todos_refresh(),
+                    Err(RecvError::Closed) => break,
+                };
+                if sink.send(Message::text(message.into_string())).await.is_err() {
+                    break;
+                }
+            }
+            msg = stream.next() => {
+                if !matches!(msg, Some(Ok(_))) {
+                    break;
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,196 @@
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::state::{Priority, Todo, TodoStatus};
+
+/// A change made to a todo.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Change {
+    Created,
+    ContentEdited {
+        from: String,
+        to: String,
+    },
+    StatusChanged {
+        from: TodoStatus,
+        to: TodoStatus,
+    },
+    PriorityChanged {
+        from: Priority,
+        to: Priority,
+    },
+    Moved {
+        from_list: usize,
+        to_list: usize,
+    },
+    Tagged {
+        tag: String,
+    },
+    Untagged {
+        tag: String,
+    },
+    Deleted,
+    Restored,
+    /// The todo was permanently deleted from the trash.
+    Purged,
+}
+
+impl Change {
+    /// Changes between two versions of the same todo.
+    pub fn diff(before: &Todo, after: &Todo) -> Vec<Change> {
+        let mut changes = Vec::new();
+        if before.content != after.content {
+            changes.push(Change::ContentEdited {
+                from: before.content.clone(),
+                to: after.content.clone(),
+            });
+        }
+        if before.status != after.status {
+            changes.push(Change::StatusChanged {
+                from: before.status,
+                to: after.status,
+            });
+        }
+        if before.priority != after.priority {
+            changes.push(Change::PriorityChanged {
+                from: before.priority,
+                to: after.priority,
+            });
+        }
+        if before.list_id != after.list_id {
+            changes.push(Change::Moved {
+                from_list: before.list_id,
+                to_list: after.list_id,
+            });
+        }
+        for tag in after.tags.iter().filter(|t| !before.tags.contains(t)) {
+            changes.push(Change::Tagged { tag: tag.clone() });
+        }
+        for tag in before.tags.iter().filter(|t| !after.tags.contains(t)) {
+            changes.push(Change::Untagged { tag: tag.clone() });
+        }
+        changes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct HistoryEntry {
+    pub id: usize,
+    pub todo_id: usize,
+    /// The todo content when the change was made, so that entries remain
+    /// readable once the todo is edited or deleted.
+    pub content: String,
+    pub at: Timestamp,
+    pub actor: String,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+/// Append-only log of the changes made to todos. It is part of the todos
+/// state, changes being recorded as events, see
+/// [`TodosState::record_change`](super::state::TodosState::record_change).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TodoHistory {
+    entries: Vec<HistoryEntry>,
+}
+
+impl TodoHistory {
+    pub fn new() -> TodoHistory {
+        TodoHistory::default()
+    }
+
+    pub fn record(&mut self, actor: &str, todo: &Todo, change: Change) {
+        self.record_at(actor, todo.id, &todo.content, change, Timestamp::now());
+    }
+
+    /// Same as [`TodoHistory::record`], for the todo `todo_id` whose content
+    /// was `content`, with the change made at `at`.
+    pub fn record_at(
+        &mut self,
+        actor: &str,
+        todo_id: usize,
+        content: &str,
+        change: Change,
+        at: Timestamp,
+    ) {
+        self.entries.push(HistoryEntry {
+            id: self.entries.len(),
+            todo_id,
+            content: content.to_owned(),
+            at,
+            actor: actor.to_owned(),
+            change,
+        });
+    }
+
+    /// Changes made to a todo, oldest first.
+    pub fn for_todo(&self, todo_id: usize) -> Vec<&HistoryEntry> {
+        self.entries
+            .iter()
+            .filter(|e| e.todo_id == todo_id)
+            .collect()
+    }
+
+    /// All changes, most recent first.
+    pub fn latest(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter().rev()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::todos::state::{DEFAULT_LIST_ID, Priority, TodoStatus, TodosState};
+
+    use super::Change;
+
+    #[test]
+    fn test_diff() {
+        let mut state = TodosState::new();
+        let before = state.add_todo(DEFAULT_LIST_ID, "buy milk").unwrap().clone();
+        state.add_tags(0, &["shopping".to_owned()]);
+        state.set_priority(0, Priority::High);
+        state.toggle_todo(0);
+        let after = &state.todos()[0];
+
+        assert_eq!(
+            Change::diff(&before, after),
+            vec![
+                Change::StatusChanged {
+                    from: TodoStatus::Backlog,
+                    to: TodoStatus::Done
+                },
+                Change::PriorityChanged {
+                    from: Priority::Normal,
+                    to: Priority::High
+                },
+                Change::Tagged {
+                    tag: "shopping".to_owned()
+                },
+            ]
+        );
+        assert!(Change::diff(after, after).is_empty());
+    }
+
+    #[test]
+    fn test_history_per_todo() {
+        let mut state = TodosState::new();
+        let first = state.add_todo(DEFAULT_LIST_ID, "buy milk").unwrap().clone();
+        state.record_change("alice", 0, Change::Created);
+        state.add_todo(DEFAULT_LIST_ID, "call mom");
+        state.record_change("bob", 1, Change::Created);
+        state.toggle_todo(0);
+        state.record_diff("bob", &first);
+
+        let history = state.history();
+        let entries: Vec<_> = history
+            .for_todo(0)
+            .into_iter()
+            .map(|e| (e.id, e.actor.as_str()))
+            .collect();
+        assert_eq!(entries, vec![(0, "alice"), (2, "bob")]);
+        let latest: Vec<_> = history.latest().map(|e| e.id).collect();
+        assert_eq!(latest, vec![2, 1, 0]);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// How long an idempotency key is remembered after it was first used.
+pub const IDEMPOTENCY_WINDOW: SignedDuration = SignedDuration::from_hours(24);
+
+/// Oldest keys are forgotten first once there are more than this many.
+const MAX_KEYS: usize = 1000;
+
+/// Keys sent by clients when creating todos, along with the todo they
+/// created, so that retried requests do not create duplicates. Keys are
+/// scoped to who sent them, and only replay the request they were used with.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IdempotencyKeys {
+    keys: VecDeque<IdempotencyKey>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct IdempotencyKey {
+    #[serde(default)]
+    actor: String,
+    key: String,
+    /// See [`request_hash`].
+    #[serde(default)]
+    request_hash: String,
+    todo_id: usize,
+    at: Timestamp,
+}
+
+/// A key used before, see [`IdempotencyKeys::get`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsedKey {
+    /// The same request created this todo.
+    Replay(usize),
+    /// The key was used for another request.
+    Mismatch,
+}
+
+impl IdempotencyKeys {
+    /// What `key` of `actor` was used for, unless it was used outside the
+    /// window.
+    pub fn get(
+        &self,
+        actor: &str,
+        key: &str,
+        request_hash: &str,
+        now: Timestamp,
+    ) -> Option<UsedKey> {
+        let oldest = now
+            .checked_sub(IDEMPOTENCY_WINDOW)
+            .unwrap_or(Timestamp::MIN);
+        let used = self
+            .keys
+            .iter()
+            .find(|k| k.actor == actor && k.key == key && k.at >= oldest)?;
+        if used.request_hash != request_hash {
+            return Some(UsedKey::Mismatch);
+        }
+        Some(UsedKey::Replay(used.todo_id))
+    }
+
+    pub fn insert(
+        &mut self,
+        actor: &str,
+        key: &str,
+        request_hash: &str,
+        todo_id: usize,
+        at: Timestamp,
+    ) {
+        let oldest = at.checked_sub(IDEMPOTENCY_WINDOW).unwrap_or(Timestamp::MIN);
+        self.keys
+            .retain(|k| k.at >= oldest && (k.actor != actor || k.key != key));
+        self.keys.push_back(IdempotencyKey {
+            actor: actor.to_owned(),
+            key: key.to_owned(),
+            request_hash: request_hash.to_owned(),
+            todo_id,
+            at,
+        });
+        if self.keys.len() > MAX_KEYS {
+            self.keys.pop_front();
+        }
+    }
+}
+
+/// Hash of the JSON of a request, to tell whether a key is used again for
+/// the same one.
+pub fn request_hash(request: &impl Serialize) -> String {
+    let json = serde_json::to_vec(request).unwrap_or_default();
+    hex::encode(Sha256::digest(json))
+}
+
+/// A new key for a form. Keys only have to be unique, not unpredictable.
+pub fn new_idempotency_key() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{count:x}", Timestamp::now().as_nanosecond())
+}
+
+#[cfg(test)]
+mod test {
+    use jiff::{SignedDuration, Timestamp};
+
+    use super::{
+        IDEMPOTENCY_WINDOW, IdempotencyKeys, MAX_KEYS, UsedKey, new_idempotency_key, request_hash,
+    };
+
+    #[test]
+    fn test_idempotency_keys() {
+        let now: Timestamp = "2025-01-01T12:00:00Z".parse().unwrap();
+        let mut keys = IdempotencyKeys::default();
+        let (milk, eggs) = (request_hash(&"milk"), request_hash(&"eggs"));
+        keys.insert("alice", "a", &milk, 1, now);
+        assert_eq!(keys.get("alice", "a", &milk, now), Some(UsedKey::Replay(1)));
+        assert_eq!(keys.get("alice", "a", &eggs, now), Some(UsedKey::Mismatch));
+        assert_eq!(keys.get("alice", "b", &milk, now), None);
+        // Other users can use the same key
+        assert_eq!(keys.get("bob", "a", &milk, now), None);
+        keys.insert("bob", "a", &eggs, 2, now);
+        assert_eq!(keys.get("alice", "a", &milk, now), Some(UsedKey::Replay(1)));
+
+        let later = now + IDEMPOTENCY_WINDOW + SignedDuration::from_secs(1);
+        assert_eq!(keys.get("alice", "a", &milk, later), None);
+
+        for i in 0..MAX_KEYS {
+            keys.insert("alice", &i.to_string(), &milk, i, now);
+        }
+        assert_eq!(keys.get("alice", "a", &milk, now), None);
+        assert_eq!(keys.get("alice", "0", &milk, now), Some(UsedKey::Replay(0)));
+    }
+
+    #[test]
+    fn test_new_keys_are_unique() {
+        assert_ne!(new_idempotency_key(), new_idempotency_key());
+    }
+}
@@ -1,3 +1,12 @@
+pub mod calendar;
+pub mod events;
 pub mod handlers;
+pub mod history;
+pub mod idempotency;
+pub mod recurrence;
+pub mod reminders;
 pub mod state;
+pub mod tags;
 pub mod templates;
+pub mod transfer;
+pub mod trash;
@@ -0,0 +1,365 @@
+use std::{fmt, str::FromStr};
+
+use jiff::{
+    ToSpan, Zoned,
+    civil::{Date, Time},
+    tz::TimeZone,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Weekday {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Weekday::Mon => "mon",
+            Weekday::Tue => "tue",
+            Weekday::Wed => "wed",
+            Weekday::Thu => "thu",
+            Weekday::Fri => "fri",
+            Weekday::Sat => "sat",
+            Weekday::Sun => "sun",
+        }
+    }
+}
+
+impl From<jiff::civil::Weekday> for Weekday {
+    fn from(weekday: jiff::civil::Weekday) -> Self {
+        match weekday {
+            jiff::civil::Weekday::Monday => Weekday::Mon,
+            jiff::civil::Weekday::Tuesday => Weekday::Tue,
+            jiff::civil::Weekday::Wednesday => Weekday::Wed,
+            jiff::civil::Weekday::Thursday => Weekday::Thu,
+            jiff::civil::Weekday::Friday => Weekday::Fri,
+            jiff::civil::Weekday::Saturday => Weekday::Sat,
+            jiff::civil::Weekday::Sunday => Weekday::Sun,
+        }
+    }
+}
+
+impl FromStr for Weekday {
+    type Err = RecurrenceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "mon" | "monday" => Ok(Weekday::Mon),
+            "tue" | "tuesday" => Ok(Weekday::Tue),
+            "wed" | "wednesday" => Ok(Weekday::Wed),
+            "thu" | "thursday" => Ok(Weekday::Thu),
+            "fri" | "friday" => Ok(Weekday::Fri),
+            "sat" | "saturday" => Ok(Weekday::Sat),
+            "sun" | "sunday" => Ok(Weekday::Sun),
+            _ => Err(RecurrenceError::InvalidWeekday(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "every", rename_all = "kebab-case")]
+pub enum RecurrenceRule {
+    Day,
+    Week {
+        weekdays: Vec<Weekday>,
+    },
+    /// On day `day` of every month, or on the last day for shorter months.
+    Month {
+        day: i8,
+    },
+    /// `days` days after the todo has been completed.
+    DaysAfterCompletion {
+        days: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Recurrence {
+    #[serde(flatten)]
+    pub rule: RecurrenceRule,
+    /// Wall clock time of the occurrences. Kept apart from the due dates so
+    /// that an occurrence moved by a DST gap does not shift the next ones.
+    #[schema(value_type = Option<String>, example = "09:00:00")]
+    pub at: Option<Time>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecurrenceError {
+    InvalidWeekday(String),
+    InvalidDay(i64),
+    InvalidInterval,
+}
+
+impl fmt::Display for RecurrenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecurrenceError::InvalidWeekday(weekday) => write!(f, "invalid weekday {weekday:?}"),
+            RecurrenceError::InvalidDay(day) => write!(f, "invalid day of month {day}"),
+            RecurrenceError::InvalidInterval => write!(f, "interval must be at least one day"),
+        }
+    }
+}
+
+/// Kind of recurrence as picked in the new todo form, the details of the
+/// rule default to the todo due date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RepeatKind {
+    Daily,
+    Weekly,
+    Monthly,
+    AfterCompletion,
+}
+
+impl FromStr for RepeatKind {
+    type Err = serde::de::value::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use serde::de::{IntoDeserializer, value::StrDeserializer};
+        RepeatKind::deserialize::<StrDeserializer<'_, _>>(s.into_deserializer())
+    }
+}
+
+impl Recurrence {
+    /// Build a recurrence from the new todo form fields. `weekdays` is a
+    /// comma separated list of days, and `every` is either the day of the
+    /// month or the number of days after completion. Missing values are
+    /// taken from the due date, or from `now` when there is none.
+    pub fn from_form(
+        kind: RepeatKind,
+        every: Option<u32>,
+        weekdays: Option<&str>,
+        due: Option<&Zoned>,
+        now: &Zoned,
+    ) -> Result<Recurrence, RecurrenceError> {
+        let anchor = due.unwrap_or(now);
+        let rule = match kind {
+            RepeatKind::Daily => RecurrenceRule::Day,
+            RepeatKind::Weekly => {
+                let mut weekdays = weekdays
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter(|w| !w.trim().is_empty())
+                    .map(Weekday::from_str)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if weekdays.is_empty() {
+                    weekdays.push(anchor.weekday().into());
+                }
+                RecurrenceRule::Week { weekdays }
+            }
+            RepeatKind::Monthly => {
+                let day = every.map_or(anchor.day().into(), i64::from);
+                if !(1..=31).contains(&day) {
+                    return Err(RecurrenceError::InvalidDay(day));
+                }
+                RecurrenceRule::Month { day: day as i8 }
+            }
+            RepeatKind::AfterCompletion => match every {
+                Some(days) if days > 0 => RecurrenceRule::DaysAfterCompletion { days },
+                _ => return Err(RecurrenceError::InvalidInterval),
+            },
+        };
+        Ok(Recurrence {
+            rule,
+            at: due.map(|due| due.time()),
+        })
+    }
+
+    /// Due date of the occurrence following a todo due `due` and completed
+    /// at `completed`. Occurrences missed before completion are skipped, so
+    /// that the next occurrence is always after `completed`.
+    pub fn next_due(&self, due: Option<&Zoned>, completed: &Zoned) -> Option<Zoned> {
+        let tz = due.map_or(completed.time_zone(), |due| due.time_zone());
+        let completed = completed.with_time_zone(tz.clone());
+        let time = self
+            .at
+            .or(due.map(|due| due.time()))
+            .unwrap_or(completed.time());
+
+        if let RecurrenceRule::DaysAfterCompletion { days } = self.rule {
+            let date = completed.date().checked_add(i64::from(days).days()).ok()?;
+            return occurrence(date, time, tz);
+        }
+
+        let mut date = due.map_or(completed.date(), |due| due.date());
+        loop {
+            date = self.next_date(date)?;
+            let next = occurrence(date, time, tz)?;
+            if next > completed {
+                return Some(next);
+            }
+        }
+    }
+
+    fn next_date(&self, date: Date) -> Option<Date> {
+        match &self.rule {
+            RecurrenceRule::Day | RecurrenceRule::DaysAfterCompletion { .. } => {
+                date.tomorrow().ok()
+            }
+            RecurrenceRule::Week { weekdays } if weekdays.is_empty() => {
+                date.checked_add(1.week()).ok()
+            }
+            RecurrenceRule::Week { weekdays } => (1..=7)
+                .filter_map(|offset| date.checked_add(offset.days()).ok())
+                .find(|next| weekdays.contains(&next.weekday().into())),
+            RecurrenceRule::Month { day } => {
+                let month = date.first_of_month().checked_add(1.month()).ok()?;
+                Date::new(
+                    month.year(),
+                    month.month(),
+                    (*day).min(month.days_in_month()),
+                )
+                .ok()
+            }
+        }
+    }
+}
+
+/// Occurrence at the wall clock `time`. Times skipped by a DST gap are moved
+/// forward, and ambiguous times resolve to the earliest instant.
+fn occurrence(date: Date, time: Time, tz: &TimeZone) -> Option<Zoned> {
+    date.to_datetime(time).to_zoned(tz.clone()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use jiff::Zoned;
+
+    use super::{Recurrence, RecurrenceRule, RepeatKind, Weekday};
+
+    fn zoned(input: &str) -> Zoned {
+        input.parse().unwrap()
+    }
+
+    fn recurrence(rule: RecurrenceRule, due: &Zoned) -> Recurrence {
+        Recurrence {
+            rule,
+            at: Some(due.time()),
+        }
+    }
+
+    #[test]
+    fn test_daily_keeps_wall_clock_time_across_dst() {
+        // Europe/Paris switches to summer time on 2025-03-30
+        let due = zoned("2025-03-29T09:00[Europe/Paris]");
+        let daily = recurrence(RecurrenceRule::Day, &due);
+
+        let next = daily.next_due(Some(&due), &due).unwrap();
+        assert_eq!(next, zoned("2025-03-30T09:00+02:00[Europe/Paris]"));
+        assert_eq!(due.duration_until(&next).as_hours(), 23);
+    }
+
+    #[test]
+    fn test_daily_in_dst_gap_does_not_drift() {
+        // 02:30 does not exist on 2025-03-30 in Europe/Paris
+        let due = zoned("2025-03-29T02:30[Europe/Paris]");
+        let daily = recurrence(RecurrenceRule::Day, &due);
+
+        let in_gap = daily.next_due(Some(&due), &due).unwrap();
+        assert_eq!(in_gap, zoned("2025-03-30T03:30+02:00[Europe/Paris]"));
+        let after_gap = daily.next_due(Some(&in_gap), &in_gap).unwrap();
+        assert_eq!(after_gap, zoned("2025-03-31T02:30+02:00[Europe/Paris]"));
+    }
+
+    #[test]
+    fn test_daily_in_dst_fold_picks_first_instant() {
+        // 02:30 happens twice on 2025-10-26 in Europe/Paris
+        let due = zoned("2025-10-25T02:30[Europe/Paris]");
+        let daily = recurrence(RecurrenceRule::Day, &due);
+
+        let next = daily.next_due(Some(&due), &due).unwrap();
+        assert_eq!(next, zoned("2025-10-26T02:30+02:00[Europe/Paris]"));
+    }
+
+    #[test]
+    fn test_monthly_at_month_end() {
+        let due = zoned("2025-01-31T18:00[Europe/Paris]");
+        let monthly = recurrence(RecurrenceRule::Month { day: 31 }, &due);
+
+        let february = monthly.next_due(Some(&due), &due).unwrap();
+        assert_eq!(february, zoned("2025-02-28T18:00[Europe/Paris]"));
+        let march = monthly.next_due(Some(&february), &february).unwrap();
+        assert_eq!(march, zoned("2025-03-31T18:00[Europe/Paris]"));
+        let april = monthly.next_due(Some(&march), &march).unwrap();
+        assert_eq!(april, zoned("2025-04-30T18:00[Europe/Paris]"));
+
+        let leap = zoned("2024-01-30T18:00[Europe/Paris]");
+        let monthly = recurrence(RecurrenceRule::Month { day: 30 }, &leap);
+        assert_eq!(
+            monthly.next_due(Some(&leap), &leap).unwrap(),
+            zoned("2024-02-29T18:00[Europe/Paris]")
+        );
+    }
+
+    #[test]
+    fn test_weekly_on_weekdays() {
+        // A Friday
+        let due = zoned("2025-03-07T10:00[UTC]");
+        let weekly = recurrence(
+            RecurrenceRule::Week {
+                weekdays: vec![Weekday::Mon, Weekday::Wed],
+            },
+            &due,
+        );
+
+        let monday = weekly.next_due(Some(&due), &due).unwrap();
+        assert_eq!(monday, zoned("2025-03-10T10:00[UTC]"));
+        let wednesday = weekly.next_due(Some(&monday), &monday).unwrap();
+        assert_eq!(wednesday, zoned("2025-03-12T10:00[UTC]"));
+    }
+
+    #[test]
+    fn test_missed_occurrences_are_skipped() {
+        let due = zoned("2025-03-01T10:00[UTC]");
+        let daily = recurrence(RecurrenceRule::Day, &due);
+
+        let completed = zoned("2025-03-05T12:00[UTC]");
+        assert_eq!(
+            daily.next_due(Some(&due), &completed).unwrap(),
+            zoned("2025-03-06T10:00[UTC]")
+        );
+    }
+
+    #[test]
+    fn test_days_after_completion() {
+        let due = zoned("2025-03-01T10:00[Europe/Paris]");
+        let rule = recurrence(RecurrenceRule::DaysAfterCompletion { days: 3 }, &due);
+
+        let completed = zoned("2025-03-04T22:00[Europe/Paris]");
+        assert_eq!(
+            rule.next_due(Some(&due), &completed).unwrap(),
+            zoned("2025-03-07T10:00[Europe/Paris]")
+        );
+    }
+
+    #[test]
+    fn test_from_form_defaults_to_due_date() {
+        // A Friday
+        let due = zoned("2025-03-07T10:00[UTC]");
+
+        let weekly = Recurrence::from_form(RepeatKind::Weekly, None, None, Some(&due), &due);
+        assert_eq!(
+            weekly.unwrap().rule,
+            RecurrenceRule::Week {
+                weekdays: vec![Weekday::Fri]
+            }
+        );
+        let monthly = Recurrence::from_form(RepeatKind::Monthly, None, None, Some(&due), &due);
+        assert_eq!(monthly.unwrap().rule, RecurrenceRule::Month { day: 7 });
+
+        assert!(Recurrence::from_form(RepeatKind::Monthly, Some(32), None, None, &due).is_err());
+        assert!(Recurrence::from_form(RepeatKind::Weekly, None, Some("fun"), None, &due).is_err());
+        assert!(
+            Recurrence::from_form(RepeatKind::AfterCompletion, Some(0), None, None, &due).is_err()
+        );
+    }
+}
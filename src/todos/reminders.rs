@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use jiff::Timestamp;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{ApiState, todos::state::TodosEvent};
+
+/// Upper bound on how long the scheduler sleeps between two checks.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Fire todo reminders at the right time.
+///
+/// The scheduler sleeps until the next pending reminder, and wakes up early
+/// whenever the todos change since a closer reminder may have been added.
+/// Whether a reminder already fired is stored on the todo itself, so nothing
+/// is lost across restarts when todos are persisted.
+pub async fn run_reminders(state: ApiState) {
+    tracing::info!("Starting todos reminders scheduler");
+    let mut rx_events = state.read().await.todos_events.subscribe();
+
+    loop {
+        {
+            let mut state = state.write().await;
+            let state = &mut *state;
+            for todo in state.todos.take_due_reminders(Timestamp::now()) {
+                let Some(due) = todo.due.clone() else {
+                    continue;
+                };
+                tracing::info!("Reminder for todo {} {:?} due {due}", todo.id, todo.content);
+                let _ = state.todos_events.send(TodosEvent::Reminder {
+                    todo_id: todo.id,
+                    content: todo.content,
+                    due,
+                });
+            }
+        }
+
+        let sleep = match state.read().await.todos.next_reminder_at() {
+            Some(next) => Duration::try_from(Timestamp::now().duration_until(next))
+                .unwrap_or(Duration::ZERO)
+                .min(MAX_SLEEP),
+            None => MAX_SLEEP,
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(sleep) => {}
+            event = rx_events.recv() => {
+                if let Err(RecvError::Closed) = event {
+                    break;
+                }
+            }
+        }
+    }
+}
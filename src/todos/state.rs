@@ -1,49 +1,1673 @@
-use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
+use jiff::{SignedDuration, Timestamp, Zoned, tz::TimeZone};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::calendar::{CalendarItem, todo_id_from_uid, todo_uid};
+use super::events::{DomainEvent, RecordedEvent};
+use super::history::{Change, TodoHistory};
+use super::idempotency::{IdempotencyKeys, UsedKey};
+use super::recurrence::Recurrence;
+use super::tags::{Tag, TagColor, TagError, TagSummary, normalize_tag};
+use super::transfer::TodoRecord;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct Todo {
+    pub id: usize,
+    /// Incremented whenever the todo changes, see `If-Match` on todo routes.
+    /// Positions shifting because of other todos do not count as changes.
+    #[serde(default)]
+    pub version: u64,
+    pub list_id: usize,
+    pub content: String,
+    pub status: TodoStatus,
+    /// When the todo was completed, unset while it is open.
+    #[serde(default)]
+    pub completed_at: Option<Timestamp>,
+    pub priority: Priority,
+    pub position: usize,
+    pub due: Option<Zoned>,
+    /// How long before `due` the reminder fires.
+    pub reminder_minutes: Option<u32>,
+    /// Whether the reminder has already fired. Stored on the todo so that
+    /// reminders are not fired twice, even across restarts.
+    pub reminded: bool,
+    /// Only the latest occurrence of a recurring todo carries the rule.
+    pub recurrence: Option<Recurrence>,
+    pub subtasks: Vec<Subtask>,
+    /// Names of the todo tags, see [`TodosState::tags`].
+    pub tags: Vec<String>,
+}
+
+/// A checklist item of a todo.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Subtask {
     pub id: usize,
     pub content: String,
     pub done: bool,
 }
 
+/// Workflow of a todo, shown as the columns of the board.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum TodoStatus {
+    #[default]
+    Backlog,
+    InProgress,
+    Blocked,
+    Done,
+}
+
+impl TodoStatus {
+    pub const VARIANTS: [TodoStatus; 4] = [
+        TodoStatus::Backlog,
+        TodoStatus::InProgress,
+        TodoStatus::Blocked,
+        TodoStatus::Done,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TodoStatus::Backlog => "backlog",
+            TodoStatus::InProgress => "in-progress",
+            TodoStatus::Blocked => "blocked",
+            TodoStatus::Done => "done",
+        }
+    }
+}
+
+/// Priorities are ordered from the lowest to the most urgent.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    pub const VARIANTS: [Priority; 4] = [
+        Priority::Low,
+        Priority::Normal,
+        Priority::High,
+        Priority::Urgent,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+}
+
+impl Todo {
+    pub fn is_done(&self) -> bool {
+        self.status == TodoStatus::Done
+    }
+
+    pub fn is_overdue(&self, now: &Zoned) -> bool {
+        !self.is_done() && self.due.as_ref().is_some_and(|due| due < now)
+    }
+
+    pub fn is_due_on(&self, now: &Zoned) -> bool {
+        self.due
+            .as_ref()
+            .is_some_and(|due| due.with_time_zone(now.time_zone().clone()).date() == now.date())
+    }
+
+    pub fn reminder_at(&self) -> Option<Timestamp> {
+        let due = self.due.as_ref()?;
+        let before = SignedDuration::from_mins(self.reminder_minutes?.into());
+        due.timestamp().saturating_sub(before).ok()
+    }
+}
+
+/// Parse a due date, either as a zoned datetime
+/// (`2025-03-30T09:00[Europe/Paris]`), as an RFC 3339 timestamp, or as a
+/// civil datetime (e.g. from a `datetime-local` input) in the server time
+/// zone.
+pub fn parse_due(input: &str) -> Result<Zoned, jiff::Error> {
+    let input = input.trim();
+    if let Ok(zoned) = input.parse::<Zoned>() {
+        return Ok(zoned);
+    }
+    if let Ok(timestamp) = input.parse::<Timestamp>() {
+        return Ok(timestamp.to_zoned(TimeZone::system()));
+    }
+    input
+        .parse::<jiff::civil::DateTime>()?
+        .to_zoned(TimeZone::system())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TodoList {
+    pub id: usize,
+    pub name: String,
+    /// Whether todos are completed once all their subtasks are done.
+    pub auto_complete: bool,
+}
+
+/// A todo to create, see [`TodosState::create_todo`].
+#[derive(Debug, Clone, Default)]
+pub struct NewTodo {
+    pub content: String,
+    pub due: Option<Zoned>,
+    pub reminder_minutes: Option<u32>,
+    pub tags: Vec<String>,
+    pub priority: Priority,
+    pub recurrence: Option<Recurrence>,
+}
+
+/// A deleted todo, kept in the trash until it is restored or purged.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TrashedTodo {
+    #[serde(flatten)]
+    pub todo: Todo,
+    pub deleted_at: Timestamp,
+}
+
+/// A todo list along with its open/done counters.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListSummary {
+    pub id: usize,
+    pub name: String,
+    pub open: usize,
+    pub done: usize,
+    pub auto_complete: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum StatusFilter {
+    #[default]
+    All,
+    /// Any status but done.
+    Active,
+    Backlog,
+    InProgress,
+    Blocked,
+    Done,
+}
+
+impl StatusFilter {
+    pub const VARIANTS: [StatusFilter; 6] = [
+        StatusFilter::All,
+        StatusFilter::Active,
+        StatusFilter::Backlog,
+        StatusFilter::InProgress,
+        StatusFilter::Blocked,
+        StatusFilter::Done,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StatusFilter::All => "all",
+            StatusFilter::Active => "active",
+            StatusFilter::Backlog => "backlog",
+            StatusFilter::InProgress => "in-progress",
+            StatusFilter::Blocked => "blocked",
+            StatusFilter::Done => "done",
+        }
+    }
+
+    fn matches(&self, todo: &Todo) -> bool {
+        match self {
+            StatusFilter::All => true,
+            StatusFilter::Active => !todo.is_done(),
+            StatusFilter::Backlog => todo.status == TodoStatus::Backlog,
+            StatusFilter::InProgress => todo.status == TodoStatus::InProgress,
+            StatusFilter::Blocked => todo.status == TodoStatus::Blocked,
+            StatusFilter::Done => todo.is_done(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DueFilter {
+    #[default]
+    Any,
+    Overdue,
+    Today,
+    Upcoming,
+}
+
+impl DueFilter {
+    pub const VARIANTS: [DueFilter; 4] = [
+        DueFilter::Any,
+        DueFilter::Overdue,
+        DueFilter::Today,
+        DueFilter::Upcoming,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DueFilter::Any => "any",
+            DueFilter::Overdue => "overdue",
+            DueFilter::Today => "today",
+            DueFilter::Upcoming => "upcoming",
+        }
+    }
+
+    fn matches(&self, todo: &Todo, now: &Zoned) -> bool {
+        match self {
+            DueFilter::Any => true,
+            DueFilter::Overdue => todo.is_overdue(now),
+            DueFilter::Today => todo.is_due_on(now),
+            DueFilter::Upcoming => todo.due.as_ref().is_some_and(|due| due > now),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortOrder {
+    #[default]
+    Manual,
+    Oldest,
+    Newest,
+    Alphabetical,
+    ActiveFirst,
+    Due,
+    Priority,
+}
+
+impl SortOrder {
+    pub const VARIANTS: [SortOrder; 7] = [
+        SortOrder::Manual,
+        SortOrder::Oldest,
+        SortOrder::Newest,
+        SortOrder::Alphabetical,
+        SortOrder::ActiveFirst,
+        SortOrder::Due,
+        SortOrder::Priority,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortOrder::Manual => "manual",
+            SortOrder::Oldest => "oldest",
+            SortOrder::Newest => "newest",
+            SortOrder::Alphabetical => "alphabetical",
+            SortOrder::ActiveFirst => "active-first",
+            SortOrder::Due => "due",
+            SortOrder::Priority => "priority",
+        }
+    }
+}
+
+/// Filtering, search and ordering options shared by the HTML and JSON
+/// representations of the todos list.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, IntoParams, ToSchema)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct TodosQuery {
+    pub status: StatusFilter,
+    pub due: DueFilter,
+    pub q: String,
+    /// Only keep todos with this tag, when not empty.
+    pub tag: String,
+    pub sort: SortOrder,
+}
+
+/// Where to put a todo relative to another one when reordering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Placement {
+    Before,
+    After,
+}
+
+/// An operation applied to several todos at once.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkOperation {
+    Complete,
+    Reopen,
+    Delete,
+    Tag(String),
+}
+
+/// IDs of the todos changed by a bulk operation.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BulkSummary {
+    pub updated: Vec<usize>,
+    /// Next occurrences of the completed recurring todos.
+    pub created: Vec<usize>,
+    pub deleted: Vec<usize>,
+}
+
+/// Notification sent to connected clients whenever the todos change.
 #[derive(Debug, Clone, PartialEq)]
+pub enum TodosEvent {
+    Created(usize),
+    Updated(usize),
+    Deleted(usize),
+    Reordered,
+    ListsChanged,
+    TagsChanged,
+    Bulk(BulkSummary),
+    Restored(Vec<usize>),
+    Reminder {
+        todo_id: usize,
+        content: String,
+        due: Zoned,
+    },
+}
+
+/// The list backing `/todos`, it always exists and cannot be deleted.
+pub const DEFAULT_LIST_ID: usize = 0;
+
+/// How many of the latest events are kept in memory, see
+/// [`TodosState::events`]. Older ones are dropped once persisted, or right
+/// away when the todos only live in memory.
+pub const RETAINED_EVENTS: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TodosState {
+    lists: Vec<TodoList>,
+    list_counter: usize,
     todos: Vec<Todo>,
     todo_counter: usize,
+    subtask_counter: usize,
+    tags: Vec<Tag>,
+    tag_counter: usize,
+    trash: Vec<TrashedTodo>,
+    #[serde(default)]
+    idempotency_keys: IdempotencyKeys,
+    /// UIDs of the todos created from calendars, see [`TodosState::calendar_uid`].
+    #[serde(default)]
+    calendar_uids: BTreeMap<String, usize>,
+    /// Who changed the todos, and how.
+    #[serde(default)]
+    history: TodoHistory,
+    /// Sequence number of the last applied event.
+    version: u64,
+    /// The latest events. Snapshots are stored without the events they
+    /// result from.
+    #[serde(skip)]
+    events: Vec<RecordedEvent>,
+    /// Version up to which events are persisted, `None` when the todos only
+    /// live in memory.
+    #[serde(skip)]
+    persisted: Option<u64>,
 }
 
 impl TodosState {
     pub fn new() -> TodosState {
         TodosState {
+            lists: vec![TodoList {
+                id: DEFAULT_LIST_ID,
+                name: "Inbox".to_owned(),
+                auto_complete: false,
+            }],
+            list_counter: DEFAULT_LIST_ID + 1,
             todos: Vec::new(),
             todo_counter: 0,
+            subtask_counter: 0,
+            tags: Vec::new(),
+            tag_counter: 0,
+            trash: Vec::new(),
+            idempotency_keys: IdempotencyKeys::default(),
+            calendar_uids: BTreeMap::new(),
+            history: TodoHistory::new(),
+            version: 0,
+            events: Vec::new(),
+            persisted: None,
+        }
+    }
+
+    /// Rebuild a state from the events of another one.
+    pub fn replay<'a>(events: impl IntoIterator<Item = &'a RecordedEvent>) -> TodosState {
+        let mut state = TodosState::new();
+        state.apply_recorded(events.into_iter().cloned());
+        state
+    }
+
+    /// The state as it was at a given point in time, `None` when the events
+    /// it results from are no longer in memory, see
+    /// [`EventLog`](super::events::EventLog) to replay the persisted ones.
+    pub fn at(&self, at: Timestamp) -> Option<TodosState> {
+        if self.events.last().is_none_or(|e| e.recorded_at <= at) {
+            return Some(self.clone());
+        }
+        self.has_all_events()
+            .then(|| TodosState::replay(self.events.iter().take_while(|e| e.recorded_at <= at)))
+    }
+
+    /// The state as it was once the event `version` was applied, `None` when
+    /// the events it results from are no longer in memory.
+    pub fn at_version(&self, version: u64) -> Option<TodosState> {
+        if version >= self.version {
+            return Some(self.clone());
         }
+        self.has_all_events()
+            .then(|| TodosState::replay(self.events.iter().take_while(|e| e.seq <= version)))
+    }
+
+    /// Whether the state can be replayed from the events in memory.
+    fn has_all_events(&self) -> bool {
+        self.events
+            .first()
+            .map_or(self.version == 0, |e| e.seq == 1)
+    }
+
+    /// Sequence number of the last applied event, 0 for a new state.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The latest events, oldest first. At least the last
+    /// [`RETAINED_EVENTS`] are kept, along with the ones not persisted yet.
+    pub fn events(&self) -> &[RecordedEvent] {
+        &self.events
+    }
+
+    /// The events recorded after `version`, `None` when some of them are no
+    /// longer in memory.
+    pub fn events_after(&self, version: u64) -> Option<&[RecordedEvent]> {
+        let start = self.events.first().map_or(self.version + 1, |e| e.seq);
+        if version + 1 < start {
+            return None;
+        }
+        Some(&self.events[self.events.partition_point(|e| e.seq <= version)..])
+    }
+
+    /// Record that the events up to `version` are persisted, they can be
+    /// dropped from memory.
+    pub fn set_persisted(&mut self, version: u64) {
+        self.persisted = Some(version);
+        self.trim_events();
+    }
+
+    /// Drop the events beyond [`RETAINED_EVENTS`], in batches, unless they
+    /// are yet to be persisted.
+    fn trim_events(&mut self) {
+        if self.events.len() <= 2 * RETAINED_EVENTS {
+            return;
+        }
+        let excess = self.events.len() - RETAINED_EVENTS;
+        let persisted = self.persisted.unwrap_or(u64::MAX);
+        let count = self.events[..excess].partition_point(|e| e.seq <= persisted);
+        self.events.drain(..count);
+    }
+
+    /// Who changed the todos, and how.
+    pub fn history(&self) -> &TodoHistory {
+        &self.history
+    }
+
+    /// Record a change made by `actor` to the todo `todo_id`, which can be in
+    /// the trash.
+    pub fn record_change(&mut self, actor: &str, todo_id: usize, change: Change) {
+        let todo = self
+            .todo(todo_id)
+            .or_else(|| self.trash.iter().map(|t| &t.todo).find(|t| t.id == todo_id));
+        if let Some(todo) = todo.cloned() {
+            self.record_todo_change(actor, &todo, change);
+        }
+    }
+
+    /// Record a change made to `todo`, e.g. once it is purged from the state.
+    pub fn record_todo_change(&mut self, actor: &str, todo: &Todo, change: Change) {
+        self.commit(DomainEvent::ChangeRecorded {
+            todo_id: todo.id,
+            content: todo.content.clone(),
+            actor: actor.to_owned(),
+            change,
+            at: Timestamp::now(),
+        });
+    }
+
+    /// Record the changes made by `actor` to the todo `before` is a previous
+    /// version of, if any.
+    pub fn record_diff(&mut self, actor: &str, before: &Todo) {
+        let Some(after) = self.todo(before.id).cloned() else {
+            return;
+        };
+        for change in Change::diff(before, &after) {
+            self.record_todo_change(actor, &after, change);
+        }
+    }
+
+    /// Append events recorded elsewhere to the log. Events the state already
+    /// results from, e.g. when loaded from a snapshot, are not applied again.
+    pub fn apply_recorded(&mut self, events: impl IntoIterator<Item = RecordedEvent>) {
+        for recorded in events {
+            if recorded.seq > self.version {
+                if self.apply(&recorded.event).is_none() {
+                    tracing::warn!("Could not apply todos event {}", recorded.seq);
+                }
+                self.version = recorded.seq;
+            }
+            self.events.push(recorded);
+        }
+        self.trim_events();
     }
 
     pub fn todos(&self) -> &[Todo] {
         &self.todos
     }
 
-    pub fn add_todo(&mut self, content: &str) -> &Todo {
-        self.todos.push(Todo {
-            id: self.todo_counter,
+    pub fn todo(&self, todo_id: usize) -> Option<&Todo> {
+        self.todos.iter().find(|t| t.id == todo_id)
+    }
+
+    pub fn lists(&self) -> &[TodoList] {
+        &self.lists
+    }
+
+    pub fn list(&self, list_id: usize) -> Option<&TodoList> {
+        self.lists.iter().find(|l| l.id == list_id)
+    }
+
+    pub fn list_summary(&self, list_id: usize) -> Option<ListSummary> {
+        let list = self.list(list_id)?;
+        let (done, open): (Vec<&Todo>, Vec<&Todo>) = self
+            .todos
+            .iter()
+            .filter(|t| t.list_id == list_id)
+            .partition(|t| t.is_done());
+        Some(ListSummary {
+            id: list.id,
+            name: list.name.clone(),
+            open: open.len(),
+            done: done.len(),
+            auto_complete: list.auto_complete,
+        })
+    }
+
+    pub fn list_summaries(&self) -> Vec<ListSummary> {
+        self.lists
+            .iter()
+            .filter_map(|l| self.list_summary(l.id))
+            .collect()
+    }
+
+    pub fn add_list(&mut self, name: &str) -> &TodoList {
+        self.commit(DomainEvent::ListAdded {
+            name: name.to_owned(),
+        });
+        self.lists.last().unwrap()
+    }
+
+    pub fn rename_list(&mut self, list_id: usize, name: &str) -> Option<&TodoList> {
+        self.commit(DomainEvent::ListRenamed {
+            list_id,
+            name: name.to_owned(),
+        })?;
+        self.list(list_id)
+    }
+
+    pub fn set_list_auto_complete(
+        &mut self,
+        list_id: usize,
+        auto_complete: bool,
+    ) -> Option<&TodoList> {
+        self.commit(DomainEvent::ListAutoCompleteSet {
+            list_id,
+            auto_complete,
+        })?;
+        self.list(list_id)
+    }
+
+    /// Delete a list along with its todos. The default list cannot be deleted.
+    pub fn delete_list(&mut self, list_id: usize) -> Option<TodoList> {
+        let list = self.list(list_id)?.clone();
+        self.commit(DomainEvent::ListDeleted { list_id })?;
+        Some(list)
+    }
+
+    /// Todos of a list, filtered and sorted according to `query`.
+    pub fn query(&self, list_id: usize, query: &TodosQuery) -> Vec<&Todo> {
+        self.query_at(list_id, query, &Zoned::now())
+    }
+
+    /// Same as [`TodosState::query`], with due dates filters relative to `now`.
+    pub fn query_at(&self, list_id: usize, query: &TodosQuery, now: &Zoned) -> Vec<&Todo> {
+        let needle = query.q.trim().to_lowercase();
+        let mut todos: Vec<&Todo> = self
+            .todos
+            .iter()
+            .filter(|t| t.list_id == list_id)
+            .filter(|t| query.status.matches(t))
+            .filter(|t| query.due.matches(t, now))
+            .filter(|t| needle.is_empty() || t.content.to_lowercase().contains(&needle))
+            .filter(|t| query.tag.is_empty() || t.tags.contains(&query.tag))
+            .collect();
+
+        // Todos are stored by position, and all sorts below are stable
+        match query.sort {
+            SortOrder::Manual => {}
+            SortOrder::Oldest => todos.sort_by_key(|t| t.id),
+            SortOrder::Newest => todos.sort_by_key(|t| std::cmp::Reverse(t.id)),
+            SortOrder::Alphabetical => {
+                todos.sort_by_cached_key(|t| t.content.to_lowercase());
+            }
+            SortOrder::ActiveFirst => todos.sort_by_key(|t| t.is_done()),
+            // Todos without due date come last
+            SortOrder::Due => todos.sort_by_key(|t| (t.due.is_none(), t.due.clone())),
+            SortOrder::Priority => todos.sort_by_key(|t| std::cmp::Reverse(t.priority)),
+        }
+        todos
+    }
+
+    pub fn add_todo(&mut self, list_id: usize, content: &str) -> Option<&Todo> {
+        self.create_todo(
+            list_id,
+            NewTodo {
+                content: content.to_owned(),
+                ..NewTodo::default()
+            },
+        )
+    }
+
+    /// Add a todo along with its details, in a single event.
+    pub fn create_todo(&mut self, list_id: usize, todo: NewTodo) -> Option<&Todo> {
+        let NewTodo {
+            content,
+            due,
+            reminder_minutes,
+            tags,
+            priority,
+            recurrence,
+        } = todo;
+        self.commit(DomainEvent::TodoAdded {
+            list_id,
+            content,
+            due,
+            reminder_minutes,
+            tags,
+            priority,
+            recurrence,
+        })?;
+        self.todos.last()
+    }
+
+    /// Add imported todos at the end of a list, returning their IDs. Records
+    /// are expected to be validated, see [`super::transfer::parse`].
+    pub fn import_todos(&mut self, list_id: usize, records: &[TodoRecord]) -> Option<Vec<usize>> {
+        self.list(list_id)?;
+        let mut ids = Vec::new();
+        for record in records {
+            let todo = NewTodo {
+                content: record.content.clone(),
+                due: record.due.clone(),
+                tags: record.tags.clone(),
+                priority: record.priority,
+                ..NewTodo::default()
+            };
+            let todo_id = self.create_todo(list_id, todo)?.id;
+            self.set_status(todo_id, record.status)?;
+            ids.push(todo_id);
+        }
+        Some(ids)
+    }
+
+    /// The UID of a todo in calendars: the one of the calendar item it was
+    /// created from, if any.
+    pub fn calendar_uid(&self, todo_id: usize) -> String {
+        self.calendar_uids
+            .iter()
+            .find(|(_, id)| **id == todo_id)
+            .map(|(uid, _)| uid.clone())
+            .unwrap_or_else(|| todo_uid(todo_id))
+    }
+
+    /// The todo a calendar item refers to, see [`TodosState::calendar_uid`].
+    pub fn calendar_todo(&self, uid: &str) -> Option<usize> {
+        self.calendar_uids
+            .get(uid)
+            .copied()
+            .or_else(|| todo_id_from_uid(uid))
+            .filter(|todo_id| self.todo(*todo_id).is_some())
+    }
+
+    /// Create or update todos from calendar items, matched by UID. New todos
+    /// are added at the end of a list. Only the todos that actually changed
+    /// are reported as updated.
+    pub fn import_calendar(
+        &mut self,
+        list_id: usize,
+        items: &[CalendarItem],
+    ) -> Option<BulkSummary> {
+        self.list(list_id)?;
+        let mut summary = BulkSummary::default();
+        for item in items {
+            let (todo_id, version) = match self.calendar_todo(&item.uid) {
+                Some(todo_id) => (todo_id, Some(self.todo(todo_id)?.version)),
+                None => {
+                    let todo_id = self.add_todo(list_id, &item.summary)?.id;
+                    self.commit(DomainEvent::CalendarUidLinked {
+                        uid: item.uid.clone(),
+                        todo_id,
+                    })?;
+                    summary.created.push(todo_id);
+                    (todo_id, None)
+                }
+            };
+
+            self.set_content(todo_id, &item.summary)?;
+            if let Some(status) = item.status {
+                let at = match item.completed {
+                    Some(completed) => completed.to_zoned(TimeZone::system()),
+                    None => Zoned::now(),
+                };
+                if let (_, Some(next)) = self.set_status_at(todo_id, status, &at)? {
+                    summary.created.push(next.id);
+                }
+            }
+            if let Some(priority) = item.priority {
+                self.set_priority(todo_id, priority)?;
+            }
+            // Calendars may use another time zone for the same due date
+            let todo = self.todo(todo_id)?;
+            if todo.due.as_ref().map(Zoned::timestamp) != item.due.as_ref().map(Zoned::timestamp) {
+                let reminder_minutes = todo.reminder_minutes;
+                self.set_due(todo_id, item.due.clone(), reminder_minutes)?;
+            }
+            let todo = self.todo(todo_id)?;
+            let tags: Vec<String> = item
+                .tags
+                .iter()
+                .filter(|tag| !todo.tags.contains(tag))
+                .cloned()
+                .collect();
+            self.add_tags(todo_id, &tags)?;
+
+            let current = self.todo(todo_id)?.version;
+            if version.is_some_and(|version| version != current) {
+                summary.updated.push(todo_id);
+            }
+        }
+        Some(summary)
+    }
+
+    /// What a client idempotency key of `actor` was used for, if it was
+    /// used recently enough.
+    pub fn idempotent_todo(
+        &self,
+        actor: &str,
+        key: &str,
+        request_hash: &str,
+        now: Timestamp,
+    ) -> Option<UsedKey> {
+        self.idempotency_keys.get(actor, key, request_hash, now)
+    }
+
+    /// Remember the todo created with a client idempotency key, so that
+    /// retried requests return it instead of creating it again.
+    pub fn remember_idempotency_key(
+        &mut self,
+        actor: &str,
+        key: &str,
+        request_hash: &str,
+        todo_id: usize,
+        now: Timestamp,
+    ) -> Option<&Todo> {
+        self.commit(DomainEvent::IdempotencyKeyUsed {
+            actor: actor.to_owned(),
+            key: key.to_owned(),
+            request_hash: request_hash.to_owned(),
+            todo_id,
+            at: now,
+        })?;
+        self.todo(todo_id)
+    }
+
+    /// Toggle a todo between done and open, reopened todos go back to the
+    /// backlog. See [`TodosState::set_status`].
+    pub fn toggle_todo(&mut self, todo_id: usize) -> Option<(&Todo, Option<&Todo>)> {
+        self.toggle_todo_at(todo_id, &Zoned::now())
+    }
+
+    /// Same as [`TodosState::toggle_todo`], with the todo completed at `now`.
+    pub fn toggle_todo_at(
+        &mut self,
+        todo_id: usize,
+        now: &Zoned,
+    ) -> Option<(&Todo, Option<&Todo>)> {
+        let next_id = self.todo_counter;
+        self.commit(DomainEvent::TodoToggled {
+            todo_id,
+            at: now.clone(),
+        })?;
+        self.with_next_occurrence(todo_id, next_id)
+    }
+
+    /// Change the status of a todo. Completing a recurring todo generates its
+    /// next occurrence right after it, which is returned alongside.
+    pub fn set_status(
+        &mut self,
+        todo_id: usize,
+        status: TodoStatus,
+    ) -> Option<(&Todo, Option<&Todo>)> {
+        self.set_status_at(todo_id, status, &Zoned::now())
+    }
+
+    /// Same as [`TodosState::set_status`], with the todo completed at `now`.
+    pub fn set_status_at(
+        &mut self,
+        todo_id: usize,
+        status: TodoStatus,
+        now: &Zoned,
+    ) -> Option<(&Todo, Option<&Todo>)> {
+        if self.todo(todo_id)?.status == status {
+            return Some((self.todo(todo_id)?, None));
+        }
+        let next_id = self.todo_counter;
+        self.commit(DomainEvent::TodoStatusSet {
+            todo_id,
+            status,
+            at: now.clone(),
+        })?;
+        self.with_next_occurrence(todo_id, next_id)
+    }
+
+    /// A todo, along with its next occurrence if one was created with
+    /// `next_id`.
+    fn with_next_occurrence(
+        &self,
+        todo_id: usize,
+        next_id: usize,
+    ) -> Option<(&Todo, Option<&Todo>)> {
+        let next = match self.todo_counter > next_id {
+            true => self.todo(next_id),
+            false => None,
+        };
+        Some((self.todo(todo_id)?, next))
+    }
+
+    /// Setters record nothing when the todo is left unchanged.
+    pub fn set_content(&mut self, todo_id: usize, content: &str) -> Option<&Todo> {
+        if self.todo(todo_id)?.content == content {
+            return self.todo(todo_id);
+        }
+        self.commit(DomainEvent::TodoContentSet {
+            todo_id,
             content: content.to_owned(),
-            done: false,
+        })?;
+        self.todo(todo_id)
+    }
+
+    pub fn set_priority(&mut self, todo_id: usize, priority: Priority) -> Option<&Todo> {
+        if self.todo(todo_id)?.priority == priority {
+            return self.todo(todo_id);
+        }
+        self.commit(DomainEvent::TodoPrioritySet { todo_id, priority })?;
+        self.todo(todo_id)
+    }
+
+    pub fn add_subtask(&mut self, todo_id: usize, content: &str) -> Option<&Todo> {
+        self.commit(DomainEvent::SubtaskAdded {
+            todo_id,
+            content: content.to_owned(),
+        })?;
+        self.todo(todo_id)
+    }
+
+    /// Toggle a subtask. When the list auto-completes todos, completing the
+    /// last subtask also completes the todo, see [`TodosState::toggle_todo`].
+    pub fn toggle_subtask(
+        &mut self,
+        todo_id: usize,
+        subtask_id: usize,
+    ) -> Option<(&Todo, Option<&Todo>)> {
+        self.toggle_subtask_at(todo_id, subtask_id, &Zoned::now())
+    }
+
+    /// Same as [`TodosState::toggle_subtask`], with the todo completed at `now`.
+    pub fn toggle_subtask_at(
+        &mut self,
+        todo_id: usize,
+        subtask_id: usize,
+        now: &Zoned,
+    ) -> Option<(&Todo, Option<&Todo>)> {
+        let next_id = self.todo_counter;
+        self.commit(DomainEvent::SubtaskToggled {
+            todo_id,
+            subtask_id,
+            at: now.clone(),
+        })?;
+        self.with_next_occurrence(todo_id, next_id)
+    }
+
+    pub fn delete_subtask(&mut self, todo_id: usize, subtask_id: usize) -> Option<&Todo> {
+        self.commit(DomainEvent::SubtaskDeleted {
+            todo_id,
+            subtask_id,
+        })?;
+        self.todo(todo_id)
+    }
+
+    pub fn set_recurrence(
+        &mut self,
+        todo_id: usize,
+        recurrence: Option<Recurrence>,
+    ) -> Option<&Todo> {
+        if self.todo(todo_id)?.recurrence == recurrence {
+            return self.todo(todo_id);
+        }
+        self.commit(DomainEvent::TodoRecurrenceSet {
+            todo_id,
+            recurrence,
+        })?;
+        self.todo(todo_id)
+    }
+
+    pub fn set_due(
+        &mut self,
+        todo_id: usize,
+        due: Option<Zoned>,
+        reminder_minutes: Option<u32>,
+    ) -> Option<&Todo> {
+        let todo = self.todo(todo_id)?;
+        // Setting the same due date again still re-arms the reminder
+        if todo.due == due && todo.reminder_minutes == reminder_minutes && !todo.reminded {
+            return self.todo(todo_id);
+        }
+        self.commit(DomainEvent::TodoDueSet {
+            todo_id,
+            due,
+            reminder_minutes,
+        })?;
+        self.todo(todo_id)
+    }
+
+    /// When the next pending reminder should fire, if any.
+    pub fn next_reminder_at(&self) -> Option<Timestamp> {
+        self.todos
+            .iter()
+            .filter(|t| !t.is_done() && !t.reminded)
+            .filter_map(|t| t.reminder_at())
+            .min()
+    }
+
+    /// Mark the reminders that should have fired by `now` as sent, and return
+    /// the corresponding todos. Reminders missed while the application was
+    /// down are fired as soon as possible.
+    pub fn take_due_reminders(&mut self, now: Timestamp) -> Vec<Todo> {
+        let todo_ids: Vec<usize> = self
+            .todos
+            .iter()
+            .filter(|t| !t.is_done() && !t.reminded)
+            .filter(|t| t.reminder_at().is_some_and(|at| at <= now))
+            .map(|t| t.id)
+            .collect();
+        if todo_ids.is_empty() {
+            return Vec::new();
+        }
+        self.commit(DomainEvent::RemindersSent {
+            todo_ids: todo_ids.clone(),
+        });
+        todo_ids
+            .iter()
+            .filter_map(|id| self.todo(*id).cloned())
+            .collect()
+    }
+
+    /// Tags are global to all lists, todos refer to them by name.
+    pub fn tags(&self) -> &[Tag] {
+        &self.tags
+    }
+
+    pub fn tag(&self, tag_id: usize) -> Option<&Tag> {
+        self.tags.iter().find(|t| t.id == tag_id)
+    }
+
+    /// Tags with the number of todos using them, either in a given list or
+    /// across all lists.
+    pub fn tag_summaries(&self, list_id: Option<usize>) -> Vec<TagSummary> {
+        self.tags
+            .iter()
+            .map(|tag| TagSummary {
+                id: tag.id,
+                name: tag.name.clone(),
+                color: TagColor::for_name(&tag.name),
+                count: self
+                    .todos
+                    .iter()
+                    .filter(|t| list_id.is_none_or(|list_id| t.list_id == list_id))
+                    .filter(|t| t.tags.contains(&tag.name))
+                    .count(),
+            })
+            .collect()
+    }
+
+    /// Add tags to a todo, creating the ones that do not exist yet. Tag names
+    /// are expected to be normalized.
+    pub fn add_tags(&mut self, todo_id: usize, names: &[String]) -> Option<&Todo> {
+        if !names.is_empty() {
+            self.commit(DomainEvent::TagsAdded {
+                todo_id,
+                names: names.to_vec(),
+            })?;
+        }
+        self.todo(todo_id)
+    }
+
+    /// Rename a tag on every todo using it. Renaming a tag to the name of
+    /// another one is rejected, see [`TodosState::merge_tag`] instead.
+    pub fn rename_tag(&mut self, tag_id: usize, name: &str) -> Result<&Tag, TagError> {
+        let name = normalize_tag(name).ok_or(TagError::InvalidName)?;
+        if self.tags.iter().any(|t| t.name == name && t.id != tag_id) {
+            return Err(TagError::AlreadyExists);
+        }
+        self.commit(DomainEvent::TagRenamed { tag_id, name })
+            .ok_or(TagError::NotFound)?;
+        self.tag(tag_id).ok_or(TagError::NotFound)
+    }
+
+    /// Merge a tag into another one: todos tagged with `tag_id` are tagged
+    /// with `into_id` instead, and `tag_id` is deleted.
+    pub fn merge_tag(&mut self, tag_id: usize, into_id: usize) -> Result<&Tag, TagError> {
+        if tag_id == into_id {
+            return Err(TagError::InvalidName);
+        }
+        self.commit(DomainEvent::TagMerged { tag_id, into_id })
+            .ok_or(TagError::NotFound)?;
+        self.tag(into_id).ok_or(TagError::NotFound)
+    }
+
+    /// Delete a tag, removing it from every todo.
+    pub fn delete_tag(&mut self, tag_id: usize) -> Option<Tag> {
+        let tag = self.tag(tag_id)?.clone();
+        self.commit(DomainEvent::TagDeleted { tag_id })?;
+        Some(tag)
+    }
+
+    /// Apply an operation to several todos. Nothing is changed when one of
+    /// the todos does not exist. Todos already in the requested state are
+    /// left untouched and not reported as updated.
+    pub fn bulk(&mut self, ids: &[usize], operation: &BulkOperation) -> Option<BulkSummary> {
+        self.bulk_at(ids, operation, &Zoned::now())
+    }
+
+    /// Same as [`TodosState::bulk`], with the todos completed at `now`.
+    pub fn bulk_at(
+        &mut self,
+        ids: &[usize],
+        operation: &BulkOperation,
+        now: &Zoned,
+    ) -> Option<BulkSummary> {
+        let mut ids = ids.to_vec();
+        ids.sort_unstable();
+        ids.dedup();
+        let todos = ids
+            .iter()
+            .map(|id| self.todo(*id))
+            .collect::<Option<Vec<&Todo>>>()?;
+
+        let mut summary = BulkSummary::default();
+        match operation {
+            BulkOperation::Complete | BulkOperation::Reopen => {
+                let complete = *operation == BulkOperation::Complete;
+                summary.updated = todos
+                    .iter()
+                    .filter(|t| t.is_done() != complete)
+                    .map(|t| t.id)
+                    .collect();
+            }
+            BulkOperation::Delete => summary.deleted = ids.clone(),
+            BulkOperation::Tag(name) => {
+                summary.updated = todos
+                    .iter()
+                    .filter(|t| !t.tags.contains(name))
+                    .map(|t| t.id)
+                    .collect();
+            }
+        }
+        if summary.updated.is_empty() && summary.deleted.is_empty() {
+            return Some(summary);
+        }
+
+        let next_id = self.todo_counter;
+        self.commit(DomainEvent::BulkApplied {
+            ids,
+            operation: operation.clone(),
+            at: now.clone(),
+        })?;
+        // Next occurrences are numbered in the order todos are completed
+        summary.created = (next_id..self.todo_counter).collect();
+        Some(summary)
+    }
+
+    /// Move the done todos of a list to the trash.
+    pub fn clear_completed(&mut self, list_id: usize) -> Option<BulkSummary> {
+        self.list(list_id)?;
+        let ids: Vec<usize> = self
+            .todos
+            .iter()
+            .filter(|t| t.list_id == list_id && t.is_done())
+            .map(|t| t.id)
+            .collect();
+        self.bulk(&ids, &BulkOperation::Delete)
+    }
+
+    /// Move a todo to the trash, see [`TodosState::restore`].
+    pub fn delete_todo(&mut self, todo_id: usize) -> Option<&TrashedTodo> {
+        self.delete_todo_at(todo_id, Timestamp::now())
+    }
+
+    /// Same as [`TodosState::delete_todo`], with the todo deleted at `now`.
+    pub fn delete_todo_at(&mut self, todo_id: usize, now: Timestamp) -> Option<&TrashedTodo> {
+        self.commit(DomainEvent::TodoDeleted { todo_id, at: now })?;
+        self.trash.last()
+    }
+
+    /// Deleted todos, oldest deletion first.
+    pub fn trash(&self) -> &[TrashedTodo] {
+        &self.trash
+    }
+
+    /// Put trashed todos back at their position in their list, or at the end
+    /// of the default list if their list has been deleted since. Nothing is
+    /// restored when one of the todos is not in the trash.
+    pub fn restore(&mut self, ids: &[usize]) -> Option<Vec<usize>> {
+        let mut restored: Vec<&Todo> = self
+            .trash
+            .iter()
+            .map(|t| &t.todo)
+            .filter(|t| ids.contains(&t.id))
+            .collect();
+        restored.sort_by_key(|t| t.position);
+        let restored_ids = restored.iter().map(|t| t.id).collect();
+
+        self.commit(DomainEvent::TodosRestored { ids: ids.to_vec() })?;
+        Some(restored_ids)
+    }
+
+    /// Permanently delete the todos trashed before `before`.
+    pub fn purge_trash(&mut self, before: Timestamp) -> Vec<TrashedTodo> {
+        let purged: Vec<TrashedTodo> = self
+            .trash
+            .iter()
+            .filter(|t| t.deleted_at < before)
+            .cloned()
+            .collect();
+        if !purged.is_empty() {
+            self.commit(DomainEvent::TrashPurged { before });
+        }
+        purged
+    }
+
+    /// Move a todo at the end of another list.
+    pub fn move_todo_to_list(&mut self, todo_id: usize, list_id: usize) -> Option<&Todo> {
+        self.commit(DomainEvent::TodoMovedToList { todo_id, list_id })?;
+        self.todo(todo_id)
+    }
+
+    /// Move a todo right before or after another one of the same list. Moves
+    /// are expressed relative to todo IDs rather than indexes, so that
+    /// concurrent reorders applied one after the other under the write lock
+    /// always compose into a well defined order.
+    pub fn move_todo(
+        &mut self,
+        todo_id: usize,
+        placement: Placement,
+        target_id: usize,
+    ) -> Option<&Todo> {
+        self.commit(DomainEvent::TodoMoved {
+            todo_id,
+            placement,
+            target_id,
+        })?;
+        self.todo(todo_id)
+    }
+
+    /// Apply a full ordering. Todos listed in `ids` come first, in that order;
+    /// unknown and duplicated IDs are ignored, and todos missing from `ids`
+    /// keep their relative order after the listed ones.
+    pub fn reorder(&mut self, ids: &[usize]) {
+        self.commit(DomainEvent::TodosReordered { ids: ids.to_vec() });
+    }
+
+    /// Apply an event and append it to the log, unless it could not be
+    /// applied in which case the state is left untouched.
+    fn commit(&mut self, event: DomainEvent) -> Option<()> {
+        self.apply(&event)?;
+        self.version += 1;
+        self.events.push(RecordedEvent {
+            seq: self.version,
+            recorded_at: Timestamp::now(),
+            event,
+        });
+        self.trim_events();
+        Some(())
+    }
+
+    /// The only place where the state is changed. Events are validated before
+    /// anything is changed, so that rejected events leave no trace.
+    fn apply(&mut self, event: &DomainEvent) -> Option<()> {
+        match event {
+            DomainEvent::ListAdded { name } => {
+                self.lists.push(TodoList {
+                    id: self.list_counter,
+                    name: name.clone(),
+                    auto_complete: false,
+                });
+                self.list_counter += 1;
+            }
+            DomainEvent::ListRenamed { list_id, name } => {
+                let list = self.lists.iter_mut().find(|l| l.id == *list_id)?;
+                list.name = name.clone();
+            }
+            DomainEvent::ListAutoCompleteSet {
+                list_id,
+                auto_complete,
+            } => {
+                let list = self.lists.iter_mut().find(|l| l.id == *list_id)?;
+                list.auto_complete = *auto_complete;
+            }
+            DomainEvent::ListDeleted { list_id } => {
+                if *list_id == DEFAULT_LIST_ID {
+                    return None;
+                }
+                let position = self.lists.iter().position(|l| l.id == *list_id)?;
+                self.todos.retain(|t| t.list_id != *list_id);
+                self.lists.remove(position);
+            }
+            DomainEvent::TodoAdded {
+                list_id,
+                content,
+                due,
+                reminder_minutes,
+                tags,
+                priority,
+                recurrence,
+            } => {
+                self.list(*list_id)?;
+                let position = self.todos.iter().filter(|t| t.list_id == *list_id).count();
+                let todo_id = self.todo_counter;
+                self.todos.push(Todo {
+                    id: todo_id,
+                    version: 1,
+                    list_id: *list_id,
+                    content: content.clone(),
+                    status: TodoStatus::default(),
+                    completed_at: None,
+                    priority: *priority,
+                    position,
+                    due: due.clone(),
+                    reminder_minutes: *reminder_minutes,
+                    reminded: false,
+                    recurrence: recurrence.clone(),
+                    subtasks: Vec::new(),
+                    tags: Vec::new(),
+                });
+                self.todo_counter += 1;
+                self.apply_tags(todo_id, tags)?;
+                // New todos start at version 1, whatever their details
+                self.todo_mut(todo_id)?.version = 1;
+            }
+            DomainEvent::TodoToggled { todo_id, at } => self.apply_toggle(*todo_id, at)?,
+            DomainEvent::TodoStatusSet {
+                todo_id,
+                status,
+                at,
+            } => self.apply_status(*todo_id, *status, at)?,
+            DomainEvent::TodoContentSet { todo_id, content } => {
+                let todo = self.todo_mut(*todo_id)?;
+                todo.content = content.clone();
+                todo.version += 1;
+            }
+            DomainEvent::TodoPrioritySet { todo_id, priority } => {
+                let todo = self.todo_mut(*todo_id)?;
+                todo.priority = *priority;
+                todo.version += 1;
+            }
+            DomainEvent::TodoDueSet {
+                todo_id,
+                due,
+                reminder_minutes,
+            } => {
+                let todo = self.todo_mut(*todo_id)?;
+                todo.due = due.clone();
+                todo.reminder_minutes = *reminder_minutes;
+                todo.reminded = false;
+                todo.version += 1;
+            }
+            DomainEvent::TodoRecurrenceSet {
+                todo_id,
+                recurrence,
+            } => {
+                let todo = self.todo_mut(*todo_id)?;
+                todo.recurrence = recurrence.clone();
+                todo.version += 1;
+            }
+            DomainEvent::RemindersSent { todo_ids } => {
+                for todo in self.todos.iter_mut().filter(|t| todo_ids.contains(&t.id)) {
+                    todo.reminded = true;
+                    todo.version += 1;
+                }
+            }
+            DomainEvent::SubtaskAdded { todo_id, content } => {
+                let id = self.subtask_counter;
+                let todo = self.todo_mut(*todo_id)?;
+                todo.subtasks.push(Subtask {
+                    id,
+                    content: content.clone(),
+                    done: false,
+                });
+                todo.version += 1;
+                self.subtask_counter += 1;
+            }
+            DomainEvent::SubtaskToggled {
+                todo_id,
+                subtask_id,
+                at,
+            } => {
+                let todo = self.todos.iter_mut().find(|t| t.id == *todo_id)?;
+                let subtask = todo.subtasks.iter_mut().find(|s| s.id == *subtask_id)?;
+                subtask.done = !subtask.done;
+                todo.version += 1;
+
+                let auto_complete = self
+                    .lists
+                    .iter()
+                    .any(|l| l.id == todo.list_id && l.auto_complete);
+                if auto_complete && !todo.is_done() && todo.subtasks.iter().all(|s| s.done) {
+                    self.apply_toggle(*todo_id, at)?;
+                }
+            }
+            DomainEvent::SubtaskDeleted {
+                todo_id,
+                subtask_id,
+            } => {
+                let todo = self.todo_mut(*todo_id)?;
+                let position = todo.subtasks.iter().position(|s| s.id == *subtask_id)?;
+                todo.subtasks.remove(position);
+                todo.version += 1;
+            }
+            DomainEvent::TagsAdded { todo_id, names } => self.apply_tags(*todo_id, names)?,
+            DomainEvent::TagRenamed { tag_id, name } => {
+                let tag = self.tags.iter_mut().find(|t| t.id == *tag_id)?;
+                let previous = std::mem::replace(&mut tag.name, name.clone());
+                // Trashed todos are kept in sync, in case they are restored
+                let trashed = self.trash.iter_mut().map(|t| &mut t.todo);
+                for todo in self.todos.iter_mut().chain(trashed) {
+                    if let Some(todo_tag) = todo.tags.iter_mut().find(|t| **t == previous) {
+                        *todo_tag = name.clone();
+                        todo.version += 1;
+                    }
+                }
+            }
+            DomainEvent::TagMerged { tag_id, into_id } => {
+                let into = self.tag(*into_id)?.name.clone();
+                let name = self.tag(*tag_id)?.name.clone();
+                // Trashed todos are kept in sync, in case they are restored
+                let trashed = self.trash.iter_mut().map(|t| &mut t.todo);
+                for todo in self.todos.iter_mut().chain(trashed) {
+                    if todo.tags.contains(&name) && !todo.tags.contains(&into) {
+                        todo.tags.push(into.clone());
+                        todo.version += 1;
+                    }
+                }
+                self.apply_delete_tag(*tag_id)?;
+            }
+            DomainEvent::TagDeleted { tag_id } => self.apply_delete_tag(*tag_id)?,
+            DomainEvent::BulkApplied { ids, operation, at } => {
+                if !ids.iter().all(|id| self.todos.iter().any(|t| t.id == *id)) {
+                    return None;
+                }
+                match operation {
+                    BulkOperation::Complete | BulkOperation::Reopen => {
+                        let complete = *operation == BulkOperation::Complete;
+                        for id in ids {
+                            if self.todo(*id)?.is_done() != complete {
+                                self.apply_toggle(*id, at)?;
+                            }
+                        }
+                    }
+                    BulkOperation::Delete => self.trash_todos(ids, at.timestamp()),
+                    BulkOperation::Tag(name) => {
+                        for id in ids {
+                            self.apply_tags(*id, std::slice::from_ref(name))?;
+                        }
+                    }
+                }
+            }
+            DomainEvent::TodoDeleted { todo_id, at } => {
+                self.todo(*todo_id)?;
+                self.trash_todos(&[*todo_id], *at);
+            }
+            DomainEvent::TodosRestored { ids } => self.apply_restore(ids)?,
+            DomainEvent::TrashPurged { before } => {
+                self.trash.retain(|t| t.deleted_at >= *before);
+            }
+            DomainEvent::TodoMovedToList { todo_id, list_id } => {
+                self.list(*list_id)?;
+                let from = self.todos.iter().position(|t| t.id == *todo_id)?;
+                let mut todo = self.todos.remove(from);
+                todo.list_id = *list_id;
+                todo.version += 1;
+                self.todos.push(todo);
+                self.renumber();
+            }
+            DomainEvent::TodoMoved {
+                todo_id,
+                placement,
+                target_id,
+            } => {
+                let from = self.todos.iter().position(|t| t.id == *todo_id)?;
+                let target = self.todo(*target_id)?;
+                if target.list_id != self.todos[from].list_id {
+                    return None;
+                }
+
+                let mut todo = self.todos.remove(from);
+                todo.version += 1;
+                let target = self.todos.iter().position(|t| t.id == *target_id);
+                let to = match (target, placement) {
+                    // Moving a todo relative to itself leaves it where it was
+                    (None, _) => from,
+                    (Some(target), Placement::Before) => target,
+                    (Some(target), Placement::After) => target + 1,
+                };
+                self.todos.insert(to, todo);
+                self.renumber();
+            }
+            DomainEvent::IdempotencyKeyUsed {
+                actor,
+                key,
+                request_hash,
+                todo_id,
+                at,
+            } => {
+                self.todo(*todo_id)?;
+                self.idempotency_keys
+                    .insert(actor, key, request_hash, *todo_id, *at);
+            }
+            DomainEvent::CalendarUidLinked { uid, todo_id } => {
+                self.todo(*todo_id)?;
+                self.calendar_uids.insert(uid.clone(), *todo_id);
+            }
+            DomainEvent::ChangeRecorded {
+                todo_id,
+                content,
+                actor,
+                change,
+                at,
+            } => {
+                self.history
+                    .record_at(actor, *todo_id, content, change.clone(), *at);
+            }
+            DomainEvent::TodosReordered { ids } => {
+                let rank = |todo: &Todo| {
+                    ids.iter()
+                        .position(|id| *id == todo.id)
+                        .unwrap_or(ids.len())
+                };
+                self.todos.sort_by_key(rank);
+                self.renumber();
+            }
+        }
+        Some(())
+    }
+
+    fn todo_mut(&mut self, todo_id: usize) -> Option<&mut Todo> {
+        self.todos.iter_mut().find(|t| t.id == todo_id)
+    }
+
+    fn apply_toggle(&mut self, todo_id: usize, now: &Zoned) -> Option<()> {
+        let status = match self.todo(todo_id)?.is_done() {
+            true => TodoStatus::Backlog,
+            false => TodoStatus::Done,
+        };
+        self.apply_status(todo_id, status, now)
+    }
+
+    fn apply_status(&mut self, todo_id: usize, status: TodoStatus, now: &Zoned) -> Option<()> {
+        let index = self.todos.iter().position(|t| t.id == todo_id)?;
+        let todo = &mut self.todos[index];
+        let completed = !todo.is_done() && status == TodoStatus::Done;
+        if completed {
+            todo.completed_at = Some(now.timestamp());
+        } else if status != TodoStatus::Done {
+            todo.completed_at = None;
+        }
+        todo.status = status;
+        todo.version += 1;
+
+        let next_due = match (&todo.recurrence, completed) {
+            (Some(recurrence), true) => recurrence.next_due(todo.due.as_ref(), now),
+            _ => None,
+        };
+        let next = next_due.map(|due| Todo {
+            id: self.todo_counter,
+            version: 1,
+            list_id: todo.list_id,
+            content: todo.content.clone(),
+            status: TodoStatus::default(),
+            completed_at: None,
+            priority: todo.priority,
+            position: todo.position + 1,
+            due: Some(due),
+            reminder_minutes: todo.reminder_minutes,
+            reminded: false,
+            recurrence: todo.recurrence.take(),
+            // The checklist starts over for each occurrence
+            subtasks: todo
+                .subtasks
+                .iter()
+                .map(|subtask| Subtask {
+                    done: false,
+                    ..subtask.clone()
+                })
+                .collect(),
+            tags: todo.tags.clone(),
         });
-        self.todo_counter += 1;
-        self.todos.last().unwrap()
+        if let Some(next) = next {
+            self.todos.insert(index + 1, next);
+            self.todo_counter += 1;
+            self.renumber();
+        }
+        Some(())
+    }
+
+    fn apply_tags(&mut self, todo_id: usize, names: &[String]) -> Option<()> {
+        let index = self.todos.iter().position(|t| t.id == todo_id)?;
+        for name in names {
+            if !self.tags.iter().any(|t| &t.name == name) {
+                self.tags.push(Tag {
+                    id: self.tag_counter,
+                    name: name.clone(),
+                });
+                self.tag_counter += 1;
+            }
+            let todo = &mut self.todos[index];
+            if !todo.tags.contains(name) {
+                todo.tags.push(name.clone());
+                todo.version += 1;
+            }
+        }
+        Some(())
+    }
+
+    fn apply_delete_tag(&mut self, tag_id: usize) -> Option<()> {
+        let position = self.tags.iter().position(|t| t.id == tag_id)?;
+        let tag = self.tags.remove(position);
+        // Trashed todos are kept in sync, in case they are restored
+        let trashed = self.trash.iter_mut().map(|t| &mut t.todo);
+        for todo in self.todos.iter_mut().chain(trashed) {
+            if todo.tags.contains(&tag.name) {
+                todo.tags.retain(|t| *t != tag.name);
+                todo.version += 1;
+            }
+        }
+        Some(())
     }
 
-    pub fn toggle_todo(&mut self, todo_id: usize) -> Option<&Todo> {
-        let todo = self.todos.iter_mut().find(|t| t.id == todo_id)?;
-        todo.done = !todo.done;
-        Some(todo)
+    /// Trashed todos keep their position, so that they can be restored where
+    /// they were.
+    fn trash_todos(&mut self, ids: &[usize], now: Timestamp) {
+        let (trashed, todos) = std::mem::take(&mut self.todos)
+            .into_iter()
+            .partition(|t| ids.contains(&t.id));
+        self.todos = todos;
+        self.trash
+            .extend(trashed.into_iter().map(|todo| TrashedTodo {
+                todo,
+                deleted_at: now,
+            }));
+        self.renumber();
     }
 
-    pub fn delete_todo(&mut self, todo_id: usize) -> Option<Todo> {
-        let position = self.todos.iter().position(|t| t.id == todo_id)?;
-        Some(self.todos.swap_remove(position))
+    fn apply_restore(&mut self, ids: &[usize]) -> Option<()> {
+        if !ids
+            .iter()
+            .all(|id| self.trash.iter().any(|t| t.todo.id == *id))
+        {
+            return None;
+        }
+        let (restored, trash): (Vec<TrashedTodo>, Vec<TrashedTodo>) =
+            std::mem::take(&mut self.trash)
+                .into_iter()
+                .partition(|t| ids.contains(&t.todo.id));
+        self.trash = trash;
+
+        let mut restored: Vec<Todo> = restored.into_iter().map(|t| t.todo).collect();
+        // Inserting the lowest positions first puts todos deleted together
+        // back in their original order
+        restored.sort_by_key(|t| t.position);
+        for mut todo in restored {
+            if self.list(todo.list_id).is_none() {
+                todo.list_id = DEFAULT_LIST_ID;
+                todo.position = usize::MAX;
+            }
+            let index = self
+                .todos
+                .iter()
+                .position(|t| t.list_id == todo.list_id && t.position >= todo.position)
+                .or_else(|| {
+                    self.todos
+                        .iter()
+                        .rposition(|t| t.list_id == todo.list_id)
+                        .map(|index| index + 1)
+                })
+                .unwrap_or(self.todos.len());
+            self.todos.insert(index, todo);
+            self.renumber();
+        }
+        Some(())
+    }
+
+    /// Positions are per list, and follow the order of `self.todos`.
+    fn renumber(&mut self) {
+        let mut next_positions: HashMap<usize, usize> = HashMap::new();
+        for todo in self.todos.iter_mut() {
+            let position = next_positions.entry(todo.list_id).or_default();
+            todo.position = *position;
+            *position += 1;
+        }
     }
 }
 
@@ -52,3 +1676,790 @@ impl Default for TodosState {
         TodosState::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use jiff::{Timestamp, Zoned};
+
+    use crate::todos::{
+        calendar::{CalendarItem, todo_uid},
+        recurrence::{Recurrence, RecurrenceRule},
+        tags::TagError,
+    };
+
+    use super::{
+        BulkOperation, BulkSummary, DEFAULT_LIST_ID, DueFilter, ListSummary, NewTodo, Placement,
+        Priority, RETAINED_EVENTS, SortOrder, StatusFilter, TodoStatus, TodosQuery, TodosState,
+    };
+
+    fn trash_ids(state: &TodosState) -> Vec<usize> {
+        state.trash().iter().map(|t| t.todo.id).collect()
+    }
+
+    fn contents(state: &TodosState, query: &TodosQuery) -> Vec<String> {
+        state
+            .query(DEFAULT_LIST_ID, query)
+            .iter()
+            .map(|t| t.content.clone())
+            .collect()
+    }
+
+    fn positions(state: &TodosState) -> Vec<(usize, usize)> {
+        state.todos().iter().map(|t| (t.id, t.position)).collect()
+    }
+
+    fn sample_state() -> TodosState {
+        let mut state = TodosState::new();
+        state.add_todo(DEFAULT_LIST_ID, "buy milk");
+        state.add_todo(DEFAULT_LIST_ID, "Call mom");
+        state.add_todo(DEFAULT_LIST_ID, "answer emails");
+        state.toggle_todo(1);
+        state
+    }
+
+    #[test]
+    fn test_delete_keeps_insertion_order() {
+        let mut state = sample_state();
+        state.delete_todo(0);
+
+        assert_eq!(
+            contents(&state, &TodosQuery::default()),
+            vec!["Call mom", "answer emails"]
+        );
+    }
+
+    #[test]
+    fn test_filter_by_status() {
+        let state = sample_state();
+
+        let active = TodosQuery {
+            status: StatusFilter::Active,
+            ..Default::default()
+        };
+        assert_eq!(contents(&state, &active), vec!["buy milk", "answer emails"]);
+
+        let done = TodosQuery {
+            status: StatusFilter::Done,
+            ..Default::default()
+        };
+        assert_eq!(contents(&state, &done), vec!["Call mom"]);
+    }
+
+    #[test]
+    fn test_search_is_case_insensitive() {
+        let state = sample_state();
+        let query = TodosQuery {
+            q: "  CALL ".to_owned(),
+            ..Default::default()
+        };
+
+        assert_eq!(contents(&state, &query), vec!["Call mom"]);
+    }
+
+    #[test]
+    fn test_sort_orders() {
+        let state = sample_state();
+        let with_sort = |sort| TodosQuery {
+            sort,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            contents(&state, &with_sort(SortOrder::Newest)),
+            vec!["answer emails", "Call mom", "buy milk"]
+        );
+        assert_eq!(
+            contents(&state, &with_sort(SortOrder::Alphabetical)),
+            vec!["answer emails", "buy milk", "Call mom"]
+        );
+        assert_eq!(
+            contents(&state, &with_sort(SortOrder::ActiveFirst)),
+            vec!["buy milk", "answer emails", "Call mom"]
+        );
+    }
+
+    #[test]
+    fn test_move_todo_before_and_after() {
+        let mut state = sample_state();
+
+        state.move_todo(2, Placement::Before, 0).unwrap();
+        assert_eq!(positions(&state), vec![(2, 0), (0, 1), (1, 2)]);
+
+        state.move_todo(2, Placement::After, 1).unwrap();
+        assert_eq!(positions(&state), vec![(0, 0), (1, 1), (2, 2)]);
+
+        state.move_todo(1, Placement::After, 1).unwrap();
+        assert_eq!(positions(&state), vec![(0, 0), (1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn test_move_todo_unknown_ids() {
+        let mut state = sample_state();
+
+        assert!(state.move_todo(42, Placement::Before, 0).is_none());
+        assert!(state.move_todo(0, Placement::Before, 42).is_none());
+        assert_eq!(positions(&state), vec![(0, 0), (1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn test_reorder_with_partial_ordering() {
+        let mut state = sample_state();
+        state.add_todo(DEFAULT_LIST_ID, "walk the dog");
+
+        state.reorder(&[3, 42, 1, 3]);
+        assert_eq!(positions(&state), vec![(3, 0), (1, 1), (0, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn test_positions_after_delete() {
+        let mut state = sample_state();
+        state.delete_todo(1);
+        let todo = state.add_todo(DEFAULT_LIST_ID, "walk the dog").unwrap();
+        assert_eq!(todo.position, 2);
+
+        assert_eq!(positions(&state), vec![(0, 0), (2, 1), (3, 2)]);
+    }
+
+    #[test]
+    fn test_lists_have_their_own_todos_and_counters() {
+        let mut state = sample_state();
+        let list_id = state.add_list("Groceries").id;
+        state.add_todo(list_id, "eggs");
+        assert!(state.add_todo(42, "nowhere").is_none());
+
+        let moved = state.move_todo_to_list(0, list_id).unwrap();
+        assert_eq!((moved.list_id, moved.position), (list_id, 1));
+        assert_eq!(positions(&state), vec![(1, 0), (2, 1), (3, 0), (0, 1)]);
+
+        assert_eq!(
+            state.list_summaries(),
+            vec![
+                ListSummary {
+                    id: DEFAULT_LIST_ID,
+                    name: "Inbox".to_owned(),
+                    open: 1,
+                    done: 1,
+                    auto_complete: false,
+                },
+                ListSummary {
+                    id: list_id,
+                    name: "Groceries".to_owned(),
+                    open: 2,
+                    done: 0,
+                    auto_complete: false,
+                },
+            ]
+        );
+        assert_eq!(
+            contents(&state, &TodosQuery::default()),
+            vec!["Call mom", "answer emails"]
+        );
+    }
+
+    #[test]
+    fn test_move_todo_across_lists_is_rejected() {
+        let mut state = sample_state();
+        let list_id = state.add_list("Groceries").id;
+        let eggs = state.add_todo(list_id, "eggs").unwrap().id;
+
+        assert!(state.move_todo(eggs, Placement::Before, 0).is_none());
+    }
+
+    #[test]
+    fn test_delete_list() {
+        let mut state = sample_state();
+        let list_id = state.add_list("Groceries").id;
+        state.add_todo(list_id, "eggs");
+
+        assert!(state.delete_list(DEFAULT_LIST_ID).is_none());
+        assert_eq!(state.delete_list(list_id).unwrap().name, "Groceries");
+        assert_eq!(state.lists().len(), 1);
+        assert!(state.todos().iter().all(|t| t.list_id == DEFAULT_LIST_ID));
+    }
+
+    fn zoned(input: &str) -> Zoned {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    fn test_due_filters() {
+        let mut state = sample_state();
+        state.set_due(0, Some(zoned("2025-03-10T08:00[Europe/Paris]")), None);
+        state.set_due(1, Some(zoned("2025-03-09T08:00[Europe/Paris]")), None);
+        state.set_due(2, Some(zoned("2025-03-10T23:30[Europe/Paris]")), None);
+        state.add_todo(DEFAULT_LIST_ID, "no due date");
+        let now = zoned("2025-03-10T12:00[Europe/Paris]");
+
+        let with_due = |due| TodosQuery {
+            due,
+            ..Default::default()
+        };
+        let ids = |query: &TodosQuery| -> Vec<usize> {
+            state
+                .query_at(DEFAULT_LIST_ID, query, &now)
+                .iter()
+                .map(|t| t.id)
+                .collect()
+        };
+
+        // The todo due yesterday is done, so not overdue
+        assert_eq!(ids(&with_due(DueFilter::Overdue)), vec![0]);
+        assert_eq!(ids(&with_due(DueFilter::Today)), vec![0, 2]);
+        assert_eq!(ids(&with_due(DueFilter::Upcoming)), vec![2]);
+        assert_eq!(
+            ids(&TodosQuery {
+                sort: SortOrder::Due,
+                ..Default::default()
+            }),
+            vec![1, 0, 2, 3]
+        );
+    }
+
+    #[test]
+    fn test_due_today_uses_the_viewer_time_zone() {
+        let mut state = sample_state();
+        // 2025-03-10 23:30 in Paris is already the 11th in Tokyo
+        state.set_due(0, Some(zoned("2025-03-10T23:30[Europe/Paris]")), None);
+        let query = TodosQuery {
+            due: DueFilter::Today,
+            ..Default::default()
+        };
+
+        let in_tokyo = zoned("2025-03-11T09:00[Asia/Tokyo]");
+        assert_eq!(state.query_at(DEFAULT_LIST_ID, &query, &in_tokyo).len(), 1);
+        let in_paris = zoned("2025-03-11T09:00[Europe/Paris]");
+        assert!(
+            state
+                .query_at(DEFAULT_LIST_ID, &query, &in_paris)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_reminders_fire_once() {
+        let mut state = sample_state();
+        state.set_due(0, Some(zoned("2025-03-10T12:00[UTC]")), Some(15));
+        state.set_due(2, Some(zoned("2025-03-10T13:00[UTC]")), Some(60));
+        // Done todos are never reminded
+        state.set_due(1, Some(zoned("2025-03-10T09:00[UTC]")), Some(0));
+
+        let first: Timestamp = "2025-03-10T11:45Z".parse().unwrap();
+        assert_eq!(state.next_reminder_at(), Some(first));
+        assert!(
+            state
+                .take_due_reminders("2025-03-10T11:44Z".parse().unwrap())
+                .is_empty()
+        );
+
+        let fired = state.take_due_reminders("2025-03-10T12:30Z".parse().unwrap());
+        let fired: Vec<usize> = fired.iter().map(|t| t.id).collect();
+        assert_eq!(fired, vec![0, 2]);
+        assert!(
+            state
+                .take_due_reminders("2025-03-10T12:30Z".parse().unwrap())
+                .is_empty()
+        );
+        assert_eq!(state.next_reminder_at(), None);
+
+        // Changing the due date re-arms the reminder
+        state.set_due(0, Some(zoned("2025-03-11T12:00[UTC]")), Some(15));
+        assert_eq!(
+            state.next_reminder_at(),
+            Some("2025-03-11T11:45Z".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_completing_recurring_todo_creates_next_occurrence() {
+        let mut state = sample_state();
+        let due = zoned("2025-01-31T18:00[Europe/Paris]");
+        state.set_due(0, Some(due.clone()), Some(30));
+        state.set_recurrence(
+            0,
+            Some(Recurrence {
+                rule: RecurrenceRule::Month { day: 31 },
+                at: Some(due.time()),
+            }),
+        );
+
+        let (done, next) = state.toggle_todo_at(0, &due).unwrap();
+        assert!(done.is_done());
+        assert!(done.recurrence.is_none());
+        let next = next.expect("next occurrence should be created");
+        assert_eq!(
+            (next.id, next.content.as_str(), next.is_done()),
+            (3, "buy milk", false)
+        );
+        assert_eq!(next.due, Some(zoned("2025-02-28T18:00[Europe/Paris]")));
+        assert_eq!(next.reminder_minutes, Some(30));
+        assert!(next.recurrence.is_some());
+        assert!(next.subtasks.is_empty());
+        assert_eq!(positions(&state), vec![(0, 0), (3, 1), (1, 2), (2, 3)]);
+
+        // Reopening and completing again does not create another occurrence
+        state.toggle_todo_at(0, &due);
+        let (_, next) = state.toggle_todo_at(0, &due).unwrap();
+        assert!(next.is_none());
+        assert_eq!(state.todos().len(), 4);
+    }
+
+    #[test]
+    fn test_status_workflow() {
+        let mut state = sample_state();
+        state.set_status(0, TodoStatus::InProgress);
+        state.set_status(2, TodoStatus::Blocked);
+
+        let with_status = |status| {
+            let query = TodosQuery {
+                status,
+                ..Default::default()
+            };
+            contents(&state, &query)
+        };
+        assert_eq!(with_status(StatusFilter::InProgress), vec!["buy milk"]);
+        assert_eq!(with_status(StatusFilter::Blocked), vec!["answer emails"]);
+        assert_eq!(
+            with_status(StatusFilter::Active),
+            vec!["buy milk", "answer emails"]
+        );
+
+        // Toggling completes any open status, and reopens to the backlog
+        let (todo, _) = state.toggle_todo(2).unwrap();
+        assert_eq!(todo.status, TodoStatus::Done);
+        let (todo, _) = state.toggle_todo(2).unwrap();
+        assert_eq!(todo.status, TodoStatus::Backlog);
+    }
+
+    #[test]
+    fn test_moving_recurring_todo_to_done_creates_next_occurrence() {
+        let mut state = sample_state();
+        let due = zoned("2025-01-31T18:00[Europe/Paris]");
+        state.set_due(0, Some(due.clone()), None);
+        state.set_priority(0, Priority::High);
+        state.set_recurrence(
+            0,
+            Some(Recurrence {
+                rule: RecurrenceRule::Day,
+                at: Some(due.time()),
+            }),
+        );
+
+        let (_, next) = state.set_status_at(0, TodoStatus::Blocked, &due).unwrap();
+        assert!(next.is_none());
+        let (_, next) = state.set_status_at(0, TodoStatus::Done, &due).unwrap();
+        let next = next.expect("next occurrence should be created");
+        assert_eq!(next.status, TodoStatus::Backlog);
+        assert_eq!(next.priority, Priority::High);
+        let (_, next) = state.set_status_at(0, TodoStatus::Done, &due).unwrap();
+        assert!(next.is_none());
+    }
+
+    #[test]
+    fn test_sort_by_priority() {
+        let mut state = sample_state();
+        state.set_priority(0, Priority::Low);
+        state.set_priority(2, Priority::Urgent);
+        let query = TodosQuery {
+            sort: SortOrder::Priority,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            contents(&state, &query),
+            vec!["answer emails", "Call mom", "buy milk"]
+        );
+    }
+
+    #[test]
+    fn test_subtasks() {
+        let mut state = sample_state();
+        state.add_subtask(0, "skimmed");
+        let todo = state.add_subtask(0, "organic").unwrap();
+        let ids: Vec<usize> = todo.subtasks.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![0, 1]);
+        assert!(state.add_subtask(42, "nope").is_none());
+
+        // Without auto-completion, the todo stays open
+        state.toggle_subtask(0, 0);
+        let (todo, _) = state.toggle_subtask(0, 1).unwrap();
+        assert!(todo.subtasks.iter().all(|s| s.done));
+        assert!(!todo.is_done());
+
+        let todo = state.delete_subtask(0, 0).unwrap();
+        assert_eq!(todo.subtasks.len(), 1);
+        assert!(state.delete_subtask(0, 0).is_none());
+        assert!(state.toggle_subtask(0, 0).is_none());
+    }
+
+    #[test]
+    fn test_completing_all_subtasks_auto_completes_todo() {
+        let mut state = sample_state();
+        state.set_list_auto_complete(DEFAULT_LIST_ID, true);
+        let due = zoned("2025-03-10T09:00[UTC]");
+        state.set_due(0, Some(due.clone()), None);
+        state.set_recurrence(
+            0,
+            Some(Recurrence {
+                rule: RecurrenceRule::Day,
+                at: Some(due.time()),
+            }),
+        );
+        state.add_subtask(0, "skimmed");
+        state.add_subtask(0, "organic");
+
+        let (todo, next) = state.toggle_subtask_at(0, 0, &due).unwrap();
+        assert!(!todo.is_done());
+        assert!(next.is_none());
+
+        let (todo, next) = state.toggle_subtask_at(0, 1, &due).unwrap();
+        assert!(todo.is_done());
+        let next = next.expect("recurring todo should have a next occurrence");
+        assert_eq!(next.subtasks.len(), 2);
+        assert!(next.subtasks.iter().all(|s| !s.done));
+    }
+
+    #[test]
+    fn test_tags() {
+        let mut state = sample_state();
+        state.add_tags(0, &["shopping".to_owned(), "home".to_owned()]);
+        state.add_tags(2, &["home".to_owned(), "home".to_owned()]);
+        let other_list = state.add_list("Work").id;
+        state.add_todo(other_list, "write report");
+        state.add_tags(3, &["home".to_owned()]);
+
+        let counts = |state: &TodosState, list_id| {
+            state
+                .tag_summaries(list_id)
+                .into_iter()
+                .map(|t| (t.name, t.count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            counts(&state, None),
+            vec![("shopping".to_owned(), 1), ("home".to_owned(), 3)]
+        );
+        assert_eq!(
+            counts(&state, Some(DEFAULT_LIST_ID)),
+            vec![("shopping".to_owned(), 1), ("home".to_owned(), 2)]
+        );
+
+        let query = TodosQuery {
+            tag: "home".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(contents(&state, &query), vec!["buy milk", "answer emails"]);
+    }
+
+    #[test]
+    fn test_rename_merge_and_delete_tags() {
+        let mut state = sample_state();
+        state.add_tags(0, &["shopping".to_owned(), "groceries".to_owned()]);
+        state.add_tags(1, &["groceries".to_owned()]);
+        state.add_tags(2, &["work".to_owned()]);
+        let tags = |state: &TodosState| -> Vec<Vec<String>> {
+            state.todos().iter().map(|t| t.tags.clone()).collect()
+        };
+
+        assert_eq!(state.rename_tag(2, "#Office").map(|t| t.id), Ok(2));
+        assert_eq!(
+            state.rename_tag(2, "shopping"),
+            Err(TagError::AlreadyExists)
+        );
+        assert_eq!(state.rename_tag(2, "two words"), Err(TagError::InvalidName));
+        assert_eq!(state.rename_tag(9, "nine"), Err(TagError::NotFound));
+        assert_eq!(tags(&state)[2], vec!["office"]);
+
+        assert_eq!(state.merge_tag(1, 0).map(|t| t.id), Ok(0));
+        assert_eq!(
+            tags(&state),
+            vec![vec!["shopping"], vec!["shopping"], vec!["office"]]
+        );
+        assert!(state.tag(1).is_none());
+        assert_eq!(state.merge_tag(0, 0), Err(TagError::InvalidName));
+        assert_eq!(state.merge_tag(0, 1), Err(TagError::NotFound));
+
+        assert!(state.delete_tag(0).is_some());
+        assert_eq!(tags(&state), vec![vec![], vec![], vec!["office"]]);
+        assert_eq!(state.tags().len(), 1);
+    }
+
+    #[test]
+    fn test_bulk_operations() {
+        let mut state = sample_state();
+        let due = zoned("2025-01-31T18:00[Europe/Paris]");
+        state.set_due(2, Some(due.clone()), None);
+        state.set_recurrence(
+            2,
+            Some(Recurrence {
+                rule: RecurrenceRule::Day,
+                at: Some(due.time()),
+            }),
+        );
+
+        // Todo 1 is already done
+        let summary = state
+            .bulk_at(&[2, 1, 0, 0], &BulkOperation::Complete, &due)
+            .unwrap();
+        assert_eq!(
+            summary,
+            BulkSummary {
+                updated: vec![0, 2],
+                created: vec![3],
+                deleted: vec![],
+            }
+        );
+        assert!(state.todos().iter().take(3).all(|t| t.is_done()));
+
+        let summary = state.bulk(&[0, 3], &BulkOperation::Reopen).unwrap();
+        assert_eq!(summary.updated, vec![0]);
+
+        let summary = state
+            .bulk(&[0, 3], &BulkOperation::Tag("home".to_owned()))
+            .unwrap();
+        assert_eq!(summary.updated, vec![0, 3]);
+        assert_eq!(state.tag_summaries(None)[0].count, 2);
+
+        // Unknown IDs abort the whole operation
+        assert!(state.bulk(&[0, 42], &BulkOperation::Delete).is_none());
+        assert_eq!(state.todos().len(), 4);
+
+        let summary = state.bulk(&[3, 0], &BulkOperation::Delete).unwrap();
+        assert_eq!(summary.deleted, vec![0, 3]);
+        assert_eq!(positions(&state), vec![(1, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn test_clear_completed() {
+        let mut state = sample_state();
+        let other_list = state.add_list("Work").id;
+        state.add_todo(other_list, "write report");
+        state.toggle_todo(3);
+
+        let summary = state.clear_completed(DEFAULT_LIST_ID).unwrap();
+        assert_eq!(summary.deleted, vec![1]);
+        assert_eq!(
+            contents(&state, &TodosQuery::default()),
+            vec!["buy milk", "answer emails"]
+        );
+        assert_eq!(state.todos().len(), 3);
+        assert!(state.clear_completed(42).is_none());
+    }
+
+    #[test]
+    fn test_restore_deleted_todos_at_their_position() {
+        let mut state = sample_state();
+        state.add_todo(DEFAULT_LIST_ID, "water plants");
+        let deleted_at = "2025-01-01T10:00:00Z".parse::<Timestamp>().unwrap();
+
+        state.delete_todo_at(1, deleted_at);
+        state.bulk(&[0, 3], &BulkOperation::Delete);
+        assert_eq!(
+            contents(&state, &TodosQuery::default()),
+            vec!["answer emails"]
+        );
+        assert_eq!(trash_ids(&state), vec![1, 0, 3]);
+        assert!(state.restore(&[0, 42]).is_none());
+
+        state.add_todo(DEFAULT_LIST_ID, "new todo");
+        assert_eq!(state.restore(&[3, 0]), Some(vec![0, 3]));
+        assert_eq!(
+            contents(&state, &TodosQuery::default()),
+            vec!["buy milk", "answer emails", "water plants", "new todo"]
+        );
+        state.restore(&[1]);
+        assert_eq!(
+            contents(&state, &TodosQuery::default()),
+            vec![
+                "buy milk",
+                "Call mom",
+                "answer emails",
+                "water plants",
+                "new todo"
+            ]
+        );
+        assert!(state.trash().is_empty());
+    }
+
+    #[test]
+    fn test_restore_todo_of_deleted_list() {
+        let mut state = sample_state();
+        let other_list = state.add_list("Work").id;
+        state.add_todo(other_list, "write report");
+        state.delete_todo(3);
+        state.delete_list(other_list);
+
+        state.restore(&[3]);
+        let todo = state.todos().last().unwrap();
+        assert_eq!(
+            (todo.id, todo.list_id, todo.position),
+            (3, DEFAULT_LIST_ID, 3)
+        );
+    }
+
+    #[test]
+    fn test_purge_trash() {
+        let mut state = sample_state();
+        let at = |input: &str| input.parse::<Timestamp>().unwrap();
+        state.delete_todo_at(0, at("2025-01-01T10:00:00Z"));
+        state.delete_todo_at(1, at("2025-01-02T10:00:00Z"));
+        state.add_tags(2, &["home".to_owned()]);
+        state.bulk(&[2], &BulkOperation::Delete);
+
+        // Tag changes also apply to trashed todos
+        state.rename_tag(0, "house").unwrap();
+        assert_eq!(state.trash()[2].todo.tags, vec!["house"]);
+
+        let purged = state.purge_trash(at("2025-01-02T00:00:00Z"));
+        assert_eq!(purged.len(), 1);
+        assert_eq!(trash_ids(&state), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_replay_rebuilds_state() {
+        let mut state = sample_state();
+        let due = zoned("2025-01-31T18:00[Europe/Paris]");
+        state.set_due(0, Some(due.clone()), Some(30));
+        state.set_recurrence(
+            0,
+            Some(Recurrence {
+                rule: RecurrenceRule::DaysAfterCompletion { days: 2 },
+                at: None,
+            }),
+        );
+        state.add_tags(2, &["work".to_owned()]);
+        state.add_subtask(2, "draft");
+        let list_id = state.add_list("Groceries").id;
+        state.move_todo_to_list(2, list_id);
+        state.bulk(&[0, 1], &BulkOperation::Complete);
+        state.delete_todo(1);
+        state.move_todo(3, Placement::Before, 0);
+        // Rejected changes are not recorded
+        assert!(state.restore(&[42]).is_none());
+        assert_eq!(state.version() as usize, state.events().len());
+
+        assert_eq!(TodosState::replay(state.events()), state);
+    }
+
+    #[test]
+    fn test_state_at_version() {
+        let mut state = sample_state();
+        state.delete_todo(0);
+        let version = state.version();
+        state.restore(&[0]);
+        state.set_content(0, "buy oat milk");
+
+        let past = state.at_version(version).unwrap();
+        assert_eq!(trash_ids(&past), vec![0]);
+        assert_eq!(past.todos().len(), 2);
+        assert_eq!(state.at(Timestamp::MIN).unwrap().todos().len(), 0);
+        assert_eq!(state.at(Timestamp::MAX).unwrap(), state);
+    }
+
+    #[test]
+    fn test_events_window() {
+        let mut state = TodosState::new();
+        for i in 0..3 * RETAINED_EVENTS {
+            state.add_todo(DEFAULT_LIST_ID, &format!("todo {i}"));
+        }
+        // Nothing to keep them for when the todos only live in memory
+        let len = state.events().len();
+        assert!((RETAINED_EVENTS..=2 * RETAINED_EVENTS).contains(&len));
+        assert_eq!(state.events().last().unwrap().seq, state.version());
+        assert!(state.at_version(1).is_none());
+        assert!(state.events_after(0).is_none());
+        let latest = state.version() - 10;
+        assert_eq!(state.events_after(latest).unwrap().len(), 10);
+
+        // Events yet to be persisted are kept
+        state.set_persisted(state.version());
+        let persisted = state.version();
+        for i in 0..3 * RETAINED_EVENTS {
+            state.add_todo(DEFAULT_LIST_ID, &format!("todo {i}"));
+        }
+        assert!(state.events_after(persisted).is_some());
+        state.set_persisted(state.version());
+        assert!(state.events().len() <= 2 * RETAINED_EVENTS);
+    }
+
+    #[test]
+    fn test_create_todo() {
+        let mut state = TodosState::new();
+        let todo = NewTodo {
+            content: "file taxes".to_owned(),
+            due: Some(zoned("2025-05-31T12:00[Europe/Paris]")),
+            reminder_minutes: Some(60),
+            tags: vec!["admin".to_owned()],
+            priority: Priority::High,
+            recurrence: None,
+        };
+        let todo = state.create_todo(DEFAULT_LIST_ID, todo).unwrap();
+        assert_eq!(todo.version, 1);
+        assert_eq!(todo.tags, vec!["admin".to_owned()]);
+        assert_eq!(todo.priority, Priority::High);
+        assert_eq!(todo.reminder_minutes, Some(60));
+        // Created in a single event
+        assert_eq!(state.events().len(), 1);
+        assert_eq!(state.tags().len(), 1);
+        assert_eq!(TodosState::replay(state.events()), state);
+    }
+
+    #[test]
+    fn test_todo_versions() {
+        let mut state = sample_state();
+        let version = |state: &TodosState, id| state.todo(id).unwrap().version;
+        assert_eq!(version(&state, 0), 1);
+        assert_eq!(version(&state, 1), 2);
+
+        state.set_content(0, "buy oat milk");
+        state.add_tags(0, &["shop".to_owned()]);
+        assert_eq!(version(&state, 0), 3);
+        // Unchanged todos keep their version
+        state.set_status(0, TodoStatus::Backlog);
+        state.add_tags(0, &["shop".to_owned()]);
+        state.move_todo(2, Placement::Before, 0);
+        assert_eq!(version(&state, 0), 3);
+        assert_eq!(version(&state, 2), 2);
+    }
+
+    #[test]
+    fn test_import_calendar() {
+        let mut state = TodosState::new();
+        state.add_todo(DEFAULT_LIST_ID, "buy milk");
+        let completed: Timestamp = "2025-01-30T10:00:00Z".parse().unwrap();
+        let item = |uid: &str, summary: &str| CalendarItem {
+            uid: uid.to_owned(),
+            summary: summary.to_owned(),
+            status: None,
+            priority: None,
+            due: None,
+            completed: None,
+            tags: Vec::new(),
+        };
+        let items = vec![
+            CalendarItem {
+                status: Some(TodoStatus::Done),
+                completed: Some(completed),
+                ..item(&todo_uid(0), "buy milk")
+            },
+            CalendarItem {
+                priority: Some(Priority::High),
+                ..item("abc-123", "renew passport")
+            },
+        ];
+
+        let summary = state.import_calendar(DEFAULT_LIST_ID, &items).unwrap();
+        assert_eq!(summary.updated, vec![0]);
+        assert_eq!(summary.created, vec![1]);
+        assert_eq!(state.todo(0).unwrap().completed_at, Some(completed));
+        assert_eq!(state.todo(1).unwrap().priority, Priority::High);
+        assert_eq!(state.calendar_uid(1), "abc-123");
+        assert_eq!(state.calendar_todo("abc-123"), Some(1));
+
+        // Syncing the same calendar again changes nothing
+        let summary = state.import_calendar(DEFAULT_LIST_ID, &items).unwrap();
+        assert_eq!(summary, BulkSummary::default());
+
+        state.toggle_todo(0);
+        assert_eq!(state.todo(0).unwrap().completed_at, None);
+    }
+}
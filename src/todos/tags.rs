@@ -0,0 +1,160 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tag {
+    pub id: usize,
+    pub name: String,
+}
+
+/// A tag along with the number of todos it is attached to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TagSummary {
+    pub id: usize,
+    pub name: String,
+    pub color: TagColor,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagError {
+    NotFound,
+    InvalidName,
+    AlreadyExists,
+}
+
+impl fmt::Display for TagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagError::NotFound => write!(f, "tag not found"),
+            TagError::InvalidName => write!(f, "invalid tag name"),
+            TagError::AlreadyExists => write!(f, "tag already exists"),
+        }
+    }
+}
+
+/// daisyUI badge colors, picked from the tag name so that a tag always has
+/// the same color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagColor {
+    Primary,
+    Secondary,
+    Accent,
+    Info,
+    Success,
+    Warning,
+    Error,
+    Neutral,
+}
+
+impl TagColor {
+    const VARIANTS: [TagColor; 8] = [
+        TagColor::Primary,
+        TagColor::Secondary,
+        TagColor::Accent,
+        TagColor::Info,
+        TagColor::Success,
+        TagColor::Warning,
+        TagColor::Error,
+        TagColor::Neutral,
+    ];
+
+    pub fn for_name(name: &str) -> TagColor {
+        // FNV-1a, stable across runs unlike the std hasher
+        let hash = name.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+        TagColor::VARIANTS[(hash % TagColor::VARIANTS.len() as u64) as usize]
+    }
+
+    pub fn badge_class(&self) -> &'static str {
+        match self {
+            TagColor::Primary => "badge-primary",
+            TagColor::Secondary => "badge-secondary",
+            TagColor::Accent => "badge-accent",
+            TagColor::Info => "badge-info",
+            TagColor::Success => "badge-success",
+            TagColor::Warning => "badge-warning",
+            TagColor::Error => "badge-error",
+            TagColor::Neutral => "badge-neutral",
+        }
+    }
+}
+
+/// Normalize a tag name: an optional leading `#` is dropped, and names are
+/// lowercase ASCII letters, digits, `-` and `_`.
+pub fn normalize_tag(name: &str) -> Option<String> {
+    let name = name.trim();
+    let name = name.strip_prefix('#').unwrap_or(name);
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| name.to_lowercase())
+}
+
+/// Extract inline `#tags` from a todo content, returning the content without
+/// them and the tags in order of appearance.
+pub fn parse_tags(content: &str) -> (String, Vec<String>) {
+    let mut tags: Vec<String> = Vec::new();
+    let mut words = Vec::new();
+    for word in content.split_whitespace() {
+        match word.starts_with('#').then(|| normalize_tag(word)).flatten() {
+            Some(tag) => {
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+            None => words.push(word),
+        }
+    }
+
+    if tags.is_empty() {
+        return (content.to_owned(), tags);
+    }
+    (words.join(" "), tags)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TagColor, normalize_tag, parse_tags};
+
+    #[test]
+    fn test_parse_tags() {
+        assert_eq!(
+            parse_tags("Buy #Groceries milk #urgent #groceries"),
+            (
+                "Buy milk".to_owned(),
+                vec!["groceries".to_owned(), "urgent".to_owned()]
+            )
+        );
+        assert_eq!(
+            parse_tags("Learn C# and # headings  "),
+            ("Learn C# and # headings  ".to_owned(), vec![])
+        );
+        assert_eq!(
+            parse_tags("#only-tags"),
+            ("".to_owned(), vec!["only-tags".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_normalize_tag() {
+        assert_eq!(normalize_tag(" #Work "), Some("work".to_owned()));
+        assert_eq!(
+            normalize_tag("side_project-2"),
+            Some("side_project-2".to_owned())
+        );
+        assert_eq!(normalize_tag("#"), None);
+        assert_eq!(normalize_tag("two words"), None);
+        assert_eq!(normalize_tag("#a#b"), None);
+        assert_eq!(normalize_tag("café"), None);
+    }
+
+    #[test]
+    fn test_tag_color_is_stable() {
+        assert_eq!(TagColor::for_name("work"), TagColor::for_name("work"));
+    }
+}
@@ -1,8 +1,92 @@
+use jiff::{Zoned, tz::TimeZone};
 use maud::{Markup, html};
 
-use crate::todos::state::Todo;
+use crate::todos::{
+    history::{Change, HistoryEntry},
+    idempotency::new_idempotency_key,
+    recurrence::{Recurrence, RecurrenceRule},
+    state::{
+        BulkSummary, DEFAULT_LIST_ID, DueFilter, ListSummary, Priority, SortOrder, StatusFilter,
+        Todo, TodoStatus, TodosQuery, TrashedTodo,
+    },
+    tags::{TagColor, TagSummary},
+    transfer::{Format, ImportError, TodoRecord},
+    trash::TRASH_RETENTION,
+};
 
-pub fn todos_view(todos: &[Todo]) -> Markup {
+const DUE_FORMAT: &str = "%a %d %b %H:%M";
+
+/// Reminder offsets offered in the new todo form, in minutes.
+const REMINDER_CHOICES: [(u32, &str); 6] = [
+    (0, "At due time"),
+    (5, "5 minutes before"),
+    (15, "15 minutes before"),
+    (60, "1 hour before"),
+    (24 * 60, "1 day before"),
+    (7 * 24 * 60, "1 week before"),
+];
+
+pub fn list_url(list_id: usize) -> String {
+    format!("/lists/{list_id}/todos")
+}
+
+pub fn board_url(list_id: usize) -> String {
+    format!("/lists/{list_id}/board")
+}
+
+/// Move the dragged todo into the list it was dropped on.
+const LIST_DROP_HANDLER: &str = "event.preventDefault();
+const id = event.dataTransfer.getData('text/plain');
+htmx.ajax('POST', `/todo/${id}/move`, {values: {list_id: this.dataset.listId}, swap: 'none'});";
+
+pub fn lists_switcher(lists: &[ListSummary], current: usize, oob: bool) -> Markup {
+    let current_list = lists.iter().find(|l| l.id == current);
+    html! {
+        div.flex.flex-col.items-center.gap-2.mt-4 #lists-switcher hx-swap-oob=[oob.then_some("true")] {
+            div.tabs.tabs-box role="tablist" {
+                @for list in lists {
+                    a.tab.gap-2.tab-active[list.id == current] role="tab"
+                        href=(list_url(list.id))
+                        data-list-id=(list.id)
+                        ondragover="event.preventDefault()"
+                        ondrop=(LIST_DROP_HANDLER) {
+                        (list.name)
+                        span.badge.badge-sm title="Open" { (list.open) }
+                        span.badge.badge-sm.badge-success title="Done" { (list.done) }
+                    }
+                }
+            }
+            div.join {
+                form.join #new-list hx-post="/lists" {
+                    input.input.input-sm.join-item type="text" name="name" placeholder="New list";
+                    button.btn.btn-sm.join-item { "Create" }
+                }
+                @if let Some(list) = current_list {
+                    form.join.ml-2 #rename-list hx-patch=(format!("/lists/{}", list.id)) {
+                        input.input.input-sm.join-item type="text" name="name" value=(list.name);
+                        button.btn.btn-sm.join-item { "Rename" }
+                    }
+                    label.label.ml-2.text-sm {
+                        input.checkbox.checkbox-sm type="checkbox"
+                            checked[list.auto_complete]
+                            hx-patch=(format!("/lists/{}", list.id))
+                            hx-vals=(format!(r#"{{"auto_complete": {}}}"#, !list.auto_complete));
+                        "Complete todos with their checklist"
+                    }
+                    @if list.id != DEFAULT_LIST_ID {
+                        button.btn.btn-sm.btn-error.ml-2
+                            hx-delete=(format!("/lists/{}", list.id))
+                            hx-confirm="Delete this list and all its todos?" {
+                            "Delete list"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn todos_view(todos: &[&Todo]) -> Markup {
     html! {
         ul.list.bg-base-100.rounded-box.shadow-md.m-6 id="todos-list" {
             @for todo in todos {
@@ -13,72 +97,967 @@ pub fn todos_view(todos: &[Todo]) -> Markup {
 }
 
 pub fn todo_view(todo: &Todo) -> Markup {
+    todo_row(todo, false)
+}
+
+/// Same as [`todo_view`], with the subtasks checklist opened.
+pub fn todo_view_expanded(todo: &Todo) -> Markup {
+    todo_row(todo, true)
+}
+
+/// Requests made from a todo carry the version it was rendered at, so that
+/// they are rejected once the todo has changed since.
+fn if_match_headers(todo: &Todo) -> String {
+    format!(r#"{{"If-Match": "\"{}\""}}"#, todo.version)
+}
+
+fn todo_row(todo: &Todo, expanded: bool) -> Markup {
     let toggle_url = format!("/todo/{}/toggle", todo.id);
     let delete_url = format!("/todo/{}", todo.id);
-    let content_style = if todo.done {
+    let content_style = if todo.is_done() {
         "line-through text-gray-500"
     } else {
         ""
     };
     html! {
-        li.list-row.hover:bg-base-300
+        li.list-row.hover:bg-base-300.todo
+            data-todo-id=(todo.id)
+            hx-headers=(if_match_headers(todo))
+            ondragover="event.preventDefault()"
+            ondrop=(DROP_HANDLER)
             hx-post=(toggle_url)
             hx-trigger="click"
             hx-target="closest li"
             hx-swap="outerHTML" {
+            input.checkbox.checkbox-sm.self-center.bulk-select type="checkbox"
+                name="ids"
+                value=(todo.id)
+                form="todos-bulk"
+                title="Select"
+                onclick="event.stopPropagation()";
+            div.cursor-grab.select-none
+                draggable="true"
+                title="Drag to reorder"
+                onclick="event.stopPropagation()"
+                ondragstart="event.dataTransfer.setData('text/plain', this.closest('li').dataset.todoId)" {
+                "☰"
+            }
             div.list-col-grow
             {
                 span class=(content_style) {
                     (todo.content)
                 }
+                (status_badge(todo.status))
+                (priority_badge(todo.priority))
+                (due_badge(todo, &Zoned::now()))
+                @if let Some(recurrence) = &todo.recurrence {
+                    span.badge.badge-sm.badge-outline.ml-2.recurrence-badge {
+                        "🔁 " (recurrence_label(recurrence))
+                    }
+                }
+                (tag_badges(&todo.tags))
             }
             div {
+                button.btn.btn-ghost.mr-2
+                    title="History"
+                    hx-get=(format!("/todo/{}/history", todo.id))
+                    hx-target="#history"
+                    hx-swap="outerHTML"
+                    hx-trigger="click consume"
+                    hx-on::after-request="document.getElementById('history-drawer').checked = true" {
+                    "🕘"
+                }
                 button.btn.btn-secondary
                 hx-delete=(delete_url)
                 hx-target="closest li"
+                hx-swap="outerHTML"
                 hx-trigger="click consume" {
                     "X"
                 }
             }
+            (subtasks_view(todo, expanded))
+        }
+    }
+}
+
+fn subtasks_view(todo: &Todo, expanded: bool) -> Markup {
+    let subtasks_url = format!("/todo/{}/subtasks", todo.id);
+    let done = todo.subtasks.iter().filter(|s| s.done).count();
+    html! {
+        // Keep clicks in the checklist from toggling the todo itself
+        div.list-col-wrap.text-sm onclick="event.stopPropagation()" {
+            details.subtasks open[expanded] {
+                summary.cursor-pointer.text-gray-500 {
+                    "Checklist"
+                    @if !todo.subtasks.is_empty() {
+                        span.badge.badge-sm.ml-2.subtasks-progress {
+                            (done) "/" (todo.subtasks.len())
+                        }
+                    }
+                }
+                ul.mt-2 {
+                    @for subtask in &todo.subtasks {
+                        li.flex.items-center.gap-2.py-1 {
+                            input.checkbox.checkbox-sm type="checkbox"
+                                checked[subtask.done]
+                                hx-post=(format!("{subtasks_url}/{}/toggle", subtask.id))
+                                hx-target="closest li.todo"
+                                hx-swap="outerHTML";
+                            span.line-through[subtask.done].text-gray-500[subtask.done] {
+                                (subtask.content)
+                            }
+                            a.link.link-hover.text-gray-500
+                                title="Remove item"
+                                hx-delete=(format!("{subtasks_url}/{}", subtask.id))
+                                hx-target="closest li.todo"
+                                hx-swap="outerHTML" {
+                                "✕"
+                            }
+                        }
+                    }
+                }
+                form.join.mt-2
+                    hx-post=(subtasks_url)
+                    hx-target="closest li.todo"
+                    hx-swap="outerHTML" {
+                    input.input.input-sm.join-item type="text" name="content" placeholder="New item";
+                    button.btn.btn-sm.join-item { "Add item" }
+                }
+            }
+        }
+    }
+}
+
+fn status_badge(status: TodoStatus) -> Markup {
+    let color = match status {
+        TodoStatus::Backlog => "badge-ghost",
+        TodoStatus::InProgress => "badge-info",
+        TodoStatus::Blocked => "badge-error",
+        TodoStatus::Done => "badge-success",
+    };
+    html! {
+        span class={ "badge badge-sm ml-2 status-badge " (color) } { (status_label(status)) }
+    }
+}
+
+/// Normal priority is the default and is not shown.
+fn priority_badge(priority: Priority) -> Markup {
+    let (color, icon) = match priority {
+        Priority::Normal => return html! {},
+        Priority::Low => ("badge-ghost", "↓"),
+        Priority::High => ("badge-warning", "↑"),
+        Priority::Urgent => ("badge-error", "‼"),
+    };
+    html! {
+        span class={ "badge badge-sm badge-outline ml-2 priority-badge " (color) } {
+            (icon) " " (priority_label(priority))
+        }
+    }
+}
+
+fn status_label(status: TodoStatus) -> &'static str {
+    match status {
+        TodoStatus::Backlog => "Backlog",
+        TodoStatus::InProgress => "In progress",
+        TodoStatus::Blocked => "Blocked",
+        TodoStatus::Done => "Done",
+    }
+}
+
+fn priority_label(priority: Priority) -> &'static str {
+    match priority {
+        Priority::Low => "Low",
+        Priority::Normal => "Normal",
+        Priority::High => "High",
+        Priority::Urgent => "Urgent",
+    }
+}
+
+fn tag_badges(tags: &[String]) -> Markup {
+    html! {
+        @for tag in tags {
+            span class={ "badge badge-sm ml-2 tag-badge " (TagColor::for_name(tag).badge_class()) } {
+                "#" (tag)
+            }
+        }
+    }
+}
+
+fn recurrence_label(recurrence: &Recurrence) -> String {
+    match &recurrence.rule {
+        RecurrenceRule::Day => "Every day".to_owned(),
+        RecurrenceRule::Week { weekdays } => {
+            let weekdays: Vec<&str> = weekdays.iter().map(|w| w.as_str()).collect();
+            format!("Every week on {}", weekdays.join(", "))
+        }
+        RecurrenceRule::Month { day } => format!("Every month on day {day}"),
+        RecurrenceRule::DaysAfterCompletion { days: 1 } => "1 day after completion".to_owned(),
+        RecurrenceRule::DaysAfterCompletion { days } => format!("{days} days after completion"),
+    }
+}
+
+fn due_badge(todo: &Todo, now: &Zoned) -> Markup {
+    let Some(due) = &todo.due else {
+        return html! {};
+    };
+    let overdue = todo.is_overdue(now);
+    let today = !todo.is_done() && !overdue && todo.is_due_on(now);
+    let reminder = todo
+        .reminder_minutes
+        .map(|minutes| format!("Reminder {minutes} minutes before"));
+    html! {
+        span.badge.badge-sm.ml-2.due-badge
+            .badge-error[overdue]
+            .badge-warning[today]
+            .badge-ghost[!overdue && !today]
+            title=(due) {
+            @if overdue { "Overdue: " }
+            (due.strftime(DUE_FORMAT))
+            @if let Some(reminder) = reminder {
+                span title=(reminder) { " 🔔" }
+            }
+        }
+    }
+}
+
+pub fn toasts() -> Markup {
+    html! {
+        div.toast.toast-end #toasts {}
+    }
+}
+
+/// Pushed over the websocket when a todo reminder fires.
+pub fn reminder_toast(content: &str, due: &Zoned) -> Markup {
+    html! {
+        div hx-swap-oob="beforeend:#toasts" {
+            div.alert.alert-info.cursor-pointer role="alert" title="Dismiss" onclick="this.remove()" {
+                span {
+                    "Reminder: " strong { (content) } " is due " (due.strftime(DUE_FORMAT))
+                }
+            }
+        }
+    }
+}
+
+/// Offers to restore the todos that were just deleted, dismissed after a
+/// few seconds.
+pub fn undo_toast(message: &str, ids: &[usize]) -> Markup {
+    html! {
+        div hx-swap-oob="beforeend:#toasts" {
+            div.alert.alert-warning.undo-toast role="alert"
+                hx-on::load="setTimeout(() => this.remove(), 10000)" {
+                span { (message) }
+                form hx-post="/todos/trash/restore"
+                    hx-swap="none"
+                    hx-on::after-request="this.closest('.alert').remove()" {
+                    @for id in ids {
+                        input type="hidden" name="ids" value=(id);
+                    }
+                    button.btn.btn-sm { "Undo" }
+                }
+            }
         }
     }
 }
 
-pub fn todo_form() -> Markup {
+/// Trashed todos, most recently deleted first.
+pub fn trash_view(trash: &[TrashedTodo]) -> Markup {
+    let retention_days = TRASH_RETENTION.as_hours() / 24;
+    html! {
+        div.flex.flex-col.items-center.gap-4 #trash {
+            p.text-gray-500 {
+                "Deleted todos are kept " (retention_days) " days before being permanently deleted."
+            }
+            @if !trash.is_empty() {
+                table.table.bg-base-100.rounded-box.shadow-md {
+                    thead {
+                        tr {
+                            th { "Todo" }
+                            th { "Deleted" }
+                            th {}
+                        }
+                    }
+                    tbody {
+                        @for trashed in trash.iter().rev() {
+                            tr data-todo-id=(trashed.todo.id) {
+                                td { (trashed.todo.content) }
+                                td {
+                                    (trashed.deleted_at.to_zoned(TimeZone::system()).strftime(DUE_FORMAT))
+                                }
+                                td {
+                                    form hx-post="/todos/trash/restore"
+                                        hx-target="#trash"
+                                        hx-swap="outerHTML" {
+                                        input type="hidden" name="ids" value=(trashed.todo.id);
+                                        button.btn.btn-sm { "Restore" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                button.btn.btn-sm.btn-error
+                    hx-delete="/todos/trash"
+                    hx-target="#trash"
+                    hx-swap="outerHTML"
+                    hx-confirm="Permanently delete all the todos in the trash?" {
+                    "Empty trash"
+                }
+            }
+        }
+    }
+}
+
+const SET_USERNAME_HANDLER: &str = "document.cookie = `username=${encodeURIComponent(this.value)}; path=/; max-age=31536000; samesite=lax`";
+
+/// The name changes are recorded under, see [`crate::utils::Actor`].
+pub fn username_input(actor: &str) -> Markup {
+    html! {
+        label.input.input-sm.self-center.mt-2 {
+            "Name"
+            input #username type="text" value=(actor) onchange=(SET_USERNAME_HANDLER);
+        }
+    }
+}
+
+/// Wraps a page in a drawer showing the history of a todo, see
+/// [`history_view`].
+pub fn history_drawer(content: Markup) -> Markup {
+    html! {
+        div.drawer.drawer-end {
+            input.drawer-toggle #history-drawer type="checkbox";
+            div.drawer-content.flex.flex-col {
+                (content)
+            }
+            div.drawer-side.z-10 {
+                label.drawer-overlay for="history-drawer" aria-label="Close history" {}
+                div #history {}
+            }
+        }
+    }
+}
+
+/// Changes made to a todo, with a form to edit it when it still exists. The
+/// history is refreshed whenever the todos change.
+pub fn history_view(todo_id: usize, todo: Option<&Todo>, entries: &[&HistoryEntry]) -> Markup {
+    let history_url = format!("/todo/{todo_id}/history");
+    html! {
+        div.bg-base-200.min-h-full.w-96.p-4 #history
+            hx-get=(history_url)
+            hx-trigger="todos-changed from:body"
+            hx-swap="outerHTML" {
+            h2.text-xl.mb-4 { "History" }
+            @if let Some(todo) = todo {
+                form.join.mb-4 hx-patch=(format!("/todo/{todo_id}")) hx-swap="none" {
+                    input.input.input-sm.join-item type="text" name="content" value=(todo.content);
+                    button.btn.btn-sm.join-item { "Edit" }
+                }
+            }
+            ul {
+                @for entry in entries.iter().rev() {
+                    li.py-2.history-entry {
+                        strong { (entry.actor) } " " (change_label(&entry.change))
+                        div.text-xs.text-gray-500 { (entry_time(entry)) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The latest changes made to all todos.
+pub fn activity_view(entries: &[&HistoryEntry]) -> Markup {
+    html! {
+        table.table.bg-base-100.rounded-box.shadow-md #activity {
+            thead {
+                tr {
+                    th { "When" }
+                    th { "Who" }
+                    th { "Todo" }
+                    th { "What" }
+                }
+            }
+            tbody {
+                @for entry in entries {
+                    tr.history-entry {
+                        td { (entry_time(entry)) }
+                        td { (entry.actor) }
+                        td { (entry.content) }
+                        td { (change_label(&entry.change)) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn entry_time(entry: &HistoryEntry) -> String {
+    entry
+        .at
+        .to_zoned(TimeZone::system())
+        .strftime(DUE_FORMAT)
+        .to_string()
+}
+
+fn change_label(change: &Change) -> String {
+    match change {
+        Change::Created => "created it".to_owned(),
+        Change::ContentEdited { from, to } => format!("renamed it from “{from}” to “{to}”"),
+        Change::StatusChanged {
+            to: TodoStatus::Done,
+            ..
+        } => "completed it".to_owned(),
+        Change::StatusChanged {
+            from: TodoStatus::Done,
+            to: TodoStatus::Backlog,
+        } => "reopened it".to_owned(),
+        Change::StatusChanged { to, .. } => format!("moved it to {}", status_label(*to)),
+        Change::PriorityChanged { to, .. } => {
+            format!("set its priority to {}", priority_label(*to))
+        }
+        Change::Moved { .. } => "moved it to another list".to_owned(),
+        Change::Tagged { tag } => format!("tagged it #{tag}"),
+        Change::Untagged { tag } => format!("removed the #{tag} tag"),
+        Change::Deleted => "deleted it".to_owned(),
+        Change::Restored => "restored it".to_owned(),
+        Change::Purged => "permanently deleted it".to_owned(),
+    }
+}
+
+/// Fill the hidden reorder form from the drop event, dropping on the upper
+/// half of a row places the dragged todo before it, on the lower half after it.
+const DROP_HANDLER: &str = "event.preventDefault();
+const form = document.getElementById('todos-reorder');
+const rect = this.getBoundingClientRect();
+form.elements.id.value = event.dataTransfer.getData('text/plain');
+form.elements.target.value = this.dataset.todoId;
+form.elements.placement.value = event.clientY < rect.top + rect.height / 2 ? 'before' : 'after';
+htmx.trigger(form, 'submit');";
+
+pub fn todos_reorder_form() -> Markup {
+    html! {
+        form.hidden #todos-reorder hx-post="/todos/reorder" hx-swap="none" {
+            input type="hidden" name="id";
+            input type="hidden" name="placement";
+            input type="hidden" name="target";
+        }
+    }
+}
+
+const SELECT_ALL_HANDLER: &str =
+    "document.querySelectorAll('#todos-list .bulk-select').forEach(c => c.checked = this.checked)";
+
+/// Actions on the todos selected with their checkbox, which belong to this
+/// form through their `form` attribute.
+pub fn todos_bulk_form(list_id: usize) -> Markup {
+    html! {
+        form.flex.flex-wrap.gap-2.justify-center.items-center.mx-6.mt-4 #todos-bulk
+            hx-post="/todos/bulk"
+            hx-include="#todos-filter"
+            hx-target="#todos-list"
+            hx-swap="outerHTML"
+            hx-on::after-request="if(event.detail.successful) {this.reset();}" {
+            input type="hidden" name="list_id" value=(list_id);
+            label.label.text-sm {
+                input.checkbox.checkbox-sm type="checkbox" onclick=(SELECT_ALL_HANDLER);
+                "Select all"
+            }
+            button.btn.btn-sm name="action" value="complete" { "Complete" }
+            button.btn.btn-sm name="action" value="reopen" { "Reopen" }
+            button.btn.btn-sm.btn-error name="action" value="delete" { "Delete" }
+            div.join {
+                input.input.input-sm.join-item.w-28 type="text" name="add_tag" placeholder="Tag";
+                button.btn.btn-sm.join-item name="action" value="tag" { "Tag" }
+            }
+            button.btn.btn-sm.btn-ghost name="action" value="clear-completed" { "Clear completed" }
+        }
+    }
+}
+
+/// Refreshes `#todos-list` with the current filters on `todos-changed`
+/// events.
+pub fn todos_sync(list_id: usize) -> Markup {
+    html! {
+        div #todos-sync
+            hx-get=(list_url(list_id))
+            hx-include="#todos-filter"
+            hx-target="#todos-list"
+            hx-swap="outerHTML"
+            hx-trigger="todos-changed from:body" {}
+        div #todos-refresh {}
+    }
+}
+
+/// Pushed over the websocket to have the client refresh its current list.
+pub fn todos_refresh() -> Markup {
+    html! {
+        div #todos-refresh
+            hx-swap-oob="true"
+            hx-on::load="htmx.trigger('body', 'todos-changed')" {}
+    }
+}
+
+pub fn todos_filter(list_id: usize, query: &TodosQuery, tags: &[TagSummary]) -> Markup {
+    html! {
+        form.mx-6.mt-6 #todos-filter
+            hx-get=(list_url(list_id))
+            hx-target="#todos-list"
+            hx-swap="outerHTML"
+            hx-push-url="true"
+            hx-trigger="submit, change, input changed delay:300ms from:#todos-search" {
+            div.flex.gap-2.justify-center {
+                input.input #todos-search type="search" name="q" placeholder="Search" value=(query.q);
+                select.select.w-auto name="status" {
+                    @for status in StatusFilter::VARIANTS {
+                        option value=(status.as_str()) selected[status == query.status] {
+                            (status_filter_label(status))
+                        }
+                    }
+                }
+                select.select.w-auto name="due" {
+                    @for due in DueFilter::VARIANTS {
+                        option value=(due.as_str()) selected[due == query.due] {
+                            (due_filter_label(due))
+                        }
+                    }
+                }
+                select.select.w-auto name="sort" {
+                    @for sort in SortOrder::VARIANTS {
+                        option value=(sort.as_str()) selected[sort == query.sort] {
+                            (sort_order_label(sort))
+                        }
+                    }
+                }
+            }
+            (tags_cloud(tags, &query.tag, false))
+        }
+    }
+}
+
+/// The tags used in the current list, picking one filters the todos on it.
+pub fn tags_cloud(tags: &[TagSummary], selected: &str, oob: bool) -> Markup {
+    html! {
+        div.flex.gap-2.justify-center.items-center.mt-2 #tags-cloud hx-swap-oob=[oob.then_some("true")] {
+            div.filter {
+                input.btn.btn-sm.filter-reset type="radio" name="tag" value="" aria-label="All tags"
+                    checked[selected.is_empty()];
+                @for tag in tags.iter().filter(|t| t.count > 0 || t.name == selected) {
+                    input.btn.btn-sm type="radio" name="tag" value=(tag.name)
+                        aria-label=(format!("#{} ({})", tag.name, tag.count))
+                        checked[tag.name == selected];
+                }
+            }
+            a.link.link-hover.text-sm href="/tags" { "Manage tags" }
+        }
+    }
+}
+
+/// Rename, merge and delete tags, each action re-renders the whole table.
+pub fn tags_table(tags: &[TagSummary]) -> Markup {
+    html! {
+        table.table.bg-base-100.rounded-box.shadow-md #tags-table {
+            thead {
+                tr {
+                    th { "Tag" }
+                    th { "Todos" }
+                    th { "Rename" }
+                    th { "Merge into" }
+                    th {}
+                }
+            }
+            tbody {
+                @for tag in tags {
+                    tr data-tag-id=(tag.id) {
+                        td {
+                            span class={ "badge " (tag.color.badge_class()) } { "#" (tag.name) }
+                        }
+                        td { (tag.count) }
+                        td {
+                            form.join
+                                hx-patch=(format!("/tags/{}", tag.id))
+                                hx-target="#tags-table"
+                                hx-swap="outerHTML" {
+                                input.input.input-sm.join-item type="text" name="name" value=(tag.name);
+                                button.btn.btn-sm.join-item { "Rename" }
+                            }
+                        }
+                        td {
+                            @if tags.len() > 1 {
+                                form.join
+                                    hx-post=(format!("/tags/{}/merge", tag.id))
+                                    hx-target="#tags-table"
+                                    hx-swap="outerHTML" {
+                                    select.select.select-sm.join-item name="into" {
+                                        @for other in tags.iter().filter(|t| t.id != tag.id) {
+                                            option value=(other.id) { "#" (other.name) }
+                                        }
+                                    }
+                                    button.btn.btn-sm.join-item { "Merge" }
+                                }
+                            }
+                        }
+                        td {
+                            button.btn.btn-sm.btn-error
+                                hx-delete=(format!("/tags/{}", tag.id))
+                                hx-target="#tags-table"
+                                hx-swap="outerHTML"
+                                hx-confirm=(format!("Remove #{} from all todos?", tag.name)) {
+                                "Delete"
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn status_filter_label(status: StatusFilter) -> &'static str {
+    match status {
+        StatusFilter::All => "All",
+        StatusFilter::Active => "Active",
+        StatusFilter::Backlog => "Backlog",
+        StatusFilter::InProgress => "In progress",
+        StatusFilter::Blocked => "Blocked",
+        StatusFilter::Done => "Done",
+    }
+}
+
+fn due_filter_label(due: DueFilter) -> &'static str {
+    match due {
+        DueFilter::Any => "Any due date",
+        DueFilter::Overdue => "Overdue",
+        DueFilter::Today => "Due today",
+        DueFilter::Upcoming => "Upcoming",
+    }
+}
+
+fn sort_order_label(sort: SortOrder) -> &'static str {
+    match sort {
+        SortOrder::Manual => "Manual order",
+        SortOrder::Oldest => "Oldest first",
+        SortOrder::Newest => "Newest first",
+        SortOrder::Alphabetical => "A to Z",
+        SortOrder::ActiveFirst => "Active first",
+        SortOrder::Due => "Due date",
+        SortOrder::Priority => "Priority",
+    }
+}
+
+pub fn todo_form(list_id: usize) -> Markup {
     html!(
         div {
             form
                 hx-post="/todo"
-                hx-target="#todos-list"
-                hx-swap="beforeend"
+                hx-swap="none"
                 hx-on::after-request="if(event.detail.successful) {this.reset();}" {
                 fiedlset.fieldset.w-xs.bg-base-200.border.border-base-300.p-4.rounded-box {
                     legend.fieldset-legend { "New todo" }
+                    input type="hidden" name="list_id" value=(list_id);
+                    (idempotency_key_input(false))
                     div.join {
                         input.input.join-item #todo type="text" name="content" {}
                         button.btn.btn-primary.join-item {"Add"}
                     }
+                    label.label { "Priority" }
+                    select.select #todo-priority name="priority" {
+                        @for priority in Priority::VARIANTS {
+                            option value=(priority.as_str()) selected[priority == Priority::default()] {
+                                (priority_label(priority))
+                            }
+                        }
+                    }
+                    label.label { "Due" }
+                    input.input #todo-due type="datetime-local" name="due";
+                    select.select #todo-reminder name="reminder_minutes" {
+                        option value="" { "No reminder" }
+                        @for (minutes, label) in REMINDER_CHOICES {
+                            option value=(minutes) { (label) }
+                        }
+                    }
+                    label.label { "Repeat" }
+                    div.join {
+                        select.select.join-item #todo-repeat name="repeat" {
+                            option value="" { "Does not repeat" }
+                            option value="daily" { "Every day" }
+                            option value="weekly" { "Every week" }
+                            option value="monthly" { "Every month" }
+                            option value="after-completion" { "Days after completion" }
+                        }
+                        input.input.join-item.w-20 #todo-repeat-every type="number" min="1"
+                            name="repeat_every" placeholder="Days";
+                    }
                 }
             }
         }
     )
 }
 
+fn format_label(format: Format) -> &'static str {
+    match format {
+        Format::Json => "JSON",
+        Format::Csv => "CSV",
+        Format::TodoTxt => "todo.txt",
+    }
+}
+
+/// Export links, and a file upload previewed in `#import-preview` before
+/// being imported. Calendar uploads are imported right away since they
+/// update todos in place.
+pub fn transfer_form(list_id: usize) -> Markup {
+    html! {
+        details.collapse.collapse-arrow.bg-base-200.w-md.self-center.mt-2 #transfer {
+            summary.collapse-title { "Import / export" }
+            div.collapse-content.flex.flex-col.gap-2 {
+                div.flex.gap-2.items-center {
+                    "Export as"
+                    @for format in Format::VARIANTS {
+                        a.btn.btn-sm
+                            href={ "/todos/export?format=" (format.as_str()) "&list_id=" (list_id) }
+                            download {
+                            (format_label(format))
+                        }
+                    }
+                }
+                form.flex.gap-2
+                    hx-post="/todos/import"
+                    hx-encoding="multipart/form-data"
+                    hx-target="#import-preview" {
+                    input type="hidden" name="list_id" value=(list_id);
+                    input type="hidden" name="dry_run" value="true";
+                    input.file-input.file-input-sm type="file" name="file"
+                        accept=".json,.csv,.txt" required;
+                    select.select.select-sm name="format" {
+                        option value="" { "Detect" }
+                        @for format in Format::VARIANTS {
+                            option value=(format.as_str()) { (format_label(format)) }
+                        }
+                    }
+                    button.btn.btn-sm.btn-primary { "Preview" }
+                }
+                div.flex.gap-2.items-center {
+                    a.link.link-hover href={ "/todos/calendar.ics?list_id=" (list_id) } {
+                        "Calendar feed"
+                    }
+                    form.flex.gap-2
+                        hx-post="/todos/calendar.ics"
+                        hx-encoding="multipart/form-data"
+                        hx-target="#import-preview" {
+                        input type="hidden" name="list_id" value=(list_id);
+                        input.file-input.file-input-sm type="file" name="file" accept=".ics" required;
+                        button.btn.btn-sm { "Sync calendar" }
+                    }
+                }
+                div #import-preview {}
+            }
+        }
+    }
+}
+
+/// The todos parsed from an upload along with the lines that could not be,
+/// and a form importing them once there are no errors.
+pub fn import_preview(
+    list_id: usize,
+    format: Format,
+    data: &str,
+    records: &[TodoRecord],
+    errors: &[ImportError],
+) -> Markup {
+    html! {
+        div.flex.flex-col.gap-2 #import-preview {
+            (import_errors(errors))
+            @if !records.is_empty() {
+                table.table.table-sm.bg-base-100.rounded-box {
+                    thead {
+                        tr {
+                            th { "Todo" }
+                            th { "Status" }
+                            th { "Priority" }
+                            th { "Due" }
+                            th { "Tags" }
+                        }
+                    }
+                    tbody {
+                        @for record in records {
+                            tr.import-record {
+                                td { (record.content) }
+                                td { (status_label(record.status)) }
+                                td { (priority_label(record.priority)) }
+                                td {
+                                    @if let Some(due) = &record.due {
+                                        (due.strftime(DUE_FORMAT))
+                                    }
+                                }
+                                td { (tag_badges(&record.tags)) }
+                            }
+                        }
+                    }
+                }
+            }
+            form hx-post="/todos/import"
+                hx-encoding="multipart/form-data"
+                hx-target="#import-preview" {
+                input type="hidden" name="list_id" value=(list_id);
+                input type="hidden" name="format" value=(format.as_str());
+                textarea.hidden name="data" { (data) }
+                button.btn.btn-sm.btn-primary disabled[!errors.is_empty() || records.is_empty()] {
+                    "Import " (records.len()) " todos"
+                }
+            }
+        }
+    }
+}
+
+fn import_errors(errors: &[ImportError]) -> Markup {
+    html! {
+        @if !errors.is_empty() {
+            div.alert.alert-error role="alert" {
+                ul {
+                    @for error in errors {
+                        li.import-error { "Line " (error.line) ": " (error.message) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Outcome of a calendar upload, nothing is imported when there are errors.
+pub fn calendar_import_result(summary: &BulkSummary, errors: &[ImportError]) -> Markup {
+    html! {
+        div #import-preview {
+            @if errors.is_empty() {
+                div.alert.alert-success role="alert" {
+                    "Created " (summary.created.len()) " todos, updated " (summary.updated.len())
+                }
+            }
+            (import_errors(errors))
+        }
+    }
+}
+
+pub fn import_done(count: usize) -> Markup {
+    html! {
+        div.alert.alert-success #import-preview role="alert" {
+            "Imported " (count) " todos"
+        }
+    }
+}
+
+/// A fresh key for each form submission, so that submitting the same form
+/// twice creates a single todo. Swapped out of band once the todo is created.
+pub fn idempotency_key_input(oob: bool) -> Markup {
+    html! {
+        input #todo-idempotency-key type="hidden" name="idempotency_key"
+            value=(new_idempotency_key())
+            hx-swap-oob=[oob.then_some("true")];
+    }
+}
+
+/// Move the dragged card to the status column it was dropped on.
+const BOARD_DROP_HANDLER: &str = "event.preventDefault();
+const id = event.dataTransfer.getData('text/plain');
+htmx.ajax('PATCH', `/todo/${id}`, {values: {status: this.dataset.status}, swap: 'none'});";
+
+/// Kanban board of a list, with a column per status.
+pub fn todos_board(todos: &[&Todo]) -> Markup {
+    html! {
+        div.grid.grid-cols-4.gap-4.m-6 #todos-board {
+            @for status in TodoStatus::VARIANTS {
+                @let cards: Vec<&&Todo> = todos.iter().filter(|t| t.status == status).collect();
+                div.bg-base-200.rounded-box.p-3.min-h-48.board-column
+                    data-status=(status.as_str())
+                    ondragover="event.preventDefault()"
+                    ondrop=(BOARD_DROP_HANDLER) {
+                    h2.font-bold.mb-2 {
+                        (status_label(status))
+                        span.badge.badge-sm.ml-2 { (cards.len()) }
+                    }
+                    @for todo in cards {
+                        (board_card(todo))
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn board_card(todo: &Todo) -> Markup {
+    html! {
+        div.card.card-sm.bg-base-100.shadow-sm.mb-2.cursor-grab.board-card
+            draggable="true"
+            data-todo-id=(todo.id)
+            hx-headers=(if_match_headers(todo))
+            ondragstart="event.dataTransfer.setData('text/plain', this.dataset.todoId)" {
+            div.card-body {
+                span { (todo.content) }
+                div {
+                    (priority_badge(todo.priority))
+                    (due_badge(todo, &Zoned::now()))
+                    (tag_badges(&todo.tags))
+                }
+                select.select.select-xs.w-auto name="priority"
+                    hx-patch=(format!("/todo/{}", todo.id))
+                    hx-trigger="change"
+                    hx-swap="none" {
+                    @for priority in Priority::VARIANTS {
+                        option value=(priority.as_str()) selected[priority == todo.priority] {
+                            (priority_label(priority))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Refreshes `#todos-board` on `todos-changed` events, see [`todos_sync`].
+pub fn board_sync(list_id: usize) -> Markup {
+    html! {
+        div #board-sync
+            hx-get=(board_url(list_id))
+            hx-target="#todos-board"
+            hx-swap="outerHTML"
+            hx-trigger="todos-changed from:body" {}
+        div #todos-refresh {}
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use jiff::Zoned;
     use scraper::{Html, Selector};
 
-    use crate::todos::state::Todo;
+    use crate::todos::{
+        history::{Change, TodoHistory},
+        recurrence::{Recurrence, RecurrenceRule, Weekday},
+        state::{
+            DueFilter, ListSummary, Priority, SortOrder, StatusFilter, Subtask, Todo, TodoStatus,
+            TodosQuery,
+        },
+        tags::{TagColor, TagSummary},
+        transfer::{Format, parse},
+    };
+
+    use super::{
+        due_badge, history_view, import_preview, lists_switcher, tags_table, todo_view,
+        todo_view_expanded, todos_board, todos_filter,
+    };
 
-    use super::todo_view;
+    /// An open todo of the default list, fixtures override the fields they
+    /// are about.
+    fn todo(id: usize, content: &str) -> Todo {
+        Todo {
+            id,
+            version: 1,
+            content: content.to_owned(),
+            ..Todo::default()
+        }
+    }
 
     #[test]
     fn test_todo_view_not_done_todo() {
-        let todo = Todo {
-            content: "not done todo".to_owned(),
-            done: false,
-            id: 0,
-        };
+        let todo = todo(0, "not done todo");
         let fragment = Html::parse_fragment(&todo_view(&todo).into_string());
         let selector = Selector::parse("span").unwrap();
 
@@ -93,9 +1072,8 @@ mod test {
     #[test]
     fn test_todo_view_done_todo() {
         let todo = Todo {
-            content: "done todo".to_owned(),
-            done: true,
-            id: 0,
+            status: TodoStatus::Done,
+            ..todo(0, "done todo")
         };
         let fragment = Html::parse_fragment(&todo_view(&todo).into_string());
         let selector = Selector::parse("span").unwrap();
@@ -111,9 +1089,8 @@ mod test {
     #[test]
     fn test_toggle_url() {
         let todo = Todo {
-            content: "done todo".to_owned(),
-            done: true,
-            id: 42,
+            status: TodoStatus::Done,
+            ..todo(42, "done todo")
         };
         let fragment = Html::parse_fragment(&todo_view(&todo).into_string());
         let selector = Selector::parse("li").unwrap();
@@ -132,12 +1109,11 @@ mod test {
     #[test]
     fn test_delete_url() {
         let todo = Todo {
-            content: "done todo".to_owned(),
-            done: true,
-            id: 42,
+            status: TodoStatus::Done,
+            ..todo(42, "done todo")
         };
         let fragment = Html::parse_fragment(&todo_view(&todo).into_string());
-        let selector = Selector::parse("button").unwrap();
+        let selector = Selector::parse("button[hx-delete]").unwrap();
 
         let button = fragment
             .select(&selector)
@@ -146,4 +1122,364 @@ mod test {
 
         assert_eq!(button.value().attr("hx-delete").unwrap(), "/todo/42");
     }
+
+    #[test]
+    fn test_todos_filter_reflects_query() {
+        let query = TodosQuery {
+            status: StatusFilter::Done,
+            due: DueFilter::Today,
+            q: "milk".to_owned(),
+            tag: "shopping".to_owned(),
+            sort: SortOrder::Newest,
+        };
+        let tags = vec![
+            TagSummary {
+                id: 0,
+                name: "shopping".to_owned(),
+                color: TagColor::for_name("shopping"),
+                count: 0,
+            },
+            TagSummary {
+                id: 1,
+                name: "work".to_owned(),
+                color: TagColor::for_name("work"),
+                count: 2,
+            },
+            TagSummary {
+                id: 2,
+                name: "unused".to_owned(),
+                color: TagColor::for_name("unused"),
+                count: 0,
+            },
+        ];
+        let fragment = Html::parse_fragment(&todos_filter(3, &query, &tags).into_string());
+
+        let search = fragment
+            .select(&Selector::parse("input[name=q]").unwrap())
+            .next()
+            .expect("search input should exist");
+        assert_eq!(search.value().attr("value").unwrap(), "milk");
+
+        let selected = Selector::parse("option[selected]").unwrap();
+        let selected: Vec<_> = fragment
+            .select(&selected)
+            .map(|el| el.value().attr("value").unwrap())
+            .collect();
+        assert_eq!(selected, vec!["done", "today", "newest"]);
+
+        let form = fragment
+            .select(&Selector::parse("form").unwrap())
+            .next()
+            .expect("form should exist");
+        assert_eq!(form.value().attr("hx-get").unwrap(), "/lists/3/todos");
+
+        // Unused tags are hidden, unless selected
+        let tags: Vec<_> = fragment
+            .select(&Selector::parse("#tags-cloud input[name=tag]").unwrap())
+            .map(|el| {
+                (
+                    el.value().attr("value").unwrap(),
+                    el.value().attr("checked").is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(tags, vec![("", false), ("shopping", true), ("work", false)]);
+    }
+
+    #[test]
+    fn test_lists_switcher() {
+        let lists = vec![
+            ListSummary {
+                id: 0,
+                name: "Inbox".to_owned(),
+                open: 1,
+                done: 2,
+                auto_complete: false,
+            },
+            ListSummary {
+                id: 1,
+                name: "Groceries".to_owned(),
+                open: 3,
+                done: 0,
+                auto_complete: true,
+            },
+        ];
+        let delete = Selector::parse("button[hx-delete]").unwrap();
+        let active_tab = Selector::parse("a.tab-active").unwrap();
+
+        let fragment = Html::parse_fragment(&lists_switcher(&lists, 0, false).into_string());
+        assert!(fragment.select(&delete).next().is_none());
+
+        let fragment = Html::parse_fragment(&lists_switcher(&lists, 1, false).into_string());
+        let tab = fragment.select(&active_tab).next().unwrap();
+        assert_eq!(tab.value().attr("href").unwrap(), "/lists/1/todos");
+        assert_eq!(
+            fragment
+                .select(&delete)
+                .next()
+                .unwrap()
+                .value()
+                .attr("hx-delete")
+                .unwrap(),
+            "/lists/1"
+        );
+    }
+
+    #[test]
+    fn test_due_badge() {
+        let now: Zoned = "2025-03-10T12:00[Europe/Paris]".parse().unwrap();
+        let mut todo = Todo {
+            due: Some("2025-03-09T18:00[Europe/Paris]".parse().unwrap()),
+            ..todo(0, "file taxes")
+        };
+        let badge_class = |todo: &Todo| {
+            let fragment = Html::parse_fragment(&due_badge(todo, &now).into_string());
+            fragment
+                .select(&Selector::parse(".due-badge").unwrap())
+                .next()
+                .map(|el| el.value().attr("class").unwrap().to_owned())
+        };
+
+        assert!(badge_class(&todo).unwrap().contains("badge-error"));
+
+        todo.status = TodoStatus::Done;
+        assert!(badge_class(&todo).unwrap().contains("badge-ghost"));
+
+        todo.status = TodoStatus::Backlog;
+        todo.due = Some("2025-03-10T18:00[Europe/Paris]".parse().unwrap());
+        assert!(badge_class(&todo).unwrap().contains("badge-warning"));
+
+        todo.due = None;
+        assert!(badge_class(&todo).is_none());
+    }
+
+    #[test]
+    fn test_todo_view_shows_recurrence() {
+        let todo = Todo {
+            recurrence: Some(Recurrence {
+                rule: RecurrenceRule::Week {
+                    weekdays: vec![Weekday::Mon, Weekday::Thu],
+                },
+                at: None,
+            }),
+            ..todo(0, "water plants")
+        };
+        let fragment = Html::parse_fragment(&todo_view(&todo).into_string());
+
+        let badge = fragment
+            .select(&Selector::parse(".recurrence-badge").unwrap())
+            .next()
+            .expect("recurrence badge should exist");
+        assert_eq!(badge.inner_html(), "🔁 Every week on mon, thu");
+    }
+
+    #[test]
+    fn test_todo_view_subtasks() {
+        let mut todo = Todo {
+            subtasks: vec![
+                Subtask {
+                    id: 3,
+                    content: "eggs".to_owned(),
+                    done: true,
+                },
+                Subtask {
+                    id: 4,
+                    content: "milk".to_owned(),
+                    done: false,
+                },
+            ],
+            ..todo(7, "groceries")
+        };
+        let fragment = Html::parse_fragment(&todo_view(&todo).into_string());
+
+        let progress = fragment
+            .select(&Selector::parse(".subtasks-progress").unwrap())
+            .next()
+            .expect("progress should be shown");
+        assert_eq!(progress.inner_html(), "1/2");
+        let toggles: Vec<_> = fragment
+            .select(&Selector::parse(".subtasks input[type=checkbox]").unwrap())
+            .map(|el| {
+                (
+                    el.value().attr("hx-post").unwrap(),
+                    el.value().attr("checked").is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            toggles,
+            vec![
+                ("/todo/7/subtasks/3/toggle", true),
+                ("/todo/7/subtasks/4/toggle", false)
+            ]
+        );
+        let details = Selector::parse("details[open]").unwrap();
+        assert!(fragment.select(&details).next().is_none());
+
+        let fragment = Html::parse_fragment(&todo_view_expanded(&todo).into_string());
+        assert!(fragment.select(&details).next().is_some());
+
+        todo.subtasks.clear();
+        let fragment = Html::parse_fragment(&todo_view(&todo).into_string());
+        let progress = Selector::parse(".subtasks-progress").unwrap();
+        assert!(fragment.select(&progress).next().is_none());
+    }
+
+    #[test]
+    fn test_todo_view_tags() {
+        let todo = Todo {
+            tags: vec!["shopping".to_owned(), "home".to_owned()],
+            ..todo(7, "groceries")
+        };
+        let fragment = Html::parse_fragment(&todo_view(&todo).into_string());
+
+        let badges: Vec<_> = fragment
+            .select(&Selector::parse(".tag-badge").unwrap())
+            .map(|el| el.inner_html())
+            .collect();
+        assert_eq!(badges, vec!["#shopping", "#home"]);
+        let badge = fragment
+            .select(&Selector::parse(".tag-badge").unwrap())
+            .next()
+            .unwrap();
+        let class = badge.value().attr("class").unwrap();
+        assert!(class.contains(TagColor::for_name("shopping").badge_class()));
+    }
+
+    #[test]
+    fn test_tags_table_merge_targets() {
+        let tags = vec![
+            TagSummary {
+                id: 0,
+                name: "shopping".to_owned(),
+                color: TagColor::for_name("shopping"),
+                count: 1,
+            },
+            TagSummary {
+                id: 1,
+                name: "groceries".to_owned(),
+                color: TagColor::for_name("groceries"),
+                count: 2,
+            },
+        ];
+        let fragment = Html::parse_fragment(&tags_table(&tags).into_string());
+
+        let merge = fragment
+            .select(&Selector::parse("tr[data-tag-id='0'] form[hx-post]").unwrap())
+            .next()
+            .expect("merge form should exist");
+        assert_eq!(merge.value().attr("hx-post").unwrap(), "/tags/0/merge");
+        let targets: Vec<_> = merge
+            .select(&Selector::parse("option").unwrap())
+            .map(|el| el.value().attr("value").unwrap())
+            .collect();
+        assert_eq!(targets, vec!["1"]);
+    }
+
+    #[test]
+    fn test_todos_board() {
+        let card = |id, status, priority| Todo {
+            status,
+            priority,
+            position: id,
+            ..todo(id, &format!("todo {id}"))
+        };
+        let todos = [
+            card(0, TodoStatus::Blocked, Priority::Urgent),
+            card(1, TodoStatus::Backlog, Priority::Normal),
+            card(2, TodoStatus::Blocked, Priority::Normal),
+        ];
+        let todos: Vec<&Todo> = todos.iter().collect();
+        let fragment = Html::parse_fragment(&todos_board(&todos).into_string());
+
+        let columns: Vec<(&str, usize)> = fragment
+            .select(&Selector::parse(".board-column").unwrap())
+            .map(|column| {
+                (
+                    column.value().attr("data-status").unwrap(),
+                    column
+                        .select(&Selector::parse(".board-card").unwrap())
+                        .count(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                ("backlog", 1),
+                ("in-progress", 0),
+                ("blocked", 2),
+                ("done", 0)
+            ]
+        );
+
+        let priorities: Vec<_> = fragment
+            .select(&Selector::parse(".priority-badge").unwrap())
+            .map(|el| el.inner_html())
+            .collect();
+        assert_eq!(priorities, vec!["‼ Urgent"]);
+    }
+
+    #[test]
+    fn test_history_view_latest_first() {
+        let todo = Todo {
+            status: TodoStatus::Done,
+            ..todo(3, "water plants")
+        };
+        let mut history = TodoHistory::new();
+        history.record("alice", &todo, Change::Created);
+        history.record(
+            "bob",
+            &todo,
+            Change::StatusChanged {
+                from: TodoStatus::Backlog,
+                to: TodoStatus::Done,
+            },
+        );
+
+        let entries = history.for_todo(3);
+        let fragment = Html::parse_fragment(&history_view(3, Some(&todo), &entries).into_string());
+        let selector = Selector::parse(".history-entry").unwrap();
+        let entries: Vec<String> = fragment
+            .select(&selector)
+            .map(|e| e.text().take(2).collect())
+            .collect();
+
+        assert_eq!(entries, vec!["bob completed it", "alice created it"]);
+    }
+
+    #[test]
+    fn test_todo_view_sends_its_version() {
+        let todo = Todo {
+            version: 3,
+            ..todo(42, "edited todo")
+        };
+        let fragment = Html::parse_fragment(&todo_view(&todo).into_string());
+        let selector = Selector::parse("li").unwrap();
+
+        let li_element = fragment.select(&selector).next().unwrap();
+        assert_eq!(
+            li_element.value().attr("hx-headers").unwrap(),
+            r#"{"If-Match": "\"3\""}"#
+        );
+    }
+
+    #[test]
+    fn test_import_preview_blocks_invalid_imports() {
+        let data = "buy milk +shop\nx due:someday\n";
+        let (records, errors) = parse(Format::TodoTxt, data);
+        let preview = import_preview(0, Format::TodoTxt, data, &records, &errors).into_string();
+        let fragment = Html::parse_fragment(&preview);
+
+        let records = Selector::parse(".import-record").unwrap();
+        assert_eq!(fragment.select(&records).count(), 1);
+        let errors = Selector::parse(".import-error").unwrap();
+        let errors: Vec<String> = fragment
+            .select(&errors)
+            .map(|e| e.text().collect())
+            .collect();
+        assert_eq!(errors, vec!["Line 2: invalid due date \"someday\""]);
+        let button = Selector::parse("button[disabled]").unwrap();
+        assert!(fragment.select(&button).next().is_some());
+    }
 }
@@ -0,0 +1,639 @@
+use std::iter::Peekable;
+
+use jiff::{Zoned, civil::Date, tz::TimeZone};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    state::{Priority, Todo, TodoStatus, parse_due},
+    tags::{normalize_tag, parse_tags},
+};
+
+/// File formats todos are imported from and exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Json,
+    Csv,
+    /// See <https://github.com/todotxt/todo.txt>.
+    TodoTxt,
+}
+
+impl Format {
+    pub const VARIANTS: [Format; 3] = [Format::Json, Format::Csv, Format::TodoTxt];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::TodoTxt => "todotxt",
+        }
+    }
+
+    pub fn parse(input: &str) -> Option<Format> {
+        Format::VARIANTS
+            .into_iter()
+            .find(|f| f.as_str() == input.trim().to_lowercase())
+    }
+
+    /// Guess the format of an uploaded file from its name.
+    pub fn from_file_name(name: &str) -> Option<Format> {
+        match name.rsplit_once('.')?.1.to_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "csv" => Some(Format::Csv),
+            "txt" => Some(Format::TodoTxt),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::TodoTxt => "text/plain; charset=utf-8",
+        }
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Format::Json => "todos.json",
+            Format::Csv => "todos.csv",
+            Format::TodoTxt => "todo.txt",
+        }
+    }
+}
+
+/// The fields of a todo that are imported and exported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TodoRecord {
+    pub content: String,
+    #[serde(default)]
+    pub status: TodoStatus,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default)]
+    pub due: Option<Zoned>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl From<&Todo> for TodoRecord {
+    fn from(todo: &Todo) -> Self {
+        TodoRecord {
+            content: todo.content.clone(),
+            status: todo.status,
+            priority: todo.priority,
+            due: todo.due.clone(),
+            tags: todo.tags.clone(),
+        }
+    }
+}
+
+/// A record that could not be imported. Lines start at 1, for JSON they are
+/// the position of the todo in the array.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImportError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl ImportError {
+    pub fn new(line: usize, message: impl Into<String>) -> ImportError {
+        ImportError {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+pub fn export(format: Format, todos: &[&Todo]) -> String {
+    let records: Vec<TodoRecord> = todos.iter().map(|t| TodoRecord::from(*t)).collect();
+    match format {
+        Format::Json => serde_json::to_string_pretty(&records).unwrap_or_default(),
+        Format::Csv => export_csv(&records),
+        Format::TodoTxt => records.iter().map(|r| todotxt_line(r) + "\n").collect(),
+    }
+}
+
+/// Parse all the records of an import. Invalid records are reported rather
+/// than failing the whole import, so that they can all be fixed at once.
+pub fn parse(format: Format, input: &str) -> (Vec<TodoRecord>, Vec<ImportError>) {
+    let results = match format {
+        Format::Json => parse_json(input),
+        Format::Csv => parse_csv(input),
+        Format::TodoTxt => input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| (index + 1, parse_todotxt_line(line)))
+            .collect(),
+    };
+
+    let mut records = Vec::new();
+    let mut errors = Vec::new();
+    for (line, result) in results {
+        match result.and_then(validate) {
+            Ok(record) => records.push(record),
+            Err(message) => errors.push(ImportError::new(line, message)),
+        }
+    }
+    (records, errors)
+}
+
+/// Apply the same rules as todos created from the app: inline `#tags` are
+/// extracted from the content, and tags are normalized.
+fn validate(mut record: TodoRecord) -> Result<TodoRecord, String> {
+    let (content, inline_tags) = parse_tags(record.content.trim());
+    if content.is_empty() {
+        return Err("content is empty".to_owned());
+    }
+    record.content = content;
+
+    let mut tags: Vec<String> = Vec::new();
+    for tag in record.tags.iter().chain(&inline_tags) {
+        let tag = normalize_tag(tag).ok_or_else(|| format!("invalid tag {tag:?}"))?;
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    record.tags = tags;
+    Ok(record)
+}
+
+fn parse_status(input: &str) -> Result<TodoStatus, String> {
+    TodoStatus::VARIANTS
+        .into_iter()
+        .find(|s| s.as_str() == input.trim())
+        .ok_or_else(|| format!("invalid status {input:?}"))
+}
+
+fn parse_priority(input: &str) -> Result<Priority, String> {
+    Priority::VARIANTS
+        .into_iter()
+        .find(|p| p.as_str() == input.trim())
+        .ok_or_else(|| format!("invalid priority {input:?}"))
+}
+
+/// Due dates are exported with their time zone, dates and local date times
+/// from other tools are in the server time zone.
+fn parse_import_due(input: &str) -> Result<Zoned, String> {
+    let input = input.trim();
+    input
+        .parse::<Zoned>()
+        .or_else(|_| parse_due(input))
+        .or_else(|_| {
+            input
+                .parse::<Date>()
+                .and_then(|date| date.to_zoned(TimeZone::system()))
+        })
+        .map_err(|_| format!("invalid due date {input:?}"))
+}
+
+fn parse_done(input: &str) -> Result<bool, String> {
+    match input.trim().to_lowercase().as_str() {
+        "" | "false" | "0" | "no" => Ok(false),
+        "true" | "1" | "yes" | "x" => Ok(true),
+        _ => Err(format!("invalid done value {input:?}")),
+    }
+}
+
+/// JSON todos may have a `done` boolean instead of a status.
+#[derive(Debug, Deserialize)]
+struct JsonRecord {
+    #[serde(flatten)]
+    record: TodoRecord,
+    status: Option<TodoStatus>,
+    done: Option<bool>,
+}
+
+fn parse_json(input: &str) -> Vec<(usize, Result<TodoRecord, String>)> {
+    let values: Vec<serde_json::Value> = match serde_json::from_str(input) {
+        Ok(values) => values,
+        Err(err) => return vec![(err.line(), Err(format!("invalid JSON: {err}")))],
+    };
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let result = serde_json::from_value::<JsonRecord>(value)
+                .map(|json| TodoRecord {
+                    status: match (json.status, json.done) {
+                        (Some(status), _) => status,
+                        (None, Some(true)) => TodoStatus::Done,
+                        (None, _) => TodoStatus::default(),
+                    },
+                    ..json.record
+                })
+                .map_err(|err| err.to_string());
+            (index + 1, result)
+        })
+        .collect()
+}
+
+const CSV_COLUMNS: [&str; 5] = ["content", "status", "priority", "due", "tags"];
+
+fn export_csv(records: &[TodoRecord]) -> String {
+    let mut csv = CSV_COLUMNS.join(",") + "\n";
+    for record in records {
+        let due = record
+            .due
+            .as_ref()
+            .map(Zoned::to_string)
+            .unwrap_or_default();
+        let fields = [
+            record.content.as_str(),
+            record.status.as_str(),
+            record.priority.as_str(),
+            &due,
+            &record.tags.join(" "),
+        ];
+        let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) || field.trim() != field {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Split CSV input into records, along with the line each record starts on.
+/// Quoted fields may contain commas, newlines and doubled quotes.
+fn csv_records(input: &str) -> Result<Vec<(usize, Vec<String>)>, ImportError> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if field.is_empty() => in_quotes = true,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) if chars.peek() == Some(&'\n') => {}
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push((record_line, std::mem::take(&mut record)));
+                line += 1;
+                record_line = line;
+            }
+            (c, _) => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if in_quotes {
+        return Err(ImportError::new(record_line, "unterminated quoted field"));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((record_line, record));
+    }
+    // Blank lines are skipped
+    records.retain(|(_, record)| record.iter().any(|f| !f.trim().is_empty()));
+    Ok(records)
+}
+
+fn parse_csv(input: &str) -> Vec<(usize, Result<TodoRecord, String>)> {
+    let records = match csv_records(input) {
+        Ok(records) => records,
+        Err(err) => return vec![(err.line, Err(err.message))],
+    };
+    let mut records = records.into_iter();
+    let Some((header_line, header)) = records.next() else {
+        return Vec::new();
+    };
+    let header: Vec<String> = header.iter().map(|h| h.trim().to_lowercase()).collect();
+    let column = |name: &str| header.iter().position(|h| h == name);
+    let Some(content_column) = column("content") else {
+        return vec![(header_line, Err("missing content column".to_owned()))];
+    };
+    let status_column = column("status");
+    let done_column = column("done");
+    let priority_column = column("priority");
+    let due_column = column("due");
+    let tags_column = column("tags");
+
+    records
+        .map(|(line, fields)| {
+            let field = |column: Option<usize>| {
+                column
+                    .and_then(|c| fields.get(c))
+                    .map(|f| f.trim())
+                    .filter(|f| !f.is_empty())
+            };
+            let result = (|| {
+                let status = match (field(status_column), field(done_column)) {
+                    (Some(status), _) => parse_status(status)?,
+                    (None, Some(done)) if parse_done(done)? => TodoStatus::Done,
+                    (None, _) => TodoStatus::default(),
+                };
+                Ok(TodoRecord {
+                    content: field(Some(content_column)).unwrap_or_default().to_owned(),
+                    status,
+                    priority: field(priority_column)
+                        .map(parse_priority)
+                        .transpose()?
+                        .unwrap_or_default(),
+                    due: field(due_column).map(parse_import_due).transpose()?,
+                    tags: field(tags_column)
+                        .map(|tags| tags.split_whitespace().map(str::to_owned).collect())
+                        .unwrap_or_default(),
+                })
+            })();
+            (line, result)
+        })
+        .collect()
+}
+
+/// todo.txt has no normal priority, which is left out. Low priority todos
+/// use `(D)` so that `(C)` can still be imported as normal.
+fn todotxt_priority(priority: Priority) -> Option<char> {
+    match priority {
+        Priority::Urgent => Some('A'),
+        Priority::High => Some('B'),
+        Priority::Normal => None,
+        Priority::Low => Some('D'),
+    }
+}
+
+/// Whether a word would be read as a tag, a due date or a status.
+fn is_todotxt_metadata(word: &str) -> bool {
+    word.strip_prefix(['+', '@'])
+        .is_some_and(|tag| normalize_tag(tag).is_some())
+        || word.starts_with("due:")
+        || word.starts_with("status:")
+}
+
+/// Whether the first word of a line would be read as a completion mark, a
+/// priority or a date.
+fn is_todotxt_prefix(word: &str) -> bool {
+    word == "x" || todotxt_priority_letter(word).is_some() || word.parse::<Date>().is_ok()
+}
+
+fn todotxt_priority_letter(word: &str) -> Option<char> {
+    let letter = word.strip_prefix('(')?.strip_suffix(')')?;
+    let mut chars = letter.chars();
+    chars
+        .next()
+        .filter(|c| c.is_ascii_uppercase() && chars.next().is_none())
+}
+
+/// todo.txt has no escaping: words of the content that would be read as
+/// metadata are prefixed with a backslash, as are the ones starting with
+/// one. Words are separated by single spaces, so that runs of whitespace are
+/// kept.
+fn todotxt_content(content: &str) -> String {
+    let words: Vec<String> = content
+        .split(' ')
+        .enumerate()
+        .map(|(index, word)| {
+            let escape = word.starts_with('\\')
+                || is_todotxt_metadata(word)
+                || (index == 0 && is_todotxt_prefix(word));
+            match escape {
+                true => format!("\\{word}"),
+                false => word.to_owned(),
+            }
+        })
+        .collect();
+    words.join(" ")
+}
+
+fn todotxt_line(record: &TodoRecord) -> String {
+    let mut words: Vec<String> = Vec::new();
+    if record.status == TodoStatus::Done {
+        words.push("x".to_owned());
+    }
+    if let Some(priority) = todotxt_priority(record.priority) {
+        words.push(format!("({priority})"));
+    }
+    words.push(todotxt_content(&record.content));
+    words.extend(record.tags.iter().map(|tag| format!("+{tag}")));
+    if let Some(due) = &record.due {
+        words.push(format!("due:{due}"));
+    }
+    if !matches!(record.status, TodoStatus::Backlog | TodoStatus::Done) {
+        words.push(format!("status:{}", record.status.as_str()));
+    }
+    words.join(" ")
+}
+
+fn skip_spaces<'a>(words: &mut Peekable<impl Iterator<Item = &'a str>>) {
+    while words.next_if_eq(&"").is_some() {}
+}
+
+fn parse_todotxt_line(line: &str) -> Result<TodoRecord, String> {
+    // Runs of spaces are only kept within the content, see `todotxt_content`
+    let mut words = line.split(' ').peekable();
+    skip_spaces(&mut words);
+    let mut status = TodoStatus::default();
+    if words.peek() == Some(&"x") {
+        words.next();
+        status = TodoStatus::Done;
+    }
+    skip_spaces(&mut words);
+    let mut priority = Priority::default();
+    if let Some(letter) = words.peek().and_then(|w| todotxt_priority_letter(w)) {
+        words.next();
+        priority = match letter {
+            'A' => Priority::Urgent,
+            'B' => Priority::High,
+            'C' => Priority::Normal,
+            _ => Priority::Low,
+        };
+    }
+    // Completion and creation dates are not kept
+    skip_spaces(&mut words);
+    while words.peek().is_some_and(|w| w.parse::<Date>().is_ok()) {
+        words.next();
+        skip_spaces(&mut words);
+    }
+
+    let mut content = Vec::new();
+    let mut tags = Vec::new();
+    let mut due = None;
+    for word in words {
+        if let Some(word) = word.strip_prefix('\\') {
+            content.push(word);
+        } else if let Some(value) = word.strip_prefix("due:") {
+            due = Some(parse_import_due(value)?);
+        } else if let Some(value) = word.strip_prefix("status:") {
+            status = parse_status(value)?;
+        } else if let Some(tag) = word
+            .strip_prefix(['+', '@'])
+            .filter(|tag| normalize_tag(tag).is_some())
+        {
+            tags.push(tag.to_owned());
+        } else {
+            content.push(word);
+        }
+    }
+    Ok(TodoRecord {
+        content: content.join(" "),
+        status,
+        priority,
+        due,
+        tags,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use jiff::Zoned;
+
+    use super::{Format, ImportError, TodoRecord, export, parse};
+    use crate::todos::state::{
+        DEFAULT_LIST_ID, Priority, Todo, TodoStatus, TodosQuery, TodosState,
+    };
+
+    fn sample_state() -> TodosState {
+        let mut state = TodosState::new();
+        let todos = [
+            ("buy milk", TodoStatus::Done, Priority::Urgent),
+            (
+                "call \"mom\", then dad",
+                TodoStatus::InProgress,
+                Priority::Low,
+            ),
+            ("answer emails", TodoStatus::Blocked, Priority::Normal),
+            ("water plants", TodoStatus::Backlog, Priority::High),
+        ];
+        for (content, status, priority) in todos {
+            let todo_id = state.add_todo(DEFAULT_LIST_ID, content).unwrap().id;
+            state.set_status(todo_id, status);
+            state.set_priority(todo_id, priority);
+        }
+        state.add_tags(0, &["shop".to_owned(), "home".to_owned()]);
+        let due = "2025-01-31T18:00[Europe/Paris]".parse::<Zoned>().unwrap();
+        state.set_due(2, Some(due), None);
+        state
+    }
+
+    fn list_todos(state: &TodosState) -> Vec<&Todo> {
+        state.query(DEFAULT_LIST_ID, &TodosQuery::default())
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let state = sample_state();
+        for format in Format::VARIANTS {
+            let exported = export(format, &list_todos(&state));
+            let (records, errors) = parse(format, &exported);
+            assert_eq!(errors, vec![], "{format:?}");
+
+            let mut imported = TodosState::new();
+            imported.import_todos(DEFAULT_LIST_ID, &records).unwrap();
+            assert_eq!(
+                export(format, &list_todos(&imported)),
+                exported,
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_export_keeps_content() {
+        let record = |content: &str, status, priority| TodoRecord {
+            content: content.to_owned(),
+            status,
+            priority,
+            due: None,
+            tags: Vec::new(),
+        };
+        let records = vec![
+            record("x marks the spot", TodoStatus::Backlog, Priority::Normal),
+            record("x", TodoStatus::Done, Priority::Normal),
+            record("(A) is not a priority", TodoStatus::Backlog, Priority::High),
+            record(
+                "2025-01-01 was a Wednesday",
+                TodoStatus::Done,
+                Priority::Low,
+            ),
+            TodoRecord {
+                tags: vec!["work".to_owned()],
+                ..record(
+                    "ask +bob and @alice  about due:friday, status:done",
+                    TodoStatus::Blocked,
+                    Priority::Urgent,
+                )
+            },
+            record(
+                r"copy \\server\share   to \backup",
+                TodoStatus::InProgress,
+                Priority::Normal,
+            ),
+        ];
+        let mut state = TodosState::new();
+        state.import_todos(DEFAULT_LIST_ID, &records).unwrap();
+        for format in Format::VARIANTS {
+            let exported = export(format, &list_todos(&state));
+            let (imported, errors) = parse(format, &exported);
+            assert_eq!(errors, vec![], "{format:?}");
+            assert_eq!(imported, records, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_import_reports_errors_per_line() {
+        let input = "content,done,due,tags\nbuy milk,x,2025-01-31,shop\n,,,\n  ,no,,\nfix bike,maybe,,\nread,,tomorrow,\n";
+        let (records, errors) = parse(Format::Csv, input);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].status, TodoStatus::Done);
+        assert_eq!(records[0].tags, vec!["shop"]);
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![4, 5, 6]);
+
+        let (_, errors) = parse(Format::Json, "[{\"content\": \"a\"},\n{\"title\": \"b\"}]");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+
+        let (_, errors) = parse(Format::Csv, "content\n\"unterminated\n");
+        assert_eq!(
+            errors,
+            vec![ImportError::new(2, "unterminated quoted field")]
+        );
+    }
+
+    #[test]
+    fn test_parse_todotxt() {
+        let input = "x (A) 2025-01-02 2025-01-01 Call mom +family @phone due:2025-01-05\n\n(C) pay rent #home status:blocked\n";
+        let (records, errors) = parse(Format::TodoTxt, input);
+        assert_eq!(errors, vec![]);
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| (r.content.as_str(), r.status, r.priority, r.tags.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "Call mom",
+                    TodoStatus::Done,
+                    Priority::Urgent,
+                    vec!["family".to_owned(), "phone".to_owned()]
+                ),
+                (
+                    "pay rent",
+                    TodoStatus::Blocked,
+                    Priority::Normal,
+                    vec!["home".to_owned()]
+                ),
+            ]
+        );
+        assert!(records[0].due.is_some());
+    }
+}
@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use jiff::{SignedDuration, Timestamp};
+
+use crate::{ApiState, todos::history::Change};
+
+/// How long deleted todos stay in the trash.
+pub const TRASH_RETENTION: SignedDuration = SignedDuration::from_hours(30 * 24);
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Who automatic purges are recorded under in the todos history.
+const SYSTEM_ACTOR: &str = "system";
+
+/// Permanently delete the todos that have been in the trash for longer than
+/// [`TRASH_RETENTION`].
+pub async fn run_trash_purge(state: ApiState) {
+    tracing::info!("Starting todos trash purge");
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        let Ok(before) = Timestamp::now().checked_sub(TRASH_RETENTION) else {
+            continue;
+        };
+        let mut state = state.write().await;
+        let purged = state.todos.purge_trash(before);
+        for trashed in &purged {
+            state
+                .todos
+                .record_todo_change(SYSTEM_ACTOR, &trashed.todo, Change::Purged);
+        }
+        if !purged.is_empty() {
+            tracing::info!("Purged {} todos from the trash", purged.len());
+        }
+    }
+}
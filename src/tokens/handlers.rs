@@ -0,0 +1,197 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{HeaderMap, HeaderName, StatusCode, header::SET_COOKIE},
+    response::{IntoResponse, Response},
+};
+use jiff::Timestamp;
+use maud::{DOCTYPE, html};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ApiState,
+    todos::templates::username_input,
+    utils::{Actor, ContentNegotiator, Verified},
+};
+
+use super::{
+    state::{ApiToken, SESSION_DURATION, Scope},
+    templates::{sign_in_form, tokens_view},
+};
+
+/// Tokens of the signed in user. Pages sign in by creating the first token
+/// of their name, or with one of its tokens.
+pub async fn get_tokens(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    actor: Actor,
+    verified: Option<Verified>,
+) -> Response {
+    let state = state.read().await;
+    let json = headers.get("Accept").and_then(|h| h.to_str().ok()) == Some("application/json");
+    match (&verified, json) {
+        (Some(verified), true) => return Json(state.tokens.tokens(&verified.name)).into_response(),
+        (None, true) => return StatusCode::UNAUTHORIZED.into_response(),
+        (_, false) => {}
+    }
+    let tokens = verified
+        .as_ref()
+        .map(|verified| state.tokens.tokens(&verified.name));
+
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                script src="/assets/htmx.min.js" {}
+                link href="/assets/style/output.css" rel="stylesheet";
+            }
+            body.flex.flex-col.items-center {
+                h1.text-2xl.text-center.mt-2 { "API tokens" }
+                a.link.link-hover.my-4 href="/docs" { "API documentation" }
+                @match (&verified, &tokens) {
+                    (Some(verified), Some(tokens)) => {
+                        p.text-gray-500.my-4 {
+                            "Signed in as " strong { (verified.name) } ". Send tokens as "
+                            code { "Authorization: Bearer <token>" } " to " code { "/api/v1" } "."
+                        }
+                        (tokens_view(tokens, None))
+                    }
+                    _ => {
+                        (username_input(&actor.0))
+                        @if actor.is_anonymous() {
+                            p.text-gray-500.my-4 { "Choose a name, then reload the page to manage your tokens." }
+                        } @else if state.tokens.is_claimed(&actor.0) || state.is_admin(&actor.0) {
+                            p.text-gray-500.my-4 { "This name is taken, sign in with one of its tokens." }
+                            (sign_in_form(None))
+                        } @else {
+                            p.text-gray-500.my-4 {
+                                "Creating a first token claims the name " strong { (actor.0) }
+                                ", only its tokens can then be used to sign in."
+                            }
+                            (tokens_view(&[], None))
+                        }
+                    }
+                }
+            }
+        }
+    }
+    .into_response()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<Scope>,
+}
+
+/// A created token, `secret` cannot be retrieved afterwards.
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedToken<'a> {
+    pub token: &'a ApiToken,
+    pub secret: String,
+}
+
+/// The `Set-Cookie` header signing a browser in with a session, until it
+/// expires.
+fn session_cookie(secret: &str) -> [(HeaderName, String); 1] {
+    let cookie = format!(
+        "{}={secret}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+        Verified::SESSION_COOKIE,
+        SESSION_DURATION.as_secs()
+    );
+    [(SET_COOKIE, cookie)]
+}
+
+/// Create a token for the signed in user, with at most the scopes of the
+/// token the request is authenticated with. Users who are not signed in claim
+/// their name by creating its first token, and are signed in. Admin names
+/// cannot be claimed, their first token is created on startup instead, see
+/// [`crate::build_app`].
+pub async fn create_token(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    actor: Actor,
+    verified: Option<Verified>,
+    ContentNegotiator(payload): ContentNegotiator<CreateTokenRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    let now = Timestamp::now();
+    let (owner, claim) = match verified {
+        Some(verified) => {
+            if !payload.scopes.iter().all(|scope| verified.allows(*scope)) {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+            (verified.name, false)
+        }
+        None if actor.is_anonymous() => return StatusCode::UNAUTHORIZED.into_response(),
+        None if state.tokens.is_claimed(&actor.0) || state.is_admin(&actor.0) => {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        None => (actor.0, true),
+    };
+    let created = state
+        .tokens
+        .create(&owner, &payload.name, &payload.scopes, now)
+        .map(|(token, secret)| (token.clone(), secret));
+    let Some((token, secret)) = created else {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    };
+    // Claiming a name signs the browser in
+    let session = claim.then(|| session_cookie(&state.tokens.open_session(&token, now)));
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => {
+            let token = &token;
+            (
+                StatusCode::CREATED,
+                session,
+                Json(CreatedToken { token, secret }),
+            )
+                .into_response()
+        }
+        _ => (
+            session,
+            tokens_view(&state.tokens.tokens(&owner), Some(&secret)),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignInRequest {
+    pub secret: String,
+}
+
+/// Sign a browser in with one of the tokens of the user, the session has the
+/// scopes of the token and ends when it is revoked.
+pub async fn sign_in(
+    State(state): State<ApiState>,
+    ContentNegotiator(payload): ContentNegotiator<SignInRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    let now = Timestamp::now();
+    let Some(token) = state.tokens.authenticate(&payload.secret).cloned() else {
+        return sign_in_form(Some("Invalid or revoked token")).into_response();
+    };
+    state.tokens.mark_used(token.id, now);
+    let session = session_cookie(&state.tokens.open_session(&token, now));
+    (session, [("HX-Refresh", "true")]).into_response()
+}
+
+pub async fn revoke_token(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    verified: Verified,
+    Path((id,)): Path<(usize,)>,
+) -> Response {
+    let mut state = state.write().await;
+    let Some(token) = state.tokens.revoke(&verified.name, id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(token).into_response(),
+        _ => tokens_view(&state.tokens.tokens(&verified.name), None).into_response(),
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{generate_secret, hash_secret};
+
+/// What an API token grants access to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "todos:read")]
+    TodosRead,
+    #[serde(rename = "todos:write")]
+    TodosWrite,
+    #[serde(rename = "chat:read")]
+    ChatRead,
+    #[serde(rename = "chat:post")]
+    ChatPost,
+}
+
+impl Scope {
+    pub const VARIANTS: [Scope; 4] = [
+        Scope::TodosRead,
+        Scope::TodosWrite,
+        Scope::ChatRead,
+        Scope::ChatPost,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::TodosRead => "todos:read",
+            Scope::TodosWrite => "todos:write",
+            Scope::ChatRead => "chat:read",
+            Scope::ChatPost => "chat:post",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A personal API token. Only the hash of its secret is kept, the secret
+/// itself is shown once when the token is created.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: usize,
+    pub name: String,
+    /// Requests authenticated with the token are made on behalf of its owner.
+    pub owner: String,
+    /// The first characters of the secret, to tell tokens apart.
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: Timestamp,
+    pub last_used_at: Option<Timestamp>,
+}
+
+impl ApiToken {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+/// A token along with the hash of its secret, which is never sent back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredToken {
+    #[serde(flatten)]
+    token: ApiToken,
+    hash: String,
+}
+
+/// A browser signed in with a token, see [`TokensState::open_session`].
+/// Sessions saved before they were scoped have no scopes and are expired.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredSession {
+    #[serde(flatten)]
+    session: Session,
+    hash: String,
+    created_at: Timestamp,
+    #[serde(default)]
+    expires_at: Timestamp,
+}
+
+/// The session cookie a request was sent with, added to the request
+/// extensions, see [`crate::utils::Verified`]. It grants the scopes of the
+/// token it was opened with, until that token is revoked.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub owner: String,
+    #[serde(default)]
+    pub token_id: usize,
+    #[serde(default)]
+    pub scopes: Vec<Scope>,
+}
+
+/// Prefix of the secrets, so that leaked tokens are easy to spot.
+const SECRET_PREFIX: &str = "pat_";
+const PREFIX_LEN: usize = SECRET_PREFIX.len() + 6;
+const SESSION_PREFIX: &str = "sess_";
+/// How long browsers stay signed in.
+pub const SESSION_DURATION: SignedDuration = SignedDuration::from_hours(7 * 24);
+/// Last uses of the tokens are only recorded this often, so that checking a
+/// token does not need to change the state on every request.
+const LAST_USED_RESOLUTION: SignedDuration = SignedDuration::from_secs(60);
+const STATE_FILE: &str = "tokens.json";
+
+/// API tokens and the sessions of the browsers signed in with them, saved to
+/// the data directory of the todos when set.
+///
+/// Names are claimed by the first token created for them, nobody else can
+/// then create tokens on their behalf.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TokensState {
+    tokens: Vec<StoredToken>,
+    token_counter: usize,
+    sessions: Vec<StoredSession>,
+    /// Names tokens were created for, revoked ones included.
+    claimed: Vec<String>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl TokensState {
+    pub fn new() -> TokensState {
+        TokensState::default()
+    }
+
+    /// The state saved in `dir`, or an empty one saved there from now on.
+    pub fn load(dir: &Path) -> io::Result<TokensState> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(STATE_FILE);
+        let mut state: TokensState = match fs::read(&path) {
+            Ok(saved) => serde_json::from_slice(&saved)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => TokensState::new(),
+            Err(err) => return Err(err),
+        };
+        state.path = Some(path);
+        Ok(state)
+    }
+
+    /// Last uses of the tokens are saved along with the next change.
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        // Written aside then renamed, so that a crash never leaves a partial
+        // file behind
+        let tmp = path.with_extension("json.tmp");
+        let result = serde_json::to_vec(self)
+            .map_err(io::Error::from)
+            .and_then(|json| fs::write(&tmp, json))
+            .and_then(|()| fs::rename(&tmp, path));
+        if let Err(err) = result {
+            tracing::error!("Could not save API tokens: {err}");
+        }
+    }
+
+    /// Tokens of `owner`, oldest first.
+    pub fn tokens(&self, owner: &str) -> Vec<&ApiToken> {
+        self.tokens
+            .iter()
+            .map(|t| &t.token)
+            .filter(|t| t.owner == owner)
+            .collect()
+    }
+
+    /// Whether tokens were ever created for `name`, in which case only its
+    /// tokens and sessions can create new ones.
+    pub fn is_claimed(&self, name: &str) -> bool {
+        self.claimed.iter().any(|claimed| claimed == name)
+    }
+
+    /// Sign a browser in with `token`, returning the secret of its session
+    /// cookie. The session expires after [`SESSION_DURATION`].
+    pub fn open_session(&mut self, token: &ApiToken, now: Timestamp) -> String {
+        let secret = generate_secret(SESSION_PREFIX);
+        self.sessions.retain(|s| s.expires_at > now);
+        self.sessions.push(StoredSession {
+            session: Session {
+                owner: token.owner.clone(),
+                token_id: token.id,
+                scopes: token.scopes.clone(),
+            },
+            hash: hash_secret(&secret),
+            created_at: now,
+            expires_at: now + SESSION_DURATION,
+        });
+        self.save();
+        secret
+    }
+
+    /// The session of a secret, unless it expired at `now`.
+    pub fn session(&self, secret: &str, now: Timestamp) -> Option<&Session> {
+        let hash = hash_secret(secret.trim());
+        self.sessions
+            .iter()
+            .find(|s| s.hash == hash && s.expires_at > now)
+            .map(|s| &s.session)
+    }
+
+    /// Create a token, along with its secret. `None` without a name or scopes.
+    pub fn create(
+        &mut self,
+        owner: &str,
+        name: &str,
+        scopes: &[Scope],
+        now: Timestamp,
+    ) -> Option<(&ApiToken, String)> {
+        let name = name.trim();
+        if name.is_empty() || scopes.is_empty() {
+            return None;
+        }
+        let secret = generate_secret(SECRET_PREFIX);
+        let mut scopes = scopes.to_vec();
+        scopes.sort_by_key(|s| Scope::VARIANTS.iter().position(|v| v == s));
+        scopes.dedup();
+        self.tokens.push(StoredToken {
+            token: ApiToken {
+                id: self.token_counter,
+                name: name.to_owned(),
+                owner: owner.to_owned(),
+                prefix: secret[..PREFIX_LEN].to_owned(),
+                scopes,
+                created_at: now,
+                last_used_at: None,
+            },
+            hash: hash_secret(&secret),
+        });
+        self.token_counter += 1;
+        if !self.is_claimed(owner) {
+            self.claimed.push(owner.to_owned());
+        }
+        self.save();
+        let token = &self.tokens.last()?.token;
+        Some((token, secret))
+    }
+
+    /// The token of a secret.
+    pub fn authenticate(&self, secret: &str) -> Option<&ApiToken> {
+        let hash = hash_secret(secret.trim());
+        let stored = self.tokens.iter().find(|t| t.hash == hash)?;
+        Some(&stored.token)
+    }
+
+    /// Whether the last use of `token` is worth recording at `now`, see
+    /// [`TokensState::mark_used`].
+    pub fn is_stale(token: &ApiToken, now: Timestamp) -> bool {
+        token
+            .last_used_at
+            .is_none_or(|used| now.duration_since(used) >= LAST_USED_RESOLUTION)
+    }
+
+    /// Record that the token `id` was used at `now`.
+    pub fn mark_used(&mut self, id: usize, now: Timestamp) {
+        if let Some(stored) = self.tokens.iter_mut().find(|t| t.token.id == id) {
+            stored.token.last_used_at = Some(now);
+        }
+    }
+
+    /// Revoke a token of `owner`, it can no longer be used and the browsers
+    /// signed in with it are signed out.
+    pub fn revoke(&mut self, owner: &str, id: usize) -> Option<ApiToken> {
+        let idx = self
+            .tokens
+            .iter()
+            .position(|t| t.token.id == id && t.token.owner == owner)?;
+        let token = self.tokens.remove(idx).token;
+        self.sessions.retain(|s| s.session.token_id != id);
+        self.save();
+        Some(token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use jiff::{SignedDuration, Timestamp};
+
+    use super::{SESSION_DURATION, Scope, TokensState};
+
+    #[test]
+    fn test_tokens_lifecycle() {
+        let mut state = TokensState::new();
+        let now = Timestamp::now();
+        assert!(
+            state
+                .create("alice", "", &[Scope::TodosRead], now)
+                .is_none()
+        );
+        assert!(state.create("alice", "ci", &[], now).is_none());
+
+        let scopes = [Scope::ChatPost, Scope::TodosRead, Scope::TodosRead];
+        let (token, secret) = state.create("alice", "ci", &scopes, now).unwrap();
+        let id = token.id;
+        assert_eq!(token.scopes, vec![Scope::TodosRead, Scope::ChatPost]);
+        assert!(secret.starts_with(&token.prefix));
+        assert!(token.last_used_at.is_none());
+        // Only the hash is kept
+        assert!(!format!("{:?}", state.tokens("alice")).contains(&secret));
+
+        let token = state.authenticate(&secret).unwrap();
+        assert_eq!(token.owner, "alice");
+        assert!(TokensState::is_stale(token, now));
+        state.mark_used(id, now);
+        let token = state.authenticate(&secret).unwrap();
+        assert_eq!(token.last_used_at, Some(now));
+        assert!(!TokensState::is_stale(token, now));
+        assert!(TokensState::is_stale(
+            token,
+            now + SignedDuration::from_mins(1)
+        ));
+        assert!(state.authenticate("pat_wrong").is_none());
+
+        // Sessions have the scopes of their token, and expire
+        let token = state.authenticate(&secret).unwrap().clone();
+        let session = state.open_session(&token, now);
+        assert_eq!(state.session(&session, now).unwrap().scopes, token.scopes);
+        assert!(state.session(&session, now + SESSION_DURATION).is_none());
+
+        assert!(state.revoke("bob", id).is_none());
+        assert!(state.revoke("alice", id).is_some());
+        assert!(state.authenticate(&secret).is_none());
+        // Revoking a token signs its sessions out
+        assert!(state.session(&session, now).is_none());
+        // Names stay claimed once their tokens are revoked
+        assert!(state.is_claimed("alice"));
+        assert!(!state.is_claimed("bob"));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("tokens-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let now = Timestamp::now();
+
+        let mut state = TokensState::load(&dir).unwrap();
+        let (token, secret) = state
+            .create("alice", "ci", &[Scope::TodosRead], now)
+            .unwrap();
+        let token = token.clone();
+        let session = state.open_session(&token, now);
+
+        let loaded = TokensState::load(&dir).unwrap();
+        assert_eq!(loaded.authenticate(&secret).unwrap().owner, "alice");
+        let loaded_session = loaded.session(&session, now).unwrap();
+        assert_eq!(
+            (loaded_session.owner.as_str(), loaded_session.token_id),
+            ("alice", token.id)
+        );
+        assert!(loaded.session("sess_wrong", now).is_none());
+        // Secrets are only saved hashed
+        let saved = std::fs::read_to_string(dir.join("tokens.json")).unwrap();
+        assert!(!saved.contains(&secret) && !saved.contains(&session));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+use maud::{Markup, html};
+
+use crate::utils::format_timestamp;
+
+use super::state::{ApiToken, Scope};
+
+/// The tokens of a user, along with the secret of the one just created, which
+/// is only shown once.
+pub fn tokens_view(tokens: &[&ApiToken], created_secret: Option<&str>) -> Markup {
+    html! {
+        div.flex.flex-col.items-center.gap-4 #tokens {
+            @if let Some(secret) = created_secret {
+                div.alert.alert-success.flex.flex-col role="alert" {
+                    span { "Copy the token now, it will not be shown again:" }
+                    code.select-all #token-secret { (secret) }
+                }
+            }
+            form.flex.flex-col.gap-2.bg-base-200.rounded-box.p-4 #new-token
+                hx-post="/tokens" hx-target="#tokens" hx-swap="outerHTML" {
+                input.input.input-sm type="text" name="name" placeholder="Token name" required;
+                div.flex.gap-4 {
+                    @for scope in Scope::VARIANTS {
+                        label.label.text-sm {
+                            input.checkbox.checkbox-sm type="checkbox" name="scopes" value=(scope);
+                            (scope)
+                        }
+                    }
+                }
+                button.btn.btn-sm.btn-primary { "Create token" }
+            }
+            @if tokens.is_empty() {
+                p.text-gray-500 { "No API tokens yet." }
+            } @else {
+                table.table.bg-base-100.rounded-box.shadow-md {
+                    thead {
+                        tr {
+                            th { "Name" }
+                            th { "Token" }
+                            th { "Scopes" }
+                            th { "Created" }
+                            th { "Last used" }
+                            th {}
+                        }
+                    }
+                    tbody {
+                        @for token in tokens {
+                            tr data-token-id=(token.id) {
+                                td { (token.name) }
+                                td.font-mono { (token.prefix) "…" }
+                                td {
+                                    @for scope in &token.scopes {
+                                        span.badge.badge-sm.mr-1 { (scope) }
+                                    }
+                                }
+                                td { (format_timestamp(token.created_at)) }
+                                td.last-used {
+                                    @match token.last_used_at {
+                                        Some(used) => (format_timestamp(used)),
+                                        None => span.text-gray-500 { "Never" },
+                                    }
+                                }
+                                td {
+                                    button.btn.btn-sm.btn-error
+                                        hx-delete={ "/tokens/" (token.id) }
+                                        hx-target="#tokens"
+                                        hx-swap="outerHTML"
+                                        hx-confirm={ "Revoke " (token.name) "?" } {
+                                        "Revoke"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sign in with a token, for names already claimed. The page is reloaded once
+/// signed in.
+pub fn sign_in_form(error: Option<&str>) -> Markup {
+    html! {
+        form.flex.flex-col.items-center.gap-2 #sign-in hx-post="/tokens/session" hx-swap="outerHTML" {
+            div.join {
+                input.input.input-sm.join-item.input-error[error.is_some()]
+                    type="password" name="secret" placeholder="pat_…" required;
+                button.btn.btn-sm.btn-primary.join-item { "Sign in" }
+            }
+            @if let Some(error) = error {
+                p.text-error.text-sm { (error) }
+            }
+        }
+    }
+}
@@ -1,9 +1,23 @@
 use axum::{
-    Form, Json,
-    extract::{FromRequest, Request},
-    http::{StatusCode, header},
+    Json,
+    extract::{ConnectInfo, FromRequest, FromRequestParts, OptionalFromRequestParts, Request},
+    http::{HeaderMap, StatusCode, header, request::Parts},
 };
-use serde::Deserialize;
+use axum_extra::extract::Form;
+use jiff::{Timestamp, tz::TimeZone};
+use percent_encoding::percent_decode_str;
+use std::{
+    convert::Infallible,
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use rand::Rng;
+use serde::{Deserialize, Deserializer, de};
+use sha2::{Digest, Sha256};
+
+use crate::tokens::state::{ApiToken, Scope, Session};
 
 pub struct ContentNegotiator<T>(pub T);
 
@@ -31,7 +45,8 @@ where
             return Ok(ContentNegotiator(payload));
         }
 
-        // Default to form data
+        // Default to form data, repeated keys (e.g. checkboxes) can be
+        // collected into a `Vec`
         let Form(payload) = Form::<T>::from_request(req, state)
             .await
             .map_err(|_| StatusCode::BAD_REQUEST)?;
@@ -39,3 +54,220 @@ where
         Ok(ContentNegotiator(payload))
     }
 }
+
+/// Who issued a request: the owner of the API token or of the session it was
+/// authenticated with, the `X-Username` header for API clients, or the
+/// `username` cookie set from the pages, `anonymous` otherwise. Anyone can
+/// send any name, see [`Verified`] for routes that need to trust it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Actor(pub String);
+
+/// Environment variable listing the users allowed to administrate the app,
+/// e.g. `ADMIN_USERS=alice,bob`. Nobody is an admin when it is unset.
+pub const ADMIN_USERS_VAR: &str = "ADMIN_USERS";
+
+impl Actor {
+    pub const COOKIE: &str = "username";
+    const ANONYMOUS: &str = "anonymous";
+
+    pub fn is_anonymous(&self) -> bool {
+        self.0 == Actor::ANONYMOUS
+    }
+}
+
+/// The names listed in [`ADMIN_USERS_VAR`].
+pub fn admin_users() -> Vec<String> {
+    std::env::var(ADMIN_USERS_VAR)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|admin| !admin.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+impl<S> FromRequestParts<S> for Actor
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(token) = parts.extensions.get::<ApiToken>() {
+            return Ok(Actor(token.owner.clone()));
+        }
+        if let Some(session) = parts.extensions.get::<Session>() {
+            return Ok(Actor(session.owner.clone()));
+        }
+        let from_header = parts
+            .headers
+            .get("X-Username")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned);
+        let from_cookie = || cookie(&parts.headers, Actor::COOKIE);
+
+        let name = from_header
+            .or_else(from_cookie)
+            .map(|name| name.trim().to_owned())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| Actor::ANONYMOUS.to_owned());
+        Ok(Actor(name))
+    }
+}
+
+/// The value of a cookie of a request, percent-decoded.
+pub fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|cookie| {
+            let (key, value) = cookie.trim().split_once('=')?;
+            (key == name).then_some(value)
+        })
+        .and_then(|value| percent_decode_str(value).decode_utf8().ok())
+        .map(|value| value.into_owned())
+}
+
+/// Who issued a request, as checked by the server: the owner of the API token
+/// sent as `Authorization: Bearer`, or of the session cookie of a browser
+/// signed in with one, see [`crate::api::auth::authenticate`]. Requests
+/// without either are rejected as unauthorized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verified {
+    pub name: String,
+    /// The scopes of the token, which sessions inherit from the token they
+    /// were opened with.
+    pub scopes: Vec<Scope>,
+}
+
+impl Verified {
+    /// Cookie holding the secret of the session of a signed in browser.
+    pub const SESSION_COOKIE: &str = "session";
+
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+impl<S> OptionalFromRequestParts<S> for Verified
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        if let Some(token) = parts.extensions.get::<ApiToken>() {
+            return Ok(Some(Verified {
+                name: token.owner.clone(),
+                scopes: token.scopes.clone(),
+            }));
+        }
+        let session = parts.extensions.get::<Session>();
+        Ok(session.map(|session| Verified {
+            name: session.owner.clone(),
+            scopes: session.scopes.clone(),
+        }))
+    }
+}
+
+impl<S> FromRequestParts<S> for Verified
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let verified = <Verified as OptionalFromRequestParts<S>>::from_request_parts(parts, state);
+        match verified.await {
+            Ok(Some(verified)) => Ok(verified),
+            _ => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+}
+
+/// The address a request comes from, unknown when the app is not served with
+/// its [`ConnectInfo`], e.g. in tests.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        Ok(ClientIp(ip))
+    }
+}
+
+/// Whether the request was issued by htmx to swap a fragment of the page.
+///
+/// History restoration requests also carry `HX-Request`, but they expect the
+/// full page to be returned.
+pub fn is_htmx_request(headers: &HeaderMap) -> bool {
+    headers.contains_key("HX-Request") && !headers.contains_key("HX-History-Restore-Request")
+}
+
+/// A timestamp in the server time zone, for the pages.
+pub fn format_timestamp(timestamp: Timestamp) -> String {
+    timestamp
+        .to_zoned(TimeZone::system())
+        .strftime("%a %d %b %Y %H:%M")
+        .to_string()
+}
+
+/// A random secret, e.g. for API tokens. `prefix` tells what it is for.
+pub fn generate_secret(prefix: &str) -> String {
+    let bytes: [u8; 20] = rand::rng().random();
+    format!("{prefix}{}", hex::encode(bytes))
+}
+
+/// Secrets are only stored hashed, and compared through their hash.
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// Whether the `If-Match` header of a request matches `etag`. Requests
+/// without the header always match.
+pub fn if_match(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(value) = headers.get(header::IF_MATCH).and_then(|h| h.to_str().ok()) else {
+        return true;
+    };
+    value
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOr<T> {
+    String(String),
+    Value(T),
+}
+
+/// Deserialize an optional value that form data sends as a string, where an
+/// empty string (e.g. an empty `<select>` option) means `None`.
+pub fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr + Deserialize<'de>,
+    T::Err: Display,
+{
+    match Option::<StringOr<T>>::deserialize(de)? {
+        None => Ok(None),
+        Some(StringOr::Value(value)) => Ok(Some(value)),
+        Some(StringOr::String(s)) if s.trim().is_empty() => Ok(None),
+        Some(StringOr::String(s)) => s.trim().parse().map(Some).map_err(de::Error::custom),
+    }
+}
@@ -0,0 +1,394 @@
+use std::{collections::HashSet, fs, io, path::Path, time::Duration};
+
+use futures_util::future::join_all;
+use jiff::Timestamp;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    ApiState,
+    chat::state::ChatEvent,
+    todos::state::{TodosEvent, TodosState},
+};
+
+use super::state::{Attempt, Delivery, EventType, sign};
+
+/// How long receivers have to answer a delivery.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Upper bound on how long the worker sleeps between two checks.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Longest error kept in the delivery log.
+const MAX_ERROR_LEN: usize = 200;
+
+/// Queue a delivery for the todos and chat events subscriptions may be
+/// interested in.
+pub async fn run_webhook_events(state: ApiState) {
+    tracing::info!("Starting webhook events");
+    let (mut rx_todos, mut rx_messages, mut rx_chat, mut done) = {
+        let state = state.read().await;
+        (
+            state.todos_events.subscribe(),
+            state.chat.tx_broadcast.subscribe(),
+            state.chat.tx_events.subscribe(),
+            done_todos(&state.todos),
+        )
+    };
+
+    loop {
+        tokio::select! {
+            event = rx_todos.recv() => match event {
+                Ok(event) => {
+                    let mut state = state.write().await;
+                    let state = &mut *state;
+                    for (event_type, data) in todo_events(&state.todos, &mut done, event) {
+                        state.webhooks.enqueue(event_type, data, Timestamp::now());
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Webhooks missed {skipped} todos events");
+                    done = done_todos(&state.read().await.todos);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = rx_messages.recv() => match message {
+                Ok(message) => {
+                    let data = json!(message);
+                    let mut state = state.write().await;
+                    state.webhooks.enqueue(EventType::ChatMessagePosted, data, Timestamp::now());
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Webhooks missed {skipped} chat messages");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            event = rx_chat.recv() => match event {
+                Ok(ChatEvent::Joined { room, username }) => {
+                    let data = json!({ "room": room, "username": username });
+                    let mut state = state.write().await;
+                    state.webhooks.enqueue(EventType::ChatUserJoined, data, Timestamp::now());
+                }
+                Ok(ChatEvent::MessageDeleted { .. } | ChatEvent::Kicked { .. }) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Webhooks missed {skipped} chat events");
+                }
+                Err(RecvError::Closed) => break,
+            },
+        }
+    }
+}
+
+fn done_todos(todos: &TodosState) -> HashSet<usize> {
+    todos
+        .todos()
+        .iter()
+        .filter(|t| t.is_done())
+        .map(|t| t.id)
+        .collect()
+}
+
+/// The webhook events of a todos event. Todos events only tell which todo
+/// changed, `done` keeps track of the completed ones to tell completions
+/// apart from other updates.
+fn todo_events(
+    todos: &TodosState,
+    done: &mut HashSet<usize>,
+    event: TodosEvent,
+) -> Vec<(EventType, serde_json::Value)> {
+    let (created, updated, deleted) = match event {
+        TodosEvent::Created(id) => (vec![id], vec![], vec![]),
+        TodosEvent::Updated(id) => (vec![], vec![id], vec![]),
+        TodosEvent::Deleted(id) => (vec![], vec![], vec![id]),
+        TodosEvent::Bulk(summary) => (summary.created, summary.updated, summary.deleted),
+        TodosEvent::Restored(ids) => (vec![], ids, vec![]),
+        TodosEvent::Reordered
+        | TodosEvent::ListsChanged
+        | TodosEvent::TagsChanged
+        | TodosEvent::Reminder { .. } => return vec![],
+    };
+
+    let mut events = vec![];
+    for todo in created.iter().filter_map(|&id| todos.todo(id)) {
+        if todo.is_done() {
+            done.insert(todo.id);
+        }
+        events.push((EventType::TodoCreated, json!(todo)));
+    }
+    for todo in updated.iter().filter_map(|&id| todos.todo(id)) {
+        if !todo.is_done() {
+            done.remove(&todo.id);
+        } else if done.insert(todo.id) {
+            events.push((EventType::TodoCompleted, json!(todo)));
+        }
+    }
+    for id in deleted {
+        done.remove(&id);
+        if let Some(trashed) = todos.trash().iter().find(|t| t.todo.id == id) {
+            events.push((EventType::TodoDeleted, json!(trashed.todo)));
+        }
+    }
+    events
+}
+
+/// Post a delivery to `url`, signed with `secret`.
+pub async fn attempt_delivery(
+    client: &reqwest::Client,
+    url: &str,
+    secret: &str,
+    delivery: &Delivery,
+) -> Attempt {
+    let at = Timestamp::now();
+    let body = delivery.payload.to_string();
+    let timestamp = at.as_second();
+    let response = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Event", delivery.event.as_str())
+        .header("X-Webhook-Delivery", delivery.id)
+        .header("X-Webhook-Timestamp", timestamp)
+        .header("X-Webhook-Signature", sign(secret, timestamp, &body))
+        .body(body)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) => {
+            let status = response.status();
+            let error = (!status.is_success()).then(|| {
+                status
+                    .canonical_reason()
+                    .unwrap_or("Unexpected status")
+                    .to_owned()
+            });
+            Attempt {
+                at,
+                status_code: Some(status.as_u16()),
+                error,
+            }
+        }
+        Err(err) => {
+            let error: String = err.to_string().chars().take(MAX_ERROR_LEN).collect();
+            Attempt {
+                at,
+                status_code: None,
+                error: Some(error),
+            }
+        }
+    }
+}
+
+/// Send the queued deliveries when they are due.
+///
+/// Like reminders, the worker sleeps until the next due delivery, and is
+/// woken up early when deliveries are queued.
+pub async fn run_webhook_deliveries(state: ApiState) {
+    tracing::info!("Starting webhook deliveries");
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent(concat!("poc-rust-htmx/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("Could not build the webhooks HTTP client");
+    let queued = state.read().await.webhooks.queued.clone();
+
+    loop {
+        let due: Vec<(Delivery, String, String)> = {
+            let state = state.read().await;
+            state
+                .webhooks
+                .due_deliveries(Timestamp::now())
+                .into_iter()
+                .filter_map(|delivery| {
+                    let subscription = state.webhooks.subscription(delivery.subscription_id)?;
+                    let (url, secret) =
+                        (subscription.url.clone(), subscription.secret().to_owned());
+                    Some((delivery, url, secret))
+                })
+                .collect()
+        };
+        let client = &client;
+        let attempts = join_all(due.iter().map(|(delivery, url, secret)| async move {
+            (
+                delivery.id,
+                attempt_delivery(client, url, secret, delivery).await,
+            )
+        }))
+        .await;
+        if !attempts.is_empty() {
+            let mut state = state.write().await;
+            for (id, attempt) in attempts {
+                if let Some(delivery) = state.webhooks.record_attempt(id, attempt) {
+                    tracing::info!(
+                        "Webhook delivery {} of {} is {:?}",
+                        delivery.id,
+                        delivery.event.as_str(),
+                        delivery.status
+                    );
+                }
+            }
+        }
+
+        let sleep = match state.read().await.webhooks.next_attempt_at() {
+            Some(next) => Duration::try_from(Timestamp::now().duration_until(next))
+                .unwrap_or(Duration::ZERO)
+                .min(MAX_SLEEP),
+            None => MAX_SLEEP,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(sleep) => {}
+            _ = queued.notified() => {}
+        }
+    }
+}
+
+/// Save the subscriptions whenever they change, if they are persisted. The
+/// file is written on the blocking threads, without holding the lock of the
+/// state.
+pub async fn run_webhooks_persistence(state: ApiState) {
+    let (path, changed) = {
+        let state = state.read().await;
+        let Some(path) = state.webhooks.path() else {
+            return;
+        };
+        (path.to_owned(), state.webhooks.changed.clone())
+    };
+    tracing::info!("Starting webhooks persistence");
+
+    loop {
+        changed.notified().await;
+        let saved = serde_json::to_vec(&state.read().await.webhooks);
+        let path = path.clone();
+        let result = match saved {
+            Ok(json) => tokio::task::spawn_blocking(move || save(&path, &json))
+                .await
+                .unwrap_or_else(|err| Err(io::Error::other(err))),
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = result {
+            tracing::error!("Could not save webhooks: {err}");
+        }
+    }
+}
+
+/// Written aside then renamed, so that a crash never leaves a partial file
+/// behind.
+fn save(path: &Path, json: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json)?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{Router, extract::State, http::HeaderMap, http::StatusCode, routing::post};
+    use jiff::Timestamp;
+    use serde_json::json;
+
+    use super::{attempt_delivery, todo_events};
+    use crate::{
+        todos::state::{DEFAULT_LIST_ID, TodosEvent, TodosState},
+        webhooks::state::{DeliveryStatus, EventType, WebhooksState, sign},
+    };
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+    /// A receiver failing its first request, and accepting the next ones.
+    async fn receiver(
+        State(received): State<Received>,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        let mut received = received.lock().unwrap();
+        received.push((headers, body));
+        if received.len() == 1 {
+            StatusCode::INTERNAL_SERVER_ERROR
+        } else {
+            StatusCode::NO_CONTENT
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deliver_with_retry() {
+        let received = Received::default();
+        let app = Router::new()
+            .route("/hook", post(receiver))
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut state = WebhooksState::new();
+        let now = Timestamp::now();
+        let (subscription, secret) = state
+            .subscribe(&url, &[EventType::ChatUserJoined], "alice", now)
+            .unwrap();
+        let subscription_id = subscription.id;
+        let data = json!({ "room": "general", "username": "bob" });
+        state.enqueue(EventType::ChatUserJoined, data, now);
+        let client = reqwest::Client::new();
+
+        let [delivery] = &state.due_deliveries(now)[..] else {
+            panic!("One delivery is due");
+        };
+        assert_eq!(delivery.subscription_id, subscription_id);
+        let attempt = attempt_delivery(&client, &url, &secret, delivery).await;
+        assert_eq!(attempt.status_code, Some(500));
+        let delivery = state.record_attempt(delivery.id, attempt).unwrap().clone();
+        assert_eq!(delivery.status, DeliveryStatus::Pending);
+
+        let attempt = attempt_delivery(&client, &url, &secret, &delivery).await;
+        assert_eq!(attempt.status_code, Some(204));
+        let delivery = state.record_attempt(delivery.id, attempt).unwrap();
+        assert_eq!(delivery.status, DeliveryStatus::Delivered);
+        assert_eq!(delivery.attempts.len(), 2);
+
+        let (headers, body) = received.lock().unwrap()[1].clone();
+        let header = |name| headers[name].to_str().unwrap();
+        assert_eq!(header("X-Webhook-Event"), "chat.user_joined");
+        let timestamp = header("X-Webhook-Timestamp").parse().unwrap();
+        assert_eq!(
+            header("X-Webhook-Signature"),
+            sign(&secret, timestamp, &body)
+        );
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["event"], "chat.user_joined");
+        assert_eq!(body["data"]["username"], "bob");
+
+        // Nothing listens on port 1
+        let attempt = attempt_delivery(&client, "http://127.0.0.1:1", &secret, delivery).await;
+        assert_eq!(attempt.status_code, None);
+        assert!(attempt.error.is_some());
+    }
+
+    #[test]
+    fn test_todo_events() {
+        let mut todos = TodosState::new();
+        let mut done = HashSet::new();
+        let id = todos.add_todo(DEFAULT_LIST_ID, "Write tests").unwrap().id;
+        let events = |todos: &TodosState, done: &mut _, event| -> Vec<EventType> {
+            let events = todo_events(todos, done, event);
+            events
+                .into_iter()
+                .map(|(event_type, _)| event_type)
+                .collect()
+        };
+
+        let created = events(&todos, &mut done, TodosEvent::Created(id));
+        assert_eq!(created, [EventType::TodoCreated]);
+        assert!(events(&todos, &mut done, TodosEvent::Updated(id)).is_empty());
+        todos.toggle_todo(id);
+        let completed = events(&todos, &mut done, TodosEvent::Updated(id));
+        assert_eq!(completed, [EventType::TodoCompleted]);
+        // Only the completion itself is delivered
+        assert!(events(&todos, &mut done, TodosEvent::Updated(id)).is_empty());
+        todos.delete_todo(id);
+        let deleted = events(&todos, &mut done, TodosEvent::Deleted(id));
+        assert_eq!(deleted, [EventType::TodoDeleted]);
+        assert!(done.is_empty());
+    }
+}
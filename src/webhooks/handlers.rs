@@ -0,0 +1,269 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use jiff::Timestamp;
+use maud::{DOCTYPE, html};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ApiState,
+    todos::templates::username_input,
+    utils::{Actor, ContentNegotiator, Verified},
+};
+
+use super::{
+    state::{Delivery, EventType, Subscription},
+    templates::{deliveries_log, subscriptions_view},
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Webhooks<'a> {
+    pub subscriptions: Vec<Subscription>,
+    pub deliveries: Vec<&'a Delivery>,
+}
+
+/// Outgoing webhooks and their deliveries, for verified admins only.
+pub async fn get_subscriptions(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    actor: Actor,
+    verified: Option<Verified>,
+) -> Response {
+    let state = state.read().await;
+    let admin = verified.is_some_and(|verified| state.is_admin(&verified.name));
+    let subscriptions: Vec<Subscription> = state
+        .webhooks
+        .subscriptions()
+        .iter()
+        .map(Subscription::redacted)
+        .collect();
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => {
+            if !admin {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+            let deliveries = state.webhooks.deliveries().collect();
+            Json(Webhooks {
+                subscriptions,
+                deliveries,
+            })
+            .into_response()
+        }
+        _ => html! {
+            (DOCTYPE)
+            html {
+                head {
+                    script src="/assets/htmx.min.js" {}
+                    link href="/assets/style/output.css" rel="stylesheet";
+                }
+                body.flex.flex-col.items-center.gap-4 {
+                    h1.text-2xl.text-center.mt-2 { "Webhooks" }
+                    (username_input(&actor.0))
+                    @if !admin {
+                        p.text-gray-500 {
+                            "Only admins can manage webhooks, once "
+                            a.link href="/tokens" { "signed in" } "."
+                        }
+                    } @else {
+                        p.text-gray-500 {
+                            "Events are posted as JSON, signed in " code { "X-Webhook-Signature" }
+                            " with the HMAC-SHA256 of " code { "{X-Webhook-Timestamp}.{body}" } "."
+                        }
+                        (subscriptions_view(&subscriptions, None))
+                        h2.text-xl { "Deliveries" }
+                        (deliveries_log(state.webhooks.deliveries()))
+                    }
+                }
+            }
+        }
+        .into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubscriptionRequest {
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<EventType>,
+}
+
+/// A created subscription, `secret` cannot be retrieved afterwards.
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedSubscription<'a> {
+    pub subscription: &'a Subscription,
+    pub secret: String,
+}
+
+pub async fn create_subscription(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Verified { name, .. }: Verified,
+    ContentNegotiator(payload): ContentNegotiator<SubscriptionRequest>,
+) -> Response {
+    let mut state = state.write().await;
+    if !state.is_admin(&name) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let Some((subscription, secret)) =
+        state
+            .webhooks
+            .subscribe(&payload.url, &payload.events, &name, Timestamp::now())
+    else {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    };
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => {
+            let subscription = &subscription.redacted();
+            let created = CreatedSubscription {
+                subscription,
+                secret,
+            };
+            (StatusCode::CREATED, Json(created)).into_response()
+        }
+        _ => subscriptions_view(&redacted(&state.webhooks), Some(&secret)).into_response(),
+    }
+}
+
+pub async fn delete_subscription(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Verified { name, .. }: Verified,
+    Path((id,)): Path<(usize,)>,
+) -> Response {
+    let mut state = state.write().await;
+    if !state.is_admin(&name) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let Some(subscription) = state.webhooks.unsubscribe(id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => Json(subscription.redacted()).into_response(),
+        _ => subscriptions_view(&redacted(&state.webhooks), None).into_response(),
+    }
+}
+
+/// The delivery log, most recent first.
+pub async fn get_deliveries(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Verified { name, .. }: Verified,
+) -> Response {
+    let state = state.read().await;
+    if !state.is_admin(&name) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    match headers.get("Accept").and_then(|h| h.to_str().ok()) {
+        Some("application/json") => {
+            Json(state.webhooks.deliveries().collect::<Vec<_>>()).into_response()
+        }
+        _ => deliveries_log(state.webhooks.deliveries()).into_response(),
+    }
+}
+
+fn redacted(webhooks: &super::state::WebhooksState) -> Vec<Subscription> {
+    webhooks
+        .subscriptions()
+        .iter()
+        .map(Subscription::redacted)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use serde_json::{Value, json};
+    use tower::ServiceExt;
+
+    use crate::build_test_app;
+
+    async fn send(
+        (app, authorization): &(Router, String),
+        method: &str,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Accept", "application/json")
+            .header("Authorization", authorization);
+        if body.is_some() {
+            request = request.header("Content-Type", "application/json");
+        }
+        let body = body.map_or_else(Body::empty, |body| Body::from(body.to_string()));
+        let response = app
+            .clone()
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn test_manage_subscriptions() {
+        let app = build_test_app("alice");
+        // Let the background tasks subscribe to the events
+        tokio::task::yield_now().await;
+        let body = json!({ "url": "file:///etc/passwd", "events": ["todo.created"] });
+        let (status, _) = send(&app, "POST", "/webhooks", Some(body)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body = json!({ "url": "http://127.0.0.1:1/hook", "events": ["todo.created"] });
+        let (status, created) = send(&app, "POST", "/webhooks", Some(body)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let secret = created["secret"].as_str().unwrap();
+        assert!(secret.starts_with("whsec_"));
+        let id = created["subscription"]["id"].as_u64().unwrap();
+
+        // Creating a todo queues a delivery
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/todo",
+            Some(json!({ "content": "Ship webhooks" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, webhooks) = send(&app, "GET", "/webhooks", None).await;
+        assert!(!webhooks.to_string().contains(secret));
+        assert_eq!(
+            webhooks["subscriptions"][0]["url"],
+            "http://127.0.0.1:1/hook"
+        );
+        let mut deliveries = json!([]);
+        for _ in 0..50 {
+            (_, deliveries) = send(&app, "GET", "/webhooks/deliveries", None).await;
+            if deliveries[0]["attempts"]
+                .as_array()
+                .is_some_and(|a| !a.is_empty())
+            {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(deliveries[0]["event"], "todo.created");
+        assert_eq!(deliveries[0]["payload"]["data"]["content"], "Ship webhooks");
+        assert_eq!(deliveries[0]["status"], "pending");
+        assert_eq!(deliveries[0]["attempts"][0]["status_code"], Value::Null);
+
+        let (status, _) = send(&app, "DELETE", &format!("/webhooks/{id}"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, "DELETE", &format!("/webhooks/{id}"), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
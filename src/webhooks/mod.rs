@@ -0,0 +1,4 @@
+pub mod delivery;
+pub mod handlers;
+pub mod state;
+pub mod templates;
@@ -0,0 +1,422 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use hmac::{Hmac, Mac};
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::sync::Notify;
+
+use crate::utils::generate_secret;
+
+/// What outgoing webhooks can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventType {
+    #[serde(rename = "todo.created")]
+    TodoCreated,
+    #[serde(rename = "todo.completed")]
+    TodoCompleted,
+    #[serde(rename = "todo.deleted")]
+    TodoDeleted,
+    #[serde(rename = "chat.message_posted")]
+    ChatMessagePosted,
+    #[serde(rename = "chat.user_joined")]
+    ChatUserJoined,
+}
+
+impl EventType {
+    pub const VARIANTS: [EventType; 5] = [
+        EventType::TodoCreated,
+        EventType::TodoCompleted,
+        EventType::TodoDeleted,
+        EventType::ChatMessagePosted,
+        EventType::ChatUserJoined,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventType::TodoCreated => "todo.created",
+            EventType::TodoCompleted => "todo.completed",
+            EventType::TodoDeleted => "todo.deleted",
+            EventType::ChatMessagePosted => "chat.message_posted",
+            EventType::ChatUserJoined => "chat.user_joined",
+        }
+    }
+}
+
+/// A URL events are posted to. Deliveries are signed with `secret`, which is
+/// only shown when the subscription is created.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Subscription {
+    pub id: usize,
+    pub url: String,
+    pub events: Vec<EventType>,
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    secret: String,
+    pub created_by: String,
+    pub created_at: Timestamp,
+}
+
+impl Subscription {
+    /// The subscription as shown to clients, without its secret.
+    pub fn redacted(&self) -> Subscription {
+        Subscription {
+            secret: String::new(),
+            ..self.clone()
+        }
+    }
+
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+/// One try at delivering an event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attempt {
+    pub at: Timestamp,
+    /// Status code of the response, `None` when no response was received.
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+}
+
+impl Attempt {
+    pub fn succeeded(&self) -> bool {
+        self.status_code
+            .is_some_and(|code| (200..300).contains(&code))
+    }
+}
+
+/// An event to deliver to a subscription, along with the attempts made so
+/// far.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: usize,
+    pub subscription_id: usize,
+    pub event: EventType,
+    /// The JSON body posted to the subscription.
+    pub payload: Value,
+    pub status: DeliveryStatus,
+    pub attempts: Vec<Attempt>,
+    pub next_attempt_at: Option<Timestamp>,
+    pub created_at: Timestamp,
+}
+
+/// Deliveries are retried this many times in total before being given up.
+pub const MAX_ATTEMPTS: usize = 6;
+
+/// Delay before the first retry, doubled after each failed attempt.
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(10);
+
+/// How many finished deliveries are kept in the log.
+const LOG_SIZE: usize = 200;
+
+const SECRET_PREFIX: &str = "whsec_";
+const STATE_FILE: &str = "webhooks.json";
+
+/// Delay before the attempt following `attempts` failed ones.
+pub fn retry_delay(attempts: usize) -> SignedDuration {
+    let factor = 1u32 << attempts.saturating_sub(1).min(16);
+    SignedDuration::try_from(RETRY_BASE_DELAY * factor).unwrap_or(SignedDuration::MAX)
+}
+
+/// `sha256=<hex>` HMAC of `{timestamp}.{body}`, sent as `X-Webhook-Signature`
+/// along with `X-Webhook-Timestamp`, so that receivers can check deliveries
+/// come from here and are not replayed.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Subscriptions and the queue of their deliveries. Subscriptions are saved
+/// to the data directory of the todos, when set, see
+/// [`run_webhooks_persistence`](super::delivery::run_webhooks_persistence).
+/// Deliveries only live in memory, as they change with every event.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WebhooksState {
+    subscriptions: Vec<Subscription>,
+    subscription_counter: usize,
+    #[serde(skip)]
+    deliveries: Vec<Delivery>,
+    #[serde(skip)]
+    delivery_counter: usize,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// Wakes the delivery worker up when deliveries are queued.
+    #[serde(skip)]
+    pub queued: Arc<Notify>,
+    /// Wakes the persistence worker up when subscriptions change.
+    #[serde(skip)]
+    pub changed: Arc<Notify>,
+}
+
+impl WebhooksState {
+    pub fn new() -> WebhooksState {
+        WebhooksState::default()
+    }
+
+    /// The state saved in `dir`, or an empty one saved there from now on.
+    pub fn load(dir: &Path) -> io::Result<WebhooksState> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(STATE_FILE);
+        let mut state: WebhooksState = match fs::read(&path) {
+            Ok(saved) => serde_json::from_slice(&saved)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => WebhooksState::new(),
+            Err(err) => return Err(err),
+        };
+        state.path = Some(path);
+        Ok(state)
+    }
+
+    /// The file the subscriptions are saved to, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn subscriptions(&self) -> &[Subscription] {
+        &self.subscriptions
+    }
+
+    pub fn subscription(&self, id: usize) -> Option<&Subscription> {
+        self.subscriptions.iter().find(|s| s.id == id)
+    }
+
+    /// Subscribe an `http(s)` URL to events, `None` for other URLs or without
+    /// events. The secret deliveries are signed with is returned as well.
+    pub fn subscribe(
+        &mut self,
+        url: &str,
+        events: &[EventType],
+        created_by: &str,
+        now: Timestamp,
+    ) -> Option<(&Subscription, String)> {
+        let url = url.trim();
+        let valid_url = reqwest::Url::parse(url)
+            .is_ok_and(|url| ["http", "https"].contains(&url.scheme()) && url.has_host());
+        if !valid_url || events.is_empty() {
+            return None;
+        }
+        let mut events = events.to_vec();
+        events.sort_by_key(|e| EventType::VARIANTS.iter().position(|v| v == e));
+        events.dedup();
+        let secret = generate_secret(SECRET_PREFIX);
+        self.subscriptions.push(Subscription {
+            id: self.subscription_counter,
+            url: url.to_owned(),
+            events,
+            secret: secret.clone(),
+            created_by: created_by.to_owned(),
+            created_at: now,
+        });
+        self.subscription_counter += 1;
+        self.changed.notify_one();
+        let subscription = self.subscriptions.last()?;
+        Some((subscription, secret))
+    }
+
+    /// Remove a subscription, its pending deliveries are dropped.
+    pub fn unsubscribe(&mut self, id: usize) -> Option<Subscription> {
+        let idx = self.subscriptions.iter().position(|s| s.id == id)?;
+        let subscription = self.subscriptions.remove(idx);
+        self.deliveries
+            .retain(|d| d.subscription_id != id || d.status != DeliveryStatus::Pending);
+        self.changed.notify_one();
+        Some(subscription)
+    }
+
+    /// Queue a delivery of an event to each subscription to its type.
+    pub fn enqueue(&mut self, event: EventType, data: Value, now: Timestamp) -> Vec<usize> {
+        let payload = serde_json::json!({
+            "event": event.as_str(),
+            "occurred_at": now,
+            "data": data,
+        });
+        let subscriptions: Vec<usize> = self
+            .subscriptions
+            .iter()
+            .filter(|s| s.events.contains(&event))
+            .map(|s| s.id)
+            .collect();
+        let mut ids = vec![];
+        for subscription_id in subscriptions {
+            self.deliveries.push(Delivery {
+                id: self.delivery_counter,
+                subscription_id,
+                event,
+                payload: payload.clone(),
+                status: DeliveryStatus::Pending,
+                attempts: vec![],
+                next_attempt_at: Some(now),
+                created_at: now,
+            });
+            ids.push(self.delivery_counter);
+            self.delivery_counter += 1;
+        }
+        if !ids.is_empty() {
+            self.queued.notify_one();
+        }
+        ids
+    }
+
+    /// Deliveries, most recent first.
+    pub fn deliveries(&self) -> impl Iterator<Item = &Delivery> {
+        self.deliveries.iter().rev()
+    }
+
+    pub fn delivery(&self, id: usize) -> Option<&Delivery> {
+        self.deliveries.iter().find(|d| d.id == id)
+    }
+
+    /// Pending deliveries whose next attempt is due at `now`.
+    pub fn due_deliveries(&self, now: Timestamp) -> Vec<Delivery> {
+        self.deliveries
+            .iter()
+            .filter(|d| d.next_attempt_at.is_some_and(|at| at <= now))
+            .cloned()
+            .collect()
+    }
+
+    /// When the next attempt is due, if any delivery is pending.
+    pub fn next_attempt_at(&self) -> Option<Timestamp> {
+        self.deliveries
+            .iter()
+            .filter_map(|d| d.next_attempt_at)
+            .min()
+    }
+
+    /// Record an attempt at a delivery. Failed ones are retried with an
+    /// exponential backoff, until [`MAX_ATTEMPTS`].
+    pub fn record_attempt(&mut self, id: usize, attempt: Attempt) -> Option<&Delivery> {
+        let delivery = self.deliveries.iter_mut().find(|d| d.id == id)?;
+        let at = attempt.at;
+        let succeeded = attempt.succeeded();
+        delivery.attempts.push(attempt);
+        (delivery.status, delivery.next_attempt_at) = if succeeded {
+            (DeliveryStatus::Delivered, None)
+        } else if delivery.attempts.len() >= MAX_ATTEMPTS {
+            (DeliveryStatus::Failed, None)
+        } else {
+            let delay = retry_delay(delivery.attempts.len());
+            let next = at.checked_add(delay).unwrap_or(at);
+            (DeliveryStatus::Pending, Some(next))
+        };
+
+        // Only the most recent finished deliveries are kept
+        let finished = self
+            .deliveries
+            .iter()
+            .filter(|d| d.status != DeliveryStatus::Pending)
+            .count();
+        let mut to_drop = finished.saturating_sub(LOG_SIZE);
+        self.deliveries.retain(|d| {
+            let drop = to_drop > 0 && d.status != DeliveryStatus::Pending;
+            to_drop -= usize::from(drop);
+            !drop
+        });
+        self.delivery(id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use jiff::{SignedDuration, Timestamp};
+    use serde_json::json;
+
+    use super::{
+        Attempt, DeliveryStatus, EventType, MAX_ATTEMPTS, WebhooksState, retry_delay, sign,
+    };
+
+    fn failure(at: Timestamp) -> Attempt {
+        Attempt {
+            at,
+            status_code: Some(500),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_retry_with_backoff() {
+        let mut state = WebhooksState::new();
+        let now = Timestamp::now();
+        assert!(
+            state
+                .subscribe("ftp://example.com", &[EventType::TodoCreated], "alice", now)
+                .is_none()
+        );
+        assert!(
+            state
+                .subscribe("http://example.com", &[], "alice", now)
+                .is_none()
+        );
+        let events = [EventType::TodoCreated];
+        let (subscription, secret) = state
+            .subscribe("http://example.com/hook", &events, "alice", now)
+            .unwrap();
+        assert_eq!(subscription.secret(), secret);
+        assert!(
+            serde_json::to_string(&subscription.redacted())
+                .unwrap()
+                .contains("url")
+        );
+        assert!(
+            !serde_json::to_string(&subscription.redacted())
+                .unwrap()
+                .contains(&secret)
+        );
+
+        assert!(
+            state
+                .enqueue(EventType::TodoDeleted, json!({}), now)
+                .is_empty()
+        );
+        let [id] = state.enqueue(EventType::TodoCreated, json!({"id": 1}), now)[..] else {
+            panic!("One delivery per subscription");
+        };
+        assert_eq!(state.due_deliveries(now).len(), 1);
+
+        let delivery = state.record_attempt(id, failure(now)).unwrap();
+        assert_eq!(delivery.status, DeliveryStatus::Pending);
+        assert_eq!(
+            delivery.next_attempt_at,
+            Some(now + SignedDuration::from_secs(10))
+        );
+        assert!(state.due_deliveries(now).is_empty());
+        assert_eq!(retry_delay(3), SignedDuration::from_secs(40));
+
+        for _ in 1..MAX_ATTEMPTS {
+            state.record_attempt(id, failure(now));
+        }
+        let delivery = state.delivery(id).unwrap();
+        assert_eq!(delivery.status, DeliveryStatus::Failed);
+        assert_eq!(delivery.attempts.len(), MAX_ATTEMPTS);
+        assert_eq!(state.next_attempt_at(), None);
+        // Only subscriptions are saved
+        let saved = serde_json::to_value(&state).unwrap();
+        assert!(saved.get("deliveries").is_none());
+        assert_eq!(saved["subscriptions"][0]["secret"], secret);
+    }
+
+    #[test]
+    fn test_sign() {
+        // echo -n '1700000000.{}' | openssl dgst -sha256 -hmac secret
+        assert_eq!(
+            sign("secret", 1_700_000_000, "{}"),
+            "sha256=b8569b78799ff9e3cbff0fc2d63a33a2b57f3282abd07c37ae5e8e7d79a5f163"
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use maud::{Markup, html};
+
+use crate::utils::format_timestamp;
+
+use super::state::{Delivery, DeliveryStatus, EventType, Subscription};
+
+/// The subscriptions, along with the secret of the one just created, which is
+/// only shown once.
+pub fn subscriptions_view(subscriptions: &[Subscription], created_secret: Option<&str>) -> Markup {
+    html! {
+        div.flex.flex-col.items-center.gap-4 #subscriptions {
+            @if let Some(secret) = created_secret {
+                div.alert.alert-success.flex.flex-col role="alert" {
+                    span { "Copy the signing secret now, it will not be shown again:" }
+                    code.select-all #subscription-secret { (secret) }
+                }
+            }
+            form.flex.flex-col.gap-2.bg-base-200.rounded-box.p-4 #new-subscription
+                hx-post="/webhooks" hx-target="#subscriptions" hx-swap="outerHTML" {
+                input.input.input-sm type="url" name="url" placeholder="https://example.com/hook" required;
+                div.flex.flex-wrap.gap-4 {
+                    @for event in EventType::VARIANTS {
+                        label.label.text-sm {
+                            input.checkbox.checkbox-sm type="checkbox" name="events" value=(event.as_str());
+                            (event.as_str())
+                        }
+                    }
+                }
+                button.btn.btn-sm.btn-primary { "Add webhook" }
+            }
+            @if subscriptions.is_empty() {
+                p.text-gray-500 { "No webhooks yet." }
+            } @else {
+                table.table.bg-base-100.rounded-box.shadow-md {
+                    thead {
+                        tr {
+                            th { "URL" }
+                            th { "Events" }
+                            th { "Created" }
+                            th {}
+                        }
+                    }
+                    tbody {
+                        @for subscription in subscriptions {
+                            tr data-subscription-id=(subscription.id) {
+                                td.font-mono { (subscription.url) }
+                                td {
+                                    @for event in &subscription.events {
+                                        span.badge.badge-sm.mr-1 { (event.as_str()) }
+                                    }
+                                }
+                                td { (format_timestamp(subscription.created_at)) " by " (subscription.created_by) }
+                                td {
+                                    button.btn.btn-sm.btn-error
+                                        hx-delete={ "/webhooks/" (subscription.id) }
+                                        hx-target="#subscriptions"
+                                        hx-swap="outerHTML"
+                                        hx-confirm={ "Remove " (subscription.url) "?" } {
+                                        "Remove"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The log of recent deliveries, refreshed while the page is open.
+pub fn deliveries_log<'a>(deliveries: impl IntoIterator<Item = &'a Delivery>) -> Markup {
+    html! {
+        div.overflow-x-auto #deliveries
+            hx-get="/webhooks/deliveries" hx-trigger="every 5s" hx-swap="outerHTML" {
+            table.table.table-sm.bg-base-100.rounded-box.shadow-md {
+                thead {
+                    tr {
+                        th { "Delivery" }
+                        th { "Event" }
+                        th { "Webhook" }
+                        th { "Status" }
+                        th { "Attempts" }
+                        th { "Response" }
+                        th { "Next attempt" }
+                    }
+                }
+                tbody {
+                    @for delivery in deliveries {
+                        @let last = delivery.attempts.last();
+                        tr data-delivery-id=(delivery.id) {
+                            td { (format_timestamp(delivery.created_at)) }
+                            td { (delivery.event.as_str()) }
+                            td { "#" (delivery.subscription_id) }
+                            td {
+                                @match delivery.status {
+                                    DeliveryStatus::Pending => span.badge.badge-warning { "pending" },
+                                    DeliveryStatus::Delivered => span.badge.badge-success { "delivered" },
+                                    DeliveryStatus::Failed => span.badge.badge-error { "failed" },
+                                }
+                            }
+                            td { (delivery.attempts.len()) }
+                            td.response {
+                                @if let Some(code) = last.and_then(|a| a.status_code) {
+                                    (code) " "
+                                }
+                                @if let Some(error) = last.and_then(|a| a.error.as_ref()) {
+                                    span.text-gray-500 { (error) }
+                                }
+                            }
+                            td {
+                                @if let Some(next) = delivery.next_attempt_at {
+                                    (format_timestamp(next))
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -66,7 +66,41 @@ mod tests {
                 .await
                 .unwrap();
             todo_nav.click().await.unwrap();
+            let tokens_url = format!("{addr}/tokens");
+            assert_eq!(c.current_url().await.unwrap().as_ref(), tokens_url);
+
+            // Sign in by claiming a name with its first token
+            c.execute("document.cookie = 'username=e2e; path=/'", vec![])
+                .await
+                .unwrap();
+            c.refresh().await.unwrap();
+            let name = c
+                .find(Locator::Css("#new-token input[name=name]"))
+                .await
+                .unwrap();
+            name.send_keys("browser").await.unwrap();
+            for scope in ["todos:read", "todos:write"] {
+                let checkbox = format!("#new-token input[value='{scope}']");
+                c.find(Locator::Css(&checkbox))
+                    .await
+                    .unwrap()
+                    .click()
+                    .await
+                    .unwrap();
+            }
+            c.find(Locator::XPath("//button[text()='Create token']"))
+                .await
+                .unwrap()
+                .click()
+                .await
+                .unwrap();
+            c.wait()
+                .at_most(DEFAULT_WAIT_TIMEOUT)
+                .for_element(Locator::Id("token-secret"))
+                .await
+                .unwrap();
             let todos_url = format!("{addr}/todos");
+            c.goto(&todos_url).await.unwrap();
             assert_eq!(c.current_url().await.unwrap().as_ref(), todos_url);
 
             // Add a new todo